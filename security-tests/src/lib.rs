@@ -0,0 +1,11 @@
+//! Malicious programs the threat model tests point the escrow at. Each one is registered with
+//! `solana-program-test` as a native program and swapped in wherever the escrow expects the token program.
+
+pub mod fake_token;
+pub mod hostile_hook;
+pub mod reentrant;
+
+/// The id the escrow program is deployed under in the tests, the reentrant fixture needs it to call back in
+pub mod escrow_program {
+    solana_program::declare_id!("CKqAWAngmy8VMs8UHBWgZu3XgiSnjV5XpEzJxxTz3G25");
+}
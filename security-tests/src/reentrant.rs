@@ -0,0 +1,36 @@
+use solana_program::{
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
+    msg,
+    program::invoke,
+    pubkey::Pubkey,
+};
+
+use crate::escrow_program;
+
+solana_program::declare_id!("B6AM3ZSS2SJ6VY8JtBpC6GCWwWqj9NPXKdVx1eeWPTn4");
+
+/// Calls straight back into the escrow with the accounts it was given, as a token program (or hook) re-entering
+/// mid-settlement would. The instruction data it receives is forwarded as the escrow instruction
+pub fn process_instruction(
+    _program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction_data: &[u8],
+) -> ProgramResult {
+    let callback_ix = Instruction {
+        program_id: escrow_program::id(),
+        accounts: accounts
+            .iter()
+            .map(|account| AccountMeta {
+                pubkey: *account.key,
+                is_signer: account.is_signer,
+                is_writable: account.is_writable,
+            })
+            .collect(),
+        data: instruction_data.to_vec(),
+    };
+
+    msg!("reentrant: calling back into the escrow program");
+    invoke(&callback_ix, accounts)
+}
@@ -0,0 +1,266 @@
+//! The test validator setup shared by the test suites: an escrow offering 100 X for 50 Y and a taker
+//! holding exactly 50 Y.
+
+#![allow(dead_code)] // each suite only uses part of the harness
+
+use solana_escrow_program::{instruction, processor::Processor, state::Escrow};
+use solana_escrow_security_tests::{escrow_program, fake_token, hostile_hook, reentrant};
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    program_option::COption,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    system_instruction,
+};
+use solana_program_test::{processor, BanksClient, ProgramTest};
+use solana_sdk::{
+    account::Account,
+    hash::Hash,
+    signature::{Keypair, Signer},
+    transaction::{Transaction, TransactionError},
+};
+use spl_token::state::{Account as TokenAccount, AccountState, Mint};
+
+pub const OFFERED_AMOUNT: u64 = 100;
+pub const EXPECTED_AMOUNT: u64 = 50;
+
+pub struct Env {
+    pub banks_client: BanksClient,
+    pub payer: Keypair,
+    pub recent_blockhash: Hash,
+    pub initializer: Keypair,
+    pub taker: Keypair,
+    pub vault: Pubkey,
+    pub initializer_receive: Pubkey,
+    pub initializer_refund: Pubkey,
+    pub taker_send: Pubkey,
+    pub taker_receive: Pubkey,
+    pub escrow: Keypair,
+    pub pda: Pubkey,
+}
+
+pub fn token_account(mint: Pubkey, owner: Pubkey, amount: u64) -> Account {
+    let mut data = vec![0; TokenAccount::LEN];
+    TokenAccount::pack(
+        TokenAccount {
+            mint,
+            owner,
+            amount,
+            state: AccountState::Initialized,
+            ..TokenAccount::default()
+        },
+        &mut data,
+    )
+    .unwrap();
+    Account {
+        lamports: 1_000_000_000,
+        data,
+        owner: spl_token::id(),
+        executable: false,
+        rent_epoch: 0,
+    }
+}
+
+pub fn mint_account() -> Account {
+    let mut data = vec![0; Mint::LEN];
+    Mint::pack(
+        Mint {
+            mint_authority: COption::None,
+            supply: u64::MAX,
+            decimals: 0,
+            is_initialized: true,
+            freeze_authority: COption::None,
+        },
+        &mut data,
+    )
+    .unwrap();
+    Account {
+        lamports: 1_000_000_000,
+        data,
+        owner: spl_token::id(),
+        executable: false,
+        rent_epoch: 0,
+    }
+}
+
+pub fn program_test() -> ProgramTest {
+    let mut program_test = ProgramTest::new(
+        "solana_escrow_program",
+        escrow_program::id(),
+        processor!(Processor::process),
+    );
+    program_test.add_program("fake_token", fake_token::id(), processor!(fake_token::process_instruction));
+    program_test.add_program("hostile_hook", hostile_hook::id(), processor!(hostile_hook::process_instruction));
+    program_test.add_program("reentrant", reentrant::id(), processor!(reentrant::process_instruction));
+    program_test
+}
+
+/// Everything the escrow gets set up with, created before the test validator starts so scenarios can add accounts of their own
+pub struct Setup {
+    pub program_test: ProgramTest,
+    pub initializer: Keypair,
+    pub taker: Keypair,
+    pub mint_x: Pubkey,
+    pub mint_y: Pubkey,
+    pub vault: Pubkey,
+    pub initializer_receive: Pubkey,
+    pub initializer_refund: Pubkey,
+    pub taker_send: Pubkey,
+    pub taker_receive: Pubkey,
+    pub creation_deposit: u64, // lamports the escrow account is funded with on top of rent
+}
+
+impl Setup {
+    /// The initializer offers 100 X for 50 Y and the taker holds exactly 50 Y
+    pub fn new() -> Self {
+        let mut program_test = program_test();
+
+        let initializer = Keypair::new();
+        let taker = Keypair::new();
+        let mint_x = Pubkey::new_unique();
+        let mint_y = Pubkey::new_unique();
+        let vault = Pubkey::new_unique();
+        let initializer_receive = Pubkey::new_unique();
+        let initializer_refund = Pubkey::new_unique();
+        let taker_send = Pubkey::new_unique();
+        let taker_receive = Pubkey::new_unique();
+
+        program_test.add_account(mint_x, mint_account());
+        program_test.add_account(mint_y, mint_account());
+        program_test.add_account(vault, token_account(mint_x, initializer.pubkey(), OFFERED_AMOUNT));
+        program_test.add_account(initializer_receive, token_account(mint_y, initializer.pubkey(), 0));
+        program_test.add_account(initializer_refund, token_account(mint_x, initializer.pubkey(), 0));
+        program_test.add_account(taker_send, token_account(mint_y, taker.pubkey(), EXPECTED_AMOUNT));
+        program_test.add_account(taker_receive, token_account(mint_x, taker.pubkey(), 0));
+
+        Setup {
+            program_test,
+            initializer,
+            taker,
+            mint_x,
+            mint_y,
+            vault,
+            initializer_receive,
+            initializer_refund,
+            taker_send,
+            taker_receive,
+            creation_deposit: 0,
+        }
+    }
+
+    /// Starts the test validator and opens the escrow with an InitEscrow from the initializer
+    pub async fn open_escrow(self, expires_at: i64) -> Env {
+        let (mut banks_client, payer, recent_blockhash) = self.program_test.start().await;
+        let (pda, _bump_seed) = Pubkey::find_program_address(&[b"escrow"], &escrow_program::id());
+
+        let escrow = Keypair::new();
+        let rent = banks_client.get_rent().await.unwrap();
+        let mut transaction = Transaction::new_with_payer(
+            &[
+                system_instruction::create_account(
+                    &payer.pubkey(),
+                    &escrow.pubkey(),
+                    rent.minimum_balance(Escrow::LEN) + self.creation_deposit,
+                    Escrow::LEN as u64,
+                    &escrow_program::id(),
+                ),
+                instruction::init_escrow(
+                    &escrow_program::id(),
+                    &self.initializer.pubkey(),
+                    &self.vault,
+                    &self.initializer_receive,
+                    &escrow.pubkey(),
+                    EXPECTED_AMOUNT,
+                    expires_at,
+                    0,
+                    None,
+                    None,
+                ),
+            ],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[&payer, &self.initializer, &escrow], recent_blockhash);
+        banks_client.process_transaction(transaction).await.unwrap();
+
+        Env {
+            banks_client,
+            payer,
+            recent_blockhash,
+            initializer: self.initializer,
+            taker: self.taker,
+            vault: self.vault,
+            initializer_receive: self.initializer_receive,
+            initializer_refund: self.initializer_refund,
+            taker_send: self.taker_send,
+            taker_receive: self.taker_receive,
+            escrow,
+            pda,
+        }
+    }
+}
+
+impl Env {
+    pub fn taker(&self) -> Keypair {
+        Keypair::from_bytes(&self.taker.to_bytes()).unwrap()
+    }
+
+    pub fn exchange_ix(&self, token_program: Pubkey) -> Instruction {
+        let mut data = vec![1];
+        data.extend_from_slice(&OFFERED_AMOUNT.to_le_bytes());
+        Instruction {
+            program_id: escrow_program::id(),
+            accounts: vec![
+                AccountMeta::new_readonly(self.taker.pubkey(), true),
+                AccountMeta::new(self.taker_send, false),
+                AccountMeta::new(self.taker_receive, false),
+                AccountMeta::new(self.vault, false),
+                AccountMeta::new(self.initializer.pubkey(), false),
+                AccountMeta::new(self.initializer_receive, false),
+                AccountMeta::new(self.escrow.pubkey(), false),
+                AccountMeta::new_readonly(token_program, false),
+                AccountMeta::new_readonly(self.pda, false),
+            ],
+            data,
+        }
+    }
+
+    pub async fn send(&mut self, instruction: Instruction, signer: &Keypair) -> bool {
+        let mut transaction = Transaction::new_with_payer(&[instruction], Some(&self.payer.pubkey()));
+        transaction.sign(&[&self.payer, signer], self.recent_blockhash);
+        self.banks_client.process_transaction(transaction).await.is_ok()
+    }
+
+    /// What `instruction` would cost in compute units, simulated against the current state so nothing is committed
+    pub async fn units_consumed(&mut self, instruction: Instruction, signers: &[&Keypair]) -> u64 {
+        let mut transaction = Transaction::new_with_payer(&[instruction], Some(&self.payer.pubkey()));
+        let mut all_signers = vec![&self.payer];
+        all_signers.extend_from_slice(signers);
+        transaction.sign(&all_signers, self.recent_blockhash);
+        let simulation = self.banks_client.simulate_transaction(transaction).await.unwrap();
+        assert!(matches!(simulation.result, Some(Ok(()))), "simulation failed: {:?}", simulation.result);
+        simulation.simulation_details.unwrap().units_consumed
+    }
+
+    pub async fn send_and_fail(&mut self, instruction: Instruction, signer: &Keypair) -> TransactionError {
+        let mut transaction = Transaction::new_with_payer(&[instruction], Some(&self.payer.pubkey()));
+        transaction.sign(&[&self.payer, signer], self.recent_blockhash);
+        self.banks_client.process_transaction(transaction).await.unwrap_err().unwrap()
+    }
+
+    /// The vault still holds the offer under the PDA and the escrow state is untouched
+    pub async fn assert_escrow_intact(&mut self) {
+        let vault = self.banks_client.get_account(self.vault).await.unwrap().unwrap();
+        let vault = TokenAccount::unpack(&vault.data).unwrap();
+        assert_eq!(vault.owner, self.pda);
+        assert_eq!(vault.amount, OFFERED_AMOUNT);
+
+        let escrow = self.banks_client.get_account(self.escrow.pubkey()).await.unwrap().unwrap();
+        let escrow = Escrow::unpack(&escrow.data).unwrap();
+        assert_eq!(escrow.initializer_pubkey, self.initializer.pubkey());
+        assert_eq!(escrow.temp_token_account_pubkey, self.vault);
+        assert_eq!(escrow.expected_amount, EXPECTED_AMOUNT);
+
+        let taker_send = self.banks_client.get_account(self.taker_send).await.unwrap().unwrap();
+        assert_eq!(TokenAccount::unpack(&taker_send.data).unwrap().amount, EXPECTED_AMOUNT);
+    }
+}
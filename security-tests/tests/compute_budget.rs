@@ -0,0 +1,23 @@
+//! Compute unit ceilings for the hot paths, so a change that makes them noticeably more expensive fails here
+//! rather than on a congested mainnet block.
+//!
+//! The limits are only enforced when the program runs as BPF, i.e. with `BPF_OUT_DIR` pointing at the built program. A plain
+//! `cargo test` runs the processor natively, which isn't metered, so these always pass there.
+
+mod common;
+
+use common::Setup;
+
+/// What an Exchange of a plain escrow is allowed to cost: three token program CPIs plus our own checks, with some headroom
+const EXCHANGE_COMPUTE_BUDGET: u64 = 45_000;
+
+#[tokio::test]
+async fn exchange_fits_the_compute_budget() {
+    let mut setup = Setup::new();
+    setup.program_test.set_bpf_compute_max_units(EXCHANGE_COMPUTE_BUDGET);
+    let mut env = setup.open_escrow(0).await;
+    let taker = env.taker();
+
+    let exchange_ix = env.exchange_ix(spl_token::id());
+    assert!(env.send(exchange_ix, &taker).await, "Exchange went over {} compute units", EXCHANGE_COMPUTE_BUDGET);
+}
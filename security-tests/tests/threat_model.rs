@@ -0,0 +1,1168 @@
+//! Adversarial scenarios against the escrow. Every attack has to be rejected and leave the vault and the
+//! escrow state exactly as they were.
+
+mod common;
+
+use std::convert::TryInto;
+
+use common::{token_account, Env, Setup, EXPECTED_AMOUNT, OFFERED_AMOUNT};
+use solana_escrow_program::{
+    client,
+    error::EscrowError,
+    instruction, metadata,
+    signed_offer::{self, SignedOffer},
+    state::{Config, Escrow, FilledOffer, Stats, TakerClaim},
+};
+use solana_escrow_security_tests::{escrow_program, fake_token, hostile_hook, reentrant};
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    program_option::COption,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    system_instruction,
+};
+use solana_sdk::{
+    account::Account,
+    signature::{Keypair, Signer},
+    instruction::InstructionError,
+    transaction::{Transaction, TransactionError},
+};
+use spl_token::state::{Account as TokenAccount, Mint};
+
+#[tokio::test]
+async fn honest_exchange_settles() {
+    // the baseline every attack below is measured against, the same accounts with nothing tampered succeed
+    let mut env = Setup::new().open_escrow(0).await;
+    let taker = env.taker();
+
+    let exchange_ix = env.exchange_ix(spl_token::id());
+    assert!(env.send(exchange_ix, &taker).await);
+
+    let taker_receive = env.banks_client.get_account(env.taker_receive).await.unwrap().unwrap();
+    assert_eq!(TokenAccount::unpack(&taker_receive.data).unwrap().amount, OFFERED_AMOUNT);
+    let initializer_receive = env.banks_client.get_account(env.initializer_receive).await.unwrap().unwrap();
+    assert_eq!(TokenAccount::unpack(&initializer_receive.data).unwrap().amount, EXPECTED_AMOUNT);
+    assert!(env.banks_client.get_account(env.escrow.pubkey()).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn fake_token_program_cannot_settle_an_exchange() {
+    let mut env = Setup::new().open_escrow(0).await;
+    let taker = env.taker();
+
+    let exchange_ix = env.exchange_ix(fake_token::id());
+    assert_eq!(
+        env.send_and_fail(exchange_ix, &taker).await,
+        TransactionError::InstructionError(0, InstructionError::Custom(EscrowError::InvalidTokenProgram as u32))
+    );
+    env.assert_escrow_intact().await;
+}
+
+#[tokio::test]
+async fn hostile_hook_cannot_use_the_pda_signature() {
+    let mut env = Setup::new().open_escrow(0).await;
+    let taker = env.taker();
+
+    let exchange_ix = env.exchange_ix(hostile_hook::id());
+    assert_eq!(
+        env.send_and_fail(exchange_ix, &taker).await,
+        TransactionError::InstructionError(0, InstructionError::Custom(EscrowError::InvalidTokenProgram as u32))
+    );
+    env.assert_escrow_intact().await;
+}
+
+#[tokio::test]
+async fn reentrant_callback_is_rejected() {
+    let mut env = Setup::new().open_escrow(0).await;
+    let taker = env.taker();
+
+    let mut exchange_ix = env.exchange_ix(reentrant::id());
+    exchange_ix.accounts.push(AccountMeta::new_readonly(escrow_program::id(), false));
+    assert!(!env.send(exchange_ix, &taker).await);
+    env.assert_escrow_intact().await;
+}
+
+#[tokio::test]
+async fn substituted_vault_is_rejected() {
+    let mut env = Setup::new().open_escrow(0).await;
+    let taker = env.taker();
+
+    // the taker's own X account in place of the vault, so they would pay and get their own tokens back
+    let mut exchange_ix = env.exchange_ix(spl_token::id());
+    exchange_ix.accounts[3] = AccountMeta::new(env.taker_receive, false);
+    assert!(!env.send(exchange_ix, &taker).await);
+    env.assert_escrow_intact().await;
+}
+
+#[tokio::test]
+async fn redirected_payment_is_rejected() {
+    let mut env = Setup::new().open_escrow(0).await;
+    let taker = env.taker();
+
+    // pay the initializer's share back into an account the taker controls
+    let mut exchange_ix = env.exchange_ix(spl_token::id());
+    exchange_ix.accounts[5] = AccountMeta::new(env.taker_send, false);
+    assert!(!env.send(exchange_ix, &taker).await);
+    env.assert_escrow_intact().await;
+}
+
+#[tokio::test]
+async fn redirected_rent_is_rejected() {
+    let mut env = Setup::new().open_escrow(0).await;
+    let taker = env.taker();
+
+    let mut exchange_ix = env.exchange_ix(spl_token::id());
+    exchange_ix.accounts[4] = AccountMeta::new(taker.pubkey(), false);
+    assert!(!env.send(exchange_ix, &taker).await);
+    env.assert_escrow_intact().await;
+}
+
+#[tokio::test]
+async fn front_run_amount_change_is_rejected() {
+    let mut env = Setup::new().open_escrow(0).await;
+    let taker = env.taker();
+
+    let mut exchange_ix = env.exchange_ix(spl_token::id());
+    exchange_ix.data[1..9].copy_from_slice(&(OFFERED_AMOUNT + 1).to_le_bytes());
+    assert!(!env.send(exchange_ix, &taker).await);
+    env.assert_escrow_intact().await;
+}
+
+#[tokio::test]
+async fn cancel_by_someone_else_is_rejected() {
+    let mut env = Setup::new().open_escrow(0).await;
+    let taker = env.taker();
+
+    let cancel_ix = Instruction {
+        program_id: escrow_program::id(),
+        accounts: vec![
+            AccountMeta::new(taker.pubkey(), true),
+            AccountMeta::new(env.vault, false),
+            AccountMeta::new(env.taker_receive, false),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(env.pda, false),
+        ],
+        data: vec![5],
+    };
+    assert!(!env.send(cancel_ix, &taker).await);
+    env.assert_escrow_intact().await;
+}
+
+#[tokio::test]
+async fn crank_before_expiry_is_rejected() {
+    let mut env = Setup::new().open_escrow(i64::MAX).await;
+    let cranker = env.taker();
+
+    let crank_ix = Instruction {
+        program_id: escrow_program::id(),
+        accounts: vec![
+            AccountMeta::new(cranker.pubkey(), true),
+            AccountMeta::new(env.vault, false),
+            AccountMeta::new(env.initializer.pubkey(), false),
+            AccountMeta::new(env.initializer_refund, false),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(env.pda, false),
+        ],
+        data: vec![2],
+    };
+    assert!(!env.send(crank_ix, &cranker).await);
+    env.assert_escrow_intact().await;
+}
+
+#[tokio::test]
+async fn forged_escrow_account_is_rejected() {
+    let mut setup = Setup::new();
+
+    // an escrow-shaped account outside the program, pointing the victim's vault at the taker for a price of 1
+    let forged = Pubkey::new_unique();
+    let forged_escrow = Escrow {
+        is_initialized: true,
+        initializer_pubkey: setup.taker.pubkey(),
+        temp_token_account_pubkey: setup.vault,
+        initializer_token_to_receive_account_pubkey: setup.taker_send,
+        expected_amount: 1,
+        ..Escrow::default()
+    };
+    let mut data = vec![0; Escrow::LEN];
+    Escrow::pack(forged_escrow, &mut data).unwrap();
+    setup.program_test.add_account(
+        forged,
+        Account {
+            lamports: 1_000_000_000,
+            data,
+            owner: fake_token::id(),
+            executable: false,
+            rent_epoch: 0,
+        },
+    );
+
+    let mut env = setup.open_escrow(0).await;
+    let taker = env.taker();
+
+    let mut exchange_ix = env.exchange_ix(spl_token::id());
+    exchange_ix.accounts[4] = AccountMeta::new(taker.pubkey(), false);
+    exchange_ix.accounts[5] = AccountMeta::new(env.taker_send, false);
+    exchange_ix.accounts[6] = AccountMeta::new(forged, false);
+    assert_eq!(
+        env.send_and_fail(exchange_ix, &taker).await,
+        TransactionError::InstructionError(0, InstructionError::Custom(EscrowError::InvalidAccountOwner as u32))
+    );
+    env.assert_escrow_intact().await;
+}
+
+const DEPOSIT: u64 = 10_000_000;
+
+/// Adds a config that makes every escrow put down `DEPOSIT`, forfeited to the returned fee recipient within 1,000 slots of opening
+fn add_deposit_config(setup: &mut Setup) -> Pubkey {
+    let fee_recipient = Pubkey::new_unique();
+    let config = Config {
+        is_initialized: true,
+        admin_pubkey: Pubkey::new_unique(),
+        max_referral_bps: 0,
+        creation_deposit: DEPOSIT,
+        deposit_lock_slots: 1_000,
+        fee_recipient,
+        ..Config::default()
+    };
+    let mut data = vec![0; Config::LEN];
+    Config::pack(config, &mut data).unwrap();
+    let (config_pda, _bump_seed) = Pubkey::find_program_address(&[b"config"], &escrow_program::id());
+    setup.program_test.add_account(
+        config_pda,
+        Account {
+            lamports: 1_000_000_000,
+            data,
+            owner: escrow_program::id(),
+            executable: false,
+            rent_epoch: 0,
+        },
+    );
+    setup.creation_deposit = DEPOSIT;
+    fee_recipient
+}
+
+#[tokio::test]
+async fn spam_offer_forfeits_its_creation_deposit() {
+    let mut setup = Setup::new();
+    let fee_recipient = add_deposit_config(&mut setup);
+    let spare_vault = Pubkey::new_unique();
+    setup.program_test.add_account(spare_vault, token_account(setup.mint_x, setup.initializer.pubkey(), OFFERED_AMOUNT));
+
+    let mut env = setup.open_escrow(0).await;
+
+    // an escrow account funded with only its rent can't be opened
+    let unfunded_escrow = Keypair::new();
+    let rent = env.banks_client.get_rent().await.unwrap();
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            system_instruction::create_account(
+                &env.payer.pubkey(),
+                &unfunded_escrow.pubkey(),
+                rent.minimum_balance(Escrow::LEN),
+                Escrow::LEN as u64,
+                &escrow_program::id(),
+            ),
+            instruction::init_escrow(
+                &escrow_program::id(),
+                &env.initializer.pubkey(),
+                &spare_vault,
+                &env.initializer_receive,
+                &unfunded_escrow.pubkey(),
+                EXPECTED_AMOUNT,
+                0,
+                0,
+                None,
+                None,
+            ),
+        ],
+        Some(&env.payer.pubkey()),
+    );
+    transaction.sign(&[&env.payer, &env.initializer, &unfunded_escrow], env.recent_blockhash);
+    assert_eq!(
+        env.banks_client.process_transaction(transaction).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(1, InstructionError::Custom(EscrowError::MissingCreationDeposit as u32))
+    );
+
+    // cancelling straight away pays the deposit to the fee recipient instead of back to the initializer
+    let initializer = Keypair::from_bytes(&env.initializer.to_bytes()).unwrap();
+    let cancel_ix = Instruction {
+        program_id: escrow_program::id(),
+        accounts: vec![
+            AccountMeta::new(initializer.pubkey(), true),
+            AccountMeta::new(env.vault, false),
+            AccountMeta::new(env.initializer_refund, false),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(env.pda, false),
+            AccountMeta::new(fee_recipient, false),
+        ],
+        data: vec![5],
+    };
+    assert!(env.send(cancel_ix, &initializer).await);
+    assert_eq!(env.banks_client.get_balance(fee_recipient).await.unwrap(), DEPOSIT);
+}
+
+/// The taker locking in the whole offer with their send account as the temp token account
+fn deposit_taker_side_ix(env: &Env) -> Instruction {
+    let mut data = vec![13];
+    data.extend_from_slice(&OFFERED_AMOUNT.to_le_bytes());
+    Instruction {
+        program_id: escrow_program::id(),
+        accounts: vec![
+            AccountMeta::new_readonly(env.taker.pubkey(), true),
+            AccountMeta::new(env.taker_send, false),
+            AccountMeta::new_readonly(env.taker_receive, false),
+            AccountMeta::new_readonly(env.vault, false),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(env.initializer_receive, false),
+        ],
+        data,
+    }
+}
+
+/// Adds the stats PDA of `owner`, the global stats for `None`, already counting one open escrow
+fn add_stats(setup: &mut Setup, owner: Option<Pubkey>) -> Pubkey {
+    let (stats_pda, bump_seed) = match owner {
+        Some(owner) => Pubkey::find_program_address(&[b"stats", owner.as_ref()], &escrow_program::id()),
+        None => Pubkey::find_program_address(&[b"stats"], &escrow_program::id()),
+    };
+    let stats = Stats {
+        is_initialized: true,
+        owner_pubkey: owner.unwrap_or_default(),
+        bump_seed,
+        escrows_created: 1,
+        open_escrows: 1,
+        ..Stats::default()
+    };
+    let mut data = vec![0; Stats::LEN];
+    Stats::pack(stats, &mut data).unwrap();
+    setup.program_test.add_account(
+        stats_pda,
+        Account {
+            lamports: 1_000_000_000,
+            data,
+            owner: escrow_program::id(),
+            executable: false,
+            rent_epoch: 0,
+        },
+    );
+    stats_pda
+}
+
+#[tokio::test]
+async fn settling_a_deposited_escrow_takes_it_off_the_open_escrows() {
+    let mut setup = Setup::new();
+    let initializer = setup.initializer.pubkey();
+    let global_stats = add_stats(&mut setup, None);
+    let initializers_stats = add_stats(&mut setup, Some(initializer));
+    let mut env = setup.open_escrow(0).await;
+
+    let taker = env.taker();
+    assert!(env.send(deposit_taker_side_ix(&env), &taker).await);
+
+    let settle_ix = Instruction {
+        program_id: escrow_program::id(),
+        accounts: vec![
+            AccountMeta::new_readonly(taker.pubkey(), true),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new(env.vault, false),
+            AccountMeta::new(env.taker_send, false),
+            AccountMeta::new(env.initializer_receive, false),
+            AccountMeta::new(env.taker_receive, false),
+            AccountMeta::new(env.initializer.pubkey(), false),
+            AccountMeta::new(taker.pubkey(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(env.pda, false),
+            AccountMeta::new(global_stats, false),
+            AccountMeta::new(initializers_stats, false),
+        ],
+        data: vec![14],
+    };
+    assert!(env.send(settle_ix, &taker).await);
+
+    for stats in [global_stats, initializers_stats] {
+        let stats = env.banks_client.get_account(stats).await.unwrap().unwrap();
+        assert_eq!(Stats::unpack(&stats.data).unwrap().open_escrows, 0);
+    }
+}
+
+#[tokio::test]
+async fn withdrawing_from_a_deposited_escrow_forfeits_the_creation_deposit_like_a_cancel() {
+    let mut setup = Setup::new();
+    let fee_recipient = add_deposit_config(&mut setup);
+    let mut env = setup.open_escrow(0).await;
+
+    let taker = env.taker();
+    assert!(env.send(deposit_taker_side_ix(&env), &taker).await);
+
+    // backing out straight away is the same spam as cancelling straight away, the taker's payment goes back to them
+    let initializer = Keypair::from_bytes(&env.initializer.to_bytes()).unwrap();
+    let withdraw_ix = Instruction {
+        program_id: escrow_program::id(),
+        accounts: vec![
+            AccountMeta::new(initializer.pubkey(), true),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new(env.vault, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(env.pda, false),
+            AccountMeta::new(env.taker_send, false),
+            AccountMeta::new(fee_recipient, false),
+        ],
+        data: vec![15],
+    };
+    assert!(env.send(withdraw_ix, &initializer).await);
+    assert_eq!(env.banks_client.get_balance(fee_recipient).await.unwrap(), DEPOSIT);
+
+    let taker_send = env.banks_client.get_account(env.taker_send).await.unwrap().unwrap();
+    let taker_send = TokenAccount::unpack(&taker_send.data).unwrap();
+    assert_eq!(taker_send.owner, taker.pubkey());
+    assert_eq!(taker_send.amount, EXPECTED_AMOUNT);
+}
+
+#[tokio::test]
+async fn orphan_recovery_only_returns_unclaimed_accounts_to_their_owner() {
+    let mut setup = Setup::new();
+    let (pda, _bump_seed) = Pubkey::find_program_address(&[b"escrow"], &escrow_program::id());
+    let orphan_authority = |owner: Pubkey| client::find_orphan_authority_address(&owner, &escrow_program::id());
+    let add_pda_held_account = |setup: &mut Setup, close_authority: Pubkey| {
+        let token_account_key = Pubkey::new_unique();
+        let mut account = token_account(setup.mint_x, pda, OFFERED_AMOUNT);
+        let mut state = TokenAccount::unpack(&account.data).unwrap();
+        state.close_authority = COption::Some(close_authority);
+        TokenAccount::pack(state, &mut account.data).unwrap();
+        setup.program_test.add_account(token_account_key, account);
+        token_account_key
+    };
+
+    // a vault handed to the PDA whose InitEscrow never landed, closable by the initializer's orphan authority, and the vault of an
+    // escrow opened before InitEscrow refused close authorities, closable by the initializer themselves
+    let initializers_orphan_authority = orphan_authority(setup.initializer.pubkey());
+    let orphan = add_pda_held_account(&mut setup, initializers_orphan_authority);
+    let initializer_key = setup.initializer.pubkey();
+    let old_vault = add_pda_held_account(&mut setup, initializer_key);
+
+    let mut env = setup.open_escrow(0).await;
+    let initializer = Keypair::from_bytes(&env.initializer.to_bytes()).unwrap();
+    let taker = env.taker();
+    let recover_ix = |claimant: Pubkey, token_account: Pubkey, authority: Pubkey| Instruction {
+        program_id: escrow_program::id(),
+        accounts: vec![
+            AccountMeta::new_readonly(claimant, true),
+            AccountMeta::new(token_account, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(pda, false),
+            AccountMeta::new_readonly(authority, false),
+        ],
+        data: vec![22],
+    };
+
+    assert_eq!(
+        env.send_and_fail(recover_ix(taker.pubkey(), orphan, orphan_authority(taker.pubkey())), &taker).await,
+        TransactionError::InstructionError(0, InstructionError::Custom(EscrowError::InvalidOrphanClaim as u32))
+    );
+    // whichever escrow account is passed, an old vault without the orphan authority stays with its escrow
+    for unrelated_escrow in [Pubkey::new_unique(), env.escrow.pubkey()] {
+        assert_eq!(
+            env.send_and_fail(recover_ix(initializer.pubkey(), old_vault, unrelated_escrow), &initializer).await,
+            TransactionError::InstructionError(0, InstructionError::Custom(EscrowError::InvalidOrphanClaim as u32))
+        );
+    }
+    assert_eq!(
+        env.send_and_fail(recover_ix(initializer.pubkey(), env.vault, initializers_orphan_authority), &initializer).await,
+        TransactionError::InstructionError(0, InstructionError::Custom(EscrowError::InvalidOrphanClaim as u32))
+    );
+    env.assert_escrow_intact().await;
+
+    assert!(env.send(recover_ix(initializer.pubkey(), orphan, initializers_orphan_authority), &initializer).await);
+    let orphan = env.banks_client.get_account(orphan).await.unwrap().unwrap();
+    let orphan = TokenAccount::unpack(&orphan.data).unwrap();
+    assert_eq!(orphan.owner, initializer.pubkey());
+    assert_eq!(orphan.amount, OFFERED_AMOUNT);
+    assert_eq!(orphan.close_authority, COption::None);
+}
+
+#[tokio::test]
+async fn closed_escrow_cannot_be_revived_in_the_same_transaction() {
+    let mut env = Setup::new().open_escrow(0).await;
+    let taker = env.taker();
+    let rent = env.banks_client.get_rent().await.unwrap();
+
+    // the exchange closes the escrow, the transfer puts its rent straight back and the second exchange tries to use it
+    let exchange_ix = env.exchange_ix(spl_token::id());
+    let refund_ix =
+        system_instruction::transfer(&env.payer.pubkey(), &env.escrow.pubkey(), rent.minimum_balance(Escrow::LEN));
+    let mut transaction = Transaction::new_with_payer(
+        &[exchange_ix.clone(), refund_ix.clone(), exchange_ix.clone()],
+        Some(&env.payer.pubkey()),
+    );
+    transaction.sign(&[&env.payer, &taker], env.recent_blockhash);
+    assert_eq!(
+        env.banks_client.process_transaction(transaction).await.unwrap_err().unwrap(),
+        TransactionError::InstructionError(2, InstructionError::Custom(EscrowError::InvalidAccountOwner as u32))
+    );
+    env.assert_escrow_intact().await;
+
+    // without the second exchange it goes through, and what is left is an empty system account
+    let mut transaction = Transaction::new_with_payer(&[exchange_ix, refund_ix], Some(&env.payer.pubkey()));
+    transaction.sign(&[&env.payer, &taker], env.recent_blockhash);
+    env.banks_client.process_transaction(transaction).await.unwrap();
+    let revived = env.banks_client.get_account(env.escrow.pubkey()).await.unwrap().unwrap();
+    assert_eq!(revived.owner, solana_program::system_program::id());
+    assert!(revived.data.is_empty());
+}
+
+#[tokio::test]
+async fn escrow_from_an_existing_account_only_takes_the_offered_amount() {
+    let mut setup = Setup::new();
+    let wallet = Pubkey::new_unique();
+    setup.program_test.add_account(wallet, token_account(setup.mint_x, setup.initializer.pubkey(), 3 * OFFERED_AMOUNT));
+    setup.program_test.add_account(
+        setup.initializer.pubkey(),
+        Account {
+            lamports: 1_000_000_000, // the vault's rent
+            owner: solana_program::system_program::id(),
+            ..Account::default()
+        },
+    );
+    let mint_x = setup.mint_x;
+
+    let mut env = setup.open_escrow(0).await;
+    let initializer = Keypair::from_bytes(&env.initializer.to_bytes()).unwrap();
+    let escrow = Keypair::new();
+    let rent = env.banks_client.get_rent().await.unwrap();
+    let create_escrow_ix = system_instruction::create_account(
+        &env.payer.pubkey(),
+        &escrow.pubkey(),
+        rent.minimum_balance(Escrow::LEN),
+        Escrow::LEN as u64,
+        &escrow_program::id(),
+    );
+    assert!(env.send(create_escrow_ix, &escrow).await);
+    let init_ix = instruction::init_escrow_from_account(
+        &escrow_program::id(),
+        &initializer.pubkey(),
+        &wallet,
+        &env.initializer_receive,
+        &escrow.pubkey(),
+        &mint_x,
+        EXPECTED_AMOUNT,
+        OFFERED_AMOUNT,
+        0,
+        0,
+    );
+
+    // a vault anywhere but the escrow's own PDA is refused
+    let mut stray_vault_ix = init_ix.clone();
+    stray_vault_ix.accounts[4] = AccountMeta::new(Pubkey::new_unique(), false);
+    assert_eq!(
+        env.send_and_fail(stray_vault_ix, &initializer).await,
+        TransactionError::InstructionError(0, InstructionError::Custom(EscrowError::InvalidVaultAccount as u32))
+    );
+
+    assert!(env.send(init_ix.clone(), &initializer).await);
+    let wallet_account = env.banks_client.get_account(wallet).await.unwrap().unwrap();
+    assert_eq!(TokenAccount::unpack(&wallet_account.data).unwrap().amount, 2 * OFFERED_AMOUNT);
+    let vault = init_ix.accounts[4].pubkey;
+    let vault_account = env.banks_client.get_account(vault).await.unwrap().unwrap();
+    let vault_account = TokenAccount::unpack(&vault_account.data).unwrap();
+    assert_eq!(vault_account.owner, env.pda);
+    assert_eq!(vault_account.amount, OFFERED_AMOUNT);
+    let escrow_account = env.banks_client.get_account(escrow.pubkey()).await.unwrap().unwrap();
+    assert_eq!(Escrow::unpack(&escrow_account.data).unwrap().temp_token_account_pubkey, vault);
+}
+
+/// An NFT mint and its Metaplex metadata naming `collection`, verified or not, with a 10% seller fee split between `creators`
+fn add_nft(setup: &mut Setup, collection: Pubkey, verified: bool, creators: &[(Pubkey, u8)]) -> Pubkey {
+    let nft_mint = Pubkey::new_unique();
+    let mut data = vec![0; Mint::LEN];
+    Mint::pack(
+        Mint {
+            supply: 1,
+            decimals: 0,
+            is_initialized: true,
+            ..Mint::default()
+        },
+        &mut data,
+    )
+    .unwrap();
+    setup.program_test.add_account(
+        nft_mint,
+        Account {
+            lamports: 1_000_000_000,
+            data,
+            owner: spl_token::id(),
+            executable: false,
+            rent_epoch: 0,
+        },
+    );
+
+    let mut data = vec![4]; // a v1 metadata account
+    data.extend_from_slice(Pubkey::new_unique().as_ref());
+    data.extend_from_slice(nft_mint.as_ref());
+    for field in ["Member", "MBR", "https://example.com"] {
+        data.extend_from_slice(&(field.len() as u32).to_le_bytes());
+        data.extend_from_slice(field.as_bytes());
+    }
+    data.extend_from_slice(&1_000u16.to_le_bytes());
+    if creators.is_empty() {
+        data.push(0);
+    } else {
+        data.push(1);
+        data.extend_from_slice(&(creators.len() as u32).to_le_bytes());
+        for (creator, share) in creators {
+            data.extend_from_slice(creator.as_ref());
+            data.extend_from_slice(&[1, *share]);
+        }
+    }
+    data.extend_from_slice(&[0, 1, 0, 0]); // not sold, mutable, no edition nonce or token standard
+    data.extend_from_slice(&[1, verified as u8]);
+    data.extend_from_slice(collection.as_ref());
+    setup.program_test.add_account(
+        metadata::find_metadata_address(&nft_mint),
+        Account {
+            lamports: 1_000_000_000,
+            data,
+            owner: metadata::mpl_token_metadata::id(),
+            executable: false,
+            rent_epoch: 0,
+        },
+    );
+    nft_mint
+}
+
+#[tokio::test]
+async fn bid_is_only_accepted_for_verified_members_of_its_collection() {
+    let mut setup = Setup::new();
+    let collection = Pubkey::new_unique();
+    let unverified_nft = add_nft(&mut setup, collection, false, &[]);
+    let nft = add_nft(&mut setup, collection, true, &[]);
+    let bid_vault = Pubkey::new_unique();
+    setup.program_test.add_account(bid_vault, token_account(setup.mint_x, setup.initializer.pubkey(), OFFERED_AMOUNT));
+    let (seller_unverified_nft, seller_nft, bidder_nft) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    setup.program_test.add_account(seller_unverified_nft, token_account(unverified_nft, setup.taker.pubkey(), 1));
+    setup.program_test.add_account(seller_nft, token_account(nft, setup.taker.pubkey(), 1));
+    setup.program_test.add_account(bidder_nft, token_account(nft, setup.initializer.pubkey(), 0));
+
+    let mut env = setup.open_escrow(0).await;
+    let bidder = Keypair::from_bytes(&env.initializer.to_bytes()).unwrap();
+    let seller = env.taker();
+    let bid = Keypair::new();
+    let rent = env.banks_client.get_rent().await.unwrap();
+    let create_bid_ix = system_instruction::create_account(
+        &env.payer.pubkey(),
+        &bid.pubkey(),
+        rent.minimum_balance(Escrow::LEN),
+        Escrow::LEN as u64,
+        &escrow_program::id(),
+    );
+    assert!(env.send(create_bid_ix, &bid).await);
+    let mut init_bid_data = vec![25];
+    init_bid_data.extend_from_slice(&0i64.to_le_bytes());
+    init_bid_data.extend_from_slice(&0u64.to_le_bytes());
+    init_bid_data.extend_from_slice(collection.as_ref());
+    let init_bid_ix = Instruction {
+        program_id: escrow_program::id(),
+        accounts: vec![
+            AccountMeta::new_readonly(bidder.pubkey(), true),
+            AccountMeta::new(bid_vault, false),
+            AccountMeta::new(bid.pubkey(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+        data: init_bid_data,
+    };
+    assert!(env.send(init_bid_ix, &bidder).await);
+
+    let accept_bid_ix = |seller_nft: Pubkey, nft: Pubkey| Instruction {
+        program_id: escrow_program::id(),
+        accounts: vec![
+            AccountMeta::new_readonly(seller.pubkey(), true),
+            AccountMeta::new(seller_nft, false),
+            AccountMeta::new(env.taker_receive, false),
+            AccountMeta::new(bidder_nft, false),
+            AccountMeta::new(bid.pubkey(), false),
+            AccountMeta::new(bid_vault, false),
+            AccountMeta::new(bidder.pubkey(), false),
+            AccountMeta::new_readonly(nft, false),
+            AccountMeta::new_readonly(metadata::find_metadata_address(&nft), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(env.pda, false),
+        ],
+        data: vec![26],
+    };
+
+    // anyone can name a collection in their NFT's metadata, only its authority can verify it
+    let unverified_ix = accept_bid_ix(seller_unverified_nft, unverified_nft);
+    let mut wrong_metadata_ix = accept_bid_ix(seller_unverified_nft, unverified_nft);
+    wrong_metadata_ix.accounts[8].pubkey = metadata::find_metadata_address(&nft);
+    let member_ix = accept_bid_ix(seller_nft, nft);
+    assert_eq!(
+        env.send_and_fail(unverified_ix, &seller).await,
+        TransactionError::InstructionError(0, InstructionError::Custom(EscrowError::NotInCollection as u32))
+    );
+    assert_eq!(
+        env.send_and_fail(wrong_metadata_ix, &seller).await,
+        TransactionError::InstructionError(0, InstructionError::Custom(EscrowError::InvalidMetadataAccount as u32))
+    );
+
+    assert!(env.send(member_ix, &seller).await);
+    let bidder_nft = env.banks_client.get_account(bidder_nft).await.unwrap().unwrap();
+    assert_eq!(TokenAccount::unpack(&bidder_nft.data).unwrap().amount, 1);
+    let seller_payment = env.banks_client.get_account(env.taker_receive).await.unwrap().unwrap();
+    assert_eq!(TokenAccount::unpack(&seller_payment.data).unwrap().amount, OFFERED_AMOUNT);
+    assert!(env.banks_client.get_account(bid.pubkey()).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn bid_with_royalties_pays_every_creator_their_share() {
+    let mut setup = Setup::new();
+    let collection = Pubkey::new_unique();
+    let (first_creator, second_creator) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let nft = add_nft(&mut setup, collection, true, &[(first_creator, 60), (second_creator, 40)]);
+    let bid_vault = Pubkey::new_unique();
+    setup.program_test.add_account(bid_vault, token_account(setup.mint_x, setup.initializer.pubkey(), OFFERED_AMOUNT));
+    let (seller_nft, bidder_nft) = (Pubkey::new_unique(), Pubkey::new_unique());
+    setup.program_test.add_account(seller_nft, token_account(nft, setup.taker.pubkey(), 1));
+    setup.program_test.add_account(bidder_nft, token_account(nft, setup.initializer.pubkey(), 0));
+    let (first_royalty, second_royalty, impostor) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    setup.program_test.add_account(first_royalty, token_account(setup.mint_x, first_creator, 0));
+    setup.program_test.add_account(second_royalty, token_account(setup.mint_x, second_creator, 0));
+    setup.program_test.add_account(impostor, token_account(setup.mint_x, setup.taker.pubkey(), 0));
+
+    let mut env = setup.open_escrow(0).await;
+    let bidder = Keypair::from_bytes(&env.initializer.to_bytes()).unwrap();
+    let seller = env.taker();
+    let bid = Keypair::new();
+    let rent = env.banks_client.get_rent().await.unwrap();
+    let create_bid_ix = system_instruction::create_account(
+        &env.payer.pubkey(),
+        &bid.pubkey(),
+        rent.minimum_balance(Escrow::LEN),
+        Escrow::LEN as u64,
+        &escrow_program::id(),
+    );
+    assert!(env.send(create_bid_ix, &bid).await);
+    let mut init_bid_data = vec![25];
+    init_bid_data.extend_from_slice(&0i64.to_le_bytes());
+    init_bid_data.extend_from_slice(&0u64.to_le_bytes());
+    init_bid_data.extend_from_slice(collection.as_ref());
+    init_bid_data.push(1); // enforce royalties
+    let init_bid_ix = Instruction {
+        program_id: escrow_program::id(),
+        accounts: vec![
+            AccountMeta::new_readonly(bidder.pubkey(), true),
+            AccountMeta::new(bid_vault, false),
+            AccountMeta::new(bid.pubkey(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+        data: init_bid_data,
+    };
+    assert!(env.send(init_bid_ix, &bidder).await);
+
+    let mut accept_bid_ix = Instruction {
+        program_id: escrow_program::id(),
+        accounts: vec![
+            AccountMeta::new_readonly(seller.pubkey(), true),
+            AccountMeta::new(seller_nft, false),
+            AccountMeta::new(env.taker_receive, false),
+            AccountMeta::new(bidder_nft, false),
+            AccountMeta::new(bid.pubkey(), false),
+            AccountMeta::new(bid_vault, false),
+            AccountMeta::new(bidder.pubkey(), false),
+            AccountMeta::new_readonly(nft, false),
+            AccountMeta::new_readonly(metadata::find_metadata_address(&nft), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(env.pda, false),
+            AccountMeta::new(first_royalty, false),
+            AccountMeta::new(impostor, false),
+        ],
+        data: vec![26],
+    };
+
+    // the seller can't route a creator's royalty back to themselves
+    assert_eq!(
+        env.send_and_fail(accept_bid_ix.clone(), &seller).await,
+        TransactionError::InstructionError(0, InstructionError::Custom(EscrowError::RoyaltyAccountMismatch as u32))
+    );
+
+    // 10% of the 100 bid, split 60/40
+    accept_bid_ix.accounts[12].pubkey = second_royalty;
+    assert!(env.send(accept_bid_ix, &seller).await);
+    for (account, amount) in [(first_royalty, 6), (second_royalty, 4), (env.taker_receive, OFFERED_AMOUNT - 10)] {
+        let account = env.banks_client.get_account(account).await.unwrap().unwrap();
+        assert_eq!(TokenAccount::unpack(&account.data).unwrap().amount, amount);
+    }
+}
+
+#[tokio::test]
+async fn offer_nonce_gives_a_known_address_that_cant_be_opened_twice() {
+    let mut setup = Setup::new();
+    let (first_vault, second_vault) = (Pubkey::new_unique(), Pubkey::new_unique());
+    setup.program_test.add_account(first_vault, token_account(setup.mint_x, setup.initializer.pubkey(), OFFERED_AMOUNT));
+    setup.program_test.add_account(second_vault, token_account(setup.mint_x, setup.initializer.pubkey(), OFFERED_AMOUNT));
+    setup.program_test.add_account(
+        setup.initializer.pubkey(),
+        Account {
+            lamports: 1_000_000_000, // the escrow account's rent
+            owner: solana_program::system_program::id(),
+            ..Account::default()
+        },
+    );
+
+    let mut env = setup.open_escrow(0).await;
+    let initializer = Keypair::from_bytes(&env.initializer.to_bytes()).unwrap();
+    let init_ix = |vault: Pubkey| {
+        instruction::init_escrow_with_nonce(
+            &escrow_program::id(),
+            &initializer.pubkey(),
+            &vault,
+            &env.initializer_receive,
+            EXPECTED_AMOUNT,
+            0,
+            0,
+            7,
+        )
+    };
+    let escrow = client::find_escrow_address(&initializer.pubkey(), 7, &escrow_program::id());
+
+    // the nonce pins the address, any other account in its place is refused
+    let mut elsewhere_ix = init_ix(first_vault);
+    elsewhere_ix.accounts[3].pubkey = client::find_escrow_address(&initializer.pubkey(), 8, &escrow_program::id());
+    let first_ix = init_ix(first_vault);
+    let duplicate_ix = init_ix(second_vault);
+    assert_eq!(
+        env.send_and_fail(elsewhere_ix, &initializer).await,
+        TransactionError::InstructionError(0, InstructionError::Custom(EscrowError::InvalidEscrowAddress as u32))
+    );
+
+    assert!(env.send(first_ix, &initializer).await);
+    let escrow_account = env.banks_client.get_account(escrow).await.unwrap().unwrap();
+    assert_eq!(Escrow::unpack(&escrow_account.data).unwrap().temp_token_account_pubkey, first_vault);
+
+    assert_eq!(
+        env.send_and_fail(duplicate_ix, &initializer).await,
+        TransactionError::InstructionError(0, InstructionError::AccountAlreadyInitialized)
+    );
+}
+
+#[tokio::test]
+async fn transfer_authority_has_to_sign_for_the_payment() {
+    let mut env = Setup::new().open_escrow(0).await;
+    let taker = env.taker();
+
+    // an aggregator approved to spend the taker's payment settles for them
+    let aggregator = Keypair::new();
+    let approve_ix = spl_token::instruction::approve(
+        &spl_token::id(),
+        &env.taker_send,
+        &aggregator.pubkey(),
+        &taker.pubkey(),
+        &[],
+        EXPECTED_AMOUNT,
+    )
+    .unwrap();
+    assert!(env.send(approve_ix, &taker).await);
+
+    let mut exchange_ix = env.exchange_ix(spl_token::id());
+    exchange_ix.data.extend_from_slice(&0u64.to_le_bytes()); // no deadline
+    exchange_ix.data.extend_from_slice(&0u16.to_le_bytes()); // no referral
+    exchange_ix.data.extend_from_slice(&[0, 0, 1]); // no hook accounts, a transfer authority
+    let mut unsigned_ix = exchange_ix.clone();
+    unsigned_ix.accounts.push(AccountMeta::new_readonly(aggregator.pubkey(), false));
+    assert_eq!(
+        env.send_and_fail(unsigned_ix, &taker).await,
+        TransactionError::InstructionError(0, InstructionError::MissingRequiredSignature)
+    );
+    env.assert_escrow_intact().await;
+
+    exchange_ix.accounts.push(AccountMeta::new_readonly(aggregator.pubkey(), true));
+    let mut transaction = Transaction::new_with_payer(&[exchange_ix], Some(&env.payer.pubkey()));
+    transaction.sign(&[&env.payer, &taker, &aggregator], env.recent_blockhash);
+    env.banks_client.process_transaction(transaction).await.unwrap();
+    let initializer_receive = env.banks_client.get_account(env.initializer_receive).await.unwrap().unwrap();
+    assert_eq!(TokenAccount::unpack(&initializer_receive.data).unwrap().amount, EXPECTED_AMOUNT);
+}
+
+#[tokio::test]
+async fn taker_cannot_buy_past_the_per_taker_cap_across_fills() {
+    let mut setup = Setup::new();
+    let sale_vault = Pubkey::new_unique();
+    setup.program_test.add_account(sale_vault, token_account(setup.mint_x, setup.initializer.pubkey(), OFFERED_AMOUNT));
+    setup.program_test.add_account(
+        setup.taker.pubkey(),
+        Account {
+            lamports: 1_000_000_000, // the claim account's rent
+            owner: solana_program::system_program::id(),
+            ..Account::default()
+        },
+    );
+    let mut env = setup.open_escrow(0).await;
+    let taker = env.taker();
+
+    // a fair launch of the vault at 1 Y per X, at most 40 X to any one taker
+    let sale = Keypair::new();
+    let mut init_data = vec![16];
+    for value in [1u64, 1, 0, 0, 40] {
+        init_data.extend_from_slice(&value.to_le_bytes());
+    }
+    let init_ix = Instruction {
+        program_id: escrow_program::id(),
+        accounts: vec![
+            AccountMeta::new_readonly(env.initializer.pubkey(), true),
+            AccountMeta::new(sale_vault, false),
+            AccountMeta::new_readonly(env.initializer_receive, false),
+            AccountMeta::new(sale.pubkey(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(Pubkey::find_program_address(&[b"config"], &escrow_program::id()).0, false),
+        ],
+        data: init_data,
+    };
+    let rent = env.banks_client.get_rent().await.unwrap();
+    let create_sale_ix = system_instruction::create_account(
+        &env.payer.pubkey(),
+        &sale.pubkey(),
+        rent.minimum_balance(Escrow::LEN),
+        Escrow::LEN as u64,
+        &escrow_program::id(),
+    );
+    let mut transaction = Transaction::new_with_payer(&[create_sale_ix, init_ix], Some(&env.payer.pubkey()));
+    transaction.sign(&[&env.payer, &env.initializer, &sale], env.recent_blockhash);
+    env.banks_client.process_transaction(transaction).await.unwrap();
+
+    let claim = client::find_claim_address(&sale.pubkey(), &taker.pubkey(), &escrow_program::id());
+    let take_ix = |amount: u64| {
+        let mut data = vec![1];
+        data.extend_from_slice(&amount.to_le_bytes());
+        Instruction {
+            program_id: escrow_program::id(),
+            accounts: vec![
+                AccountMeta::new(taker.pubkey(), true),
+                AccountMeta::new(env.taker_send, false),
+                AccountMeta::new(env.taker_receive, false),
+                AccountMeta::new(sale_vault, false),
+                AccountMeta::new(env.initializer.pubkey(), false),
+                AccountMeta::new(env.initializer_receive, false),
+                AccountMeta::new(sale.pubkey(), false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new_readonly(env.pda, false),
+                AccountMeta::new(claim, false),
+                AccountMeta::new_readonly(solana_program::system_program::id(), false),
+            ],
+            data,
+        }
+    };
+    let (first_fill, past_cap, up_to_cap) = (take_ix(30), take_ix(20), take_ix(10));
+
+    // the second fill on its own is well within the cap, it's what the taker already bought that rules it out
+    assert!(env.send(first_fill, &taker).await);
+    assert_eq!(
+        env.send_and_fail(past_cap, &taker).await,
+        TransactionError::InstructionError(0, InstructionError::Custom(EscrowError::PurchaseLimitExceeded as u32))
+    );
+    assert!(env.send(up_to_cap, &taker).await);
+
+    let taker_receive = env.banks_client.get_account(env.taker_receive).await.unwrap().unwrap();
+    assert_eq!(TokenAccount::unpack(&taker_receive.data).unwrap().amount, 40);
+    let claim_account = env.banks_client.get_account(claim).await.unwrap().unwrap();
+    assert_eq!(TakerClaim::unpack(&claim_account.data).unwrap().claimed_amount, 40);
+}
+
+#[tokio::test]
+async fn only_the_initializer_can_extend_their_escrow() {
+    let mut setup = Setup::new();
+    setup.program_test.add_account(
+        setup.initializer.pubkey(),
+        Account {
+            lamports: 1_000_000_000, // the extra rent
+            owner: solana_program::system_program::id(),
+            ..Account::default()
+        },
+    );
+    let mut env = setup.open_escrow(0).await;
+    let initializer = Keypair::from_bytes(&env.initializer.to_bytes()).unwrap();
+    let taker = env.taker();
+
+    let new_len = Escrow::LEN + 256;
+    let escrow_pubkey = env.escrow.pubkey();
+    let extend_ix = |payer: &Keypair| {
+        let mut data = vec![27];
+        data.extend_from_slice(&(new_len as u64).to_le_bytes());
+        Instruction {
+            program_id: escrow_program::id(),
+            accounts: vec![
+                AccountMeta::new(payer.pubkey(), true),
+                AccountMeta::new(escrow_pubkey, false),
+                AccountMeta::new_readonly(solana_program::system_program::id(), false),
+            ],
+            data,
+        }
+    };
+    assert_eq!(
+        env.send_and_fail(extend_ix(&taker), &taker).await,
+        TransactionError::InstructionError(0, InstructionError::Custom(EscrowError::InitializerMismatch as u32))
+    );
+    assert!(env.send(extend_ix(&initializer), &initializer).await);
+
+    // the escrow reads as before from the start of the longer account, and the room after it is zeroed and paid for
+    env.assert_escrow_intact().await;
+    let escrow = env.banks_client.get_account(escrow_pubkey).await.unwrap().unwrap();
+    assert_eq!(escrow.data.len(), new_len);
+    assert!(escrow.data[Escrow::LEN..].iter().all(|byte| *byte == 0));
+    let rent = env.banks_client.get_rent().await.unwrap();
+    assert!(rent.is_exempt(escrow.lamports, new_len));
+
+    let exchange_ix = env.exchange_ix(spl_token::id());
+    assert!(env.send(exchange_ix, &taker).await);
+    assert!(env.banks_client.get_account(escrow_pubkey).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn escrow_from_the_first_deployment_is_migrated_and_taken() {
+    let mut setup = Setup::new();
+    let (pda, bump_seed) = Pubkey::find_program_address(&[b"escrow"], &escrow_program::id());
+    setup.program_test.add_account(
+        setup.initializer.pubkey(),
+        Account {
+            lamports: 1_000_000_000, // the rent of the fields added since
+            owner: solana_program::system_program::id(),
+            ..Account::default()
+        },
+    );
+
+    // an escrow as the first deployment wrote it, is_initialized, the three pubkeys and expected_amount, over a vault the PDA owns
+    let legacy_escrow = Keypair::new();
+    let legacy_vault = Pubkey::new_unique();
+    let mut data = vec![1];
+    data.extend_from_slice(setup.initializer.pubkey().as_ref());
+    data.extend_from_slice(legacy_vault.as_ref());
+    data.extend_from_slice(setup.initializer_receive.as_ref());
+    data.extend_from_slice(&EXPECTED_AMOUNT.to_le_bytes());
+    assert_eq!(data.len(), 105);
+    setup.program_test.add_account(
+        legacy_escrow.pubkey(),
+        Account {
+            lamports: solana_program::rent::Rent::default().minimum_balance(data.len()),
+            data,
+            owner: escrow_program::id(),
+            ..Account::default()
+        },
+    );
+    setup.program_test.add_account(legacy_vault, token_account(setup.mint_x, pda, OFFERED_AMOUNT));
+
+    let mut env = setup.open_escrow(0).await;
+    env.escrow = legacy_escrow;
+    env.vault = legacy_vault;
+    let initializer = Keypair::from_bytes(&env.initializer.to_bytes()).unwrap();
+    let taker = env.taker();
+
+    // a ValidateExchange with the same accounts, so the Exchange below isn't dropped as a duplicate of a transaction that failed
+    let mut validate_ix = env.exchange_ix(spl_token::id());
+    validate_ix.data[0] = 21;
+    assert_eq!(
+        env.send_and_fail(validate_ix, &taker).await,
+        TransactionError::InstructionError(0, InstructionError::Custom(EscrowError::EscrowNeedsMigration as u32))
+    );
+
+    let migrate_ix = Instruction {
+        program_id: escrow_program::id(),
+        accounts: vec![
+            AccountMeta::new(initializer.pubkey(), true),
+            AccountMeta::new(env.escrow.pubkey(), false),
+            AccountMeta::new_readonly(solana_program::system_program::id(), false),
+        ],
+        data: vec![20],
+    };
+    assert!(env.send(migrate_ix, &initializer).await);
+
+    // the offer reads back in the current layout, with the bump the first layout didn't store
+    env.assert_escrow_intact().await;
+    let escrow = env.banks_client.get_account(env.escrow.pubkey()).await.unwrap().unwrap();
+    assert_eq!(escrow.data.len(), Escrow::LEN);
+    let escrow = Escrow::unpack(&escrow.data).unwrap();
+    assert_eq!(escrow.initializer_token_to_receive_account_pubkey, env.initializer_receive);
+    assert_eq!(escrow.expected_amount, EXPECTED_AMOUNT);
+    assert_eq!(escrow.bump_seed, bump_seed);
+
+    let exchange_ix = env.exchange_ix(spl_token::id());
+    assert!(env.send(exchange_ix, &taker).await);
+    let taker_receive = env.banks_client.get_account(env.taker_receive).await.unwrap().unwrap();
+    assert_eq!(TokenAccount::unpack(&taker_receive.data).unwrap().amount, OFFERED_AMOUNT);
+    assert!(env.banks_client.get_account(env.escrow.pubkey()).await.unwrap().is_none());
+}
+
+/// A token account of `owner` holding `amount` of `mint`, with the escrow PDA approved to move all of it
+fn delegated_token_account(mint: Pubkey, owner: Pubkey, amount: u64) -> Account {
+    let mut account = token_account(mint, owner, amount);
+    let mut token_account = TokenAccount::unpack(&account.data).unwrap();
+    token_account.delegate = COption::Some(Pubkey::find_program_address(&[b"escrow"], &escrow_program::id()).0);
+    token_account.delegated_amount = amount;
+    TokenAccount::pack(token_account, &mut account.data).unwrap();
+    account
+}
+
+#[tokio::test]
+async fn signed_offer_is_filled_once_exactly_as_signed() {
+    let mut setup = Setup::new();
+    let maker_account = Pubkey::new_unique();
+    let someone_elses_account = Pubkey::new_unique();
+    setup.program_test.add_account(maker_account, delegated_token_account(setup.mint_x, setup.initializer.pubkey(), OFFERED_AMOUNT));
+    setup.program_test.add_account(someone_elses_account, delegated_token_account(setup.mint_x, Pubkey::new_unique(), OFFERED_AMOUNT));
+    setup.program_test.add_account(
+        setup.taker.pubkey(),
+        Account {
+            lamports: 1_000_000_000, // the filled offer record's rent
+            owner: solana_program::system_program::id(),
+            ..Account::default()
+        },
+    );
+    let mut env = setup.open_escrow(0).await;
+    let taker = env.taker();
+
+    let offer = SignedOffer {
+        initializer_pubkey: env.initializer.pubkey(),
+        offered_token_account_pubkey: maker_account,
+        initializer_token_to_receive_account_pubkey: env.initializer_receive,
+        offered_amount: OFFERED_AMOUNT,
+        expected_amount: EXPECTED_AMOUNT,
+        expires_at: 0,
+        nonce: 7,
+    };
+    let sign = |offer: &SignedOffer| {
+        let message = offer.message(&escrow_program::id());
+        let signature = env.initializer.sign_message(&message);
+        signed_offer::ed25519_instruction(&offer.initializer_pubkey, signature.as_ref().try_into().unwrap(), &message)
+    };
+    let fill = |verify_ix: Instruction, offer: &SignedOffer, taker_receive: Pubkey| {
+        let fill_ix = instruction::fill_signed_offer(&escrow_program::id(), offer, &taker.pubkey(), &env.taker_send, &taker_receive);
+        let mut transaction = Transaction::new_with_payer(&[verify_ix, fill_ix], Some(&env.payer.pubkey()));
+        transaction.sign(&[&env.payer, &taker], env.recent_blockhash);
+        transaction
+    };
+    let fill_error = |error: EscrowError| TransactionError::InstructionError(1, InstructionError::Custom(error as u32));
+
+    // a signature of the offer doesn't cover a better deal for the taker
+    let better_deal = SignedOffer { expected_amount: 1, ..offer };
+    let tampered = fill(sign(&offer), &better_deal, env.taker_receive);
+    assert_eq!(env.banks_client.process_transaction(tampered).await.unwrap_err().unwrap(), fill_error(EscrowError::InvalidOfferSignature));
+
+    // nor can the initializer sign away tokens someone else approved the PDA for
+    let not_theirs = SignedOffer { offered_token_account_pubkey: someone_elses_account, nonce: 8, ..offer };
+    let stolen = fill(sign(&not_theirs), &not_theirs, env.taker_receive);
+    assert_eq!(env.banks_client.process_transaction(stolen).await.unwrap_err().unwrap(), fill_error(EscrowError::InitializerMismatch));
+
+    let honest = fill(sign(&offer), &offer, env.taker_receive);
+    env.banks_client.process_transaction(honest).await.unwrap();
+    let taker_receive = env.banks_client.get_account(env.taker_receive).await.unwrap().unwrap();
+    assert_eq!(TokenAccount::unpack(&taker_receive.data).unwrap().amount, OFFERED_AMOUNT);
+    let initializer_receive = env.banks_client.get_account(env.initializer_receive).await.unwrap().unwrap();
+    assert_eq!(TokenAccount::unpack(&initializer_receive.data).unwrap().amount, EXPECTED_AMOUNT);
+    let filled = client::find_filled_offer_address(&offer.initializer_pubkey, offer.nonce, &escrow_program::id());
+    let filled = env.banks_client.get_account(filled).await.unwrap().unwrap();
+    assert_eq!(FilledOffer::unpack(&filled.data).unwrap().taker_pubkey, taker.pubkey());
+
+    // the same signature again, into another account so it isn't the same transaction
+    let replayed = fill(sign(&offer), &offer, env.initializer_refund);
+    assert_eq!(env.banks_client.process_transaction(replayed).await.unwrap_err().unwrap(), fill_error(EscrowError::OfferAlreadyFilled));
+}
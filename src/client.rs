@@ -0,0 +1,708 @@
+//! Helpers for off-chain clients reading the program's accounts
+
+use num_traits::FromPrimitive;
+use solana_program::{
+    instruction::{Instruction, InstructionError},
+    program_error::ProgramError,
+    program_pack::{IsInitialized, Pack},
+    pubkey::Pubkey,
+};
+use spl_token::state::{Account as TokenAccount, Mint};
+
+use crate::{
+    error::EscrowError,
+    instruction,
+    state::{Escrow, EscrowKind, EscrowStatus, OfferCounter, Stats},
+};
+
+// where the fields the filters match on sit in an escrow account: the version byte, is_initialized, then the initializer, temp and
+// receive accounts, expected_amount, expires_at, crank_bounty, is_delegated and offered_amount come before the status
+const INITIALIZER_OFFSET: usize = 2;
+const STATUS_OFFSET: usize = 131;
+// and the offer number InitEscrow gives it is the last field
+const OFFER_ID_OFFSET: usize = Escrow::LEN - 8;
+// the events the program logs, see event.rs
+const EVENT_NAMES: [&str; 3] = ["EscrowOpened", "EscrowSettled", "ExchangeValid"];
+// and in a token account, the mint then the owner
+const TOKEN_MINT_OFFSET: usize = 0;
+const TOKEN_OWNER_OFFSET: usize = 32;
+
+/// One of the filters `getProgramAccounts` takes. `DataSize` is the RPC's `dataSize` filter and `Memcmp` its `memcmp` filter,
+/// matching `bytes` at `offset`, so each maps straight onto solana-client's `RpcFilterType`
+#[derive(Clone, Debug, PartialEq)]
+pub enum AccountFilter {
+    DataSize(u64),
+    Memcmp { offset: usize, bytes: Vec<u8> },
+}
+
+/// The `getProgramAccounts` query for a set of escrows: the program to ask for accounts of and the filters to send with it.
+/// The escrow filters only match accounts in the current layout, ones still to be migrated have a different size
+#[derive(Clone, Debug, PartialEq)]
+pub struct EscrowFilter {
+    pub program_id: Pubkey,
+    pub filters: Vec<AccountFilter>,
+}
+
+impl EscrowFilter {
+    /// Every escrow account of the program, narrowed down by chaining `initializer` and `status`
+    pub fn escrows(program_id: &Pubkey) -> Self {
+        EscrowFilter {
+            program_id: *program_id,
+            filters: vec![AccountFilter::DataSize(Escrow::LEN as u64)],
+        }
+    }
+
+    /// Only the escrows `initializer` opened
+    pub fn initializer(mut self, initializer: &Pubkey) -> Self {
+        self.filters.push(AccountFilter::Memcmp {
+            offset: INITIALIZER_OFFSET,
+            bytes: initializer.to_bytes().to_vec(),
+        });
+        self
+    }
+
+    /// Only the escrows in `status`
+    pub fn status(mut self, status: EscrowStatus) -> Self {
+        self.filters.push(AccountFilter::Memcmp {
+            offset: STATUS_OFFSET,
+            bytes: vec![status as u8],
+        });
+        self
+    }
+
+    /// Only the escrow numbered `offer_id`, so at most one account. Its address is the escrow's, which is how a UI resolves "offer
+    /// #4812" back to the escrow
+    pub fn offer_id(mut self, offer_id: u64) -> Self {
+        self.filters.push(AccountFilter::Memcmp {
+            offset: OFFER_ID_OFFSET,
+            bytes: offer_id.to_le_bytes().to_vec(),
+        });
+        self
+    }
+
+    /// An escrow doesn't store the mint it offers, so this asks the token program for the vaults holding `mint` for the program
+    /// instead. Each result is some escrow's `temp_token_account_pubkey`. Delegated escrows, whose tokens stay in the initializer's
+    /// account, and vaults of Token-2022 mints aren't found this way
+    pub fn offered_mint(mint: &Pubkey, program_id: &Pubkey) -> Self {
+        let (pda, _bump_seed) = Pubkey::find_program_address(&[b"escrow"], program_id);
+        EscrowFilter {
+            program_id: spl_token::id(),
+            filters: vec![
+                AccountFilter::DataSize(TokenAccount::LEN as u64),
+                AccountFilter::Memcmp {
+                    offset: TOKEN_MINT_OFFSET,
+                    bytes: mint.to_bytes().to_vec(),
+                },
+                AccountFilter::Memcmp {
+                    offset: TOKEN_OWNER_OFFSET,
+                    bytes: pda.to_bytes().to_vec(),
+                },
+            ],
+        }
+    }
+}
+
+/// An escrow together with the mints on both sides of it, so its amounts can be shown the way a wallet would
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecodedEscrow {
+    pub escrow: Escrow,
+    pub offered_mint: Pubkey,
+    pub offered_decimals: u8,
+    /// What an Exchange takes: the whole vault, or one lot of a reusable or delegated escrow
+    pub offered_amount: u64,
+    /// None for a collection bid, which is paid in an NFT rather than an amount of one mint
+    pub expected_mint: Option<Pubkey>,
+    pub expected_decimals: u8,
+}
+
+impl DecodedEscrow {
+    /// Fetches and decodes the escrow at `escrow_pubkey`, its vault, the account the initializer is paid into and both mints, reading
+    /// each account's data through `get_account_data`, e.g. `|key| rpc_client.get_account_data(key).ok()`. Fails with
+    /// `UninitializedAccount` if one of them doesn't exist
+    pub fn fetch<F>(escrow_pubkey: &Pubkey, mut get_account_data: F) -> Result<DecodedEscrow, ProgramError>
+    where
+        F: FnMut(&Pubkey) -> Option<Vec<u8>>,
+    {
+        let mut fetch = |pubkey: &Pubkey| get_account_data(pubkey).ok_or(ProgramError::UninitializedAccount);
+
+        let escrow = decode_escrow(&fetch(escrow_pubkey)?)?;
+        let vault = decode_token_account(&fetch(&escrow.temp_token_account_pubkey)?)?;
+        let offered_decimals = decode_mint(&fetch(&vault.mint)?)?.decimals;
+        let offered_amount = if escrow.is_delegated || escrow.is_reusable {
+            escrow.offered_amount
+        } else {
+            vault.amount
+        };
+
+        let (expected_mint, expected_decimals) = if escrow.kind == EscrowKind::CollectionBid {
+            (None, 0)
+        } else {
+            let receive_account = decode_token_account(&fetch(&escrow.initializer_token_to_receive_account_pubkey)?)?;
+            let expected_decimals = decode_mint(&fetch(&receive_account.mint)?)?.decimals;
+            (Some(receive_account.mint), expected_decimals)
+        };
+
+        Ok(DecodedEscrow {
+            offered_mint: vault.mint,
+            offered_decimals,
+            offered_amount,
+            expected_mint,
+            expected_decimals,
+            escrow,
+        })
+    }
+
+    /// `offered_amount` in whole tokens of the offered mint
+    pub fn offered_ui_amount(&self) -> f64 {
+        spl_token::amount_to_ui_amount(self.offered_amount, self.offered_decimals)
+    }
+
+    /// The price in whole tokens of the expected mint
+    pub fn expected_ui_amount(&self) -> f64 {
+        spl_token::amount_to_ui_amount(self.escrow.expected_amount, self.expected_decimals)
+    }
+}
+
+/// Decodes the data of an escrow account in any layout the program has used, failing if it isn't a live escrow.
+/// `escrow.version` below `Escrow::VERSION` means it has to be migrated before it can be taken.
+/// The memo, if the initializer set one, is `escrow.memo.as_str()`
+pub fn decode_escrow(data: &[u8]) -> Result<Escrow, ProgramError> {
+    Escrow::unpack_any_version(data)
+}
+
+/// Where an escrow opened with `nonce` lives, see `EscrowInstruction::InitEscrow`. Known before the InitEscrow is sent, so it can be linked
+/// to or signed for up front
+pub fn find_escrow_address(initializer: &Pubkey, nonce: u64, program_id: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"offer", initializer.as_ref(), &nonce.to_le_bytes()], program_id).0
+}
+
+/// Where the taker's claim on a capped escrow lives, the account an Exchange of it needs after the optional system program
+pub fn find_claim_address(escrow: &Pubkey, taker: &Pubkey, program_id: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"claim", escrow.as_ref(), taker.as_ref()], program_id).0
+}
+
+/// Where the record of a signed offer's fill lives, the account a FillSignedOffer of it needs
+pub fn find_filled_offer_address(initializer: &Pubkey, nonce: u64, program_id: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"filled", initializer.as_ref(), &nonce.to_le_bytes()], program_id).0
+}
+
+/// The orphan authority of `owner`, the close authority to hand a vault to the PDA with so RecoverOrphan can give it back
+pub fn find_orphan_authority_address(owner: &Pubkey, program_id: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"orphan", owner.as_ref()], program_id).0
+}
+
+/// Where the offer counter lives, the account InitEscrow takes after the config to number the offer
+pub fn find_offer_counter_address(program_id: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"offer_counter"], program_id).0
+}
+
+/// Decodes the data of the offer counter, `last_offer_id` being the number the latest offer got
+pub fn decode_offer_counter(data: &[u8]) -> Result<OfferCounter, ProgramError> {
+    if data.len() != OfferCounter::LEN {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    OfferCounter::unpack(data)
+}
+
+/// Where the global stats live, or an initializer's own stats when `initializer` is given
+pub fn find_stats_address(initializer: Option<&Pubkey>, program_id: &Pubkey) -> Pubkey {
+    match initializer {
+        Some(initializer) => Pubkey::find_program_address(&[b"stats", initializer.as_ref()], program_id).0,
+        None => Pubkey::find_program_address(&[b"stats"], program_id).0,
+    }
+}
+
+/// Decodes the data of a stats account, failing if it isn't an initialized one
+pub fn decode_stats(data: &[u8]) -> Result<Stats, ProgramError> {
+    if data.len() != Stats::LEN {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    Stats::unpack(data)
+}
+
+/// The RPC calls `create_escrow` and `accept_escrow` make. The program builds for on-chain and doesn't depend on solana-client, so the
+/// caller implements this over their `RpcClient`, signing with their own keypairs and mapping `simulate_transaction`'s response
+/// onto a `Simulation`
+pub trait EscrowRpc {
+    /// Whatever sending a transaction gives back, usually its `Signature`
+    type Signature;
+    /// The caller's error, which a failure to decode an account the client read has to convert into
+    type Error: From<ProgramError>;
+
+    /// The account's data, `None` if it doesn't exist
+    fn get_account_data(&self, pubkey: &Pubkey) -> Option<Vec<u8>>;
+
+    /// Runs `simulate_transaction` on a signed transaction of `instructions`
+    fn simulate_transaction(&self, instructions: &[Instruction]) -> Result<Simulation, Self::Error>;
+
+    /// Signs, sends and confirms a transaction of `instructions`
+    fn send_transaction(&self, instructions: &[Instruction]) -> Result<Self::Signature, Self::Error>;
+}
+
+/// The parts of solana-client's `RpcSimulateTransactionResult` a simulation report is built from
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Simulation {
+    /// The index of the instruction that failed and how, when the transaction error is a `TransactionError::InstructionError`
+    pub instruction_error: Option<(u8, InstructionError)>,
+    /// Any other transaction error, e.g. a blockhash that wasn't found, as the RPC described it
+    pub transaction_error: Option<String>,
+    pub logs: Vec<String>,
+    pub units_consumed: Option<u64>,
+}
+
+/// What a call to `create_escrow` or `accept_escrow` did: sent the transaction or, in simulate mode, only pre-flighted it
+#[derive(Clone, Debug, PartialEq)]
+pub enum Submission<S> {
+    Sent(S),
+    Simulated(EscrowSimulationReport),
+}
+
+/// What a simulated transaction would have done, read out of the simulation so an integrator doesn't have to pick the logs apart
+#[derive(Clone, Debug, PartialEq)]
+pub struct EscrowSimulationReport {
+    /// Why the transaction would fail, `None` if it would go through
+    pub failure: Option<SimulationFailure>,
+    pub units_consumed: Option<u64>,
+    /// The events the program logged, in order
+    pub events: Vec<EmittedEvent>,
+    pub logs: Vec<String>,
+}
+
+/// Why a simulated transaction failed
+#[derive(Clone, Debug, PartialEq)]
+pub enum SimulationFailure {
+    /// The escrow program turned the instruction at `instruction_index` down with one of its own errors
+    Escrow { instruction_index: u8, error: EscrowError },
+    /// The instruction failed some other way, e.g. with a token program error in one of its CPIs
+    Instruction { instruction_index: u8, error: InstructionError },
+    /// The transaction itself was rejected
+    Transaction(String),
+}
+
+/// One of the events in event.rs as it was logged: its name, e.g. `EscrowSettled`, and its fields in order. A memo keeps the
+/// escaping of its `{:?}` formatting, without the quotes around it
+#[derive(Clone, Debug, PartialEq)]
+pub struct EmittedEvent {
+    pub name: String,
+    pub fields: Vec<(String, String)>,
+}
+
+impl EmittedEvent {
+    /// The value of the field called `key`
+    pub fn field(&self, key: &str) -> Option<&str> {
+        self.fields.iter().find(|(field, _)| field == key).map(|(_, value)| value.as_str())
+    }
+
+    /// Reads an event back out of a log line, `None` for any line that isn't one of the program's events
+    pub fn parse(log: &str) -> Option<EmittedEvent> {
+        let (name, mut rest) = log.strip_prefix("Program log: ")?.split_once(' ')?;
+        if !EVENT_NAMES.contains(&name) {
+            return None;
+        }
+
+        let mut fields = Vec::new();
+        while !rest.is_empty() {
+            let (key, after_key) = rest.split_once('=')?;
+            let (value, after_value) = match after_key.strip_prefix('"') {
+                Some(quoted) => {
+                    let mut escaped = false;
+                    let end = quoted
+                        .char_indices()
+                        .find(|(_, c)| {
+                            let closes = *c == '"' && !escaped;
+                            escaped = *c == '\\' && !escaped;
+                            closes
+                        })?
+                        .0;
+                    (&quoted[..end], &quoted[end + 1..])
+                },
+                None => after_key.split_once(' ').unwrap_or((after_key, "")),
+            };
+            fields.push((key.to_string(), value.to_string()));
+            rest = after_value.trim_start();
+        }
+        Some(EmittedEvent {
+            name: name.to_string(),
+            fields,
+        })
+    }
+}
+
+impl EscrowSimulationReport {
+    /// Builds the report of a simulation of `instructions`. A custom error is mapped back to an `EscrowError` only when the logs
+    /// show the escrow program raised it, as a token program error a CPI failed with carries the same kind of code
+    pub fn new(program_id: &Pubkey, instructions: &[Instruction], simulation: Simulation) -> EscrowSimulationReport {
+        let failure = match (simulation.instruction_error, simulation.transaction_error) {
+            (Some((instruction_index, error)), _) => {
+                // the runtime logs the innermost program that failed first
+                let failed_program = simulation
+                    .logs
+                    .iter()
+                    .filter_map(|log| log.strip_prefix("Program ")?.split_once(" failed: "))
+                    .map(|(program, _)| program.to_string())
+                    .next()
+                    .or_else(|| instructions.get(instruction_index as usize).map(|ix| ix.program_id.to_string()));
+                let escrow_error = match &error {
+                    InstructionError::Custom(code) if failed_program == Some(program_id.to_string()) => EscrowError::from_u32(*code),
+                    _ => None,
+                };
+                Some(match escrow_error {
+                    Some(error) => SimulationFailure::Escrow { instruction_index, error },
+                    None => SimulationFailure::Instruction { instruction_index, error },
+                })
+            },
+            (None, Some(transaction_error)) => Some(SimulationFailure::Transaction(transaction_error)),
+            (None, None) => None,
+        };
+
+        EscrowSimulationReport {
+            failure,
+            units_consumed: simulation.units_consumed,
+            events: simulation.logs.iter().filter_map(|log| EmittedEvent::parse(log)).collect(),
+            logs: simulation.logs,
+        }
+    }
+
+    pub fn succeeded(&self) -> bool {
+        self.failure.is_none()
+    }
+}
+
+/// Opens an escrow at the address `find_escrow_address` gives for `nonce`, see `instruction::init_escrow_with_nonce`. With
+/// `simulate` the transaction is only simulated and the report of it returned
+#[allow(clippy::too_many_arguments)]
+pub fn create_escrow<R: EscrowRpc>(
+    rpc: &R,
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    temp_token_account: &Pubkey,
+    token_to_receive_account: &Pubkey,
+    amount: u64,
+    expires_at: i64,
+    crank_bounty: u64,
+    nonce: u64,
+    simulate: bool,
+) -> Result<Submission<R::Signature>, R::Error> {
+    let init_escrow_ix = instruction::init_escrow_with_nonce(
+        program_id,
+        initializer,
+        temp_token_account,
+        token_to_receive_account,
+        amount,
+        expires_at,
+        crank_bounty,
+        nonce,
+    );
+    submit(rpc, program_id, vec![init_escrow_ix], simulate)
+}
+
+/// Takes `amount` of the escrow at `escrow_pubkey`, reading the accounts it names through `rpc`. With `simulate` the Exchange is only
+/// simulated, which, unlike a ValidateExchange, runs its token transfers too
+#[allow(clippy::too_many_arguments)]
+pub fn accept_escrow<R: EscrowRpc>(
+    rpc: &R,
+    program_id: &Pubkey,
+    escrow_pubkey: &Pubkey,
+    taker: &Pubkey,
+    taker_send_token_account: &Pubkey,
+    taker_receive_token_account: &Pubkey,
+    amount: u64,
+    simulate: bool,
+) -> Result<Submission<R::Signature>, R::Error> {
+    let escrow_data = rpc.get_account_data(escrow_pubkey).ok_or(ProgramError::UninitializedAccount)?;
+    let escrow = decode_escrow(&escrow_data)?;
+    let exchange_ix = instruction::exchange(
+        program_id,
+        taker,
+        taker_send_token_account,
+        taker_receive_token_account,
+        &escrow.temp_token_account_pubkey,
+        &escrow.initializer_pubkey,
+        &escrow.initializer_token_to_receive_account_pubkey,
+        escrow_pubkey,
+        amount,
+    );
+    submit(rpc, program_id, vec![exchange_ix], simulate)
+}
+
+fn submit<R: EscrowRpc>(
+    rpc: &R,
+    program_id: &Pubkey,
+    instructions: Vec<Instruction>,
+    simulate: bool,
+) -> Result<Submission<R::Signature>, R::Error> {
+    if simulate {
+        let simulation = rpc.simulate_transaction(&instructions)?;
+        return Ok(Submission::Simulated(EscrowSimulationReport::new(program_id, &instructions, simulation)));
+    }
+    rpc.send_transaction(&instructions).map(Submission::Sent)
+}
+
+// token accounts and mints of Token-2022 carry extensions after the base layout, which is all we read
+fn decode_token_account(data: &[u8]) -> Result<TokenAccount, ProgramError> {
+    let base = data.get(..TokenAccount::LEN).ok_or(ProgramError::InvalidAccountData)?;
+    let account = TokenAccount::unpack_from_slice(base)?;
+    if !account.is_initialized() {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    Ok(account)
+}
+
+fn decode_mint(data: &[u8]) -> Result<Mint, ProgramError> {
+    let base = data.get(..Mint::LEN).ok_or(ProgramError::InvalidAccountData)?;
+    let mint = Mint::unpack_from_slice(base)?;
+    if !mint.is_initialized {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    Ok(mint)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, collections::HashMap};
+
+    use super::*;
+    use spl_token::state::AccountState;
+
+    fn matches(filter: &EscrowFilter, data: &[u8]) -> bool {
+        filter.filters.iter().all(|filter| match filter {
+            AccountFilter::DataSize(size) => data.len() as u64 == *size,
+            AccountFilter::Memcmp { offset, bytes } => data.get(*offset..*offset + bytes.len()) == Some(&bytes[..]),
+        })
+    }
+
+    fn packed<T: Pack>(value: T) -> Vec<u8> {
+        let mut data = vec![0; T::LEN];
+        value.pack_into_slice(&mut data);
+        data
+    }
+
+    fn token_account(mint: Pubkey, owner: Pubkey, amount: u64) -> Vec<u8> {
+        packed(TokenAccount {
+            mint,
+            owner,
+            amount,
+            state: AccountState::Initialized,
+            ..TokenAccount::default()
+        })
+    }
+
+    fn mint(decimals: u8) -> Vec<u8> {
+        packed(Mint {
+            decimals,
+            is_initialized: true,
+            ..Mint::default()
+        })
+    }
+
+    #[test]
+    fn escrow_filters_match_the_fields_they_name() {
+        let initializer = Pubkey::new_unique();
+        let escrow = packed(Escrow {
+            is_initialized: true,
+            initializer_pubkey: initializer,
+            status: EscrowStatus::PartiallyFilled,
+            ..Escrow::default()
+        });
+        let program_id = Pubkey::new_unique();
+
+        assert!(matches(&EscrowFilter::escrows(&program_id).initializer(&initializer).status(EscrowStatus::PartiallyFilled), &escrow));
+        assert!(!matches(&EscrowFilter::escrows(&program_id).initializer(&Pubkey::new_unique()), &escrow));
+        assert!(!matches(&EscrowFilter::escrows(&program_id).status(EscrowStatus::Open), &escrow));
+        assert!(!matches(&EscrowFilter::escrows(&program_id), &escrow[..Escrow::LEN - 1]));
+    }
+
+    #[test]
+    fn offer_id_filter_finds_the_numbered_escrow() {
+        let escrow = packed(Escrow {
+            is_initialized: true,
+            offer_id: 4812,
+            ..Escrow::default()
+        });
+        let program_id = Pubkey::new_unique();
+
+        assert!(matches(&EscrowFilter::escrows(&program_id).offer_id(4812), &escrow));
+        assert!(!matches(&EscrowFilter::escrows(&program_id).offer_id(4813), &escrow));
+        assert!(!matches(&EscrowFilter::escrows(&program_id).offer_id(0), &escrow));
+    }
+
+    #[test]
+    fn offered_mint_filter_finds_the_vaults_the_program_holds() {
+        let program_id = Pubkey::new_unique();
+        let (pda, _bump_seed) = Pubkey::find_program_address(&[b"escrow"], &program_id);
+        let offered_mint = Pubkey::new_unique();
+        let filter = EscrowFilter::offered_mint(&offered_mint, &program_id);
+
+        assert_eq!(filter.program_id, spl_token::id());
+        assert!(matches(&filter, &token_account(offered_mint, pda, 5)));
+        assert!(!matches(&filter, &token_account(offered_mint, Pubkey::new_unique(), 5)));
+        assert!(!matches(&filter, &token_account(Pubkey::new_unique(), pda, 5)));
+    }
+
+    #[test]
+    fn decoded_escrow_resolves_both_mints() {
+        let (escrow_pubkey, vault, receive_account) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let (offered_mint, expected_mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut accounts = HashMap::new();
+        accounts.insert(
+            escrow_pubkey,
+            packed(Escrow {
+                is_initialized: true,
+                temp_token_account_pubkey: vault,
+                initializer_token_to_receive_account_pubkey: receive_account,
+                expected_amount: 2_500_000,
+                ..Escrow::default()
+            }),
+        );
+        accounts.insert(vault, token_account(offered_mint, Pubkey::new_unique(), 1_500));
+        accounts.insert(receive_account, token_account(expected_mint, Pubkey::new_unique(), 0));
+        accounts.insert(offered_mint, mint(2));
+        accounts.insert(expected_mint, mint(6));
+
+        let decoded = DecodedEscrow::fetch(&escrow_pubkey, |key| accounts.get(key).cloned()).unwrap();
+        assert_eq!(decoded.offered_mint, offered_mint);
+        assert_eq!(decoded.expected_mint, Some(expected_mint));
+        assert_eq!(decoded.offered_ui_amount(), 15.0);
+        assert_eq!(decoded.expected_ui_amount(), 2.5);
+
+        accounts.remove(&expected_mint);
+        assert_eq!(
+            DecodedEscrow::fetch(&escrow_pubkey, |key| accounts.get(key).cloned()),
+            Err(ProgramError::UninitializedAccount)
+        );
+    }
+
+    /// An RPC holding `accounts` that answers every simulation with `simulation` and keeps the transactions it was given
+    #[derive(Default)]
+    struct MockRpc {
+        accounts: HashMap<Pubkey, Vec<u8>>,
+        simulation: Simulation,
+        simulated: RefCell<Vec<Vec<Instruction>>>,
+        sent: RefCell<Vec<Vec<Instruction>>>,
+    }
+
+    impl EscrowRpc for MockRpc {
+        type Signature = usize;
+        type Error = ProgramError;
+
+        fn get_account_data(&self, pubkey: &Pubkey) -> Option<Vec<u8>> {
+            self.accounts.get(pubkey).cloned()
+        }
+
+        fn simulate_transaction(&self, instructions: &[Instruction]) -> Result<Simulation, ProgramError> {
+            self.simulated.borrow_mut().push(instructions.to_vec());
+            Ok(self.simulation.clone())
+        }
+
+        fn send_transaction(&self, instructions: &[Instruction]) -> Result<usize, ProgramError> {
+            self.sent.borrow_mut().push(instructions.to_vec());
+            Ok(self.sent.borrow().len())
+        }
+    }
+
+    fn failed_simulation(failed_program: &Pubkey, program_id: &Pubkey, code: u32) -> Simulation {
+        Simulation {
+            instruction_error: Some((0, InstructionError::Custom(code))),
+            transaction_error: None,
+            logs: vec![
+                format!("Program {} invoke [1]", program_id),
+                "Program log: Instruction: Exchange".to_string(),
+                format!("Program {} failed: custom program error: {:#x}", failed_program, code),
+                format!("Program {} consumed 12000 of 200000 compute units", program_id),
+                format!("Program {} failed: custom program error: {:#x}", program_id, code),
+            ],
+            units_consumed: Some(12_000),
+        }
+    }
+
+    #[test]
+    fn simulated_accept_maps_the_programs_error_back_to_an_escrow_error() {
+        let program_id = Pubkey::new_unique();
+        let escrow_pubkey = Pubkey::new_unique();
+        let escrow = Escrow {
+            is_initialized: true,
+            temp_token_account_pubkey: Pubkey::new_unique(),
+            ..Escrow::default()
+        };
+        let mut rpc = MockRpc {
+            simulation: failed_simulation(&program_id, &program_id, EscrowError::AccountFrozen as u32),
+            ..MockRpc::default()
+        };
+        rpc.accounts.insert(escrow_pubkey, packed(escrow.clone()));
+
+        let (taker, send, receive) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let report = match accept_escrow(&rpc, &program_id, &escrow_pubkey, &taker, &send, &receive, 5, true) {
+            Ok(Submission::Simulated(report)) => report,
+            other => panic!("expected a simulation report, got {:?}", other),
+        };
+        assert_eq!(report.failure, Some(SimulationFailure::Escrow { instruction_index: 0, error: EscrowError::AccountFrozen }));
+        assert!(!report.succeeded());
+        assert_eq!(report.units_consumed, Some(12_000));
+        assert!(rpc.sent.borrow().is_empty());
+        let simulated_keys: Vec<Pubkey> = rpc.simulated.borrow()[0][0].accounts.iter().map(|meta| meta.pubkey).collect();
+        assert_eq!(simulated_keys[..4], [taker, send, receive, escrow.temp_token_account_pubkey]);
+
+        assert_eq!(
+            accept_escrow(&rpc, &program_id, &Pubkey::new_unique(), &taker, &send, &receive, 5, true),
+            Err(ProgramError::UninitializedAccount)
+        );
+    }
+
+    #[test]
+    fn simulation_report_leaves_a_cpis_error_alone() {
+        let program_id = Pubkey::new_unique();
+        let simulation = failed_simulation(&spl_token::id(), &program_id, 1);
+        let report = EscrowSimulationReport::new(&program_id, &[], simulation);
+        assert_eq!(report.failure, Some(SimulationFailure::Instruction { instruction_index: 0, error: InstructionError::Custom(1) }));
+
+        let rejected = Simulation {
+            transaction_error: Some("Blockhash not found".to_string()),
+            ..Simulation::default()
+        };
+        let report = EscrowSimulationReport::new(&program_id, &[], rejected);
+        assert_eq!(report.failure, Some(SimulationFailure::Transaction("Blockhash not found".to_string())));
+    }
+
+    #[test]
+    fn simulation_report_reads_the_logged_events() {
+        let program_id = Pubkey::new_unique();
+        let simulation = Simulation {
+            logs: vec![
+                format!("Program {} invoke [1]", program_id),
+                "Program log: Instruction: Exchange".to_string(),
+                "Program log: EscrowSettled escrow=E initializer=I taker=T offered_amount=100 paid_amount=50 paid_lamports=0 \
+                 referrer=none referral_fee=0 protocol_fee=1 memo=\"deal \\\"#1\\\" now\" offer_id=4812"
+                    .to_string(),
+                "Program log: Settled".to_string(),
+                format!("Program {} success", program_id),
+            ],
+            units_consumed: Some(30_000),
+            ..Simulation::default()
+        };
+
+        let report = EscrowSimulationReport::new(&program_id, &[], simulation);
+        assert!(report.succeeded());
+        assert_eq!(report.events.len(), 1);
+        let settled = &report.events[0];
+        assert_eq!(settled.name, "EscrowSettled");
+        assert_eq!(settled.field("offered_amount"), Some("100"));
+        assert_eq!(settled.field("memo"), Some("deal \\\"#1\\\" now"));
+        assert_eq!(settled.field("offer_id"), Some("4812"));
+        assert_eq!(settled.fields.len(), 11);
+    }
+
+    #[test]
+    fn create_escrow_sends_unless_simulating() {
+        let program_id = Pubkey::new_unique();
+        let rpc = MockRpc::default();
+        let (initializer, temp, receive) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+
+        assert_eq!(create_escrow(&rpc, &program_id, &initializer, &temp, &receive, 50, 0, 0, 7, false), Ok(Submission::Sent(1)));
+        let sent_keys: Vec<Pubkey> = rpc.sent.borrow()[0][0].accounts.iter().map(|meta| meta.pubkey).collect();
+        assert_eq!(sent_keys[..4], [initializer, temp, receive, find_escrow_address(&initializer, 7, &program_id)]);
+
+        let simulated = create_escrow(&rpc, &program_id, &initializer, &temp, &receive, 50, 0, 0, 7, true);
+        assert!(matches!(simulated, Ok(Submission::Simulated(report)) if report.succeeded()));
+        assert_eq!(rpc.sent.borrow().len(), 1);
+    }
+}
@@ -1,24 +1,229 @@
-use thiserror::Error;
-use solana_program::program_error::ProgramError;
-
-#[derive(Error, Debug, Copy, Clone)]
-pub enum EscrowError {
-    /// Invalid instruction
-    #[error("Invalid Instruction")]
-    InvalidInstruction,
-    /// Not rent exempt
-    #[error("Not Rent Exempt")]
-    NotRentExempt,
-    #[error("Expected Amount Mismatch")]
-    ExpectedAmountMismatch,
-    #[error("Expected Account Mismatch")]
-    ExpectedAccountMismatch,
-    #[error("Amount Overflow")]
-    AmountOverflow,
-}
-
-impl From<EscrowError> for ProgramError {
-    fn from(e: EscrowError) -> Self {
-        ProgramError::Custom(e as u32)
-    }
-}
\ No newline at end of file
+use num_derive::FromPrimitive;
+use num_traits::FromPrimitive;
+use thiserror::Error;
+use solana_program::{
+    decode_error::DecodeError,
+    msg,
+    program_error::{PrintProgramError, ProgramError},
+};
+
+#[derive(Error, Debug, Copy, Clone, PartialEq, FromPrimitive)]
+pub enum EscrowError {
+    /// Invalid instruction
+    #[error("Invalid Instruction")]
+    InvalidInstruction,
+    /// Not rent exempt
+    #[error("Not Rent Exempt")]
+    NotRentExempt,
+    #[error("Expected Amount Mismatch")]
+    ExpectedAmountMismatch,
+    #[error("Expected Account Mismatch")]
+    ExpectedAccountMismatch,
+    #[error("Amount Overflow")]
+    AmountOverflow,
+    #[error("Escrow Expired")]
+    EscrowExpired,
+    #[error("Escrow Not Expired")]
+    EscrowNotExpired,
+    #[error("Delegation Revoked")]
+    DelegationRevoked,
+    #[error("Fill Window Expired")]
+    FillWindowExpired,
+    #[error("Invalid Token Program")]
+    InvalidTokenProgram,
+    /// The temp token account passed in isn't the one stored in the escrow
+    #[error("Temp Account Mismatch")]
+    TempAccountMismatch,
+    /// The initializer account passed in isn't the one stored in the escrow
+    #[error("Initializer Mismatch")]
+    InitializerMismatch,
+    /// The initializer's receiving token account passed in isn't the one stored in the escrow
+    #[error("Receive Account Mismatch")]
+    ReceiveAccountMismatch,
+    /// A token account is for a different mint than the trade needs
+    #[error("Wrong Mint")]
+    WrongMint,
+    /// The escrow account hasn't been initialized
+    #[error("Escrow Not Initialized")]
+    EscrowNotInitialized,
+    /// A signer passed for a multisig initializer isn't one of its members
+    #[error("Invalid Multisig Signer")]
+    InvalidMultisigSigner,
+    /// The escrow's status doesn't allow this operation
+    #[error("Invalid Escrow Status")]
+    InvalidEscrowStatus,
+    /// The instruction doesn't apply to this kind of escrow, e.g. an Exchange against a subscription
+    #[error("Wrong Escrow Mode")]
+    WrongEscrowMode,
+    /// Not enough slots have passed since the last installment was claimed
+    #[error("Installment Not Due")]
+    InstallmentNotDue,
+    /// The taker account passed in isn't the one stored in the escrow
+    #[error("Taker Mismatch")]
+    TakerMismatch,
+    /// The offer's start time hasn't been reached
+    #[error("Offer Not Active Yet")]
+    OfferNotActiveYet,
+    /// The oracle account isn't the one stored in the escrow or isn't a Pyth price account
+    #[error("Invalid Oracle Account")]
+    InvalidOracleAccount,
+    /// The oracle price isn't trading or hasn't been updated recently enough
+    #[error("Stale Oracle Price")]
+    StaleOraclePrice,
+    /// The oracle's confidence interval is wider than the escrow's tolerance
+    #[error("Oracle Price Too Uncertain")]
+    OraclePriceTooUncertain,
+    /// The config account isn't the program's config PDA
+    #[error("Invalid Config Account")]
+    InvalidConfigAccount,
+    /// The referral share is more than the config allows
+    #[error("Referral Fee Too High")]
+    ReferralFeeTooHigh,
+    /// The signer isn't the config's admin
+    #[error("Admin Mismatch")]
+    AdminMismatch,
+    /// A taker has deposited their side, the escrow can only be settled or withdrawn from
+    #[error("Taker Side Deposited")]
+    TakerSideDeposited,
+    /// Settle needs a taker to have deposited their side first
+    #[error("Taker Side Not Deposited")]
+    TakerSideNotDeposited,
+    /// A state account isn't owned by this program
+    #[error("Invalid Account Owner")]
+    InvalidAccountOwner,
+    /// An account the instruction changes wasn't passed as writable
+    #[error("Account Not Writable")]
+    AccountNotWritable,
+    /// An executable account was passed where a data account is expected
+    #[error("Executable Account")]
+    ExecutableAccount,
+    /// The memo is longer than 64 bytes or isn't UTF-8
+    #[error("Invalid Memo")]
+    InvalidMemo,
+    /// A rate needs a non zero numerator and denominator
+    #[error("Invalid Rate")]
+    InvalidRate,
+    /// The taker asked for nothing or for more than is left in the vault
+    #[error("Invalid Take Amount")]
+    InvalidTakeAmount,
+    /// A stats account isn't the stats PDA for the program or for the escrow's initializer
+    #[error("Invalid Stats Account")]
+    InvalidStatsAccount,
+    /// The taker is paying in a mint that isn't on the escrow's allowlist
+    #[error("Mint Not Accepted")]
+    MintNotAccepted,
+    /// One of the token accounts or mints uses Token-2022 confidential transfers, which the escrow can't settle
+    #[error("Confidential Account Not Supported")]
+    ConfidentialAccountNotSupported,
+    /// Another taker's commit reserves the escrow until its reveal window closes
+    #[error("Offer Committed")]
+    OfferCommitted,
+    /// The reveal doesn't match a live commit of the signing taker
+    #[error("Invalid Reveal")]
+    InvalidReveal,
+    /// The escrow account doesn't start with a layout version this program knows
+    #[error("Unknown Escrow Version")]
+    UnknownEscrowVersion,
+    /// The escrow is in an older layout, Migrate it before using it
+    #[error("Escrow Needs Migration")]
+    EscrowNeedsMigration,
+    /// An offer or a take of nothing
+    #[error("Zero Amount")]
+    ZeroAmount,
+    /// The account holding the offered tokens is empty
+    #[error("Empty Offer Account")]
+    EmptyOfferAccount,
+    /// The offered token is also one the escrow would be paid in
+    #[error("Same Mint")]
+    SameMint,
+    /// The cancellation penalty is over 10000 bps, or the account it's paid to isn't the one the escrow names
+    #[error("Invalid Penalty")]
+    InvalidPenalty,
+    /// The escrow account doesn't hold the config's creation deposit on top of its rent
+    #[error("Missing Creation Deposit")]
+    MissingCreationDeposit,
+    /// The account a forfeited creation deposit is paid to isn't the one the escrow names
+    #[error("Deposit Recipient Mismatch")]
+    DepositRecipientMismatch,
+    /// A token account handed to the PDA has a close authority, which could close it from under the escrow
+    #[error("Close Authority Set")]
+    CloseAuthoritySet,
+    /// The token account isn't held by the PDA or its close authority isn't the claimant's orphan authority
+    #[error("Invalid Orphan Claim")]
+    InvalidOrphanClaim,
+    /// Two offers matched against each other don't each give the other at least what it asks for
+    #[error("Offers Don't Cross")]
+    OffersDontCross,
+    /// A fee token account isn't owned by the config's fee recipient or holds the wrong mint
+    #[error("Fee Account Mismatch")]
+    FeeAccountMismatch,
+    /// The vault passed to InitEscrowFromAccount isn't the PDA derived from the escrow account
+    #[error("Invalid Vault Account")]
+    InvalidVaultAccount,
+    /// The account isn't the Metaplex metadata of the NFT's mint, or can't be read as one
+    #[error("Invalid Metadata Account")]
+    InvalidMetadataAccount,
+    /// The NFT isn't a verified member of the collection the bid is for
+    #[error("Not In Collection")]
+    NotInCollection,
+    /// A token account royalties are paid to isn't owned by its creator or holds the wrong mint
+    #[error("Royalty Account Mismatch")]
+    RoyaltyAccountMismatch,
+    /// The escrow account isn't the PDA the InitEscrow's nonce derives for the initializer
+    #[error("Invalid Escrow Address")]
+    InvalidEscrowAddress,
+    /// The fill would take the taker past the most of the offer the escrow lets one taker buy
+    #[error("Purchase Limit Exceeded")]
+    PurchaseLimitExceeded,
+    /// The claim account passed for a capped escrow isn't the taker's claim PDA for it
+    #[error("Invalid Claim Account")]
+    InvalidClaimAccount,
+    /// A fee schedule has too many tiers, tiers out of age order, a fee above 100% or a fee that goes up with age
+    #[error("Invalid Fee Schedule")]
+    InvalidFeeSchedule,
+    /// ExtendEscrow was asked for a length that isn't longer than the escrow account already is
+    #[error("Invalid Escrow Length")]
+    InvalidEscrowLength,
+    /// The instruction before a FillSignedOffer isn't the ed25519 program verifying the initializer's signature of that exact offer
+    #[error("Invalid Offer Signature")]
+    InvalidOfferSignature,
+    /// The signed offer was filled already
+    #[error("Offer Already Filled")]
+    OfferAlreadyFilled,
+    /// The account passed to record a signed offer's fill isn't the PDA its initializer and nonce derive
+    #[error("Invalid Filled Offer Account")]
+    InvalidFilledOfferAccount,
+    /// A token account the instruction would move tokens in or out of is frozen by its mint's freeze authority
+    #[error("Account Frozen")]
+    AccountFrozen,
+    /// The account passed as the offer counter isn't the counter PDA
+    #[error("Invalid Offer Counter")]
+    InvalidOfferCounter,
+    /// The instruction data isn't exactly one instruction: an unknown version or tag, a field cut short or bytes left after the last one
+    #[error("Invalid Instruction Data")]
+    InvalidInstructionData,
+    /// A subscription with installments of nothing or no interval between them
+    #[error("Invalid Subscription Terms")]
+    InvalidSubscriptionTerms,
+}
+
+impl From<EscrowError> for ProgramError {
+    fn from(e: EscrowError) -> Self {
+        ProgramError::Custom(e as u32)
+    }
+}
+
+impl<T> DecodeError<T> for EscrowError {
+    fn type_of() -> &'static str {
+        "EscrowError"
+    }
+}
+
+impl PrintProgramError for EscrowError {
+    fn print<E>(&self)
+    where
+        E: 'static + std::error::Error + DecodeError<E> + PrintProgramError + FromPrimitive,
+    {
+        msg!("Error: {}", self);
+    }
+}
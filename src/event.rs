@@ -0,0 +1,106 @@
+use std::convert::TryInto;
+
+use solana_program::{msg, program::set_return_data, pubkey::Pubkey};
+
+use crate::state::Memo;
+
+/// Logged once a trade settles so indexers can follow fills without having to decode the token program CPIs
+pub struct SettlementEvent {
+    pub escrow_pubkey: Pubkey,
+    pub initializer_pubkey: Pubkey,
+    pub taker_pubkey: Pubkey,
+    pub offered_amount: u64,
+    pub paid_amount: u64, // everything the taker paid, including any referral and protocol fees
+    pub paid_lamports: u64,
+    pub referrer_token_account: Option<Pubkey>,
+    pub referral_fee: u64,
+    pub protocol_fee: u64,
+    pub memo: Memo,
+    pub offer_id: u64, // 0 for an offer that wasn't numbered
+}
+
+impl SettlementEvent {
+    pub fn emit(&self) {
+        msg!(
+            "EscrowSettled escrow={} initializer={} taker={} offered_amount={} paid_amount={} paid_lamports={} referrer={} referral_fee={} protocol_fee={} memo={:?} offer_id={}",
+            self.escrow_pubkey,
+            self.initializer_pubkey,
+            self.taker_pubkey,
+            self.offered_amount,
+            self.paid_amount,
+            self.paid_lamports,
+            self.referrer_token_account.map_or_else(|| "none".to_string(), |referrer| referrer.to_string()),
+            self.referral_fee,
+            self.protocol_fee,
+            self.memo.as_str().unwrap_or(""),
+            self.offer_id,
+        );
+    }
+}
+
+/// Logged when InitEscrow opens an escrow, with the number it was given if the offer counter was passed, so an indexer can map
+/// "offer #4812" to the escrow without reading the account
+pub struct EscrowOpenedEvent {
+    pub escrow_pubkey: Pubkey,
+    pub initializer_pubkey: Pubkey,
+    pub offer_id: u64, // 0 for an offer that wasn't numbered
+}
+
+impl EscrowOpenedEvent {
+    pub fn emit(&self) {
+        msg!("EscrowOpened escrow={} initializer={} offer_id={}", self.escrow_pubkey, self.initializer_pubkey, self.offer_id);
+    }
+}
+
+/// What a ValidateExchange found the take would do, logged and set as the instruction's return data so a simulation can read it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ExchangeQuote {
+    pub offered_amount: u64,
+    pub paid_amount: u64, // everything the taker would pay, including any referral and protocol fees
+    pub paid_lamports: u64,
+    pub referral_fee: u64,
+    pub protocol_fee: u64,
+    pub closes_escrow: bool,
+}
+
+impl ExchangeQuote {
+    /// The return data is offered_amount, paid_amount, paid_lamports, referral_fee and protocol_fee as little endian u64s, then
+    /// closes_escrow as a byte
+    pub const LEN: usize = 41;
+
+    pub fn emit(&self) {
+        msg!(
+            "ExchangeValid offered_amount={} paid_amount={} paid_lamports={} referral_fee={} protocol_fee={} closes_escrow={}",
+            self.offered_amount,
+            self.paid_amount,
+            self.paid_lamports,
+            self.referral_fee,
+            self.protocol_fee,
+            self.closes_escrow,
+        );
+
+        let mut data = [0; ExchangeQuote::LEN];
+        data[..8].copy_from_slice(&self.offered_amount.to_le_bytes());
+        data[8..16].copy_from_slice(&self.paid_amount.to_le_bytes());
+        data[16..24].copy_from_slice(&self.paid_lamports.to_le_bytes());
+        data[24..32].copy_from_slice(&self.referral_fee.to_le_bytes());
+        data[32..40].copy_from_slice(&self.protocol_fee.to_le_bytes());
+        data[40] = self.closes_escrow as u8;
+        set_return_data(&data);
+    }
+
+    /// Reads a quote back out of a simulated ValidateExchange's return data
+    pub fn from_return_data(data: &[u8]) -> Option<ExchangeQuote> {
+        if data.len() != ExchangeQuote::LEN {
+            return None;
+        }
+        Some(ExchangeQuote {
+            offered_amount: u64::from_le_bytes(data[..8].try_into().ok()?),
+            paid_amount: u64::from_le_bytes(data[8..16].try_into().ok()?),
+            paid_lamports: u64::from_le_bytes(data[16..24].try_into().ok()?),
+            referral_fee: u64::from_le_bytes(data[24..32].try_into().ok()?),
+            protocol_fee: u64::from_le_bytes(data[32..40].try_into().ok()?),
+            closes_escrow: data[40] != 0,
+        })
+    }
+}
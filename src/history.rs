@@ -0,0 +1,148 @@
+use solana_program::{
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
+    keccak,
+    msg,
+    program::{invoke, invoke_signed},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+/// The SPL account compression program that owns the history trees
+pub mod account_compression {
+    solana_program::declare_id!("cmtDvXumGCrqC1Age74AVPhSRVXJMd8PJS91L8KbNCK");
+}
+
+/// The noop program used to log the full summary so indexers can rebuild each leaf
+pub mod noop {
+    solana_program::declare_id!("noopb9bkMVfRPU8AsbpTUg8AQkHtKwMYZiFUjNRtMmV");
+}
+
+// anchor instruction discriminators, the first 8 bytes of sha256("global:<instruction name>")
+const APPEND_DISCRIMINATOR: [u8; 8] = [149, 120, 18, 222, 236, 225, 88, 203];
+const INIT_EMPTY_MERKLE_TREE_DISCRIMINATOR: [u8; 8] = [191, 11, 119, 7, 180, 107, 220, 110];
+
+/// How the escrow came to be closed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EscrowOutcome {
+    Exchanged,
+    Expired,
+}
+
+/// The compact record of a closed escrow that gets appended to the history tree
+pub struct EscrowSummary {
+    pub escrow_pubkey: Pubkey,
+    pub initializer_pubkey: Pubkey,
+    pub counterparty_pubkey: Pubkey, // the taker for an exchange or the cranker for an expiry
+    pub expected_amount: u64,
+    pub offered_amount: u64,
+    pub outcome: EscrowOutcome,
+    pub closed_at: i64,
+}
+
+impl EscrowSummary {
+    pub const LEN: usize = 121; // 3 pubkeys, 2 u64, 1 u8, 1 i64 = 3 * 32 + 2 * 8 + 1 + 8 = 121
+
+    pub fn to_bytes(&self) -> [u8; EscrowSummary::LEN] {
+        let mut dst = [0u8; EscrowSummary::LEN];
+        dst[0..32].copy_from_slice(self.escrow_pubkey.as_ref());
+        dst[32..64].copy_from_slice(self.initializer_pubkey.as_ref());
+        dst[64..96].copy_from_slice(self.counterparty_pubkey.as_ref());
+        dst[96..104].copy_from_slice(&self.expected_amount.to_le_bytes());
+        dst[104..112].copy_from_slice(&self.offered_amount.to_le_bytes());
+        dst[112] = self.outcome as u8;
+        dst[113..121].copy_from_slice(&self.closed_at.to_le_bytes());
+        dst
+    }
+
+    /// The leaf stored in the tree is the keccak hash of the summary bytes
+    pub fn leaf(&self) -> [u8; 32] {
+        keccak::hashv(&[&self.to_bytes()]).to_bytes()
+    }
+}
+
+/// Appends the summary to a history tree owned by the PDA, the accounts are the compression program, the tree and the noop program in that order
+pub fn append<'a>(
+    summary: &EscrowSummary,
+    history_accounts: &[&AccountInfo<'a>; 3],
+    pda_account: &AccountInfo<'a>,
+    bump_seed: u8,
+) -> ProgramResult {
+    let [compression_program, merkle_tree, noop_program] = *history_accounts;
+    if *compression_program.key != account_compression::id() || *noop_program.key != noop::id() {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let summary_bytes = summary.to_bytes();
+    msg!("Logging the escrow summary to the noop program...");
+    invoke(
+        &Instruction {
+            program_id: noop::id(),
+            accounts: vec![],
+            data: summary_bytes.to_vec(),
+        },
+        &[noop_program.clone()],
+    )?;
+
+    let mut data = APPEND_DISCRIMINATOR.to_vec();
+    data.extend_from_slice(&summary.leaf());
+    let append_ix = Instruction {
+        program_id: account_compression::id(),
+        accounts: vec![
+            AccountMeta::new(*merkle_tree.key, false),
+            AccountMeta::new_readonly(*pda_account.key, true),
+            AccountMeta::new_readonly(noop::id(), false),
+        ],
+        data,
+    };
+    msg!("Calling the account compression program to archive the escrow...");
+    invoke_signed(
+        &append_ix,
+        &[
+            merkle_tree.clone(),
+            pda_account.clone(),
+            noop_program.clone(),
+            compression_program.clone(),
+        ],
+        &[&[&b"escrow"[..], &[bump_seed]]],
+    )
+}
+
+/// Creates an empty history tree with the PDA as its authority, the tree account must already be allocated and owned by the compression program
+pub fn init_tree<'a>(
+    history_accounts: &[&AccountInfo<'a>; 3],
+    pda_account: &AccountInfo<'a>,
+    bump_seed: u8,
+    max_depth: u32,
+    max_buffer_size: u32,
+) -> ProgramResult {
+    let [compression_program, merkle_tree, noop_program] = *history_accounts;
+    if *compression_program.key != account_compression::id() || *noop_program.key != noop::id() {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut data = INIT_EMPTY_MERKLE_TREE_DISCRIMINATOR.to_vec();
+    data.extend_from_slice(&max_depth.to_le_bytes());
+    data.extend_from_slice(&max_buffer_size.to_le_bytes());
+    let init_ix = Instruction {
+        program_id: account_compression::id(),
+        accounts: vec![
+            AccountMeta::new(*merkle_tree.key, false),
+            AccountMeta::new_readonly(*pda_account.key, true),
+            AccountMeta::new_readonly(noop::id(), false),
+        ],
+        data,
+    };
+    msg!("Calling the account compression program to create the history tree...");
+    invoke_signed(
+        &init_ix,
+        &[
+            merkle_tree.clone(),
+            pda_account.clone(),
+            noop_program.clone(),
+            compression_program.clone(),
+        ],
+        &[&[&b"escrow"[..], &[bump_seed]]],
+    )
+}
@@ -0,0 +1,120 @@
+//! Shank annotated descriptions of the program's instructions, only compiled with the `idl` feature. `shank idl` reads
+//! these, along with the annotated `Escrow` in state.rs, to generate an Anchor compatible IDL that TypeScript clients can be
+//! generated from.
+//!
+//! `EscrowInstruction` only reads its optional trailing fields when they're present, which borsh can't express, so the
+//! instructions here spell out the full data layout with every field present. Unused ones are passed as zeros, exactly as
+//! the program accepts them. Shank numbers instructions by position, so the variants are kept in tag order up to Cancel.
+//! The optional trailing accounts (multisig signers, history, stats, ...) are left for the caller to append
+
+use shank::ShankInstruction;
+use solana_program::pubkey::Pubkey;
+
+use crate::state::AcceptedMint;
+
+#[derive(ShankInstruction)]
+pub enum EscrowIdlInstruction {
+    /// Starts the trade by creating and populating an escrow account and transferring ownership of the temp token account to the PDA
+    #[account(0, signer, name = "initializer", desc = "The account of the person initializing the escrow")]
+    #[account(1, writable, name = "temp_token_account", desc = "Token account holding the offered tokens, its ownership goes to the PDA")]
+    #[account(2, name = "token_to_receive_account", desc = "The initializer's token account for the token they will receive")]
+    #[account(3, writable, name = "escrow_account", desc = "The escrow account, it will hold all necessary info about the trade")]
+    #[account(4, name = "token_program", desc = "The token program")]
+    InitEscrow {
+        amount: u64,
+        expires_at: i64,
+        crank_bounty: u64,
+        /// 0 for an offer that can be taken straight away
+        start_time: i64,
+        /// The Pyth price account, all zeros for a fixed price escrow
+        oracle: Pubkey,
+        oracle_tolerance_bps: u16,
+        /// UTF-8, zero padded
+        memo: [u8; 64],
+        /// Always 4 in this layout so `reusable` lines up, zeroed pairs are skipped
+        accepted_mint_count: u8,
+        /// Leave all zeroed to accept only `amount` into the receive account
+        accepted_mints: [AcceptedMint; 4],
+        reusable: bool,
+        /// 0 for no cancellation penalty
+        cancel_penalty_bps: u16,
+        /// All zeros burns the penalty
+        penalty_recipient: Pubkey,
+        /// Lamports the taker pays on top of `amount`, 0 for none
+        lamports: u64,
+        enforce_royalties: bool,
+        /// 0 for an escrow account created beforehand
+        nonce: u64,
+        /// 0 for no cap on what one taker can buy
+        max_per_taker: u64,
+    },
+
+    /// Accepts a trade
+    #[account(0, signer, name = "taker", desc = "The account of the person taking the trade")]
+    #[account(1, writable, name = "taker_send_token_account", desc = "The taker's token account for the token they send")]
+    #[account(2, writable, name = "taker_receive_token_account", desc = "The taker's token account for the token they will receive")]
+    #[account(3, writable, name = "pda_temp_token_account", desc = "The PDA's temp token account holding the offered tokens")]
+    #[account(4, writable, name = "initializer", desc = "The initializer's main account, gets the escrow's rent back")]
+    #[account(5, writable, name = "initializer_receive_token_account", desc = "The initializer's token account that will receive the payment")]
+    #[account(6, writable, name = "escrow_account", desc = "The escrow account holding the escrow info")]
+    #[account(7, name = "token_program", desc = "The token program")]
+    #[account(8, name = "pda_account", desc = "The PDA, seeds [\"escrow\"]")]
+    Exchange {
+        amount: u64,
+        /// 0 for no deadline
+        valid_until_slot: u64,
+        referral_bps: u16,
+        /// Only with Token-2022, see `EscrowInstruction::Exchange` for where the hook accounts go
+        offered_hook_accounts: u8,
+        payment_hook_accounts: u8,
+        /// Whether a transfer authority other than the taker moves the payment
+        transfer_authority: bool,
+    },
+
+    /// Closes an expired escrow, the caller is paid the escrow's crank bounty
+    #[account(0, signer, writable, name = "cranker", desc = "Whoever cranks the escrow, receives the bounty")]
+    #[account(1, writable, name = "pda_temp_token_account", desc = "The PDA's temp token account holding the offered tokens")]
+    #[account(2, writable, name = "initializer", desc = "The initializer's main account, gets the remaining rent back")]
+    #[account(3, writable, name = "initializer_refund_token_account", desc = "The initializer's token account the offered tokens are returned to")]
+    #[account(4, writable, name = "escrow_account", desc = "The escrow account holding the escrow info")]
+    #[account(5, name = "token_program", desc = "The token program")]
+    #[account(6, name = "pda_account", desc = "The PDA, seeds [\"escrow\"]")]
+    CrankExpired,
+
+    /// Starts a trade with the PDA approved as a delegate of the initializer's token account rather than taking custody
+    #[account(0, signer, name = "initializer", desc = "The account of the person initializing the escrow")]
+    #[account(1, writable, name = "offered_token_account", desc = "The initializer's token account holding the offered tokens")]
+    #[account(2, name = "token_to_receive_account", desc = "The initializer's token account for the token they will receive")]
+    #[account(3, writable, name = "escrow_account", desc = "The escrow account, it will hold all necessary info about the trade")]
+    #[account(4, name = "rent", desc = "The rent sysvar")]
+    #[account(5, name = "token_program", desc = "The token program")]
+    #[account(6, name = "pda_account", desc = "The PDA, seeds [\"escrow\"]")]
+    InitDelegatedEscrow {
+        amount: u64,
+        offered_amount: u64,
+        expires_at: i64,
+        crank_bounty: u64,
+    },
+
+    /// Creates an empty history tree, owned by the PDA, that closed escrows can be archived to
+    #[account(0, name = "compression_program", desc = "The account compression program")]
+    #[account(1, writable, name = "merkle_tree", desc = "The history tree, allocated beforehand and owned by the compression program")]
+    #[account(2, name = "noop_program", desc = "The noop program")]
+    #[account(3, name = "pda_account", desc = "The PDA, seeds [\"escrow\"]")]
+    InitHistory {
+        max_depth: u32,
+        max_buffer_size: u32,
+    },
+
+    /// Cancels a trade, returning the offered tokens and the escrow's rent to the initializer
+    #[account(0, signer, writable, name = "initializer", desc = "The account of the person who initialized the escrow")]
+    #[account(1, writable, name = "pda_temp_token_account", desc = "The PDA's temp token account holding the offered tokens")]
+    #[account(2, writable, name = "initializer_refund_token_account", desc = "The initializer's token account the offered tokens are returned to")]
+    #[account(3, writable, name = "escrow_account", desc = "The escrow account holding the escrow info")]
+    #[account(4, name = "token_program", desc = "The token program")]
+    #[account(5, name = "pda_account", desc = "The PDA, seeds [\"escrow\"]")]
+    Cancel {
+        /// Only with Token-2022, see `EscrowInstruction::Cancel` for where the hook accounts go
+        hook_accounts: u8,
+    },
+}
@@ -1,65 +1,1412 @@
-use std::convert::TryInto;
-use solana_program::program_error::ProgramError;
-use crate::error::EscrowError::InvalidInstruction;
-
-pub enum EscrowInstruction {
-
-    /// Starts the trade by creating and populating an escrow account and transferring ownership of the given temp token account to the PDA
-    ///
-    ///
-    /// Accounts expected:
-    ///
-    /// 0. `[signer]` The account of the person initializing the escrow
-    /// 1. `[writable]` Temporary token account that should be created prior to this instruction and owned by the initializer. Needs to be writable as we need to transfer ownership 
-    /// 2. `[]` The initializer's token account for the token they will receive should the trade go through
-    /// 3. `[writable]` The escrow account, it will hold all necessary info about the trade.
-    /// 4. `[]` The rent sysvar
-    /// 5. `[]` The token program
-    InitEscrow {
-        /// The amount party A expects to receive of token Y
-        amount: u64
-    },  
-
-    /// Accepts a trade
-    ///
-    /// Accounts expected:
-    /// 0. `[signer]` The account of the person taking the trade
-    /// 1. `[writable]` The taker's token account for the token they send
-    /// 2. `[writable]`The taker's token account for the token they will receive should the trade go through
-    /// 3. `[writable]` The PDA's temp token account to get tokens from and eventually close
-    /// 4. `[writable]` The initializer's main account to send their rent fees to
-    /// 5. `[writable]` The initializer's token account that will receive tokens
-    /// 6. `[writable]` The escrow account holding the escrow info
-    /// 7. `[]` The token program
-    /// 8. `[]` The PDA account
-    Exchange {
-        /// the amount the taker expects to be paid in the other token, as a u64 because that's the max possible supply of a token
-        amount: u64,
-    },  
-}
-
-impl EscrowInstruction {
-    /// Unpacks a byte buffer into a [EscrowInstruction](enum.EscrowInstruction.html).
-    pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
-        let (tag, rest) = input.split_first().ok_or(InvalidInstruction)?;
-
-        Ok(match tag {
-            0 => Self::InitEscrow {
-                amount: Self::unpack_amount(rest)?,
-            },
-            1 => Self::Exchange {
-                amount: Self::unpack_amount(rest)?,
-            },
-            _ => return Err(InvalidInstruction.into()),
-        })
-    }
-
-    fn unpack_amount(input: &[u8]) -> Result<u64, ProgramError> {
-        let amount = input
-            .get(..8)
-            .and_then(|slice| slice.try_into().ok())
-            .map(u64::from_le_bytes)
-            .ok_or(InvalidInstruction)?;
-        Ok(amount)
-    }
-}
+use std::convert::TryInto;
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_program, sysvar,
+};
+use crate::{
+    client::{find_escrow_address, find_filled_offer_address, find_offer_counter_address},
+    error::EscrowError::{self, InvalidInstructionData},
+    signed_offer::SignedOffer,
+    state::{AcceptedMint, Config, Escrow, FeeTier, Memo},
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum EscrowInstruction {
+
+    /// Starts the trade by creating and populating an escrow account and transferring ownership of the given temp token account to the PDA
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The account of the person initializing the escrow
+    /// 1. `[writable]` Temporary token account that should be created prior to this instruction and owned by the initializer. Needs to be writable as we need to transfer ownership 
+    /// 2. `[]` The initializer's token account for the token they will receive should the trade go through
+    /// 3. `[writable]` The escrow account, it will hold all necessary info about the trade.
+    /// 4. `[]` The token program, either token program works
+    ///
+    /// 5. ..5+M `[signer]` M signer accounts, only when the initializer is an SPL Token multisig (in which case account 0 is the multisig and doesn't sign)
+    ///
+    /// For an oracle priced escrow the mints come before any multisig signers:
+    /// 5. `[]` The mint of the offered token
+    /// 6. `[]` The mint of the token party A will receive
+    /// 7. ..7+M `[signer]` M multisig signer accounts, as above
+    ///
+    /// Older clients that still pass the rent sysvar as account 4 are accepted, everything after it shifts up by one
+    ///
+    /// The temp token account has to hold some of the offered token already and have no close authority, and neither account 2's mint nor any accepted mint can be the offered one
+    ///
+    /// The config PDA comes after everything above, even when no config has been created. If the config asks for a creation deposit the escrow
+    /// account has to hold it on top of rent. It goes back to the initializer with the rent unless they cancel within the config's lock
+    ///
+    /// With a `nonce` the escrow account is the PDA `find_escrow_address` gives, not created yet. The program creates it, the initializer
+    /// signing (so not through a multisig) and paying for it as a writable account 0, and the system program comes just before the config PDA
+    ///
+    /// Passing the writable offer counter PDA straight after the config numbers the offer, see InitOfferCounter
+    ///
+    /// The global and the initializer's stats accounts can be passed as the last two accounts, see InitStats
+    InitEscrow {
+        /// The amount party A expects to receive of token Y, for an oracle priced escrow the least they'll accept whatever the oracle says. Can't be 0
+        amount: u64,
+        /// Unix timestamp after which the offer can no longer be taken and may be cranked closed, 0 means it never expires
+        expires_at: i64,
+        /// Lamports taken from the escrow account's rent and paid to whoever cranks the escrow closed once it has expired
+        crank_bounty: u64,
+        /// Unix timestamp before which the offer can't be taken. Optional trailing field in the instruction data
+        start_time: Option<i64>,
+        /// The Pyth price account the payment is worked out from at exchange time and how wide, in bps of the price, its confidence interval may be.
+        /// Optional trailing field after `start_time`, which has to be passed (as 0 if unused) for this to be read. An all zero pubkey means no oracle
+        oracle: Option<(Pubkey, u16)>,
+        /// A UTF-8 label for the offer, zero padded to 64 bytes. Optional trailing field after `oracle`, which has to be passed (zeroed if unused) for this to be read
+        memo: Option<Memo>,
+        /// Up to `Escrow::MAX_ACCEPTED_MINTS` (mint, amount) pairs the taker can pay with instead, each amount being the price of the whole offer in that mint.
+        /// Optional trailing field after `memo`, encoded as a count byte followed by each 32 byte mint and u64 amount. Pairs with an all zero mint are skipped, and a count of 0 or no field at all means only `amount` into account 2 is accepted
+        accepted_mints: Vec<AcceptedMint>,
+        /// Makes the escrow a standing offer. Each Exchange then takes one lot, the amount in the temp token account at init, for `amount`,
+        /// and the escrow stays open for the next fill until the vault is empty. TopUp adds more lots. Optional trailing byte after `accepted_mints`
+        reusable: bool,
+        /// The bps of what's left in the vault the initializer forfeits by cancelling before the offer expires, at most 10000, and the token
+        /// account for the offered token it's paid to, an all zero pubkey to burn it instead. Optional trailing u16 and pubkey after `reusable`,
+        /// 0 bps or no field at all means cancelling is free
+        cancel_penalty: Option<(u16, Pubkey)>,
+        /// Lamports the taker pays the initializer on top of `amount`, e.g. for an offer priced at 5 SOL + 1000 USDC. For a reusable escrow
+        /// they're paid per lot. Optional trailing u64 after `cancel_penalty`, which has to be passed (zeroed if unused) for this to be read
+        lamports: u64,
+        /// Pays the creators of the offered NFT their Metaplex royalties out of the payment, see Exchange. Optional trailing byte after `lamports`
+        enforce_royalties: bool,
+        /// Puts the escrow at the PDA with seeds `[b"offer", initializer, nonce]`, so its address is known before the transaction is sent and
+        /// the same offer can't be opened twice while it's live. Optional trailing u64 after `enforce_royalties`, 0 or no field at all means
+        /// the escrow account is one the client created beforehand
+        nonce: Option<u64>,
+        /// The most of the offered token one taker can buy across all their fills, for fair launch style sales through a reusable
+        /// escrow. Optional trailing u64 after `nonce`, which has to be passed (as 0 if unused) for this to be read, 0 means no cap
+        max_per_taker: u64,
+    },  
+
+    /// Accepts a trade
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The account of the person taking the trade, writable when the escrow asks for lamports as well or caps what each taker buys
+    /// 1. `[writable]` The taker's token account for the token they send
+    /// 2. `[writable]`The taker's token account for the token they will receive should the trade go through
+    /// 3. `[writable]` The PDA's temp token account to get tokens from and eventually close
+    /// 4. `[writable]` The initializer's main account to send their rent fees to
+    /// 5. `[writable]` The initializer's token account that will receive tokens. For an escrow with accepted mints, any token account of the initializer's in the mint the taker pays with
+    /// 6. `[writable]` The escrow account holding the escrow info
+    /// 7. `[]` The token program, either token program works
+    /// 8. `[]` The PDA account
+    ///
+    /// When the token program is Token-2022, so the transfers can go through TransferChecked and run any transfer hooks:
+    /// 9. `[]` The mint of the offered token
+    /// 10. `[]` The mint of the payment token
+    /// 11. ..11+N N transfer hook accounts for the offered token, see `offered_hook_accounts`, writable where the hook needs them to be
+    /// 11+N. ..11+N+P P transfer hook accounts for the payment token, see `payment_hook_accounts`
+    ///
+    /// Then, in this order and only when they apply:
+    /// - `[signer]` The transfer authority, when `transfer_authority` is set. It moves the payment out of account 1 in the taker's place, as
+    ///   its delegate or owner, and can be a program's PDA that signed the CPI into this instruction
+    /// - `[]` The system program, when the escrow asks for lamports as well
+    /// - `[writable]` The taker's claim PDA, seeds `[b"claim", escrow, taker]`, and `[]` the system program, when the escrow caps what each
+    ///   taker buys. The taker pays for the claim on their first fill
+    /// - `[]` The Pyth price account, for an oracle priced escrow
+    /// - `[]` The config account, when `referral_bps` is non zero or the escrow was opened under a protocol fee schedule
+    /// - `[writable]` The referrer's token account for the token the initializer receives, when `referral_bps` is non zero
+    /// - `[writable]` The fee recipient's token account for the payment token, when the escrow was opened under a protocol fee schedule.
+    ///   The fee comes out of what the initializer receives, at the config's tier for how many slots the escrow has been open
+    /// - `[]` The offered NFT's Metaplex metadata and `[writable]` a token account of each of its creators, in the metadata's order, for the
+    ///   payment token, when the escrow enforces royalties. The seller fee comes out of what the initializer receives and is split by share
+    /// - `[]` The SPL Memo program, to write the escrow's memo into the transaction
+    /// - `[]` The account compression program, `[writable]` the history tree and `[]` the noop program, to archive the closed escrow to the history tree
+    /// - `[writable]` The global stats account and `[writable]` the initializer's stats account, to count the exchange in them
+    Exchange {
+        /// the amount the taker expects to be paid in the other token, as a u64 because that's the max possible supply of a token.
+        /// For a rate priced escrow this is how much of the offered token the taker wants, anything up to what's left in the vault
+        amount: u64,
+        /// The last slot the taker is happy for the trade to land in, the exchange fails after it. Optional trailing field in the instruction data, 0 means no deadline
+        valid_until_slot: Option<u64>,
+        /// The share of the initializer's payment, in bps, that goes to the referrer instead. Optional trailing field after `valid_until_slot`, at most the config's `max_referral_bps`
+        referral_bps: u16,
+        /// How many transfer hook accounts follow the mints for the offered token, only with Token-2022. Optional trailing field after `referral_bps`, together with `payment_hook_accounts`
+        offered_hook_accounts: u8,
+        /// How many transfer hook accounts follow the offered token's for the payment token, only with Token-2022
+        payment_hook_accounts: u8,
+        /// Whether the payment is moved by a transfer authority other than the taker, for aggregators settling for their users. Optional
+        /// trailing byte after the hook account counts
+        transfer_authority: bool,
+    },  
+
+    /// Closes an expired escrow, returning the offered tokens and rent to the initializer. Can be called by anyone, the caller is paid the escrow's crank bounty.
+    /// A delegated escrow's approval can only be revoked by the initializer, so cranking one needs their signature as well
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The account of the person cranking the escrow, receives the bounty
+    /// 1. `[writable]` The PDA's temp token account to return tokens from and eventually close, or the delegated escrow's token account
+    /// 2. `[writable]` The initializer's main account to send the remaining rent fees to, a signer too for a delegated escrow
+    /// 3. `[writable]` The initializer's token account for the offered token, the tokens are returned here
+    /// 4. `[writable]` The escrow account holding the escrow info
+    /// 5. `[]` The token program
+    /// 6. `[]` The PDA account
+    /// 7. `[]` (optional) The account compression program, when passed the closed escrow is archived to the history tree
+    /// 8. `[writable]` (optional) The history tree
+    /// 9. `[]` (optional) The noop program
+    ///
+    /// The global and the initializer's stats accounts can be passed as the last two accounts, see InitStats
+    CrankExpired,
+
+    /// Starts a trade without taking custody of the offered tokens, the initializer's token account approves the PDA as a delegate for the offered amount instead
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The account of the person initializing the escrow
+    /// 1. `[writable]` The initializer's token account holding the tokens they are offering, the PDA is approved as its delegate
+    /// 2. `[]` The initializer's token account for the token they will receive should the trade go through
+    /// 3. `[writable]` The escrow account, it will hold all necessary info about the trade.
+    /// 4. `[]` The rent sysvar
+    /// 5. `[]` The token program
+    /// 6. `[]` The PDA account
+    InitDelegatedEscrow {
+        /// The amount party A expects to receive of token Y
+        amount: u64,
+        /// The amount of token X the PDA is approved to move out of the initializer's token account
+        offered_amount: u64,
+        /// Unix timestamp after which the offer can no longer be taken and may be cranked closed, 0 means it never expires
+        expires_at: i64,
+        /// Lamports taken from the escrow account's rent and paid to whoever cranks the escrow closed once it has expired
+        crank_bounty: u64,
+    },
+
+    /// Creates an empty history tree, owned by the PDA, that closed escrows can be archived to
+    ///
+    /// Accounts expected:
+    /// 0. `[]` The account compression program
+    /// 1. `[writable]` The history tree, allocated beforehand and owned by the account compression program
+    /// 2. `[]` The noop program
+    /// 3. `[]` The PDA account
+    InitHistory {
+        /// The depth of the tree, it can hold 2^max_depth escrow summaries
+        max_depth: u32,
+        /// The number of concurrent changes the tree can absorb
+        max_buffer_size: u32,
+    },
+
+    /// Cancels a trade, returning the offered tokens and the escrow's rent to the initializer
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The account of the person who initialized the escrow, or their SPL Token multisig (not a signer)
+    /// 1. `[writable]` The PDA's temp token account to return tokens from and eventually close, or the initializer's token account for a delegated escrow
+    /// 2. `[writable]` The initializer's token account for the offered token, the tokens are returned here
+    /// 3. `[writable]` The escrow account holding the escrow info
+    /// 4. `[]` The token program, either token program works
+    /// 5. `[]` The PDA account
+    /// 6. ..6+M `[signer]` M signer accounts, only when the initializer is an SPL Token multisig
+    ///
+    /// When the token program is Token-2022 the offered token's mint and its transfer hook accounts come before any multisig signers:
+    /// 6. `[]` The mint of the offered token
+    /// 7. ..7+H H transfer hook accounts, as the client resolved them for the returning transfer
+    /// 7+H. ..7+H+M `[signer]` M multisig signer accounts, as above
+    ///
+    /// An escrow with a cancellation penalty takes one more account straight before any multisig signers, whether or not the penalty
+    /// is still due: `[writable]` the penalty recipient's token account, or the offered token's mint when the penalty is burned
+    ///
+    /// Likewise an escrow holding a creation deposit takes `[writable]` the deposit recipient it names after that, the deposit is paid
+    /// to it when the escrow is cancelled before `deposit_locked_until_slot`
+    ///
+    /// The token program can't move tokens out of a frozen temp token account, so cancelling with one frozen leaves the tokens where
+    /// they are and parks the escrow as CancelPending, out of any taker's reach, while the deposit is settled and the rent stays put.
+    /// Cancelling it again once the mint's freeze authority has thawed the account returns the tokens and closes the escrow as usual,
+    /// and fails with AccountFrozen until then. A penalty still due has to run out before a frozen escrow can be parked
+    ///
+    /// The global and the initializer's stats accounts can be passed as the last two accounts, see InitStats
+    Cancel {
+        /// How many transfer hook accounts are passed, only with Token-2022. Optional, 0 if left out
+        hook_accounts: u8,
+    },
+
+    /// Opens a subscription, the taker will deposit a lump sum that the initializer claims in fixed installments
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The account of the person initializing the subscription, they will claim the installments
+    /// 1. `[]` The initializer's token account that installments are paid into
+    /// 2. `[writable]` The escrow account, it will hold all necessary info about the subscription
+    /// 3. `[]` The rent sysvar
+    InitSubscription {
+        /// The amount the initializer can claim each interval
+        installment_amount: u64,
+        /// The number of slots between installments
+        interval_slots: u64,
+    },
+
+    /// Takes a subscription by depositing the lump sum, ownership of the given temp token account is transferred to the PDA
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The account of the person taking the subscription
+    /// 1. `[writable]` The taker's temp token account holding the lump sum, created prior to this instruction and owned by the taker
+    /// 2. `[]` The initializer's token account that installments are paid into
+    /// 3. `[writable]` The escrow account holding the subscription info
+    /// 4. `[]` The token program
+    Subscribe {
+        /// The lump sum the taker expects to deposit, must match the temp token account's balance
+        amount: u64,
+    },
+
+    /// Pays the initializer one installment out of the deposit, once at least `interval_slots` have passed since the last claim
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The account of the person who initialized the subscription
+    /// 1. `[writable]` The PDA's temp token account holding the deposit
+    /// 2. `[writable]` The initializer's token account that installments are paid into
+    /// 3. `[writable]` The escrow account holding the subscription info
+    /// 4. `[writable]` The taker's main account, receives the temp account's rent if this claim empties it
+    /// 5. `[]` The token program
+    /// 6. `[]` The PDA account
+    ClaimInstallment,
+
+    /// Ends a subscription early, installments already due are paid to the initializer and the remainder is refunded to the taker
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The account of the person who took the subscription
+    /// 1. `[writable]` The PDA's temp token account holding the deposit
+    /// 2. `[writable]` The taker's token account the remainder is refunded to
+    /// 3. `[writable]` The initializer's token account that installments are paid into
+    /// 4. `[writable]` The escrow account holding the subscription info
+    /// 5. `[writable]` The initializer's main account to send the escrow's rent to
+    /// 6. `[]` The token program
+    /// 7. `[]` The PDA account
+    CancelSubscription,
+
+    /// Adds more of the offered token to an open escrow without having to cancel and re-create it
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The account of the person who initialized the escrow
+    /// 1. `[writable]` The initializer's token account the extra tokens come from, for a delegated escrow this is the delegated account itself
+    /// 2. `[writable]` The PDA's temp token account, or the initializer's delegated token account for a delegated escrow
+    /// 3. `[writable]` The escrow account holding the escrow info
+    /// 4. `[]` The token program
+    /// 5. `[]` The PDA account
+    TopUp {
+        /// The amount of the offered token to add
+        additional_amount: u64,
+    },
+
+    /// Creates the program's config account, whoever signs becomes its admin
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The admin, who also pays for the config account
+    /// 1. `[writable]` The config PDA, seeds `[b"config"]`
+    /// 2. `[]` The system program
+    InitConfig {
+        /// The largest referral share an Exchange may take, in bps of the payment
+        max_referral_bps: u16,
+        /// The lamports each new escrow has to hold on top of rent, how many slots after InitEscrow cancelling forfeits them, and the
+        /// system account they then go to. Optional trailing u64, u64 and pubkey after `max_referral_bps`, no field means no deposit
+        creation_deposit: Option<(u64, u64, Pubkey)>,
+        /// The protocol fee schedule, each tier's fee applying to offers taken once they're at least its age, see `Config::protocol_fee_bps`.
+        /// Optional trailing field after `creation_deposit`, which has to be passed (zeroed if unused) for this to be read, encoded as a count
+        /// byte followed by each tier's u64 `min_age_slots` and u16 `fee_bps`. Only escrows opened while there's a schedule pay the fee
+        fee_tiers: Option<Vec<FeeTier>>,
+    },
+
+    /// Changes the program's config
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The config's admin, writable when the config has to grow
+    /// 1. `[writable]` The config PDA
+    /// 2. `[]` The system program, only for a config created before the current layout, which it grows to with the admin paying the rent
+    UpdateConfig {
+        /// The largest referral share an Exchange may take, in bps of the payment
+        max_referral_bps: u16,
+        /// As in InitConfig, no field leaves the current creation deposit as it is
+        creation_deposit: Option<(u64, u64, Pubkey)>,
+        /// As in InitConfig, no field leaves the current fee schedule as it is and a count of 0 removes it
+        fee_tiers: Option<Vec<FeeTier>>,
+    },
+
+    /// Commits a taker to an open escrow by handing their payment to the PDA, so both sides are locked in until Settle or Withdraw
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The taker
+    /// 1. `[writable]` The taker's temp token account holding exactly the expected amount of the payment token, its ownership goes to the PDA
+    /// 2. `[]` The taker's token account for the offered token, the offered tokens go here on Settle
+    /// 3. `[]` The PDA's temp token account with the initializer's offered tokens
+    /// 4. `[writable]` The escrow account holding the escrow info
+    /// 5. `[]` The token program
+    /// 6. `[]` The initializer's token account that will receive the payment, the taker's temp token account has to be in its mint
+    ///
+    /// Only a plain fixed price escrow, with no royalties, protocol fee or cap per taker, can be deposited into
+    DepositTakerSide {
+        /// The amount of the offered token the taker expects to receive, the same front running check as Exchange
+        amount: u64,
+    },
+
+    /// Swaps both vaults of an escrow a taker has deposited into. Can be called by anyone since both sides are already committed
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` Whoever is settling
+    /// 1. `[writable]` The escrow account holding the escrow info
+    /// 2. `[writable]` The PDA's temp token account with the initializer's offered tokens
+    /// 3. `[writable]` The PDA's temp token account with the taker's payment
+    /// 4. `[writable]` The initializer's token account that will receive the payment
+    /// 5. `[writable]` The taker's token account that will receive the offered tokens
+    /// 6. `[writable]` The initializer's main account, gets the escrow's and their temp account's rent back
+    /// 7. `[writable]` The taker's main account, gets their temp account's rent back
+    /// 8. `[]` The token program
+    /// 9. `[]` The PDA account
+    ///
+    /// The global and the initializer's stats accounts can be passed as the last two accounts, see InitStats
+    Settle,
+
+    /// Backs one side out of a deposited escrow before it's settled, handing their temp token account back to them.
+    /// When the initializer withdraws the escrow is closed and any taker deposit is handed back too, when the taker withdraws the escrow stays open
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The initializer or the depositing taker
+    /// 1. `[writable]` The escrow account holding the escrow info
+    /// 2. `[writable]` The signer's temp token account held by the PDA
+    /// 3. `[]` The token program
+    /// 4. `[]` The PDA account
+    /// 5. `[writable]` The taker's temp token account, only when the initializer withdraws
+    ///
+    /// The initializer withdrawing cancels the escrow, so like Cancel an escrow with a cancellation penalty takes `[writable]` the
+    /// penalty recipient's token account, or the offered token's mint when the penalty is burned, after the taker's temp token account,
+    /// and an escrow holding a creation deposit takes `[writable]` the deposit recipient it names after that. The global and the
+    /// initializer's stats accounts can be passed as the last two accounts, see InitStats
+    Withdraw,
+
+    /// Like InitEscrow, but the initializer names a rate instead of a fixed price for the whole vault, and takers pick their own size.
+    /// Taking `n` offered tokens costs `n * rate_numerator / rate_denominator` of the other token, rounded up, and the escrow stays open until the vault is empty
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The account of the person initializing the escrow
+    /// 1. `[writable]` Temporary token account holding the offered tokens, its ownership goes to the PDA
+    /// 2. `[]` The initializer's token account for the token they will receive
+    /// 3. `[writable]` The escrow account
+    /// 4. `[]` The token program, either token program works
+    /// 5. ..5+M `[signer]` M signer accounts, only when the initializer is an SPL Token multisig
+    /// 5+M. `[]` The config PDA, as for InitEscrow
+    InitRateEscrow {
+        rate_numerator: u64,
+        rate_denominator: u64,
+        /// Unix timestamp after which the offer can no longer be taken, 0 means it never expires
+        expires_at: i64,
+        /// Lamports paid to whoever cranks the escrow closed once it has expired
+        crank_bounty: u64,
+        /// The most of the offered token one taker can buy across all their fills, see InitEscrow. Optional trailing field, 0 means no cap
+        max_per_taker: u64,
+    },
+
+    /// Creates a stats account that InitEscrow, and every instruction that can close an escrow it opened, keep running totals in when
+    /// it's passed to them as one of the last two accounts, the global stats first and then the initializer's
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` Whoever pays for the stats account, for an initializer's stats the initializer themselves
+    /// 1. `[writable]` The stats PDA, seeds `[b"stats"]` for the global stats or `[b"stats", initializer]` for an initializer's
+    /// 2. `[]` The system program
+    InitStats {
+        /// Whether these are the signer's own stats rather than the global stats
+        per_initializer: bool,
+    },
+
+    /// First half of a front running resistant take. Reserves the escrow for the taker for `Escrow::REVEAL_WINDOW_SLOTS` against a
+    /// hash of what they'll take, and takes `Escrow::COMMIT_BOND` lamports from them until they reveal. A stale commit on the
+    /// escrow is replaced and its bond paid to the initializer
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The taker, pays the bond
+    /// 1. `[writable]` The escrow account holding the escrow info
+    /// 2. `[writable]` The initializer's main account, receives the bond of any stale commit
+    /// 3. `[]` The system program
+    CommitExchange {
+        /// `Escrow::commit_hash_of(taker, amount, salt)`, with the amount the taker will pass to RevealExchange
+        hash: [u8; 32],
+    },
+
+    /// Second half of a front running resistant take, settles the escrow like Exchange but only for the taker holding a live commit
+    /// whose hash matches, and hands their bond back
+    ///
+    /// Accounts expected: as Exchange, except the taker (account 0) has to be writable to get the bond back. There's no referral
+    /// and, with Token-2022, no transfer hook accounts
+    RevealExchange {
+        /// As Exchange's `amount`
+        amount: u64,
+        /// The salt the commit's hash was made with
+        salt: [u8; 32],
+    },
+
+    /// Rewrites an escrow from an older layout in the current one, growing the account and topping its rent up as needed, so
+    /// offers made before a program upgrade keep working. Nothing about the offer changes, so anyone can migrate any escrow.
+    /// Migrating an escrow that's already current does nothing
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` Whoever pays for the extra rent
+    /// 1. `[writable]` The escrow account
+    /// 2. `[]` The system program
+    Migrate,
+
+    /// Runs every check Exchange would with the same data and accounts, but stops short of moving anything, so a keeper can simulate
+    /// it to find out whether a take would go through. The taker's send account also has to hold the payment, which Exchange leaves to
+    /// the token program. On success the quote is logged and set as the return data, see `ExchangeQuote`
+    ///
+    /// Accounts expected: as Exchange
+    ValidateExchange {
+        /// As Exchange's
+        amount: u64,
+        valid_until_slot: Option<u64>,
+        referral_bps: u16,
+        offered_hook_accounts: u8,
+        payment_hook_accounts: u8,
+        transfer_authority: bool,
+    },
+
+    /// Hands a token account that was given to the PDA outside of any escrow back to its owner, tokens and all. That happens when a client
+    /// transfers a vault's ownership in one transaction and its InitEscrow then never lands. To be able to claim a vault back, set its
+    /// close authority to your orphan authority, the PDA `[b"orphan", owner]`, before handing it over. The escrow instructions refuse token
+    /// accounts with any close authority, and escrows opened before they did had no reason to carry that one, so a PDA held account with
+    /// it can't be backing an escrow. The close authority is cleared as the account goes back
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The owner the token account had
+    /// 1. `[writable]` The orphaned token account, owned by the PDA
+    /// 2. `[]` The token program, either token program works
+    /// 3. `[]` The PDA account
+    /// 4. `[]` The owner's orphan authority, the token account's close authority
+    RecoverOrphan,
+
+    /// Settles two opposite plain escrows against each other, one selling X for Y and the other Y for X, with no taker involved. Anyone can
+    /// submit the match. Each side has to give the other at least what it asks for, and whatever is left over in either vault is the spread,
+    /// which goes to the config's fee recipient. Both escrows close, each initializer getting their rent back
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` Whoever submits the match
+    /// 1. `[writable]` The first escrow account
+    /// 2. `[writable]` The first escrow's PDA held temp token account, of X
+    /// 3. `[writable]` The first escrow's initializer's main account
+    /// 4. `[writable]` The first escrow's initializer's token account for Y
+    /// 5. `[writable]` The second escrow account
+    /// 6. `[writable]` The second escrow's PDA held temp token account, of Y
+    /// 7. `[writable]` The second escrow's initializer's main account
+    /// 8. `[writable]` The second escrow's initializer's token account for X
+    /// 9. `[]` The token program
+    /// 10. `[]` The PDA account
+    /// 11. `[]` The config PDA
+    /// 12. `[writable]` The fee recipient's token account for X
+    /// 13. `[writable]` The fee recipient's token account for Y
+    ///
+    /// The global stats and the first and second initializers' stats accounts can be passed as the last three accounts, see InitStats
+    MatchOffers,
+
+    /// Starts a fixed price trade straight from the initializer's regular token account, with no temp account to create first. The
+    /// program creates the vault itself at the PDA with seeds `[b"vault", escrow]` and moves exactly `offered_amount` into it, the rest
+    /// of the balance stays where it is. From then on the escrow is a plain InitEscrow one, the vault being its temp token account
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The account of the person initializing the escrow, pays the vault's rent
+    /// 1. `[writable]` The initializer's token account holding the tokens they are offering
+    /// 2. `[]` The initializer's token account for the token they will receive
+    /// 3. `[writable]` The escrow account, it will hold all necessary info about the trade
+    /// 4. `[writable]` The vault PDA, seeds `[b"vault", escrow]`, not created yet
+    /// 5. `[]` The mint of the offered token
+    /// 6. `[]` The rent sysvar
+    /// 7. `[]` The system program
+    /// 8. `[]` The token program
+    /// 9. `[]` The config PDA, as for InitEscrow
+    ///
+    /// The global and the initializer's stats accounts can be passed as the last two accounts, see InitStats
+    InitEscrowFromAccount {
+        /// The amount party A expects to receive of token Y. Can't be 0
+        amount: u64,
+        /// The amount of token X moved into the vault. Can't be 0
+        offered_amount: u64,
+        /// Unix timestamp after which the offer can no longer be taken and may be cranked closed, 0 means it never expires
+        expires_at: i64,
+        /// Lamports taken from the escrow account's rent and paid to whoever cranks the escrow closed once it has expired
+        crank_bounty: u64,
+    },
+
+    /// Bids everything in the temp token account for any one NFT of a verified collection, the inverse of listing an NFT with InitEscrow.
+    /// Whoever holds a member of the collection can take it with AcceptBid, until then the bidder can Cancel it like any escrow
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The bidder
+    /// 1. `[writable]` Temporary token account holding the payment tokens, its ownership goes to the PDA. It can't have a close authority
+    /// 2. `[writable]` The escrow account, it will hold all necessary info about the bid
+    /// 3. `[]` The token program
+    ///
+    /// With `enforce_royalties` AcceptBid pays the NFT's creators their royalties out of the bid before the rest goes to the seller
+    InitBid {
+        /// Unix timestamp after which the bid can no longer be accepted and may be cranked closed, 0 means it never expires
+        expires_at: i64,
+        /// Lamports taken from the escrow account's rent and paid to whoever cranks the escrow closed once it has expired
+        crank_bounty: u64,
+        /// The collection's mint, which the NFT's metadata has to name as its verified collection
+        collection: Pubkey,
+        /// Optional trailing byte after `collection`
+        enforce_royalties: bool,
+    },
+
+    /// Sells one NFT of the bid's collection into a bid made with InitBid. The NFT goes to the bidder, the payment to the seller and the
+    /// rent of the vault and the escrow back to the bidder
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` The NFT's holder, selling it
+    /// 1. `[writable]` The seller's token account holding the NFT
+    /// 2. `[writable]` The seller's token account for the payment token
+    /// 3. `[writable]` The bidder's token account for the NFT
+    /// 4. `[writable]` The escrow account holding the bid
+    /// 5. `[writable]` The PDA's temp token account holding the payment
+    /// 6. `[writable]` The bidder's main account, gets the rent back
+    /// 7. `[]` The NFT's mint
+    /// 8. `[]` The NFT's Metaplex metadata account
+    /// 9. `[]` The token program
+    /// 10. `[]` The PDA account
+    /// 11. ..11+C `[writable]` A token account of each of the NFT's C creators for the payment token, in the metadata's order, when the
+    ///     bid enforces royalties
+    ///
+    /// Takes no stats accounts, InitBid doesn't count a bid among the open escrows in the first place
+    AcceptBid,
+
+    /// Grows an escrow account past its layout, so fields a later layout adds have room without another realloc. The new bytes are
+    /// zeroed and the initializer tops the rent up for them. The escrow has to be in the current layout, Migrate it first otherwise.
+    /// A longer escrow no longer matches a dataSize filter on `Escrow::LEN`
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The escrow's initializer, who pays for the extra rent
+    /// 1. `[writable]` The escrow account
+    /// 2. `[]` The system program
+    ExtendEscrow {
+        /// The account's new length in bytes, longer than it is now and by at most 10 KiB per instruction
+        new_len: u64,
+    },
+
+    /// Fills an offer the initializer signed off-chain, see `SignedOffer`, without an escrow ever being opened for it. The instruction
+    /// right before this one in the transaction has to be the ed25519 program verifying the initializer's signature of the offer's
+    /// message, see `signed_offer::ed25519_instruction`. The whole offer is taken at once, the taker's payment going to the initializer
+    /// and the offered tokens coming out of the initializer's account through the PDA as its delegate. The initializer withdraws every
+    /// signed offer from an account at once by revoking that delegation
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` The taker, who pays for the filled offer record
+    /// 1. `[writable]` The taker's token account for the token the initializer receives
+    /// 2. `[writable]` The taker's token account for the offered token
+    /// 3. `[writable]` The initializer's token account holding the offered tokens, the offer's `offered_token_account_pubkey`
+    /// 4. `[writable]` The initializer's token account that will receive the payment, the offer's `initializer_token_to_receive_account_pubkey`
+    /// 5. `[writable]` The filled offer record, seeds `[b"filled", initializer, nonce]`
+    /// 6. `[]` The instructions sysvar
+    /// 7. `[]` The token program
+    /// 8. `[]` The PDA account
+    /// 9. `[]` The system program
+    FillSignedOffer {
+        /// The offer as the initializer signed it
+        offer: SignedOffer,
+    },
+
+    /// Creates the offer counter, after which every InitEscrow passed it gives its offer the next number, starting from 1. The number
+    /// is stored as the escrow's `offer_id` and logged with its events, `client::EscrowFilter::offer_id` finds the escrow it belongs to
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` Whoever pays for the counter
+    /// 1. `[writable]` The offer counter PDA, seeds `[b"offer_counter"]`
+    /// 2. `[]` The system program
+    InitOfferCounter,
+}
+
+impl EscrowInstruction {
+    /// The version byte instruction data can start with, ahead of the tag. Tags are all below it, so data from before the version
+    /// byte, which starts straight with the tag, still reads the same. The bytes above it are kept for later versions of the encoding
+    pub const VERSION: u8 = 0x80;
+
+    /// Unpacks a byte buffer into a [EscrowInstruction](enum.EscrowInstruction.html). Anything that isn't exactly one instruction
+    /// is turned down with `InvalidInstructionData`: an unknown version or tag, a field cut short, a flag other than 0 or 1 or
+    /// bytes left over after the last field
+    pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        let input = match input.split_first() {
+            Some((&EscrowInstruction::VERSION, versioned)) => versioned,
+            Some((version, _)) if *version > EscrowInstruction::VERSION => return Err(InvalidInstructionData.into()),
+            _ => input,
+        };
+        let mut data = Decoder(input);
+
+        let instruction = match data.u8()? {
+            0 => {
+                let amount = data.u64()?;
+                let expires_at = data.i64()?;
+                let crank_bounty = data.u64()?;
+                let start_time = data.optional(Decoder::i64)?;
+                let oracle = data
+                    .optional(|data| Ok((data.pubkey()?, data.u16()?)))?
+                    .filter(|(oracle_pubkey, _)| *oracle_pubkey != Pubkey::default());
+                let memo = data.optional(|data| Ok(Memo(data.array()?)))?;
+                if memo.map_or(false, |memo| memo.0 != [0; Memo::LEN] && memo.as_str().is_none()) {
+                    return Err(EscrowError::InvalidMemo.into());
+                }
+                let accepted_mints = data.optional(Self::unpack_accepted_mints)?.unwrap_or_default();
+                let reusable = data.optional(Decoder::bool)?.unwrap_or(false);
+                let cancel_penalty = data
+                    .optional(|data| Ok((data.u16()?, data.pubkey()?)))?
+                    .filter(|(penalty_bps, _)| *penalty_bps != 0);
+                let lamports = data.optional(Decoder::u64)?.unwrap_or(0);
+                let enforce_royalties = data.optional(Decoder::bool)?.unwrap_or(false);
+                let nonce = data.optional(Decoder::u64)?.filter(|nonce| *nonce != 0);
+                let max_per_taker = data.optional(Decoder::u64)?.unwrap_or(0);
+                Self::InitEscrow {
+                    amount,
+                    expires_at,
+                    crank_bounty,
+                    start_time,
+                    oracle,
+                    memo,
+                    accepted_mints,
+                    reusable,
+                    cancel_penalty,
+                    lamports,
+                    enforce_royalties,
+                    nonce,
+                    max_per_taker,
+                }
+            },
+            1 => {
+                let (amount, valid_until_slot, referral_bps, offered_hook_accounts, payment_hook_accounts, transfer_authority) = Self::unpack_exchange(&mut data)?;
+                Self::Exchange {
+                    amount,
+                    valid_until_slot,
+                    referral_bps,
+                    offered_hook_accounts,
+                    payment_hook_accounts,
+                    transfer_authority,
+                }
+            },
+            2 => Self::CrankExpired,
+            3 => Self::InitDelegatedEscrow {
+                amount: data.u64()?,
+                offered_amount: data.u64()?,
+                expires_at: data.i64()?,
+                crank_bounty: data.u64()?,
+            },
+            4 => Self::InitHistory {
+                max_depth: data.u32()?,
+                max_buffer_size: data.u32()?,
+            },
+            5 => Self::Cancel {
+                hook_accounts: data.optional(Decoder::u8)?.unwrap_or(0),
+            },
+            6 => Self::InitSubscription {
+                installment_amount: data.u64()?,
+                interval_slots: data.u64()?,
+            },
+            7 => Self::Subscribe {
+                amount: data.u64()?,
+            },
+            8 => Self::ClaimInstallment,
+            9 => Self::CancelSubscription,
+            10 => Self::TopUp {
+                additional_amount: data.u64()?,
+            },
+            11 => {
+                let (max_referral_bps, creation_deposit, fee_tiers) = Self::unpack_config(&mut data)?;
+                Self::InitConfig {
+                    max_referral_bps,
+                    creation_deposit,
+                    fee_tiers,
+                }
+            },
+            12 => {
+                let (max_referral_bps, creation_deposit, fee_tiers) = Self::unpack_config(&mut data)?;
+                Self::UpdateConfig {
+                    max_referral_bps,
+                    creation_deposit,
+                    fee_tiers,
+                }
+            },
+            13 => Self::DepositTakerSide {
+                amount: data.u64()?,
+            },
+            14 => Self::Settle,
+            15 => Self::Withdraw,
+            16 => Self::InitRateEscrow {
+                rate_numerator: data.u64()?,
+                rate_denominator: data.u64()?,
+                expires_at: data.i64()?,
+                crank_bounty: data.u64()?,
+                max_per_taker: data.optional(Decoder::u64)?.unwrap_or(0),
+            },
+            17 => Self::InitStats {
+                per_initializer: data.bool()?,
+            },
+            18 => Self::CommitExchange {
+                hash: data.array()?,
+            },
+            19 => Self::RevealExchange {
+                amount: data.u64()?,
+                salt: data.array()?,
+            },
+            20 => Self::Migrate,
+            21 => {
+                let (amount, valid_until_slot, referral_bps, offered_hook_accounts, payment_hook_accounts, transfer_authority) = Self::unpack_exchange(&mut data)?;
+                Self::ValidateExchange {
+                    amount,
+                    valid_until_slot,
+                    referral_bps,
+                    offered_hook_accounts,
+                    payment_hook_accounts,
+                    transfer_authority,
+                }
+            },
+            22 => Self::RecoverOrphan,
+            23 => Self::MatchOffers,
+            24 => Self::InitEscrowFromAccount {
+                amount: data.u64()?,
+                offered_amount: data.u64()?,
+                expires_at: data.i64()?,
+                crank_bounty: data.u64()?,
+            },
+            25 => Self::InitBid {
+                expires_at: data.i64()?,
+                crank_bounty: data.u64()?,
+                collection: data.pubkey()?,
+                enforce_royalties: data.optional(Decoder::bool)?.unwrap_or(false),
+            },
+            26 => Self::AcceptBid,
+            27 => Self::ExtendEscrow {
+                new_len: data.u64()?,
+            },
+            28 => Self::FillSignedOffer {
+                offer: SignedOffer::unpack(data.take(SignedOffer::LEN)?)?,
+            },
+            29 => Self::InitOfferCounter,
+            _ => return Err(InvalidInstructionData.into()),
+        };
+        data.finish()?;
+        Ok(instruction)
+    }
+
+    /// The data Exchange and ValidateExchange share: amount, then the optional valid_until_slot, referral_bps, hook account counts and
+    /// transfer_authority
+    fn unpack_exchange(data: &mut Decoder) -> Result<(u64, Option<u64>, u16, u8, u8, bool), ProgramError> {
+        let amount = data.u64()?;
+        let valid_until_slot = data.optional(Decoder::u64)?.filter(|slot| *slot != 0);
+        let referral_bps = data.optional(Decoder::u16)?.unwrap_or(0);
+        let (offered_hook_accounts, payment_hook_accounts) = data.optional(|data| Ok((data.u8()?, data.u8()?)))?.unwrap_or((0, 0));
+        let transfer_authority = data.optional(Decoder::bool)?.unwrap_or(false);
+        Ok((amount, valid_until_slot, referral_bps, offered_hook_accounts, payment_hook_accounts, transfer_authority))
+    }
+
+    /// The data InitConfig and UpdateConfig share: `max_referral_bps`, then the optional creation deposit and fee schedule
+    #[allow(clippy::type_complexity)]
+    fn unpack_config(data: &mut Decoder) -> Result<(u16, Option<(u64, u64, Pubkey)>, Option<Vec<FeeTier>>), ProgramError> {
+        let max_referral_bps = data.u16()?;
+        let creation_deposit = data.optional(|data| Ok((data.u64()?, data.u64()?, data.pubkey()?)))?;
+        let fee_tiers = data.optional(|data| {
+            let count = data.u8()? as usize;
+            if count > Config::MAX_FEE_TIERS {
+                return Err(InvalidInstructionData.into());
+            }
+            (0..count)
+                .map(|_| {
+                    Ok(FeeTier {
+                        min_age_slots: data.u64()?,
+                        fee_bps: data.u16()?,
+                    })
+                })
+                .collect()
+        })?;
+        Ok((max_referral_bps, creation_deposit, fee_tiers))
+    }
+
+    /// A count and that many (mint, amount) pairs, the zeroed pairs being padding
+    fn unpack_accepted_mints(data: &mut Decoder) -> Result<Vec<AcceptedMint>, ProgramError> {
+        let count = data.u8()? as usize;
+        if count > Escrow::MAX_ACCEPTED_MINTS {
+            return Err(InvalidInstructionData.into());
+        }
+        let mut accepted_mints = Vec::with_capacity(count);
+        for _pair in 0..count {
+            let accepted = AcceptedMint {
+                mint: data.pubkey()?,
+                amount: data.u64()?,
+            };
+            if accepted.mint != Pubkey::default() {
+                accepted_mints.push(accepted);
+            }
+        }
+        Ok(accepted_mints)
+    }
+}
+
+/// Reads instruction data front to back, each read failing if the data runs out before it and `finish` if any is left after the last
+struct Decoder<'a>(&'a [u8]);
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ProgramError> {
+        if self.0.len() < len {
+            return Err(InvalidInstructionData.into());
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], ProgramError> {
+        self.take(N)?.try_into().map_err(|_| InvalidInstructionData.into())
+    }
+
+    fn u8(&mut self) -> Result<u8, ProgramError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ProgramError> {
+        self.array().map(u16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, ProgramError> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> Result<u64, ProgramError> {
+        self.array().map(u64::from_le_bytes)
+    }
+
+    fn i64(&mut self) -> Result<i64, ProgramError> {
+        self.array().map(i64::from_le_bytes)
+    }
+
+    fn bool(&mut self) -> Result<bool, ProgramError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(InvalidInstructionData.into()),
+        }
+    }
+
+    fn pubkey(&mut self) -> Result<Pubkey, ProgramError> {
+        self.array().map(Pubkey::new_from_array)
+    }
+
+    /// An optional trailing field, read with `read` unless the data has already ended
+    fn optional<T>(&mut self, read: impl FnOnce(&mut Self) -> Result<T, ProgramError>) -> Result<Option<T>, ProgramError> {
+        if self.0.is_empty() {
+            return Ok(None);
+        }
+        read(self).map(Some)
+    }
+
+    fn finish(&self) -> Result<(), ProgramError> {
+        if !self.0.is_empty() {
+            return Err(InvalidInstructionData.into());
+        }
+        Ok(())
+    }
+}
+
+/// Creates an `InitEscrow` instruction for a fixed price escrow with a single signing initializer
+#[allow(clippy::too_many_arguments)]
+pub fn init_escrow(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    temp_token_account: &Pubkey,
+    token_to_receive_account: &Pubkey,
+    escrow_account: &Pubkey,
+    amount: u64,
+    expires_at: i64,
+    crank_bounty: u64,
+    start_time: Option<i64>,
+    memo: Option<Memo>,
+) -> Instruction {
+    let mut data = vec![EscrowInstruction::VERSION, 0];
+    data.extend_from_slice(&amount.to_le_bytes());
+    data.extend_from_slice(&expires_at.to_le_bytes());
+    data.extend_from_slice(&crank_bounty.to_le_bytes());
+    if start_time.is_some() || memo.is_some() {
+        data.extend_from_slice(&start_time.unwrap_or(0).to_le_bytes());
+    }
+    if let Some(memo) = memo {
+        data.extend_from_slice(&[0; 34]); // no oracle
+        data.extend_from_slice(&memo.0);
+    }
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*initializer, true),
+            AccountMeta::new(*temp_token_account, false),
+            AccountMeta::new_readonly(*token_to_receive_account, false),
+            AccountMeta::new(*escrow_account, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(Pubkey::find_program_address(&[b"config"], program_id).0, false),
+        ],
+        data,
+    }
+}
+
+/// Creates an `InitEscrow` instruction for a fixed price escrow at the address `find_escrow_address` gives for `nonce`, which has to be non
+/// zero. The initializer pays for the escrow account, so there's no account to create beforehand
+#[allow(clippy::too_many_arguments)]
+pub fn init_escrow_with_nonce(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    temp_token_account: &Pubkey,
+    token_to_receive_account: &Pubkey,
+    amount: u64,
+    expires_at: i64,
+    crank_bounty: u64,
+    nonce: u64,
+) -> Instruction {
+    let mut data = vec![EscrowInstruction::VERSION, 0];
+    data.extend_from_slice(&amount.to_le_bytes());
+    data.extend_from_slice(&expires_at.to_le_bytes());
+    data.extend_from_slice(&crank_bounty.to_le_bytes());
+    data.extend_from_slice(&0i64.to_le_bytes()); // no start time
+    data.extend_from_slice(&[0; 34]); // no oracle
+    data.extend_from_slice(&[0; Memo::LEN]);
+    data.push(0); // no accepted mints
+    data.push(0); // not reusable
+    data.extend_from_slice(&[0; 34]); // no cancel penalty
+    data.extend_from_slice(&0u64.to_le_bytes()); // no lamports
+    data.push(0); // no royalties
+    data.extend_from_slice(&nonce.to_le_bytes());
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*initializer, true),
+            AccountMeta::new(*temp_token_account, false),
+            AccountMeta::new_readonly(*token_to_receive_account, false),
+            AccountMeta::new(find_escrow_address(initializer, nonce, program_id), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(Pubkey::find_program_address(&[b"config"], program_id).0, false),
+        ],
+        data,
+    }
+}
+
+/// Creates an `InitEscrowFromAccount` instruction offering `offered_amount` out of the initializer's token account
+#[allow(clippy::too_many_arguments)]
+pub fn init_escrow_from_account(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    offered_token_account: &Pubkey,
+    token_to_receive_account: &Pubkey,
+    escrow_account: &Pubkey,
+    offered_mint: &Pubkey,
+    amount: u64,
+    offered_amount: u64,
+    expires_at: i64,
+    crank_bounty: u64,
+) -> Instruction {
+    let mut data = vec![EscrowInstruction::VERSION, 24];
+    data.extend_from_slice(&amount.to_le_bytes());
+    data.extend_from_slice(&offered_amount.to_le_bytes());
+    data.extend_from_slice(&expires_at.to_le_bytes());
+    data.extend_from_slice(&crank_bounty.to_le_bytes());
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*initializer, true),
+            AccountMeta::new(*offered_token_account, false),
+            AccountMeta::new_readonly(*token_to_receive_account, false),
+            AccountMeta::new(*escrow_account, false),
+            AccountMeta::new(Pubkey::find_program_address(&[b"vault", escrow_account.as_ref()], program_id).0, false),
+            AccountMeta::new_readonly(*offered_mint, false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(Pubkey::find_program_address(&[b"config"], program_id).0, false),
+        ],
+        data,
+    }
+}
+
+/// Creates an `Exchange` instruction taking a plain escrow, without any of the optional trailing accounts
+#[allow(clippy::too_many_arguments)]
+pub fn exchange(
+    program_id: &Pubkey,
+    taker: &Pubkey,
+    taker_send_token_account: &Pubkey,
+    taker_receive_token_account: &Pubkey,
+    pdas_temp_token_account: &Pubkey,
+    initializer: &Pubkey,
+    initializer_token_to_receive_account: &Pubkey,
+    escrow_account: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    let (pda, _bump_seed) = Pubkey::find_program_address(&[b"escrow"], program_id);
+    let mut data = vec![EscrowInstruction::VERSION, 1];
+    data.extend_from_slice(&amount.to_le_bytes());
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*taker, true),
+            AccountMeta::new(*taker_send_token_account, false),
+            AccountMeta::new(*taker_receive_token_account, false),
+            AccountMeta::new(*pdas_temp_token_account, false),
+            AccountMeta::new(*initializer, false),
+            AccountMeta::new(*initializer_token_to_receive_account, false),
+            AccountMeta::new(*escrow_account, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(pda, false),
+        ],
+        data,
+    }
+}
+
+/// Creates a `FillSignedOffer` instruction taking `offer`, to be sent right after `signed_offer::ed25519_instruction` for its signature
+pub fn fill_signed_offer(
+    program_id: &Pubkey,
+    offer: &SignedOffer,
+    taker: &Pubkey,
+    taker_send_token_account: &Pubkey,
+    taker_receive_token_account: &Pubkey,
+) -> Instruction {
+    let (pda, _bump_seed) = Pubkey::find_program_address(&[b"escrow"], program_id);
+    let mut data = vec![0; 2 + SignedOffer::LEN];
+    data[..2].copy_from_slice(&[EscrowInstruction::VERSION, 28]);
+    offer.pack_into_slice(&mut data[2..]);
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*taker, true),
+            AccountMeta::new(*taker_send_token_account, false),
+            AccountMeta::new(*taker_receive_token_account, false),
+            AccountMeta::new(offer.offered_token_account_pubkey, false),
+            AccountMeta::new(offer.initializer_token_to_receive_account_pubkey, false),
+            AccountMeta::new(find_filled_offer_address(&offer.initializer_pubkey, offer.nonce, program_id), false),
+            AccountMeta::new_readonly(sysvar::instructions::id(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(pda, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data,
+    }
+}
+
+/// Creates an `InitOfferCounter` instruction, `payer` paying for the counter
+pub fn init_offer_counter(program_id: &Pubkey, payer: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new(find_offer_counter_address(program_id), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: vec![EscrowInstruction::VERSION, 29],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(tag: u8, fields: &[&[u8]]) -> Vec<u8> {
+        let mut data = vec![tag];
+        for field in fields {
+            data.extend_from_slice(field);
+        }
+        data
+    }
+
+    fn key(byte: u8) -> Pubkey {
+        Pubkey::new_from_array([byte; 32])
+    }
+
+    /// Every instruction with all of its optional trailing fields, the lengths after the tag its data may also stop at and what the
+    /// full data decodes to
+    fn every_instruction() -> Vec<(Vec<u8>, Vec<usize>, EscrowInstruction)> {
+        let memo = Memo::new("deal #1").unwrap();
+        let offer = SignedOffer {
+            initializer_pubkey: key(1),
+            offered_token_account_pubkey: key(2),
+            initializer_token_to_receive_account_pubkey: key(3),
+            offered_amount: 100,
+            expected_amount: 50,
+            expires_at: 1_600_000_000,
+            nonce: 7,
+        };
+        let mut packed_offer = [0; SignedOffer::LEN];
+        offer.pack_into_slice(&mut packed_offer);
+        let exchange_data = |tag| encode(tag, &[&5u64.to_le_bytes(), &9u64.to_le_bytes(), &25u16.to_le_bytes(), &[1, 2], &[1]]);
+        let exchange_lens = vec![8, 16, 18, 20];
+        let config_data = |tag| {
+            encode(tag, &[
+                &50u16.to_le_bytes(),
+                &1_000u64.to_le_bytes(),
+                &10u64.to_le_bytes(),
+                key(4).as_ref(),
+                &[2],
+                &0u64.to_le_bytes(),
+                &30u16.to_le_bytes(),
+                &100u64.to_le_bytes(),
+                &10u16.to_le_bytes(),
+            ])
+        };
+        let fee_tiers = vec![FeeTier { min_age_slots: 0, fee_bps: 30 }, FeeTier { min_age_slots: 100, fee_bps: 10 }];
+
+        vec![
+            (
+                encode(0, &[
+                    &50u64.to_le_bytes(),
+                    &1_700_000_000i64.to_le_bytes(),
+                    &1_000u64.to_le_bytes(),
+                    &1_600_000_000i64.to_le_bytes(),
+                    key(5).as_ref(),
+                    &100u16.to_le_bytes(),
+                    &memo.0,
+                    &[2],
+                    key(6).as_ref(),
+                    &60u64.to_le_bytes(),
+                    key(7).as_ref(),
+                    &70u64.to_le_bytes(),
+                    &[1],
+                    &500u16.to_le_bytes(),
+                    key(8).as_ref(),
+                    &3u64.to_le_bytes(),
+                    &[1],
+                    &11u64.to_le_bytes(),
+                    &40u64.to_le_bytes(),
+                ]),
+                vec![24, 32, 66, 130, 211, 212, 246, 254, 255, 263],
+                EscrowInstruction::InitEscrow {
+                    amount: 50,
+                    expires_at: 1_700_000_000,
+                    crank_bounty: 1_000,
+                    start_time: Some(1_600_000_000),
+                    oracle: Some((key(5), 100)),
+                    memo: Some(memo),
+                    accepted_mints: vec![AcceptedMint { mint: key(6), amount: 60 }, AcceptedMint { mint: key(7), amount: 70 }],
+                    reusable: true,
+                    cancel_penalty: Some((500, key(8))),
+                    lamports: 3,
+                    enforce_royalties: true,
+                    nonce: Some(11),
+                    max_per_taker: 40,
+                },
+            ),
+            (
+                exchange_data(1),
+                exchange_lens.clone(),
+                EscrowInstruction::Exchange {
+                    amount: 5,
+                    valid_until_slot: Some(9),
+                    referral_bps: 25,
+                    offered_hook_accounts: 1,
+                    payment_hook_accounts: 2,
+                    transfer_authority: true,
+                },
+            ),
+            (encode(2, &[]), vec![], EscrowInstruction::CrankExpired),
+            (
+                encode(3, &[&50u64.to_le_bytes(), &10u64.to_le_bytes(), &1_700_000_000i64.to_le_bytes(), &1_000u64.to_le_bytes()]),
+                vec![],
+                EscrowInstruction::InitDelegatedEscrow { amount: 50, offered_amount: 10, expires_at: 1_700_000_000, crank_bounty: 1_000 },
+            ),
+            (
+                encode(4, &[&14u32.to_le_bytes(), &64u32.to_le_bytes()]),
+                vec![],
+                EscrowInstruction::InitHistory { max_depth: 14, max_buffer_size: 64 },
+            ),
+            (encode(5, &[&[3]]), vec![0], EscrowInstruction::Cancel { hook_accounts: 3 }),
+            (
+                encode(6, &[&10u64.to_le_bytes(), &100u64.to_le_bytes()]),
+                vec![],
+                EscrowInstruction::InitSubscription { installment_amount: 10, interval_slots: 100 },
+            ),
+            (encode(7, &[&30u64.to_le_bytes()]), vec![], EscrowInstruction::Subscribe { amount: 30 }),
+            (encode(8, &[]), vec![], EscrowInstruction::ClaimInstallment),
+            (encode(9, &[]), vec![], EscrowInstruction::CancelSubscription),
+            (encode(10, &[&20u64.to_le_bytes()]), vec![], EscrowInstruction::TopUp { additional_amount: 20 }),
+            (
+                config_data(11),
+                vec![2, 50],
+                EscrowInstruction::InitConfig {
+                    max_referral_bps: 50,
+                    creation_deposit: Some((1_000, 10, key(4))),
+                    fee_tiers: Some(fee_tiers.clone()),
+                },
+            ),
+            (
+                config_data(12),
+                vec![2, 50],
+                EscrowInstruction::UpdateConfig {
+                    max_referral_bps: 50,
+                    creation_deposit: Some((1_000, 10, key(4))),
+                    fee_tiers: Some(fee_tiers),
+                },
+            ),
+            (encode(13, &[&40u64.to_le_bytes()]), vec![], EscrowInstruction::DepositTakerSide { amount: 40 }),
+            (encode(14, &[]), vec![], EscrowInstruction::Settle),
+            (encode(15, &[]), vec![], EscrowInstruction::Withdraw),
+            (
+                encode(16, &[
+                    &3u64.to_le_bytes(),
+                    &2u64.to_le_bytes(),
+                    &1_700_000_000i64.to_le_bytes(),
+                    &1_000u64.to_le_bytes(),
+                    &40u64.to_le_bytes(),
+                ]),
+                vec![32],
+                EscrowInstruction::InitRateEscrow {
+                    rate_numerator: 3,
+                    rate_denominator: 2,
+                    expires_at: 1_700_000_000,
+                    crank_bounty: 1_000,
+                    max_per_taker: 40,
+                },
+            ),
+            (encode(17, &[&[1]]), vec![], EscrowInstruction::InitStats { per_initializer: true }),
+            (encode(18, &[&[9; 32]]), vec![], EscrowInstruction::CommitExchange { hash: [9; 32] }),
+            (
+                encode(19, &[&5u64.to_le_bytes(), &[8; 32]]),
+                vec![],
+                EscrowInstruction::RevealExchange { amount: 5, salt: [8; 32] },
+            ),
+            (encode(20, &[]), vec![], EscrowInstruction::Migrate),
+            (
+                exchange_data(21),
+                exchange_lens,
+                EscrowInstruction::ValidateExchange {
+                    amount: 5,
+                    valid_until_slot: Some(9),
+                    referral_bps: 25,
+                    offered_hook_accounts: 1,
+                    payment_hook_accounts: 2,
+                    transfer_authority: true,
+                },
+            ),
+            (encode(22, &[]), vec![], EscrowInstruction::RecoverOrphan),
+            (encode(23, &[]), vec![], EscrowInstruction::MatchOffers),
+            (
+                encode(24, &[&50u64.to_le_bytes(), &10u64.to_le_bytes(), &1_700_000_000i64.to_le_bytes(), &1_000u64.to_le_bytes()]),
+                vec![],
+                EscrowInstruction::InitEscrowFromAccount { amount: 50, offered_amount: 10, expires_at: 1_700_000_000, crank_bounty: 1_000 },
+            ),
+            (
+                encode(25, &[&1_700_000_000i64.to_le_bytes(), &1_000u64.to_le_bytes(), key(9).as_ref(), &[1]]),
+                vec![48],
+                EscrowInstruction::InitBid { expires_at: 1_700_000_000, crank_bounty: 1_000, collection: key(9), enforce_royalties: true },
+            ),
+            (encode(26, &[]), vec![], EscrowInstruction::AcceptBid),
+            (encode(27, &[&1_024u64.to_le_bytes()]), vec![], EscrowInstruction::ExtendEscrow { new_len: 1_024 }),
+            (encode(28, &[&packed_offer]), vec![], EscrowInstruction::FillSignedOffer { offer }),
+            (encode(29, &[]), vec![], EscrowInstruction::InitOfferCounter),
+        ]
+    }
+
+    fn versioned(data: &[u8]) -> Vec<u8> {
+        let mut versioned = vec![EscrowInstruction::VERSION];
+        versioned.extend_from_slice(data);
+        versioned
+    }
+
+    fn invalid_data() -> Result<EscrowInstruction, ProgramError> {
+        Err(EscrowError::InvalidInstructionData.into())
+    }
+
+    #[test]
+    fn every_instruction_decodes_with_or_without_the_version_byte() {
+        let instructions = every_instruction();
+        assert_eq!(instructions.len(), 30);
+        for (tag, (data, _early_lens, instruction)) in instructions.into_iter().enumerate() {
+            assert_eq!(data[0] as usize, tag);
+            assert_eq!(EscrowInstruction::unpack(&data).as_ref(), Ok(&instruction));
+            assert_eq!(EscrowInstruction::unpack(&versioned(&data)), Ok(instruction));
+        }
+    }
+
+    #[test]
+    fn data_may_only_stop_where_an_optional_field_starts() {
+        for (data, early_lens, _instruction) in every_instruction() {
+            for len in 0..data.len() - 1 {
+                let cut = &data[..1 + len];
+                if early_lens.contains(&len) {
+                    assert!(EscrowInstruction::unpack(cut).is_ok(), "tag {} stopping after {} bytes", data[0], len);
+                } else {
+                    assert_eq!(EscrowInstruction::unpack(cut), invalid_data(), "tag {} cut to {} bytes", data[0], len);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bytes_after_the_last_field_are_rejected() {
+        for (mut data, _early_lens, _instruction) in every_instruction() {
+            data.push(0);
+            assert_eq!(EscrowInstruction::unpack(&data), invalid_data(), "tag {}", data[0]);
+            assert_eq!(EscrowInstruction::unpack(&versioned(&data)), invalid_data(), "tag {}", data[0]);
+        }
+    }
+
+    #[test]
+    fn unknown_tags_and_versions_are_rejected() {
+        assert_eq!(EscrowInstruction::unpack(&[]), invalid_data());
+        assert_eq!(EscrowInstruction::unpack(&[EscrowInstruction::VERSION]), invalid_data());
+        assert_eq!(EscrowInstruction::unpack(&[EscrowInstruction::VERSION, EscrowInstruction::VERSION, 2]), invalid_data());
+        for tag in 30..EscrowInstruction::VERSION {
+            assert_eq!(EscrowInstruction::unpack(&[tag]), invalid_data());
+            assert_eq!(EscrowInstruction::unpack(&[EscrowInstruction::VERSION, tag]), invalid_data());
+        }
+        for version in EscrowInstruction::VERSION + 1..=u8::MAX {
+            assert_eq!(EscrowInstruction::unpack(&[version, 2]), invalid_data());
+        }
+    }
+
+    #[test]
+    fn flags_and_counts_out_of_range_are_rejected() {
+        assert_eq!(EscrowInstruction::unpack(&[17, 2]), invalid_data());
+        assert_eq!(EscrowInstruction::unpack(&encode(1, &[&5u64.to_le_bytes(), &[0; 8], &[0; 2], &[0, 0], &[2]])), invalid_data());
+        assert_eq!(EscrowInstruction::unpack(&encode(25, &[&[0; 16], &[0; 32], &[2]])), invalid_data());
+
+        let init_escrow = |accepted_mints: &[u8]| encode(0, &[&50u64.to_le_bytes(), &[0; 16], &[0; 8], &[0; 34], &[0; Memo::LEN], accepted_mints]);
+        let too_many_mints = [&[5][..], &[0; 5 * AcceptedMint::LEN]].concat();
+        assert_eq!(EscrowInstruction::unpack(&init_escrow(&too_many_mints)), invalid_data());
+        assert_eq!(EscrowInstruction::unpack(&init_escrow(&[0, 2])), invalid_data()); // reusable
+
+        let too_many_tiers = [&[5][..], &[0; 5 * FeeTier::LEN]].concat();
+        assert_eq!(EscrowInstruction::unpack(&encode(11, &[&[0; 50], &too_many_tiers])), invalid_data());
+    }
+
+    #[test]
+    fn zeroed_optional_fields_read_as_unset() {
+        let data = encode(0, &[
+            &50u64.to_le_bytes(),
+            &[0; 24],
+            &[0; 34],
+            &[0; Memo::LEN],
+            &[1],
+            &[0; AcceptedMint::LEN],
+            &[0],
+            &[0; 34],
+            &[0; 8],
+            &[0],
+            &[0; 8],
+        ]);
+        assert_eq!(
+            EscrowInstruction::unpack(&data),
+            Ok(EscrowInstruction::InitEscrow {
+                amount: 50,
+                expires_at: 0,
+                crank_bounty: 0,
+                start_time: Some(0),
+                oracle: None,
+                memo: Some(Memo::default()),
+                accepted_mints: Vec::new(),
+                reusable: false,
+                cancel_penalty: None,
+                lamports: 0,
+                enforce_royalties: false,
+                nonce: None,
+                max_per_taker: 0,
+            })
+        );
+
+        let mut memo = [0; Memo::LEN];
+        memo[0] = 0xff;
+        let bad_memo = encode(0, &[&50u64.to_le_bytes(), &[0; 24], &[0; 34], &memo]);
+        assert_eq!(EscrowInstruction::unpack(&bad_memo), Err(EscrowError::InvalidMemo.into()));
+
+        let exchange = encode(1, &[&5u64.to_le_bytes(), &[0; 8]]);
+        assert!(matches!(EscrowInstruction::unpack(&exchange), Ok(EscrowInstruction::Exchange { valid_until_slot: None, .. })));
+    }
+}
@@ -1,8 +1,20 @@
-pub mod error;
-pub mod instruction;
-pub mod processor;
-pub mod state;
-
-
-#[cfg(not(feature = "no-entrypoint"))]
+pub mod client;
+pub mod error;
+pub mod event;
+pub mod history;
+#[cfg(feature = "idl")]
+pub mod idl;
+pub mod instruction;
+pub mod metadata;
+pub mod oracle;
+pub mod processor;
+pub mod signed_offer;
+pub mod state;
+pub mod strategy;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils;
+pub mod utils;
+
+
+#[cfg(not(feature = "no-entrypoint"))]
 pub mod entrypoint;
\ No newline at end of file
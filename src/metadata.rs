@@ -0,0 +1,163 @@
+use std::convert::TryInto;
+
+use solana_program::{
+    account_info::AccountInfo,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+use crate::error::EscrowError;
+
+/// The Metaplex Token Metadata program, whose metadata accounts say which collection an NFT belongs to
+pub mod mpl_token_metadata {
+    solana_program::declare_id!("metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s");
+}
+
+// the account's first byte says what it is, a v1 metadata account is 4
+const KEY_METADATA_V1: u8 = 4;
+const CREATOR_LEN: usize = 34;
+const MAX_SHARE: u64 = 100;
+const MAX_BPS: u64 = 10_000;
+
+/// The collection an NFT's metadata names, only to be trusted once the collection's authority has verified it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Collection {
+    pub verified: bool,
+    pub key: Pubkey,
+}
+
+/// One of the creators an NFT's royalties are split between, `share` being their percentage of them
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Creator {
+    pub address: Pubkey,
+    pub verified: bool,
+    pub share: u8,
+}
+
+/// The parts of a Metaplex metadata account we read
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metadata {
+    pub mint: Pubkey,
+    /// The royalty on every sale, in bps of the price
+    pub seller_fee_basis_points: u16,
+    pub creators: Vec<Creator>,
+    pub collection: Option<Collection>,
+}
+
+impl Metadata {
+    /// Reads the metadata of `mint`, checking the account really is its metadata PDA
+    pub fn load(metadata_account: &AccountInfo, mint: &Pubkey) -> Result<Metadata, ProgramError> {
+        if *metadata_account.owner != mpl_token_metadata::id() || *metadata_account.key != find_metadata_address(mint) {
+            return Err(EscrowError::InvalidMetadataAccount.into());
+        }
+
+        let metadata = Metadata::unpack(&metadata_account.data.borrow()).ok_or(EscrowError::InvalidMetadataAccount)?;
+        if metadata.mint != *mint {
+            return Err(EscrowError::InvalidMetadataAccount.into());
+        }
+        Ok(metadata)
+    }
+
+    /// Whether the NFT is a verified member of `collection`, an unverified claim counts for nothing as anyone can make it
+    pub fn is_verified_member_of(&self, collection: &Pubkey) -> bool {
+        self.collection.map_or(false, |member_of| member_of.verified && member_of.key == *collection)
+    }
+
+    /// What each creator is owed out of a sale for `price`, in the metadata's order. Each share is rounded down, so the seller keeps
+    /// any dust, and a seller fee above 100% is capped at the whole price
+    pub fn royalties(&self, price: u64) -> Vec<u64> {
+        let seller_fee = price as u128 * (self.seller_fee_basis_points as u64).min(MAX_BPS) as u128 / MAX_BPS as u128;
+        self.creators
+            .iter()
+            .map(|creator| (seller_fee * creator.share as u128 / MAX_SHARE as u128) as u64)
+            .collect()
+    }
+
+    // the account is borsh encoded: key, update authority, mint, then name, symbol and uri as length prefixed strings, the seller fee,
+    // an optional list of creators, two bools, the optional edition nonce and token standard and finally the optional collection
+    fn unpack(data: &[u8]) -> Option<Metadata> {
+        let mut reader = Reader(data);
+        if reader.u8()? != KEY_METADATA_V1 {
+            return None;
+        }
+        reader.take(32)?; // update authority
+        let mint = Pubkey::new(reader.take(32)?);
+        for _string in 0..3 {
+            let len = reader.u32()? as usize;
+            reader.take(len)?;
+        }
+        let seller_fee_basis_points = reader.u16()?;
+        let mut creators = Vec::new();
+        if reader.option()? {
+            let count = reader.u32()? as usize;
+            for creator in reader.take(count.checked_mul(CREATOR_LEN)?)?.chunks_exact(CREATOR_LEN) {
+                creators.push(Creator {
+                    address: Pubkey::new(&creator[..32]),
+                    verified: creator[32] != 0,
+                    share: creator[33],
+                });
+            }
+        }
+        reader.take(2)?; // primary sale happened, is mutable
+        for _optional_byte in 0..2 {
+            if reader.option()? {
+                reader.take(1)?; // edition nonce, token standard
+            }
+        }
+        // metadata written before collections existed stops here
+        let collection = if reader.0.is_empty() || !reader.option()? {
+            None
+        } else {
+            Some(Collection {
+                verified: reader.u8()? != 0,
+                key: Pubkey::new(reader.take(32)?),
+            })
+        };
+
+        Some(Metadata {
+            mint,
+            seller_fee_basis_points,
+            creators,
+            collection,
+        })
+    }
+}
+
+/// The metadata PDA of `mint`, seeds `[b"metadata", metadata program, mint]`
+pub fn find_metadata_address(mint: &Pubkey) -> Pubkey {
+    let program_id = mpl_token_metadata::id();
+    Pubkey::find_program_address(&[b"metadata", program_id.as_ref(), mint.as_ref()], &program_id).0
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(taken)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|byte| byte[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).and_then(|bytes| bytes.try_into().ok()).map(u16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4).and_then(|bytes| bytes.try_into().ok()).map(u32::from_le_bytes)
+    }
+
+    fn option(&mut self) -> Option<bool> {
+        match self.u8()? {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+}
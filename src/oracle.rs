@@ -0,0 +1,102 @@
+use std::convert::TryInto;
+
+use solana_program::{
+    account_info::AccountInfo,
+    program_error::ProgramError,
+};
+
+use crate::error::EscrowError;
+
+// the parts of the Pyth v2 price account layout we read, offsets are in bytes from the start of the account data
+const MAGIC: u32 = 0xa1b2_c3d4;
+const VERSION: u32 = 2;
+const ACCOUNT_TYPE_PRICE: u32 = 3;
+const EXPO_OFFSET: usize = 20;
+const TIMESTAMP_OFFSET: usize = 96;
+const AGG_PRICE_OFFSET: usize = 208;
+const AGG_CONF_OFFSET: usize = 216;
+const AGG_STATUS_OFFSET: usize = 224;
+const PRICE_ACCOUNT_MIN_LEN: usize = 240;
+const STATUS_TRADING: u32 = 1;
+
+/// How old the price can be, in seconds, before an exchange against it is refused
+pub const MAX_PRICE_AGE: i64 = 60;
+
+/// The aggregate price from a Pyth price account
+pub struct OraclePrice {
+    pub price: i64,
+    pub conf: u64,
+    pub expo: i32,
+    pub status: u32,
+    pub timestamp: i64,
+}
+
+impl OraclePrice {
+    /// Reads the aggregate price out of a Pyth price account, checking it really is one
+    pub fn load(oracle_account: &AccountInfo) -> Result<OraclePrice, ProgramError> {
+        let data = oracle_account.data.borrow();
+        if data.len() < PRICE_ACCOUNT_MIN_LEN
+            || read_u32(&data, 0) != MAGIC
+            || read_u32(&data, 4) != VERSION
+            || read_u32(&data, 8) != ACCOUNT_TYPE_PRICE
+        {
+            return Err(EscrowError::InvalidOracleAccount.into());
+        }
+
+        Ok(OraclePrice {
+            price: read_u64(&data, AGG_PRICE_OFFSET) as i64,
+            conf: read_u64(&data, AGG_CONF_OFFSET),
+            expo: read_u32(&data, EXPO_OFFSET) as i32,
+            status: read_u32(&data, AGG_STATUS_OFFSET),
+            timestamp: read_u64(&data, TIMESTAMP_OFFSET) as i64,
+        })
+    }
+
+    /// Fails unless the price is trading, was published in the last `MAX_PRICE_AGE` seconds and is known to within `tolerance_bps`
+    pub fn check_usable(&self, unix_timestamp: i64, tolerance_bps: u16) -> Result<(), ProgramError> {
+        if self.status != STATUS_TRADING
+            || self.price <= 0
+            || unix_timestamp.saturating_sub(self.timestamp) > MAX_PRICE_AGE
+        {
+            return Err(EscrowError::StaleOraclePrice.into());
+        }
+
+        // conf / price > tolerance_bps / 10_000, cross multiplied to stay in integers
+        if (self.conf as u128) * 10_000 > (self.price as u128) * (tolerance_bps as u128) {
+            return Err(EscrowError::OraclePriceTooUncertain.into());
+        }
+
+        Ok(())
+    }
+
+    /// What `offered_amount` of the offered token is worth in the expected token, rounded up so the initializer is never short changed.
+    /// The price is taken to be for one whole offered token in whole expected tokens, so the decimals of both mints are needed to work in base units
+    pub fn value_of(&self, offered_amount: u64, offered_decimals: u8, expected_decimals: u8) -> Result<u64, ProgramError> {
+        let scale = self.expo + expected_decimals as i32 - offered_decimals as i32;
+        let value = (offered_amount as u128)
+            .checked_mul(self.price as u128)
+            .ok_or(EscrowError::AmountOverflow)?;
+
+        let value = if scale >= 0 {
+            10u128
+                .checked_pow(scale as u32)
+                .and_then(|factor| value.checked_mul(factor))
+                .ok_or(EscrowError::AmountOverflow)?
+        } else {
+            match 10u128.checked_pow(scale.unsigned_abs()) {
+                Some(divisor) => (value + divisor - 1) / divisor,
+                None => (value > 0) as u128, // anything non zero is less than one base unit once scaled this far down, round it up
+            }
+        };
+
+        value.try_into().map_err(|_| EscrowError::AmountOverflow.into())
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
@@ -1,217 +1,4367 @@
-use solana_program::{
-    account_info::{next_account_info, AccountInfo},
-    entrypoint::ProgramResult,
-    program_error::ProgramError,
-    msg,
-    pubkey::Pubkey,
-    program_pack::{Pack, IsInitialized},
-    sysvar::{rent::Rent, Sysvar},
-    program::{invoke, invoke_signed}
-};
-
-use spl_token::state::Account as TokenAccount;
-
-use crate::{instruction::EscrowInstruction, error::EscrowError, state::Escrow};
-
-pub struct Processor;
-
-impl Processor {
-    pub fn process(program_id: &Pubkey, accounts: &[AccountInfo], instruction_data: &[u8]) -> ProgramResult {
-        let instruction = EscrowInstruction::unpack(instruction_data)?; // uses the unpack function defined in instruction, the ? will work to either give the value if it is ok, or call the error if there is one
-
-        match instruction { // here we include code that will be called depending on the instruction given
-            EscrowInstruction::InitEscrow { amount } => {
-                msg!("Instruction: InitEscrow");
-                Self::process_init_escrow(accounts, amount, program_id)
-            },
-            EscrowInstruction::Exchange { amount } => {
-                msg!("Instruction: Exchange");
-                Self::process_exchange(accounts, amount, program_id)
-            }
-        }
-    }
-    
-    fn process_init_escrow(
-        accounts: &[AccountInfo],
-        amount: u64,
-        program_id: &Pubkey,
-    ) -> ProgramResult {
-        let account_info_iter = &mut accounts.iter(); // mut makes this accounts iterable mutable, which we need to extract elements from it
-        let initializer = next_account_info(account_info_iter)?; // this creates an iterator on the accounts, so the first iteration will return the initializer.
-
-        if !initializer.is_signer {  // the initializer needs to be a signer otherwise the transaction won't work, so check for that as so
-            return Err(ProgramError::MissingRequiredSignature);
-        }
-
-        let temp_token_account = next_account_info(account_info_iter)?;
-
-        let token_to_receive_account = next_account_info(account_info_iter)?;
-        if *token_to_receive_account.owner != spl_token::id() { // this checks whether the owner of the token_to_receive account is the token program 
-            return Err(ProgramError::IncorrectProgramId);
-        }
-
-        let escrow_account = next_account_info(account_info_iter)?;
-        let rent = &Rent::from_account_info(next_account_info(account_info_iter)?)?; // rent should be able to be taken from sysvars in new versions 
-
-        if !rent.is_exempt(escrow_account.lamports(), escrow_account.data_len()) {
-            return Err(EscrowError::NotRentExempt.into());
-        }
-
-        let mut escrow_info = Escrow::unpack_unchecked(&escrow_account.data.borrow())?; // here we are accessing the data field of the escrow account - this is a u8 array that we need to deserialize with an unpacking function
-        if escrow_info.is_initialized() {
-            return Err(ProgramError::AccountAlreadyInitialized);
-        }
-        
-        escrow_info.is_initialized = true;
-        escrow_info.initializer_pubkey = *initializer.key;
-        escrow_info.temp_token_account_pubkey = *temp_token_account.key;
-        escrow_info.initializer_token_to_receive_account_pubkey = *token_to_receive_account.key;
-        escrow_info.expected_amount = amount;
-
-        Escrow::pack(escrow_info, &mut escrow_account.data.borrow_mut())?; // pack is an internal function that calls our pack_into_slice function from state.rs
-        let (pda, _bump_seed) = Pubkey::find_program_address(&[b"escrow"], program_id); // we place the _ before the variable as we will intentionally not use that for now
-
-        let token_program = next_account_info(account_info_iter)?;
-        let owner_change_ix = spl_token::instruction::set_authority(
-            token_program.key, // token program id
-            temp_token_account.key, // the account whose authority we would like to change
-            Some(&pda), // the account that is the new authority (the PDA)
-            spl_token::instruction::AuthorityType::AccountOwner, // the type of authority change (owner change)
-            initializer.key, // the current account owner
-            &[&initializer.key], // the public key to sign the CPI (cross program invocation)
-        )?;
-        
-        msg!("Calling the token program to transfer token account ownership...");
-        invoke(
-            &owner_change_ix,
-            &[
-                temp_token_account.clone(),
-                initializer.clone(),
-                token_program.clone(),
-            ],
-        )?;
-
-        Ok(())
-    }
-
-    fn process_exchange(
-        accounts: &[AccountInfo],
-        amount_expected_by_taker: u64,
-        program_id: &Pubkey,
-    ) -> ProgramResult {
-        let account_info_iter = &mut accounts.iter(); 
-        let taker = next_account_info(account_info_iter)?; // Bob's account
-
-        if !taker.is_signer {  // the initializer needs to be a signer otherwise the transaction won't work, so check for that as so
-            return Err(ProgramError::MissingRequiredSignature);
-        }
-
-        let send_token_account = next_account_info(account_info_iter)?; // takers token account for the token they will send
-
-        //// !!! need to put in a check that this pubKey is equal to the info in the escrow account later
-
-        let receive_token_account = next_account_info(account_info_iter)?; // takers token account for the token they will receive
-
-        //// !!! need to check that this is equal to the temp account owned by the PDA
-
-        let pdas_temp_token_account = next_account_info(account_info_iter)?;
-
-        let pdas_temp_token_account_info = TokenAccount::unpack(&pdas_temp_token_account.data.borrow())?; // this part I don't get
-        let (pda, bump_seed) = Pubkey::find_program_address(&[b"escrow"], program_id); // we place the _ before the variable as we will intentionally not use that for now
-
-        if amount_expected_by_taker != pdas_temp_token_account_info.amount {
-            return Err(EscrowError::ExpectedAmountMismatch.into());
-        }
-
-        let initializers_main_account = next_account_info(account_info_iter)?;
-        let initializer_token_to_receive_account = next_account_info(account_info_iter)?;
-        let escrow_account = next_account_info(account_info_iter)?;
-
-        let escrow_info = Escrow::unpack(&escrow_account.data.borrow())?;
-
-        if escrow_info.temp_token_account_pubkey != *pdas_temp_token_account.key {
-            return Err(ProgramError::InvalidAccountData);
-        }
-
-        if escrow_info.initializer_pubkey != *initializers_main_account.key {
-            return Err(ProgramError::InvalidAccountData);
-        }
-
-        if escrow_info.initializer_token_to_receive_account_pubkey != *initializer_token_to_receive_account.key {
-            return Err(ProgramError::InvalidAccountData);
-        }
-
-        let token_program = next_account_info(account_info_iter)?;
-
-        let transfer_to_initializer_ix =  spl_token::instruction::transfer(
-            token_program.key,
-            send_token_account.key,
-            initializer_token_to_receive_account.key,
-            taker.key,
-            &[&taker.key],
-            escrow_info.expected_amount,
-        )?;
-        msg!("Calling the token program to transfer tokens to the escrow's initializer...");
-        invoke(
-            &transfer_to_initializer_ix,
-            &[
-                send_token_account.clone(),
-                initializer_token_to_receive_account.clone(),
-                taker.clone(),
-                token_program.clone(),
-            ]
-        )?;
-
-        let pda_account = next_account_info(account_info_iter)?;
-        
-        let transfer_to_taker_ix = spl_token::instruction::transfer(
-            token_program.key,
-            pdas_temp_token_account.key,
-            receive_token_account.key,
-            &pda, // done like this as pda is the key, not the keypair
-            &[&pda],
-            pdas_temp_token_account_info.amount, // check if this works should be the same as the amount in the pdas_temp_token_account_info
-        )?;
-        msg!("Calling the token program to transfer tokens to the taker..");
-        invoke_signed(
-            &transfer_to_taker_ix,
-            &[
-                pdas_temp_token_account.clone(),
-                receive_token_account.clone(),
-                pda_account.clone(), // note that this is the pda account not the pda address that was generate with the b"escrow" seed
-                token_program.clone(),
-            ],
-            &[&[&b"escrow"[..], &[bump_seed]]], // why so many []? - this is in the Calling Between Programs Solana docs under cross program invocations still don't get the b"escrow"[..]
-        )?;
-
-        let close_pda_temp_token_account_ix = spl_token::instruction::close_account(
-            token_program.key,
-            pdas_temp_token_account.key,
-            initializers_main_account.key,
-            &pda,
-            &[&pda],
-        )?;
-        msg!("Calling the token program to close pda's temp account...");
-        invoke_signed(
-            &close_pda_temp_token_account_ix,
-            &[
-                pdas_temp_token_account.clone(),
-                initializers_main_account.clone(),
-                pda_account.clone(), // note that this is the pda account not the pda address that was generate with the b"escrow" seed
-                token_program.clone(),
-            ],
-            &[&[&b"escrow"[..], &[bump_seed]]], 
-        )?;
-
-        // add the rent back to Alice's account and clear the data in the escrow account
-        msg!("Closing the escrow account...");
-        **initializers_main_account.lamports.borrow_mut() = initializers_main_account.lamports()
-        .checked_add(escrow_account.lamports())
-        .ok_or(EscrowError::AmountOverflow)?;
-        **escrow_account.lamports.borrow_mut() = 0;
-        *escrow_account.data.borrow_mut() = &mut [];
-
-        Ok(())
-    }
-}   
-
+use solana_program::{
+    account_info::{next_account_info, next_account_infos, AccountInfo},
+    entrypoint::ProgramResult,
+    program_error::{PrintProgramError, ProgramError},
+    msg,
+    pubkey::Pubkey,
+    program_pack::{Pack, IsInitialized},
+    sysvar::{self, clock::Clock, rent::Rent, Sysvar},
+    program::{invoke, invoke_signed},
+    program_option::COption,
+    system_instruction,
+};
+
+use spl_token::state::{Account as TokenAccount, Multisig};
+
+use crate::{
+    instruction::EscrowInstruction,
+    error::EscrowError,
+    event::{EscrowOpenedEvent, ExchangeQuote, SettlementEvent},
+    history::{self, EscrowOutcome, EscrowSummary},
+    metadata::Metadata,
+    signed_offer::SignedOffer,
+    state::{AcceptedMint, Config, Escrow, EscrowKind, EscrowStatus, FeeTier, FilledOffer, Memo, OfferCounter, Stats, TakerClaim},
+    strategy::{self, Amounts, Take, VaultAccounts},
+    utils,
+};
+
+pub struct Processor;
+
+/// What process_exchange is run for
+#[derive(Clone, Copy, PartialEq)]
+enum ExchangeMode {
+    /// A plain Exchange
+    Take,
+    /// A RevealExchange, the taker's commit bond goes back to them once the trade is through
+    Reveal,
+    /// A ValidateExchange, which stops once every check has passed
+    Validate,
+}
+
+impl Processor {
+    pub fn process(program_id: &Pubkey, accounts: &[AccountInfo], instruction_data: &[u8]) -> ProgramResult {
+        let result = Self::process_instruction(program_id, accounts, instruction_data);
+        if let Err(error) = &result {
+            error.print::<EscrowError>(); // decodes our custom error codes back into their names so failures are readable in explorer logs
+        }
+        result
+    }
+
+    fn process_instruction(program_id: &Pubkey, accounts: &[AccountInfo], instruction_data: &[u8]) -> ProgramResult {
+        let instruction = EscrowInstruction::unpack(instruction_data)?; // uses the unpack function defined in instruction, the ? will work to either give the value if it is ok, or call the error if there is one
+
+        match instruction { // here we include code that will be called depending on the instruction given
+            EscrowInstruction::InitEscrow {
+                amount, expires_at, crank_bounty, start_time, oracle, memo, accepted_mints, reusable, cancel_penalty, lamports, enforce_royalties, nonce, max_per_taker,
+            } => {
+                msg!("Instruction: InitEscrow");
+                if amount == 0 { // checked here rather than in process_init_escrow, which InitRateEscrow calls without a fixed price
+                    return Err(EscrowError::ZeroAmount.into());
+                }
+                Self::process_init_escrow(
+                    accounts, amount, expires_at, crank_bounty, start_time.unwrap_or(0), oracle, memo.unwrap_or_default(), &accepted_mints, reusable,
+                    cancel_penalty, lamports, enforce_royalties, nonce, max_per_taker, program_id,
+                )
+            },
+            EscrowInstruction::Exchange { amount, valid_until_slot, referral_bps, offered_hook_accounts, payment_hook_accounts, transfer_authority } => {
+                msg!("Instruction: Exchange");
+                Self::process_exchange(accounts, amount, valid_until_slot, referral_bps, offered_hook_accounts, payment_hook_accounts, transfer_authority, ExchangeMode::Take, program_id)
+            },
+            EscrowInstruction::CrankExpired => {
+                msg!("Instruction: CrankExpired");
+                Self::process_crank_expired(accounts, program_id)
+            },
+            EscrowInstruction::InitDelegatedEscrow { amount, offered_amount, expires_at, crank_bounty } => {
+                msg!("Instruction: InitDelegatedEscrow");
+                Self::process_init_delegated_escrow(accounts, amount, offered_amount, expires_at, crank_bounty, program_id)
+            },
+            EscrowInstruction::InitHistory { max_depth, max_buffer_size } => {
+                msg!("Instruction: InitHistory");
+                Self::process_init_history(accounts, max_depth, max_buffer_size, program_id)
+            },
+            EscrowInstruction::Cancel { hook_accounts } => {
+                msg!("Instruction: Cancel");
+                Self::process_cancel(accounts, hook_accounts, program_id)
+            },
+            EscrowInstruction::InitSubscription { installment_amount, interval_slots } => {
+                msg!("Instruction: InitSubscription");
+                Self::process_init_subscription(accounts, installment_amount, interval_slots, program_id)
+            },
+            EscrowInstruction::Subscribe { amount } => {
+                msg!("Instruction: Subscribe");
+                Self::process_subscribe(accounts, amount, program_id)
+            },
+            EscrowInstruction::ClaimInstallment => {
+                msg!("Instruction: ClaimInstallment");
+                Self::process_claim_installment(accounts, program_id)
+            },
+            EscrowInstruction::CancelSubscription => {
+                msg!("Instruction: CancelSubscription");
+                Self::process_cancel_subscription(accounts, program_id)
+            },
+            EscrowInstruction::TopUp { additional_amount } => {
+                msg!("Instruction: TopUp");
+                Self::process_top_up(accounts, additional_amount, program_id)
+            },
+            EscrowInstruction::InitConfig { max_referral_bps, creation_deposit, fee_tiers } => {
+                msg!("Instruction: InitConfig");
+                Self::process_init_config(accounts, max_referral_bps, creation_deposit, fee_tiers, program_id)
+            },
+            EscrowInstruction::UpdateConfig { max_referral_bps, creation_deposit, fee_tiers } => {
+                msg!("Instruction: UpdateConfig");
+                Self::process_update_config(accounts, max_referral_bps, creation_deposit, fee_tiers, program_id)
+            },
+            EscrowInstruction::DepositTakerSide { amount } => {
+                msg!("Instruction: DepositTakerSide");
+                Self::process_deposit_taker_side(accounts, amount, program_id)
+            },
+            EscrowInstruction::Settle => {
+                msg!("Instruction: Settle");
+                Self::process_settle(accounts, program_id)
+            },
+            EscrowInstruction::Withdraw => {
+                msg!("Instruction: Withdraw");
+                Self::process_withdraw(accounts, program_id)
+            },
+            EscrowInstruction::InitRateEscrow { rate_numerator, rate_denominator, expires_at, crank_bounty, max_per_taker } => {
+                msg!("Instruction: InitRateEscrow");
+                Self::process_init_rate_escrow(accounts, rate_numerator, rate_denominator, expires_at, crank_bounty, max_per_taker, program_id)
+            },
+            EscrowInstruction::InitStats { per_initializer } => {
+                msg!("Instruction: InitStats");
+                Self::process_init_stats(accounts, per_initializer, program_id)
+            },
+            EscrowInstruction::CommitExchange { hash } => {
+                msg!("Instruction: CommitExchange");
+                Self::process_commit_exchange(accounts, hash, program_id)
+            },
+            EscrowInstruction::RevealExchange { amount, salt } => {
+                msg!("Instruction: RevealExchange");
+                Self::process_reveal_exchange(accounts, amount, salt, program_id)
+            },
+            EscrowInstruction::Migrate => {
+                msg!("Instruction: Migrate");
+                Self::process_migrate(accounts, program_id)
+            },
+            EscrowInstruction::ValidateExchange { amount, valid_until_slot, referral_bps, offered_hook_accounts, payment_hook_accounts, transfer_authority } => {
+                msg!("Instruction: ValidateExchange");
+                Self::process_exchange(accounts, amount, valid_until_slot, referral_bps, offered_hook_accounts, payment_hook_accounts, transfer_authority, ExchangeMode::Validate, program_id)
+            },
+            EscrowInstruction::RecoverOrphan => {
+                msg!("Instruction: RecoverOrphan");
+                Self::process_recover_orphan(accounts, program_id)
+            },
+            EscrowInstruction::MatchOffers => {
+                msg!("Instruction: MatchOffers");
+                Self::process_match_offers(accounts, program_id)
+            },
+            EscrowInstruction::InitEscrowFromAccount { amount, offered_amount, expires_at, crank_bounty } => {
+                msg!("Instruction: InitEscrowFromAccount");
+                Self::process_init_escrow_from_account(accounts, amount, offered_amount, expires_at, crank_bounty, program_id)
+            },
+            EscrowInstruction::InitBid { expires_at, crank_bounty, collection, enforce_royalties } => {
+                msg!("Instruction: InitBid");
+                Self::process_init_bid(accounts, expires_at, crank_bounty, collection, enforce_royalties, program_id)
+            },
+            EscrowInstruction::AcceptBid => {
+                msg!("Instruction: AcceptBid");
+                Self::process_accept_bid(accounts, program_id)
+            },
+            EscrowInstruction::ExtendEscrow { new_len } => {
+                msg!("Instruction: ExtendEscrow");
+                Self::process_extend_escrow(accounts, new_len, program_id)
+            },
+            EscrowInstruction::FillSignedOffer { offer } => {
+                msg!("Instruction: FillSignedOffer");
+                Self::process_fill_signed_offer(accounts, offer, program_id)
+            },
+            EscrowInstruction::InitOfferCounter => {
+                msg!("Instruction: InitOfferCounter");
+                Self::process_init_offer_counter(accounts, program_id)
+            }
+        }
+    }
+    
+    #[allow(clippy::too_many_arguments)]
+    fn process_init_escrow(
+        accounts: &[AccountInfo],
+        amount: u64,
+        expires_at: i64,
+        crank_bounty: u64,
+        start_time: i64,
+        oracle: Option<(Pubkey, u16)>,
+        memo: Memo,
+        accepted_mints: &[AcceptedMint],
+        reusable: bool,
+        cancel_penalty: Option<(u16, Pubkey)>,
+        lamports: u64,
+        enforce_royalties: bool,
+        nonce: Option<u64>,
+        max_per_taker: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let (accounts, stats_accounts) = Self::split_stats_accounts(accounts, 2, program_id);
+        let (accounts, offer_counter_account) = Self::split_offer_counter(accounts, program_id);
+        let (config_account, accounts) = accounts.split_last().ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let (system_program, accounts) = match nonce {
+            Some(_) => accounts.split_last().map(|(system_program, accounts)| (Some(system_program), accounts)).ok_or(ProgramError::NotEnoughAccountKeys)?,
+            None => (None, accounts),
+        };
+        let account_info_iter = &mut accounts.iter(); // mut makes this accounts iterable mutable, which we need to extract elements from it
+        let initializer = next_account_info(account_info_iter)?; // this creates an iterator on the accounts, so the first iteration will return the initializer.
+
+        // older clients still pass the rent sysvar before the token program, everything after it then sits one further along
+        let has_rent_sysvar = accounts.get(4).map_or(false, |account| *account.key == sysvar::rent::id());
+        let mints_start = if has_rent_sysvar { 6 } else { 5 };
+
+        let first_signer = if oracle.is_some() { mints_start + 2 } else { mints_start }; // an oracle priced escrow passes the two mints straight after the token program
+        let multisig_signers = accounts.get(first_signer..).unwrap_or(&[]); // anything after the token program (and mints) is a signer of the initializer's multisig
+        Self::check_initializer_signature(initializer, multisig_signers)?; // the initializer needs to sign (or have its multisig sign) otherwise the transaction won't work, so check for that as so
+
+        let temp_token_account = next_account_info(account_info_iter)?;
+
+        let token_to_receive_account = next_account_info(account_info_iter)?;
+        utils::check_not_confidential(&[temp_token_account, token_to_receive_account])?;
+        if !utils::is_token_program(token_to_receive_account.owner) { // this checks whether the owner of the token_to_receive account is a token program
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let offered_tokens = utils::unpack_token_account(temp_token_account)?;
+        if offered_tokens.amount == 0 {
+            return Err(EscrowError::EmptyOfferAccount.into());
+        }
+        if offered_tokens.close_authority.is_some() { // RecoverOrphan hands back a PDA held account an orphan authority can close
+            return Err(EscrowError::CloseAuthoritySet.into());
+        }
+        let expected_mint = utils::unpack_token_account(token_to_receive_account)?.mint;
+        if offered_tokens.mint == expected_mint || accepted_mints.iter().any(|accepted| accepted.mint == offered_tokens.mint) {
+            return Err(EscrowError::SameMint.into());
+        }
+
+        let escrow_account = next_account_info(account_info_iter)?;
+        if has_rent_sysvar {
+            next_account_info(account_info_iter)?;
+        }
+        let rent = Rent::get()?;
+
+        // the config can make every offer lock up a deposit, so filling the order book with offers and cancelling them straight away costs something
+        let config = Self::unpack_optional_config(config_account, program_id)?;
+        // escrows opened under a fee schedule pay the protocol fee on every take, so a client can't skip it by leaving the config out
+        let charges_protocol_fee = config.as_ref().map_or(false, Config::has_protocol_fee);
+        let creation_deposit = config.filter(|config| config.creation_deposit != 0);
+
+        if let (Some(nonce), Some(system_program)) = (nonce, system_program) {
+            let (offer_pda, offer_bump_seed) = Pubkey::find_program_address(&[b"offer", initializer.key.as_ref(), &nonce.to_le_bytes()], program_id);
+            if offer_pda != *escrow_account.key {
+                return Err(EscrowError::InvalidEscrowAddress.into());
+            }
+            if escrow_account.owner == program_id { // an offer with this nonce is still open
+                return Err(ProgramError::AccountAlreadyInitialized);
+            }
+
+            let deposit = creation_deposit.as_ref().map_or(0, |config| config.creation_deposit);
+            let create_escrow_ix = system_instruction::create_account(
+                initializer.key,
+                escrow_account.key,
+                rent.minimum_balance(Escrow::LEN).saturating_add(deposit),
+                Escrow::LEN as u64,
+                program_id,
+            );
+            msg!("Calling the system program to create the escrow account...");
+            invoke_signed(
+                &create_escrow_ix,
+                &[
+                    initializer.clone(),
+                    escrow_account.clone(),
+                    system_program.clone(),
+                ],
+                &[&[b"offer", initializer.key.as_ref(), &nonce.to_le_bytes(), &[offer_bump_seed]]],
+            )?;
+        }
+
+        if !rent.is_exempt(escrow_account.lamports(), escrow_account.data_len()) {
+            return Err(EscrowError::NotRentExempt.into());
+        }
+
+        if crank_bounty > escrow_account.lamports() { // the bounty comes out of the escrow's rent so it can't be more than that
+            return Err(EscrowError::AmountOverflow.into());
+        }
+
+        if let Some(config) = &creation_deposit {
+            if escrow_account.lamports() < rent.minimum_balance(escrow_account.data_len()).saturating_add(config.creation_deposit) {
+                return Err(EscrowError::MissingCreationDeposit.into());
+            }
+        }
+
+        Self::check_escrow_account(escrow_account, program_id)?;
+        let mut escrow_info = Escrow::unpack_unchecked(&escrow_account.data.borrow())?; // here we are accessing the data field of the escrow account - this is a u8 array that we need to deserialize with an unpacking function
+        if escrow_info.is_initialized() {
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+        
+        escrow_info.is_initialized = true;
+        escrow_info.initializer_pubkey = *initializer.key;
+        escrow_info.temp_token_account_pubkey = *temp_token_account.key;
+        escrow_info.initializer_token_to_receive_account_pubkey = *token_to_receive_account.key;
+        escrow_info.expected_amount = amount;
+        escrow_info.expires_at = expires_at;
+        escrow_info.crank_bounty = crank_bounty;
+        escrow_info.status = EscrowStatus::Open;
+        escrow_info.start_time = start_time;
+        escrow_info.memo = memo;
+        escrow_info.expected_lamports = lamports;
+        escrow_info.enforce_royalties = enforce_royalties;
+        escrow_info.max_per_taker = max_per_taker;
+        let clock = Clock::get()?;
+        escrow_info.created_at_slot = clock.slot; // the protocol fee goes down with the escrow's age
+        escrow_info.charges_protocol_fee = charges_protocol_fee;
+
+        if let Some(config) = creation_deposit {
+            escrow_info.creation_deposit = config.creation_deposit;
+            escrow_info.deposit_locked_until_slot = clock.slot.saturating_add(config.deposit_lock_slots);
+            escrow_info.deposit_recipient = config.fee_recipient;
+        }
+
+        if !accepted_mints.is_empty() {
+            if oracle.is_some() { // the oracle prices the offer in the one expected mint, it can't price the others
+                return Err(EscrowError::WrongEscrowMode.into());
+            }
+            escrow_info.accepted_mints[..accepted_mints.len()].copy_from_slice(accepted_mints);
+        }
+
+        if reusable {
+            // whatever the temp account holds now is one lot, each fill takes a lot and TopUp adds more of them
+            escrow_info.is_reusable = true;
+            escrow_info.offered_amount = offered_tokens.amount;
+        }
+
+        if let Some((penalty_bps, penalty_recipient)) = cancel_penalty {
+            if penalty_bps > 10_000 {
+                return Err(EscrowError::InvalidPenalty.into());
+            }
+            escrow_info.cancel_penalty_bps = penalty_bps;
+            escrow_info.penalty_recipient = penalty_recipient;
+        }
+
+        if let Some((oracle_pubkey, oracle_tolerance_bps)) = oracle {
+            // the oracle price is per whole token, so we need both mints' decimals to turn it into base units at exchange time
+            let (offered_mint, expected_mint) = match accounts.get(mints_start..mints_start + 2) {
+                Some([offered_mint, expected_mint]) => (offered_mint, expected_mint),
+                _ => return Err(ProgramError::NotEnoughAccountKeys),
+            };
+            let temp_token_account_info = utils::unpack_token_account(temp_token_account)?;
+            let token_to_receive_account_info = utils::unpack_token_account(token_to_receive_account)?;
+            if temp_token_account_info.mint != *offered_mint.key
+                || token_to_receive_account_info.mint != *expected_mint.key
+                || !utils::is_token_program(offered_mint.owner)
+                || !utils::is_token_program(expected_mint.owner)
+            {
+                return Err(EscrowError::WrongMint.into());
+            }
+
+            escrow_info.kind = EscrowKind::OraclePriced;
+            escrow_info.oracle_pubkey = oracle_pubkey;
+            escrow_info.oracle_tolerance_bps = oracle_tolerance_bps;
+            escrow_info.offered_decimals = utils::unpack_mint(offered_mint)?.decimals;
+            escrow_info.expected_decimals = utils::unpack_mint(expected_mint)?.decimals;
+        }
+
+        if let Some(offer_counter_account) = offer_counter_account {
+            escrow_info.offer_id = Self::take_offer_id(offer_counter_account, program_id)?;
+        }
+
+        let (pda, bump_seed) = Pubkey::find_program_address(&[b"escrow"], program_id);
+        escrow_info.bump_seed = bump_seed; // stored so the exchange doesn't have to search for the bump again
+
+        EscrowOpenedEvent {
+            escrow_pubkey: *escrow_account.key,
+            initializer_pubkey: escrow_info.initializer_pubkey,
+            offer_id: escrow_info.offer_id,
+        }.emit();
+        Escrow::pack(escrow_info, &mut escrow_account.data.borrow_mut())?; // pack is an internal function that calls our pack_into_slice function from state.rs
+
+        let token_program = next_account_info(account_info_iter)?;
+        Self::check_any_token_program(token_program)?;
+        let owner_change_ix = utils::for_token_program(
+            spl_token::instruction::set_authority(
+                &spl_token::id(), // token program id, swapped for the one passed in afterwards
+                temp_token_account.key, // the account whose authority we would like to change
+                Some(&pda), // the account that is the new authority (the PDA)
+                spl_token::instruction::AuthorityType::AccountOwner, // the type of authority change (owner change)
+                initializer.key, // the current account owner
+                &Self::signer_pubkeys(initializer, multisig_signers), // the public keys to sign the CPI (cross program invocation)
+            )?,
+            token_program.key,
+        );
+
+        let mut owner_change_accounts = vec![
+            temp_token_account.clone(),
+            initializer.clone(),
+            token_program.clone(),
+        ];
+        owner_change_accounts.extend(multisig_signers.iter().cloned());
+
+        msg!("Calling the token program to transfer token account ownership...");
+        invoke(&owner_change_ix, &owner_change_accounts)?;
+
+        if let Some(stats_accounts) = stats_accounts {
+            Self::update_stats(stats_accounts, initializer.key, program_id, |stats| {
+                stats.escrows_created = stats.escrows_created.saturating_add(1);
+                stats.open_escrows = stats.open_escrows.saturating_add(1);
+            })?;
+        }
+
+        Ok(())
+    }
+
+    fn process_init_rate_escrow(
+        accounts: &[AccountInfo],
+        rate_numerator: u64,
+        rate_denominator: u64,
+        expires_at: i64,
+        crank_bounty: u64,
+        max_per_taker: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        if rate_numerator == 0 || rate_denominator == 0 {
+            return Err(EscrowError::InvalidRate.into());
+        }
+
+        // everything but the price is a plain escrow, so set one up and then swap its fixed price for the rate
+        Self::process_init_escrow(accounts, 0, expires_at, crank_bounty, 0, None, Memo::default(), &[], false, None, 0, false, None, max_per_taker, program_id)?;
+
+        let escrow_account = accounts.get(3).ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let mut escrow_info = Escrow::unpack(&escrow_account.data.borrow())?;
+        escrow_info.kind = EscrowKind::RatePriced;
+        escrow_info.rate_numerator = rate_numerator;
+        escrow_info.rate_denominator = rate_denominator;
+        Escrow::pack(escrow_info, &mut escrow_account.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    fn process_init_delegated_escrow(
+        accounts: &[AccountInfo],
+        amount: u64,
+        offered_amount: u64,
+        expires_at: i64,
+        crank_bounty: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let initializer = next_account_info(account_info_iter)?;
+
+        if !initializer.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        if amount == 0 || offered_amount == 0 {
+            return Err(EscrowError::ZeroAmount.into());
+        }
+
+        let offered_token_account = next_account_info(account_info_iter)?; // the initializer keeps ownership of this account, the PDA only gets to spend offered_amount from it
+
+        let token_to_receive_account = next_account_info(account_info_iter)?;
+        if *token_to_receive_account.owner != spl_token::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        if TokenAccount::unpack(&offered_token_account.data.borrow())?.mint == TokenAccount::unpack(&token_to_receive_account.data.borrow())?.mint {
+            return Err(EscrowError::SameMint.into());
+        }
+
+        let escrow_account = next_account_info(account_info_iter)?;
+        let rent = &Rent::from_account_info(next_account_info(account_info_iter)?)?;
+
+        if !rent.is_exempt(escrow_account.lamports(), escrow_account.data_len()) {
+            return Err(EscrowError::NotRentExempt.into());
+        }
+
+        if crank_bounty > escrow_account.lamports() {
+            return Err(EscrowError::AmountOverflow.into());
+        }
+
+        Self::check_escrow_account(escrow_account, program_id)?;
+        let mut escrow_info = Escrow::unpack_unchecked(&escrow_account.data.borrow())?;
+        if escrow_info.is_initialized() {
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+
+        escrow_info.is_initialized = true;
+        escrow_info.initializer_pubkey = *initializer.key;
+        escrow_info.temp_token_account_pubkey = *offered_token_account.key; // no temp account in a delegated escrow, the tokens are pulled straight from here on exchange
+        escrow_info.initializer_token_to_receive_account_pubkey = *token_to_receive_account.key;
+        escrow_info.expected_amount = amount;
+        escrow_info.expires_at = expires_at;
+        escrow_info.crank_bounty = crank_bounty;
+        escrow_info.status = EscrowStatus::Open;
+        escrow_info.created_at_slot = Clock::get()?.slot;
+        escrow_info.is_delegated = true;
+        escrow_info.kind = EscrowKind::Delegated;
+        escrow_info.offered_amount = offered_amount;
+
+        let (pda, bump_seed) = Pubkey::find_program_address(&[b"escrow"], program_id);
+        escrow_info.bump_seed = bump_seed;
+
+        Escrow::pack(escrow_info, &mut escrow_account.data.borrow_mut())?;
+
+        let token_program = next_account_info(account_info_iter)?;
+        Self::check_token_program(token_program)?;
+        let pda_account = next_account_info(account_info_iter)?;
+        let approve_ix = spl_token::instruction::approve(
+            token_program.key,
+            offered_token_account.key, // the account the PDA will be allowed to spend from
+            &pda, // the delegate
+            initializer.key, // the account owner approving the delegate
+            &[&initializer.key],
+            offered_amount,
+        )?;
+
+        msg!("Calling the token program to approve the PDA as a delegate...");
+        invoke(
+            &approve_ix,
+            &[
+                offered_token_account.clone(),
+                pda_account.clone(),
+                initializer.clone(),
+                token_program.clone(),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn process_exchange(
+        accounts: &[AccountInfo],
+        amount_expected_by_taker: u64,
+        valid_until_slot: Option<u64>,
+        referral_bps: u16,
+        offered_hook_accounts: u8,
+        payment_hook_accounts: u8,
+        has_transfer_authority: bool,
+        mode: ExchangeMode,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let (accounts, stats_accounts) = Self::split_stats_accounts(accounts, 2, program_id);
+        let account_info_iter = &mut accounts.iter(); 
+        let taker = next_account_info(account_info_iter)?; // Bob's account
+
+        if !taker.is_signer {  // the taker needs to be a signer otherwise the transaction won't work, so check for that as so
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        if amount_expected_by_taker == 0 {
+            return Err(EscrowError::ZeroAmount.into());
+        }
+
+        let clock = Clock::get()?;
+        if let Some(valid_until_slot) = valid_until_slot { // the taker's own deadline, so a fill signed in a volatile moment can't land much later
+            if clock.slot > valid_until_slot {
+                return Err(EscrowError::FillWindowExpired.into());
+            }
+        }
+
+        let send_token_account = next_account_info(account_info_iter)?; // takers token account for the token they will send
+
+        let receive_token_account = next_account_info(account_info_iter)?; // takers token account for the token they will receive
+
+        let pdas_temp_token_account = next_account_info(account_info_iter)?;
+        let initializers_main_account = next_account_info(account_info_iter)?;
+        let initializer_token_to_receive_account = next_account_info(account_info_iter)?;
+        let escrow_account = next_account_info(account_info_iter)?;
+        let token_program = next_account_info(account_info_iter)?;
+        let pda_account = next_account_info(account_info_iter)?;
+
+        utils::check_not_confidential(&[
+            send_token_account,
+            receive_token_account,
+            pdas_temp_token_account,
+            initializer_token_to_receive_account,
+        ])?;
+
+        // Token-2022 transfers go through TransferChecked, which needs each side's mint and whatever accounts its transfer hook takes
+        let (offered_transfer, payment_transfer) = if *token_program.key == utils::spl_token_2022::id() {
+            let offered_mint = next_account_info(account_info_iter)?;
+            let payment_mint = next_account_info(account_info_iter)?;
+            let offered_hooks = next_account_infos(account_info_iter, offered_hook_accounts as usize)?;
+            let payment_hooks = next_account_infos(account_info_iter, payment_hook_accounts as usize)?;
+            (Some((offered_mint, offered_hooks)), Some((payment_mint, payment_hooks)))
+        } else {
+            (None, None)
+        };
+
+        // the cheap checks against the escrow state go first so a bad take fails before we pay for unpacking the vault
+        let mut escrow_info = Self::unpack_initialized_escrow(escrow_account, program_id)?;
+        escrow_info.status.transition(EscrowStatus::Completed)?; // fails if the escrow is already in a terminal state
+        let strategy = strategy::for_kind(escrow_info.kind).ok_or(EscrowError::WrongEscrowMode)?;
+
+        if escrow_info.has_taker_deposit() { // a committed taker's payment sits in its own vault, only Settle or Withdraw know to deal with it
+            return Err(EscrowError::TakerSideDeposited.into());
+        }
+
+        if escrow_info.has_live_commit(clock.slot) { // reserved for whoever committed, they take it with RevealExchange
+            return Err(EscrowError::OfferCommitted.into());
+        }
+
+        if escrow_info.temp_token_account_pubkey != *pdas_temp_token_account.key {
+            return Err(EscrowError::TempAccountMismatch.into());
+        }
+
+        if escrow_info.initializer_pubkey != *initializers_main_account.key {
+            return Err(EscrowError::InitializerMismatch.into());
+        }
+
+        if escrow_info.is_expired(clock.unix_timestamp) {
+            return Err(EscrowError::EscrowExpired.into());
+        }
+
+        if !escrow_info.is_active(clock.unix_timestamp) {
+            return Err(EscrowError::OfferNotActiveYet.into());
+        }
+
+        // with an allowlist the taker's payment mint picks the price, and the payment can go to any of the initializer's accounts in that mint
+        let payment_mint = if escrow_info.has_accepted_mints() {
+            let payment_mint = utils::unpack_token_account(send_token_account)?.mint;
+            let initializer_token_to_receive_account_info = utils::unpack_token_account(initializer_token_to_receive_account)?;
+            if initializer_token_to_receive_account_info.owner != escrow_info.initializer_pubkey
+                || initializer_token_to_receive_account_info.mint != payment_mint
+            {
+                return Err(EscrowError::ReceiveAccountMismatch.into());
+            }
+            Some(payment_mint)
+        } else {
+            if escrow_info.initializer_token_to_receive_account_pubkey != *initializer_token_to_receive_account.key {
+                return Err(EscrowError::ReceiveAccountMismatch.into());
+            }
+            None
+        };
+
+        // a freeze authority can freeze the vault or either side's accounts at any time, which would otherwise fail inside the transfer
+        utils::check_not_frozen(&[
+            pdas_temp_token_account,
+            send_token_account,
+            receive_token_account,
+            initializer_token_to_receive_account,
+        ])?;
+
+        // an aggregator paying for its user moves the payment with its own authority, a PDA of the calling program counts as a signer here
+        let payment_authority = if has_transfer_authority {
+            let transfer_authority = next_account_info(account_info_iter)?;
+            if !transfer_authority.is_signer {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            transfer_authority
+        } else {
+            taker
+        };
+
+        // a SOL part of the price is paid through the system program, which comes before the other optional accounts
+        let system_program = if escrow_info.expected_lamports != 0 {
+            let system_program = next_account_info(account_info_iter)?;
+            if !solana_program::system_program::check_id(system_program.key) {
+                return Err(ProgramError::IncorrectProgramId);
+            }
+            Some(system_program)
+        } else {
+            None
+        };
+
+        // a capped escrow keeps what each taker has bought in a claim PDA of theirs, so splitting a purchase into fills doesn't get round it
+        let claim_accounts = if escrow_info.max_per_taker != 0 {
+            let claim_account = next_account_info(account_info_iter)?;
+            let claim_system_program = next_account_info(account_info_iter)?;
+            let (claim_pda, claim_bump_seed) = Pubkey::find_program_address(&[b"claim", escrow_account.key.as_ref(), taker.key.as_ref()], program_id);
+            if claim_pda != *claim_account.key {
+                return Err(EscrowError::InvalidClaimAccount.into());
+            }
+            Some((claim_account, claim_system_program, claim_bump_seed))
+        } else {
+            None
+        };
+
+        Self::check_any_token_program(token_program)?;
+
+        // no find_program_address here, the bump was stored at init and invoke_signed only works if pda_account really is the PDA for it
+        let bump_seed = escrow_info.bump_seed;
+
+        // what the take is worth is up to the escrow's kind, an oracle priced one reads its price account from the optional accounts here
+        let pdas_temp_token_account_info = utils::unpack_token_account(pdas_temp_token_account)?;
+        strategy.validate(&escrow_info, &pdas_temp_token_account_info, pda_account.key)?;
+        let take = Take {
+            amount: amount_expected_by_taker,
+            payment_mint,
+            unix_timestamp: clock.unix_timestamp,
+        };
+        let amounts = strategy.compute_amounts(&escrow_info, &pdas_temp_token_account_info, &take, account_info_iter)?;
+        let Amounts { offered_amount, expected_amount, fully_filled } = amounts;
+
+        let claimed_amount = match claim_accounts {
+            Some((claim_account, ..)) => {
+                let claimed_amount = Self::unpack_claimed_amount(claim_account, program_id)?
+                    .checked_add(offered_amount)
+                    .ok_or(EscrowError::AmountOverflow)?;
+                if claimed_amount > escrow_info.max_per_taker {
+                    return Err(EscrowError::PurchaseLimitExceeded.into());
+                }
+                claimed_amount
+            },
+            None => 0,
+        };
+
+        // the referral fee and the protocol fee both read the config, which is passed once for the two of them
+        let config = if referral_bps > 0 || escrow_info.charges_protocol_fee {
+            Some(Self::unpack_config(next_account_info(account_info_iter)?, program_id)?)
+        } else {
+            None
+        };
+
+        // a marketplace that routed the taker here can take its cut out of what the initializer receives, up to the config's limit
+        let referral = match &config {
+            Some(config) if referral_bps > 0 => {
+                if referral_bps > config.max_referral_bps {
+                    return Err(EscrowError::ReferralFeeTooHigh.into());
+                }
+                let referrer_token_account = next_account_info(account_info_iter)?;
+                let referral_fee = (expected_amount as u128 * referral_bps as u128 / Config::MAX_BPS as u128) as u64;
+                Some((referrer_token_account, referral_fee))
+            },
+            _ => None,
+        };
+        let referral_fee = referral.map_or(0, |(_, referral_fee)| referral_fee);
+
+        // an escrow opened under a fee schedule pays the protocol fee out of what the initializer receives, less the longer it was open
+        let protocol_fee_payment = match &config {
+            Some(config) if escrow_info.charges_protocol_fee => {
+                let fee_token_account = next_account_info(account_info_iter)?;
+                let fee_token_account_info = utils::unpack_token_account(fee_token_account)?;
+                if fee_token_account_info.owner != config.fee_recipient
+                    || fee_token_account_info.mint != Self::paid_in_mint(payment_mint, initializer_token_to_receive_account)?
+                {
+                    return Err(EscrowError::FeeAccountMismatch.into());
+                }
+                let fee_bps = config.protocol_fee_bps(clock.slot.saturating_sub(escrow_info.created_at_slot));
+                let protocol_fee = (expected_amount as u128 * fee_bps as u128 / Config::MAX_BPS as u128) as u64;
+                Some((fee_token_account, protocol_fee))
+            },
+            _ => None,
+        };
+        let protocol_fee = protocol_fee_payment.map_or(0, |(_, protocol_fee)| protocol_fee);
+
+        // the creators of an NFT on offer get their royalty out of what the initializer receives
+        let royalties = if escrow_info.enforce_royalties {
+            let metadata = Metadata::load(next_account_info(account_info_iter)?, &pdas_temp_token_account_info.mint)?;
+            let creator_accounts = next_account_infos(account_info_iter, metadata.creators.len())?;
+            let paid_in = Self::paid_in_mint(payment_mint, initializer_token_to_receive_account)?;
+            Self::royalty_payments(&metadata, creator_accounts, expected_amount, &paid_in)?
+        } else {
+            Vec::new()
+        };
+        let initializer_payment = Self::seller_share(expected_amount, referral_fee.saturating_add(protocol_fee), &royalties)?;
+
+        // the memo program is optional too, when it's passed the escrow's memo is written into the exchange transaction for the desk's records
+        let memo_program = account_info_iter
+            .as_slice()
+            .first()
+            .filter(|account| *account.key == utils::spl_memo::id());
+        if memo_program.is_some() {
+            account_info_iter.next();
+        }
+
+        if mode == ExchangeMode::Validate {
+            // the token program turns a taker who can't pay away in the transfer, which a validation never gets to
+            let send_token_account_info = utils::unpack_token_account(send_token_account)?;
+            let can_spend = send_token_account_info.owner == *payment_authority.key
+                || (send_token_account_info.delegate == COption::Some(*payment_authority.key)
+                    && send_token_account_info.delegated_amount >= expected_amount);
+            if send_token_account_info.amount < expected_amount
+                || !can_spend
+                || taker.lamports() < escrow_info.expected_lamports
+            {
+                return Err(ProgramError::InsufficientFunds);
+            }
+            ExchangeQuote {
+                offered_amount,
+                paid_amount: expected_amount,
+                paid_lamports: escrow_info.expected_lamports,
+                referral_fee,
+                protocol_fee,
+                closes_escrow: fully_filled,
+            }.emit();
+            return Ok(());
+        }
+
+        if let Some((claim_account, claim_system_program, claim_bump_seed)) = claim_accounts {
+            let claim = TakerClaim {
+                is_initialized: true,
+                escrow_pubkey: *escrow_account.key,
+                taker_pubkey: *taker.key,
+                bump_seed: claim_bump_seed,
+                claimed_amount,
+            };
+            Self::write_claim(claim, claim_account, taker, claim_system_program, program_id)?;
+        }
+
+        if let Some(system_program) = system_program {
+            msg!("Calling the system program to transfer lamports to the escrow's initializer...");
+            invoke(
+                &system_instruction::transfer(taker.key, initializers_main_account.key, escrow_info.expected_lamports),
+                &[
+                    taker.clone(),
+                    initializers_main_account.clone(),
+                    system_program.clone(),
+                ],
+            )?;
+        }
+
+        msg!("Calling the token program to transfer tokens to the escrow's initializer...");
+        Self::transfer_tokens(
+            token_program,
+            send_token_account,
+            initializer_token_to_receive_account,
+            payment_authority,
+            payment_transfer,
+            initializer_payment,
+            &[],
+        )?;
+
+        if let Some((referrer_token_account, referral_fee)) = referral {
+            msg!("Calling the token program to transfer the referral fee...");
+            Self::transfer_tokens(
+                token_program,
+                send_token_account,
+                referrer_token_account,
+                payment_authority,
+                payment_transfer,
+                referral_fee,
+                &[],
+            )?;
+        }
+
+        if let Some((fee_token_account, protocol_fee)) = protocol_fee_payment {
+            msg!("Calling the token program to transfer the protocol fee...");
+            Self::transfer_tokens(
+                token_program,
+                send_token_account,
+                fee_token_account,
+                payment_authority,
+                payment_transfer,
+                protocol_fee,
+                &[],
+            )?;
+        }
+
+        for (creator_account, royalty) in royalties {
+            msg!("Calling the token program to pay a creator's royalty...");
+            Self::transfer_tokens(token_program, send_token_account, creator_account, payment_authority, payment_transfer, royalty, &[])?;
+        }
+
+        strategy.settle(
+            &escrow_info,
+            &amounts,
+            &VaultAccounts {
+                token_program,
+                vault: pdas_temp_token_account,
+                taker_receive_account: receive_token_account,
+                initializers_main_account,
+                pda_account, // note that this is the pda account not the pda address that was generate with the b"escrow" seed
+                token_2022_transfer: offered_transfer,
+            },
+        )?;
+
+        // the history accounts are optional, when they're passed a summary of the trade outlives the escrow account
+        if let Some(history_accounts) = Self::next_history_accounts(account_info_iter)? {
+            if fully_filled {
+                let summary = EscrowSummary {
+                    escrow_pubkey: *escrow_account.key,
+                    initializer_pubkey: escrow_info.initializer_pubkey,
+                    counterparty_pubkey: *taker.key,
+                    expected_amount,
+                    offered_amount,
+                    outcome: EscrowOutcome::Exchanged,
+                    closed_at: clock.unix_timestamp,
+                };
+                history::append(&summary, &history_accounts, pda_account, bump_seed)?;
+            }
+        }
+
+        if let (Some(memo_program), Some(memo)) = (memo_program, escrow_info.memo.as_str()) {
+            utils::log_memo(memo_program, memo)?;
+        }
+
+        SettlementEvent {
+            escrow_pubkey: *escrow_account.key,
+            initializer_pubkey: escrow_info.initializer_pubkey,
+            taker_pubkey: *taker.key,
+            offered_amount,
+            paid_amount: expected_amount,
+            paid_lamports: escrow_info.expected_lamports,
+            referrer_token_account: referral.map(|(referrer_token_account, _)| *referrer_token_account.key),
+            referral_fee,
+            protocol_fee,
+            memo: escrow_info.memo,
+            offer_id: escrow_info.offer_id,
+        }.emit();
+
+        if let Some(stats_accounts) = stats_accounts {
+            let payment_mint = utils::unpack_token_account(initializer_token_to_receive_account)?.mint;
+            let closes_escrow = fully_filled && escrow_info.counts_as_open();
+            Self::update_stats(stats_accounts, &escrow_info.initializer_pubkey, program_id, |stats| {
+                stats.record_volume(&pdas_temp_token_account_info.mint, offered_amount);
+                stats.record_volume(&payment_mint, expected_amount);
+                if closes_escrow {
+                    stats.open_escrows = stats.open_escrows.saturating_sub(1);
+                }
+            })?;
+        }
+
+        // only refunded now the CPIs are done, lamports moved by hand any earlier would leave the accounts a CPI is passed out of balance
+        if mode == ExchangeMode::Reveal {
+            utils::transfer_lamports(escrow_account, taker, Escrow::COMMIT_BOND)?;
+        }
+
+        if !fully_filled {
+            escrow_info.status = escrow_info.status.transition(EscrowStatus::PartiallyFilled)?;
+            Escrow::pack(escrow_info, &mut escrow_account.data.borrow_mut())?;
+            return Ok(());
+        }
+
+        // add the rent back to Alice's account and clear the data in the escrow account
+        msg!("Closing the escrow account...");
+        utils::close_account(escrow_account, initializers_main_account)?;
+
+        Ok(())
+    }
+
+    fn process_crank_expired(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let (accounts, stats_accounts) = Self::split_stats_accounts(accounts, 2, program_id);
+        let account_info_iter = &mut accounts.iter();
+        let cranker = next_account_info(account_info_iter)?; // anyone can crank, they just need to sign so the bounty goes to them
+
+        if !cranker.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let pdas_temp_token_account = next_account_info(account_info_iter)?;
+        let initializers_main_account = next_account_info(account_info_iter)?;
+        let initializer_refund_token_account = next_account_info(account_info_iter)?;
+        let escrow_account = next_account_info(account_info_iter)?;
+
+        let escrow_info = Self::unpack_initialized_escrow(escrow_account, program_id)?;
+        escrow_info.status.transition(EscrowStatus::Expired)?;
+
+        if escrow_info.has_taker_deposit() {
+            return Err(EscrowError::TakerSideDeposited.into());
+        }
+
+        if escrow_info.temp_token_account_pubkey != *pdas_temp_token_account.key {
+            return Err(EscrowError::TempAccountMismatch.into());
+        }
+
+        if escrow_info.initializer_pubkey != *initializers_main_account.key {
+            return Err(EscrowError::InitializerMismatch.into());
+        }
+
+        let clock = Clock::get()?;
+        if !escrow_info.is_expired(clock.unix_timestamp) {
+            return Err(EscrowError::EscrowNotExpired.into());
+        }
+
+        let token_program = next_account_info(account_info_iter)?;
+        Self::check_token_program(token_program)?;
+        let pda_account = next_account_info(account_info_iter)?;
+
+        // a delegated escrow never took custody of the tokens so there is nothing to return, only the PDA's approval to take away.
+        // The token program only lets the account's owner revoke it, so cranking a delegated escrow takes the initializer's signature
+        let offered_amount = if escrow_info.is_delegated {
+            if !initializers_main_account.is_signer {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            if utils::is_frozen(pdas_temp_token_account) {
+                msg!("The initializer's account is frozen, leaving the PDA's delegation for them to revoke once it's thawed");
+            } else {
+                Self::revoke_delegation(token_program, pdas_temp_token_account, initializers_main_account, &[])?;
+            }
+            escrow_info.offered_amount
+        } else {
+            Self::return_temp_account_tokens(
+                pdas_temp_token_account,
+                initializers_main_account,
+                initializer_refund_token_account,
+                &escrow_info.initializer_pubkey,
+                token_program,
+                pda_account,
+                None,
+                program_id,
+            )?
+        };
+
+        if let Some(history_accounts) = Self::next_history_accounts(account_info_iter)? {
+            let (_pda, bump_seed) = Pubkey::find_program_address(&[b"escrow"], program_id);
+            let summary = EscrowSummary {
+                escrow_pubkey: *escrow_account.key,
+                initializer_pubkey: escrow_info.initializer_pubkey,
+                counterparty_pubkey: *cranker.key,
+                expected_amount: escrow_info.expected_amount,
+                offered_amount,
+                outcome: EscrowOutcome::Expired,
+                closed_at: clock.unix_timestamp,
+            };
+            history::append(&summary, &history_accounts, pda_account, bump_seed)?;
+        }
+
+        if let (Some(stats_accounts), true) = (stats_accounts, escrow_info.counts_as_open()) {
+            Self::update_stats(stats_accounts, &escrow_info.initializer_pubkey, program_id, |stats| {
+                stats.open_escrows = stats.open_escrows.saturating_sub(1);
+            })?;
+        }
+
+        // the cranker's bounty comes out of the escrow's rent and whatever is left goes back to the initializer
+        msg!("Closing the escrow account...");
+        let bounty = escrow_info.crank_bounty.min(escrow_account.lamports());
+        utils::transfer_lamports(escrow_account, cranker, bounty)?;
+        utils::close_account(escrow_account, initializers_main_account)?;
+
+        Ok(())
+    }
+
+    /// Takes the PDA's approval to spend from a delegated escrow's token account away, which only the account's owner can sign for
+    fn revoke_delegation<'a>(
+        token_program: &AccountInfo<'a>,
+        token_account: &AccountInfo<'a>,
+        owner: &AccountInfo<'a>,
+        multisig_signers: &[AccountInfo<'a>],
+    ) -> ProgramResult {
+        let revoke_ix = spl_token::instruction::revoke(
+            token_program.key,
+            token_account.key,
+            owner.key,
+            &Self::signer_pubkeys(owner, multisig_signers),
+        )?;
+
+        let mut revoke_accounts = vec![
+            token_account.clone(),
+            owner.clone(),
+            token_program.clone(),
+        ];
+        revoke_accounts.extend(multisig_signers.iter().cloned());
+
+        msg!("Calling the token program to revoke the PDA's delegation...");
+        invoke(&revoke_ix, &revoke_accounts)
+    }
+
+    /// Sends everything in the PDA's temp token account back to the initializer and closes the temp account, returning its rent to the initializer. Returns the amount of tokens sent back
+    fn return_temp_account_tokens<'a>(
+        pdas_temp_token_account: &AccountInfo<'a>,
+        initializers_main_account: &AccountInfo<'a>,
+        initializer_refund_token_account: &AccountInfo<'a>,
+        initializer_pubkey: &Pubkey,
+        token_program: &AccountInfo<'a>,
+        pda_account: &AccountInfo<'a>,
+        token_2022_transfer: Option<(&AccountInfo<'a>, &[AccountInfo<'a>])>,
+        program_id: &Pubkey,
+    ) -> Result<u64, ProgramError> {
+        let pdas_temp_token_account_info = utils::unpack_token_account(pdas_temp_token_account)?;
+
+        // the tokens can only go back to an account the initializer owns for the same mint as the temp account
+        let initializer_refund_token_account_info = utils::unpack_token_account(initializer_refund_token_account)?;
+        if initializer_refund_token_account_info.owner != *initializer_pubkey {
+            return Err(EscrowError::ExpectedAccountMismatch.into());
+        }
+        if initializer_refund_token_account_info.mint != pdas_temp_token_account_info.mint {
+            return Err(EscrowError::WrongMint.into());
+        }
+
+        let (pda, bump_seed) = Pubkey::find_program_address(&[b"escrow"], program_id);
+
+        msg!("Calling the token program to return tokens to the escrow's initializer...");
+        Self::transfer_tokens(
+            token_program,
+            pdas_temp_token_account,
+            initializer_refund_token_account,
+            pda_account,
+            token_2022_transfer,
+            pdas_temp_token_account_info.amount,
+            &[&[&b"escrow"[..], &[bump_seed]]],
+        )?;
+
+        let close_pda_temp_token_account_ix = utils::for_token_program(
+            spl_token::instruction::close_account(
+                &spl_token::id(),
+                pdas_temp_token_account.key,
+                initializers_main_account.key,
+                &pda,
+                &[&pda],
+            )?,
+            token_program.key,
+        );
+        msg!("Calling the token program to close pda's temp account...");
+        invoke_signed(
+            &close_pda_temp_token_account_ix,
+            &[
+                pdas_temp_token_account.clone(),
+                initializers_main_account.clone(),
+                pda_account.clone(),
+                token_program.clone(),
+            ],
+            &[&[&b"escrow"[..], &[bump_seed]]],
+        )?;
+
+
+        Ok(pdas_temp_token_account_info.amount)
+    }
+
+    /// Pays out, or burns, the share of the vault the initializer forfeits by cancelling before the offer expires
+    fn take_cancel_penalty<'a>(
+        escrow_info: &Escrow,
+        pdas_temp_token_account: &AccountInfo<'a>,
+        penalty_account: &AccountInfo<'a>,
+        token_program: &AccountInfo<'a>,
+        pda_account: &AccountInfo<'a>,
+        token_2022_transfer: Option<(&AccountInfo<'a>, &[AccountInfo<'a>])>,
+    ) -> ProgramResult {
+        let pdas_temp_token_account_info = utils::unpack_token_account(pdas_temp_token_account)?;
+        let penalty = escrow_info.cancel_penalty(pdas_temp_token_account_info.amount, Clock::get()?.unix_timestamp);
+        if penalty == 0 {
+            return Ok(());
+        }
+
+        let bump_seed = [escrow_info.bump_seed];
+        let signer_seeds: &[&[&[u8]]] = &[&[&b"escrow"[..], &bump_seed]];
+
+        if escrow_info.penalty_recipient != Pubkey::default() {
+            if *penalty_account.key != escrow_info.penalty_recipient {
+                return Err(EscrowError::InvalidPenalty.into());
+            }
+            msg!("Calling the token program to pay the cancellation penalty...");
+            return Self::transfer_tokens(
+                token_program,
+                pdas_temp_token_account,
+                penalty_account,
+                pda_account,
+                token_2022_transfer,
+                penalty,
+                signer_seeds,
+            );
+        }
+
+        if *penalty_account.key != pdas_temp_token_account_info.mint {
+            return Err(EscrowError::InvalidPenalty.into());
+        }
+        let burn_ix = utils::for_token_program(
+            spl_token::instruction::burn(
+                &spl_token::id(),
+                pdas_temp_token_account.key,
+                penalty_account.key,
+                pda_account.key,
+                &[],
+                penalty,
+            )?,
+            token_program.key,
+        );
+        msg!("Calling the token program to burn the cancellation penalty...");
+        invoke_signed(
+            &burn_ix,
+            &[
+                pdas_temp_token_account.clone(),
+                penalty_account.clone(),
+                pda_account.clone(),
+                token_program.clone(),
+            ],
+            signer_seeds,
+        )
+    }
+
+    /// Takes the optional trailing history accounts (compression program, tree, noop program), if the first one is there the other two have to be as well
+    fn next_history_accounts<'a, 'b>(
+        account_info_iter: &mut std::slice::Iter<'a, AccountInfo<'b>>,
+    ) -> Result<Option<[&'a AccountInfo<'b>; 3]>, ProgramError> {
+        let compression_program = match next_account_info(account_info_iter) {
+            Ok(account) => account,
+            Err(_) => return Ok(None),
+        };
+        let merkle_tree = next_account_info(account_info_iter)?;
+        let noop_program = next_account_info(account_info_iter)?;
+        Ok(Some([compression_program, merkle_tree, noop_program]))
+    }
+
+    fn process_init_history(
+        accounts: &[AccountInfo],
+        max_depth: u32,
+        max_buffer_size: u32,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let history_accounts = Self::next_history_accounts(account_info_iter)?
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let pda_account = next_account_info(account_info_iter)?;
+
+        let (pda, bump_seed) = Pubkey::find_program_address(&[b"escrow"], program_id);
+        if pda != *pda_account.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+
+        history::init_tree(&history_accounts, pda_account, bump_seed, max_depth, max_buffer_size)
+    }
+
+    fn process_cancel(
+        accounts: &[AccountInfo],
+        hook_accounts: u8,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let (accounts, stats_accounts) = Self::split_stats_accounts(accounts, 2, program_id);
+        let account_info_iter = &mut accounts.iter();
+        let initializer = next_account_info(account_info_iter)?;
+        let pdas_temp_token_account = next_account_info(account_info_iter)?;
+        let initializer_refund_token_account = next_account_info(account_info_iter)?;
+        let escrow_account = next_account_info(account_info_iter)?;
+
+        let escrow_info = Self::unpack_initialized_escrow(escrow_account, program_id)?;
+
+        // with Token-2022 the offered mint and its transfer hook accounts sit between the PDA and any multisig signers, then the penalty
+        // account if there's a penalty and the deposit recipient if there's a creation deposit
+        let uses_token_2022 = accounts.get(4).map_or(false, |token_program| *token_program.key == utils::spl_token_2022::id());
+        let has_penalty = escrow_info.cancel_penalty_bps != 0;
+        let has_deposit = escrow_info.creation_deposit != 0;
+        let first_signer = if uses_token_2022 { 7 + hook_accounts as usize } else { 6 } + has_penalty as usize + has_deposit as usize;
+        let multisig_signers = accounts.get(first_signer..).unwrap_or(&[]);
+        Self::check_initializer_signature(initializer, multisig_signers)?;
+
+        // the token program won't let the PDA empty, close or hand back a frozen vault, so cancelling one parks the escrow where no
+        // taker can reach it and a Cancel once the freeze authority thaws the vault returns the tokens
+        let vault_frozen = !escrow_info.is_subscription && utils::is_frozen(pdas_temp_token_account);
+        let was_pending = escrow_info.status == EscrowStatus::CancelPending;
+        if was_pending && vault_frozen {
+            return Err(EscrowError::AccountFrozen.into());
+        }
+        let parks = vault_frozen && !escrow_info.is_delegated; // a delegated escrow's tokens never left the initializer's account
+        let next_status = escrow_info.status.transition(if parks { EscrowStatus::CancelPending } else { EscrowStatus::Cancelled })?;
+
+        if escrow_info.has_taker_deposit() {
+            return Err(EscrowError::TakerSideDeposited.into());
+        }
+
+        let clock = Clock::get()?;
+        if escrow_info.has_live_commit(clock.slot) { // the initializer can't pull the offer out from under a taker mid reveal
+            return Err(EscrowError::OfferCommitted.into());
+        }
+
+        if escrow_info.temp_token_account_pubkey != *pdas_temp_token_account.key {
+            return Err(EscrowError::TempAccountMismatch.into());
+        }
+
+        if escrow_info.initializer_pubkey != *initializer.key {
+            return Err(EscrowError::InitializerMismatch.into());
+        }
+
+        let token_program = next_account_info(account_info_iter)?;
+        Self::check_any_token_program(token_program)?;
+        let pda_account = next_account_info(account_info_iter)?;
+
+        let token_2022_transfer = if uses_token_2022 {
+            let offered_mint = next_account_info(account_info_iter)?;
+            Some((offered_mint, next_account_infos(account_info_iter, hook_accounts as usize)?))
+        } else {
+            None
+        };
+        let penalty_account = if has_penalty { Some(next_account_info(account_info_iter)?) } else { None };
+        let deposit_recipient = if has_deposit { Some(next_account_info(account_info_iter)?) } else { None };
+        let mut escrow_info = escrow_info;
+
+        if parks {
+            // the penalty can't be taken out of a frozen vault, and parking shouldn't be a way round it
+            let vault_amount = utils::unpack_token_account(pdas_temp_token_account)?.amount;
+            if escrow_info.cancel_penalty(vault_amount, clock.unix_timestamp) != 0 {
+                return Err(EscrowError::AccountFrozen.into());
+            }
+        } else if escrow_info.is_subscription {
+            // an open subscription holds no tokens yet, once a taker has deposited only they can cancel it
+            if escrow_info.status != EscrowStatus::Open {
+                return Err(EscrowError::InvalidEscrowStatus.into());
+            }
+        } else if escrow_info.is_delegated && vault_frozen {
+            msg!("The initializer's account is frozen, leaving the PDA's delegation for them to revoke once it's thawed");
+        } else if escrow_info.is_delegated {
+            // the tokens never left the initializer's account, so just take the PDA's approval away
+            Self::revoke_delegation(token_program, pdas_temp_token_account, initializer, multisig_signers)?;
+        } else {
+            if let Some(penalty_account) = penalty_account {
+                Self::take_cancel_penalty(&escrow_info, pdas_temp_token_account, penalty_account, token_program, pda_account, token_2022_transfer)?;
+            }
+            Self::return_temp_account_tokens(
+                pdas_temp_token_account,
+                initializer,
+                initializer_refund_token_account,
+                &escrow_info.initializer_pubkey,
+                token_program,
+                pda_account,
+                token_2022_transfer,
+                program_id,
+            )?;
+        }
+
+        if let Some(deposit_recipient) = deposit_recipient {
+            Self::settle_creation_deposit(&escrow_info, escrow_account, deposit_recipient, initializer, parks, clock.slot)?;
+        }
+
+        // only escrows made by InitEscrow are counted as open, and a parked one stopped being open when it was parked
+        if let (Some(stats_accounts), true) = (stats_accounts, escrow_info.counts_as_open() && !was_pending) {
+            Self::update_stats(stats_accounts, &escrow_info.initializer_pubkey, program_id, |stats| {
+                stats.open_escrows = stats.open_escrows.saturating_sub(1);
+            })?;
+        }
+
+        if parks {
+            msg!("The vault is frozen, parking the escrow until it's thawed...");
+            escrow_info.status = next_status;
+            escrow_info.creation_deposit = 0; // settled above, the Cancel after the thaw has no deposit to pass
+            Escrow::pack(escrow_info, &mut escrow_account.data.borrow_mut())?;
+        } else {
+            msg!("Closing the escrow account...");
+            utils::close_account(escrow_account, initializer)?;
+        }
+
+        Ok(())
+    }
+
+    /// Cancelling too soon after creating the escrow forfeits the creation deposit to its recipient, otherwise it goes back to the
+    /// initializer with the rent when the escrow is closed, or straight away when `refund_now` because the escrow stays open
+    fn settle_creation_deposit<'a>(
+        escrow_info: &Escrow,
+        escrow_account: &AccountInfo<'a>,
+        deposit_recipient: &AccountInfo<'a>,
+        initializer: &AccountInfo<'a>,
+        refund_now: bool,
+        slot: u64,
+    ) -> ProgramResult {
+        if *deposit_recipient.key != escrow_info.deposit_recipient {
+            return Err(EscrowError::DepositRecipientMismatch.into());
+        }
+        if slot < escrow_info.deposit_locked_until_slot {
+            msg!("Forfeiting the creation deposit...");
+            utils::transfer_lamports(escrow_account, deposit_recipient, escrow_info.creation_deposit)?;
+        } else if refund_now {
+            utils::transfer_lamports(escrow_account, initializer, escrow_info.creation_deposit)?;
+        }
+        Ok(())
+    }
+
+    fn process_init_subscription(
+        accounts: &[AccountInfo],
+        installment_amount: u64,
+        interval_slots: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let initializer = next_account_info(account_info_iter)?;
+
+        if !initializer.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let token_to_receive_account = next_account_info(account_info_iter)?;
+        if *token_to_receive_account.owner != spl_token::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let escrow_account = next_account_info(account_info_iter)?;
+        let rent = &Rent::from_account_info(next_account_info(account_info_iter)?)?;
+
+        if !rent.is_exempt(escrow_account.lamports(), escrow_account.data_len()) {
+            return Err(EscrowError::NotRentExempt.into());
+        }
+
+        if installment_amount == 0 || interval_slots == 0 {
+            return Err(EscrowError::InvalidSubscriptionTerms.into());
+        }
+
+        Self::check_escrow_account(escrow_account, program_id)?;
+        let mut escrow_info = Escrow::unpack_unchecked(&escrow_account.data.borrow())?;
+        if escrow_info.is_initialized() {
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+
+        escrow_info.is_initialized = true;
+        escrow_info.initializer_pubkey = *initializer.key;
+        escrow_info.initializer_token_to_receive_account_pubkey = *token_to_receive_account.key;
+        escrow_info.status = EscrowStatus::Open;
+        escrow_info.created_at_slot = Clock::get()?.slot;
+        escrow_info.is_subscription = true;
+        escrow_info.kind = EscrowKind::Subscription;
+        escrow_info.installment_amount = installment_amount;
+        escrow_info.interval_slots = interval_slots;
+
+        Escrow::pack(escrow_info, &mut escrow_account.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    fn process_subscribe(
+        accounts: &[AccountInfo],
+        amount: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let taker = next_account_info(account_info_iter)?;
+
+        if !taker.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let temp_token_account = next_account_info(account_info_iter)?;
+        let initializer_token_to_receive_account = next_account_info(account_info_iter)?;
+        let escrow_account = next_account_info(account_info_iter)?;
+
+        let mut escrow_info = Self::unpack_initialized_escrow(escrow_account, program_id)?;
+
+        if !escrow_info.is_subscription {
+            return Err(EscrowError::WrongEscrowMode.into());
+        }
+
+        if escrow_info.status != EscrowStatus::Open { // someone has already subscribed
+            return Err(EscrowError::InvalidEscrowStatus.into());
+        }
+
+        if escrow_info.initializer_token_to_receive_account_pubkey != *initializer_token_to_receive_account.key {
+            return Err(EscrowError::ReceiveAccountMismatch.into());
+        }
+
+        // the deposit has to be in the token the installments are paid in
+        let temp_token_account_info = TokenAccount::unpack(&temp_token_account.data.borrow())?;
+        let initializer_token_to_receive_account_info = TokenAccount::unpack(&initializer_token_to_receive_account.data.borrow())?;
+        if temp_token_account_info.mint != initializer_token_to_receive_account_info.mint {
+            return Err(EscrowError::WrongMint.into());
+        }
+        if temp_token_account_info.close_authority.is_some() {
+            return Err(EscrowError::CloseAuthoritySet.into());
+        }
+
+        if amount != temp_token_account_info.amount {
+            return Err(EscrowError::ExpectedAmountMismatch.into());
+        }
+
+        escrow_info.status = escrow_info.status.transition(EscrowStatus::PartiallyFilled)?;
+        escrow_info.temp_token_account_pubkey = *temp_token_account.key;
+        escrow_info.taker_pubkey = *taker.key;
+        escrow_info.last_claim_slot = Clock::get()?.slot; // the first installment becomes claimable one interval from now
+
+        Escrow::pack(escrow_info, &mut escrow_account.data.borrow_mut())?;
+        let (pda, _bump_seed) = Pubkey::find_program_address(&[b"escrow"], program_id);
+
+        let token_program = next_account_info(account_info_iter)?;
+        Self::check_token_program(token_program)?;
+        let owner_change_ix = spl_token::instruction::set_authority(
+            token_program.key,
+            temp_token_account.key,
+            Some(&pda),
+            spl_token::instruction::AuthorityType::AccountOwner,
+            taker.key,
+            &[&taker.key],
+        )?;
+
+        msg!("Calling the token program to transfer token account ownership...");
+        invoke(
+            &owner_change_ix,
+            &[
+                temp_token_account.clone(),
+                taker.clone(),
+                token_program.clone(),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    fn process_claim_installment(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let initializer = next_account_info(account_info_iter)?;
+
+        if !initializer.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let pdas_temp_token_account = next_account_info(account_info_iter)?;
+        let initializer_token_to_receive_account = next_account_info(account_info_iter)?;
+        let escrow_account = next_account_info(account_info_iter)?;
+        let takers_main_account = next_account_info(account_info_iter)?;
+
+        let mut escrow_info = Self::unpack_initialized_escrow(escrow_account, program_id)?;
+        Self::check_subscription_accounts(
+            &escrow_info,
+            initializer.key,
+            pdas_temp_token_account.key,
+            initializer_token_to_receive_account.key,
+            takers_main_account.key,
+        )?;
+
+        let slot = Clock::get()?.slot;
+        let next_claim_slot = escrow_info.last_claim_slot
+            .checked_add(escrow_info.interval_slots)
+            .ok_or(EscrowError::AmountOverflow)?;
+        if slot < next_claim_slot {
+            return Err(EscrowError::InstallmentNotDue.into());
+        }
+
+        let token_program = next_account_info(account_info_iter)?;
+        Self::check_token_program(token_program)?;
+        let pda_account = next_account_info(account_info_iter)?;
+        let (_pda, bump_seed) = Pubkey::find_program_address(&[b"escrow"], program_id);
+
+        // the last installment is whatever is left if the deposit doesn't divide evenly
+        let deposit_remaining = TokenAccount::unpack(&pdas_temp_token_account.data.borrow())?.amount;
+        let installment = escrow_info.installment_amount.min(deposit_remaining);
+        Self::transfer_from_vault(
+            token_program,
+            pdas_temp_token_account,
+            initializer_token_to_receive_account,
+            pda_account,
+            bump_seed,
+            installment,
+        )?;
+
+        // advance by exactly one interval rather than to the current slot, so missed installments can still be claimed one at a time
+        escrow_info.last_claim_slot = next_claim_slot;
+
+        if installment < deposit_remaining {
+            Escrow::pack(escrow_info, &mut escrow_account.data.borrow_mut())?;
+            return Ok(());
+        }
+
+        escrow_info.status.transition(EscrowStatus::Completed)?;
+
+        let close_pda_temp_token_account_ix = spl_token::instruction::close_account(
+            token_program.key,
+            pdas_temp_token_account.key,
+            takers_main_account.key,
+            pda_account.key,
+            &[pda_account.key],
+        )?;
+        msg!("Calling the token program to close pda's temp account...");
+        invoke_signed(
+            &close_pda_temp_token_account_ix,
+            &[
+                pdas_temp_token_account.clone(),
+                takers_main_account.clone(),
+                pda_account.clone(),
+                token_program.clone(),
+            ],
+            &[&[&b"escrow"[..], &[bump_seed]]],
+        )?;
+
+        msg!("Closing the escrow account...");
+        utils::close_account(escrow_account, initializer)?;
+
+        Ok(())
+    }
+
+    fn process_cancel_subscription(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let taker = next_account_info(account_info_iter)?;
+
+        if !taker.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let pdas_temp_token_account = next_account_info(account_info_iter)?;
+        let taker_refund_token_account = next_account_info(account_info_iter)?;
+        let initializer_token_to_receive_account = next_account_info(account_info_iter)?;
+        let escrow_account = next_account_info(account_info_iter)?;
+        let initializers_main_account = next_account_info(account_info_iter)?;
+
+        let escrow_info = Self::unpack_initialized_escrow(escrow_account, program_id)?;
+        Self::check_subscription_accounts(
+            &escrow_info,
+            initializers_main_account.key,
+            pdas_temp_token_account.key,
+            initializer_token_to_receive_account.key,
+            taker.key,
+        )?;
+        escrow_info.status.transition(EscrowStatus::Cancelled)?;
+
+        let token_program = next_account_info(account_info_iter)?;
+        Self::check_token_program(token_program)?;
+        let pda_account = next_account_info(account_info_iter)?;
+        let (_pda, bump_seed) = Pubkey::find_program_address(&[b"escrow"], program_id);
+
+        // installments that fell due before the cancel still belong to the initializer
+        let elapsed_intervals = Clock::get()?.slot.saturating_sub(escrow_info.last_claim_slot) / escrow_info.interval_slots;
+        let deposit_remaining = TokenAccount::unpack(&pdas_temp_token_account.data.borrow())?.amount;
+        let owed = elapsed_intervals.saturating_mul(escrow_info.installment_amount).min(deposit_remaining);
+        if owed > 0 {
+            Self::transfer_from_vault(
+                token_program,
+                pdas_temp_token_account,
+                initializer_token_to_receive_account,
+                pda_account,
+                bump_seed,
+                owed,
+            )?;
+        }
+
+        Self::return_temp_account_tokens(
+            pdas_temp_token_account,
+            taker,
+            taker_refund_token_account,
+            &escrow_info.taker_pubkey,
+            token_program,
+            pda_account,
+            None,
+            program_id,
+        )?;
+
+        msg!("Closing the escrow account...");
+        utils::close_account(escrow_account, initializers_main_account)?;
+
+        Ok(())
+    }
+
+    fn process_top_up(
+        accounts: &[AccountInfo],
+        additional_amount: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let initializer = next_account_info(account_info_iter)?;
+
+        if !initializer.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let source_token_account = next_account_info(account_info_iter)?;
+        let pdas_temp_token_account = next_account_info(account_info_iter)?;
+        let escrow_account = next_account_info(account_info_iter)?;
+
+        let mut escrow_info = Self::unpack_initialized_escrow(escrow_account, program_id)?;
+
+        if escrow_info.is_subscription {
+            return Err(EscrowError::WrongEscrowMode.into());
+        }
+
+        if escrow_info.status != EscrowStatus::Open && escrow_info.status != EscrowStatus::PartiallyFilled {
+            return Err(EscrowError::InvalidEscrowStatus.into());
+        }
+
+        if escrow_info.initializer_pubkey != *initializer.key {
+            return Err(EscrowError::InitializerMismatch.into());
+        }
+
+        if escrow_info.temp_token_account_pubkey != *pdas_temp_token_account.key {
+            return Err(EscrowError::TempAccountMismatch.into());
+        }
+
+        let token_program = next_account_info(account_info_iter)?;
+        Self::check_token_program(token_program)?;
+        let pda_account = next_account_info(account_info_iter)?;
+
+        if escrow_info.is_delegated {
+            // the tokens stay where they are, the PDA is just approved for more of them
+            escrow_info.offered_amount = escrow_info.offered_amount
+                .checked_add(additional_amount)
+                .ok_or(EscrowError::AmountOverflow)?;
+
+            let (pda, _bump_seed) = Pubkey::find_program_address(&[b"escrow"], program_id);
+            let approve_ix = spl_token::instruction::approve(
+                token_program.key,
+                pdas_temp_token_account.key,
+                &pda,
+                initializer.key,
+                &[&initializer.key],
+                escrow_info.offered_amount,
+            )?;
+            msg!("Calling the token program to raise the PDA's delegated amount...");
+            invoke(
+                &approve_ix,
+                &[
+                    pdas_temp_token_account.clone(),
+                    pda_account.clone(),
+                    initializer.clone(),
+                    token_program.clone(),
+                ],
+            )?;
+        } else {
+            // the offer is whatever the temp account holds (or for a reusable escrow, lots of it), so the exchange picks the extra tokens up without any state change
+            let transfer_to_temp_ix = spl_token::instruction::transfer(
+                token_program.key,
+                source_token_account.key,
+                pdas_temp_token_account.key,
+                initializer.key,
+                &[&initializer.key],
+                additional_amount,
+            )?;
+            msg!("Calling the token program to transfer tokens to the pda's temp account...");
+            invoke(
+                &transfer_to_temp_ix,
+                &[
+                    source_token_account.clone(),
+                    pdas_temp_token_account.clone(),
+                    initializer.clone(),
+                    token_program.clone(),
+                ],
+            )?;
+        }
+
+        Escrow::pack(escrow_info, &mut escrow_account.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    fn process_init_config(
+        accounts: &[AccountInfo],
+        max_referral_bps: u16,
+        creation_deposit: Option<(u64, u64, Pubkey)>,
+        fee_tiers: Option<Vec<FeeTier>>,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let admin = next_account_info(account_info_iter)?;
+
+        if !admin.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let config_account = next_account_info(account_info_iter)?;
+        let system_program = next_account_info(account_info_iter)?;
+
+        let (config_pda, bump_seed) = Pubkey::find_program_address(&[b"config"], program_id);
+        if config_pda != *config_account.key {
+            return Err(EscrowError::InvalidConfigAccount.into());
+        }
+
+        if max_referral_bps > Config::MAX_BPS {
+            return Err(EscrowError::ReferralFeeTooHigh.into());
+        }
+
+        // the config lives at a PDA so only this program can create it, which also means there can only ever be one
+        let create_config_ix = system_instruction::create_account(
+            admin.key,
+            config_account.key,
+            Rent::get()?.minimum_balance(Config::LEN),
+            Config::LEN as u64,
+            program_id,
+        );
+        msg!("Calling the system program to create the config account...");
+        invoke_signed(
+            &create_config_ix,
+            &[
+                admin.clone(),
+                config_account.clone(),
+                system_program.clone(),
+            ],
+            &[&[&b"config"[..], &[bump_seed]]],
+        )?;
+
+        let (creation_deposit, deposit_lock_slots, fee_recipient) = creation_deposit.unwrap_or_default();
+        let mut config = Config {
+            is_initialized: true,
+            admin_pubkey: *admin.key,
+            max_referral_bps,
+            creation_deposit,
+            deposit_lock_slots,
+            fee_recipient,
+            ..Config::default()
+        };
+        if let Some(fee_tiers) = fee_tiers {
+            Self::set_fee_tiers(&mut config, &fee_tiers)?;
+        }
+        Config::pack(config, &mut config_account.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    fn process_update_config(
+        accounts: &[AccountInfo],
+        max_referral_bps: u16,
+        creation_deposit: Option<(u64, u64, Pubkey)>,
+        fee_tiers: Option<Vec<FeeTier>>,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let admin = next_account_info(account_info_iter)?;
+
+        if !admin.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let config_account = next_account_info(account_info_iter)?;
+        utils::check_writable(config_account)?;
+        let mut config = Self::unpack_config(config_account, program_id)?;
+
+        if config.admin_pubkey != *admin.key {
+            return Err(EscrowError::AdminMismatch.into());
+        }
+
+        if max_referral_bps > Config::MAX_BPS {
+            return Err(EscrowError::ReferralFeeTooHigh.into());
+        }
+
+        config.max_referral_bps = max_referral_bps;
+        if let Some((creation_deposit, deposit_lock_slots, fee_recipient)) = creation_deposit {
+            config.creation_deposit = creation_deposit;
+            config.deposit_lock_slots = deposit_lock_slots;
+            config.fee_recipient = fee_recipient;
+        }
+        if let Some(fee_tiers) = fee_tiers {
+            Self::set_fee_tiers(&mut config, &fee_tiers)?;
+        }
+
+        // a config from before the current layout is too short for the newer settings, the admin pays to grow it
+        if config_account.data_len() < Config::LEN {
+            let system_program = next_account_info(account_info_iter)?;
+            let rent_shortfall = Rent::get()?
+                .minimum_balance(Config::LEN)
+                .saturating_sub(config_account.lamports());
+            if rent_shortfall > 0 {
+                msg!("Calling the system program to top up the config's rent...");
+                invoke(
+                    &system_instruction::transfer(admin.key, config_account.key, rent_shortfall),
+                    &[
+                        admin.clone(),
+                        config_account.clone(),
+                        system_program.clone(),
+                    ],
+                )?;
+            }
+            config_account.realloc(Config::LEN, false)?; // every byte is rewritten by the pack below
+        }
+        Config::pack(config, &mut config_account.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    fn set_fee_tiers(config: &mut Config, fee_tiers: &[FeeTier]) -> ProgramResult {
+        Config::check_fee_tiers(fee_tiers)?;
+        config.fee_tiers = [FeeTier::default(); Config::MAX_FEE_TIERS];
+        config.fee_tiers[..fee_tiers.len()].copy_from_slice(fee_tiers);
+        config.fee_tier_count = fee_tiers.len() as u8;
+        Ok(())
+    }
+
+    /// Unpacks the config, making sure it's really the program's config PDA and not a lookalike
+    fn unpack_config(config_account: &AccountInfo, program_id: &Pubkey) -> Result<Config, ProgramError> {
+        let (config_pda, _bump_seed) = Pubkey::find_program_address(&[b"config"], program_id);
+        if config_pda != *config_account.key {
+            return Err(EscrowError::InvalidConfigAccount.into());
+        }
+        Self::check_program_account(config_account, program_id)?;
+        Config::unpack_any_len(&config_account.data.borrow())
+    }
+
+    /// Like unpack_config, but a config PDA that was never created is fine and means the defaults
+    fn unpack_optional_config(config_account: &AccountInfo, program_id: &Pubkey) -> Result<Option<Config>, ProgramError> {
+        if config_account.data_is_empty() {
+            let (config_pda, _bump_seed) = Pubkey::find_program_address(&[b"config"], program_id);
+            if config_pda != *config_account.key {
+                return Err(EscrowError::InvalidConfigAccount.into());
+            }
+            return Ok(None);
+        }
+        Self::unpack_config(config_account, program_id).map(Some)
+    }
+
+    fn process_init_stats(
+        accounts: &[AccountInfo],
+        per_initializer: bool,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let payer = next_account_info(account_info_iter)?;
+
+        if !payer.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let stats_account = next_account_info(account_info_iter)?;
+        let system_program = next_account_info(account_info_iter)?;
+
+        let owner_pubkey = if per_initializer { *payer.key } else { Pubkey::default() };
+        let (stats_pda, bump_seed) = if per_initializer {
+            Pubkey::find_program_address(&[b"stats", owner_pubkey.as_ref()], program_id)
+        } else {
+            Pubkey::find_program_address(&[b"stats"], program_id)
+        };
+        if stats_pda != *stats_account.key {
+            return Err(EscrowError::InvalidStatsAccount.into());
+        }
+
+        let create_stats_ix = system_instruction::create_account(
+            payer.key,
+            stats_account.key,
+            Rent::get()?.minimum_balance(Stats::LEN),
+            Stats::LEN as u64,
+            program_id,
+        );
+        let bump = [bump_seed];
+        let seeds: &[&[u8]] = if per_initializer {
+            &[b"stats", owner_pubkey.as_ref(), &bump]
+        } else {
+            &[b"stats", &bump]
+        };
+        msg!("Calling the system program to create the stats account...");
+        invoke_signed(
+            &create_stats_ix,
+            &[
+                payer.clone(),
+                stats_account.clone(),
+                system_program.clone(),
+            ],
+            &[seeds],
+        )?;
+
+        let stats = Stats {
+            is_initialized: true,
+            owner_pubkey,
+            bump_seed,
+            ..Stats::default()
+        };
+        Stats::pack(stats, &mut stats_account.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    fn process_init_offer_counter(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let payer = next_account_info(account_info_iter)?;
+
+        if !payer.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let offer_counter_account = next_account_info(account_info_iter)?;
+        let system_program = next_account_info(account_info_iter)?;
+
+        let (counter_pda, bump_seed) = Pubkey::find_program_address(&[b"offer_counter"], program_id);
+        if counter_pda != *offer_counter_account.key {
+            return Err(EscrowError::InvalidOfferCounter.into());
+        }
+
+        let create_counter_ix = system_instruction::create_account(
+            payer.key,
+            offer_counter_account.key,
+            Rent::get()?.minimum_balance(OfferCounter::LEN),
+            OfferCounter::LEN as u64,
+            program_id,
+        );
+        msg!("Calling the system program to create the offer counter...");
+        invoke_signed(
+            &create_counter_ix,
+            &[
+                payer.clone(),
+                offer_counter_account.clone(),
+                system_program.clone(),
+            ],
+            &[&[b"offer_counter", &[bump_seed]]],
+        )?;
+
+        let offer_counter = OfferCounter {
+            is_initialized: true,
+            bump_seed,
+            last_offer_id: 0,
+        };
+        OfferCounter::pack(offer_counter, &mut offer_counter_account.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    fn process_commit_exchange(
+        accounts: &[AccountInfo],
+        hash: [u8; 32],
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let taker = next_account_info(account_info_iter)?;
+
+        if !taker.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let escrow_account = next_account_info(account_info_iter)?;
+        let initializers_main_account = next_account_info(account_info_iter)?;
+        let system_program = next_account_info(account_info_iter)?;
+
+        let mut escrow_info = Self::unpack_initialized_escrow(escrow_account, program_id)?;
+        escrow_info.status.transition(EscrowStatus::Completed)?; // only an escrow that can still be taken can be committed to
+
+        if escrow_info.is_subscription {
+            return Err(EscrowError::WrongEscrowMode.into());
+        }
+
+        if escrow_info.has_taker_deposit() {
+            return Err(EscrowError::TakerSideDeposited.into());
+        }
+
+        let clock = Clock::get()?;
+        if escrow_info.has_live_commit(clock.slot) {
+            return Err(EscrowError::OfferCommitted.into());
+        }
+
+        msg!("Calling the system program to take the commit bond...");
+        invoke(
+            &system_instruction::transfer(taker.key, escrow_account.key, Escrow::COMMIT_BOND),
+            &[
+                taker.clone(),
+                escrow_account.clone(),
+                system_program.clone(),
+            ],
+        )?;
+
+        // a stale commit held the offer up for nothing, so its bond goes to the initializer. Only after the CPI above, which
+        // would otherwise see the escrow's lamports out of step with the initializer's
+        if escrow_info.commit_taker != Pubkey::default() {
+            if escrow_info.initializer_pubkey != *initializers_main_account.key {
+                return Err(EscrowError::InitializerMismatch.into());
+            }
+            msg!("Slashing the bond of a stale commit...");
+            utils::transfer_lamports(escrow_account, initializers_main_account, Escrow::COMMIT_BOND)?;
+        }
+
+        escrow_info.commit_taker = *taker.key;
+        escrow_info.commit_hash = hash;
+        escrow_info.commit_slot = clock.slot;
+        Escrow::pack(escrow_info, &mut escrow_account.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    fn process_reveal_exchange(
+        accounts: &[AccountInfo],
+        amount: u64,
+        salt: [u8; 32],
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let taker = accounts.get(0).ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let escrow_account = accounts.get(6).ok_or(ProgramError::NotEnoughAccountKeys)?;
+
+        if !taker.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let mut escrow_info = Self::unpack_initialized_escrow(escrow_account, program_id)?;
+        if escrow_info.commit_taker != *taker.key
+            || !escrow_info.has_live_commit(Clock::get()?.slot)
+            || escrow_info.commit_hash != Escrow::commit_hash_of(taker.key, amount, &salt)
+        {
+            return Err(EscrowError::InvalidReveal.into());
+        }
+
+        // the commit is used up, so the exchange below sees an escrow nobody has reserved and hands the bond back
+        escrow_info.commit_taker = Pubkey::default();
+        escrow_info.commit_hash = [0; 32];
+        escrow_info.commit_slot = 0;
+        Escrow::pack(escrow_info, &mut escrow_account.data.borrow_mut())?;
+
+        Self::process_exchange(accounts, amount, None, 0, 0, 0, false, ExchangeMode::Reveal, program_id)
+    }
+
+    fn process_migrate(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let payer = next_account_info(account_info_iter)?;
+
+        if !payer.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let escrow_account = next_account_info(account_info_iter)?;
+        let system_program = next_account_info(account_info_iter)?;
+
+        Self::check_escrow_account(escrow_account, program_id)?;
+        let mut escrow_info = Escrow::unpack_any_version(&escrow_account.data.borrow())?;
+        if escrow_info.version == Escrow::VERSION {
+            msg!("Escrow is already at version {}", Escrow::VERSION);
+            return Ok(());
+        }
+
+        // an escrow ExtendEscrow grew before an upgrade keeps its extra room, the new fields go into it
+        let new_len = escrow_account.data_len().max(Escrow::LEN);
+        let rent_shortfall = Rent::get()?
+            .minimum_balance(new_len)
+            .saturating_sub(escrow_account.lamports());
+        if rent_shortfall > 0 {
+            msg!("Calling the system program to top up the escrow's rent...");
+            invoke(
+                &system_instruction::transfer(payer.key, escrow_account.key, rent_shortfall),
+                &[
+                    payer.clone(),
+                    escrow_account.clone(),
+                    system_program.clone(),
+                ],
+            )?;
+        }
+
+        // an escrow from before the bump was stored reads it as 0, the PDA only has the one seed so the bump is the same for every escrow
+        let (_pda, bump_seed) = Pubkey::find_program_address(&[b"escrow"], program_id);
+        escrow_info.bump_seed = bump_seed;
+
+        msg!("Migrating escrow from version {} to {}", escrow_info.version, Escrow::VERSION);
+        escrow_account.realloc(new_len, false)?; // the layout's bytes are rewritten by the pack below and any past it are already zero
+        Escrow::pack(escrow_info, &mut escrow_account.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    fn process_extend_escrow(
+        accounts: &[AccountInfo],
+        new_len: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let initializer = next_account_info(account_info_iter)?;
+
+        if !initializer.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let escrow_account = next_account_info(account_info_iter)?;
+        let system_program = next_account_info(account_info_iter)?;
+
+        let escrow_info = Self::unpack_initialized_escrow(escrow_account, program_id)?;
+        if escrow_info.initializer_pubkey != *initializer.key {
+            return Err(EscrowError::InitializerMismatch.into());
+        }
+        if new_len <= escrow_account.data_len() as u64 {
+            return Err(EscrowError::InvalidEscrowLength.into());
+        }
+        let new_len = new_len as usize;
+
+        let rent_shortfall = Rent::get()?
+            .minimum_balance(new_len)
+            .saturating_sub(escrow_account.lamports());
+        if rent_shortfall > 0 {
+            msg!("Calling the system program to top up the escrow's rent...");
+            invoke(
+                &system_instruction::transfer(initializer.key, escrow_account.key, rent_shortfall),
+                &[
+                    initializer.clone(),
+                    escrow_account.clone(),
+                    system_program.clone(),
+                ],
+            )?;
+        }
+
+        msg!("Extending escrow from {} to {} bytes", escrow_account.data_len(), new_len);
+        escrow_account.realloc(new_len, true) // zeroed, so whatever a later layout reads from it starts out as its default
+    }
+
+    fn process_fill_signed_offer(
+        accounts: &[AccountInfo],
+        offer: SignedOffer,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let taker = next_account_info(account_info_iter)?;
+
+        if !taker.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let taker_send_token_account = next_account_info(account_info_iter)?;
+        let taker_receive_token_account = next_account_info(account_info_iter)?;
+        let offered_token_account = next_account_info(account_info_iter)?;
+        let initializer_token_to_receive_account = next_account_info(account_info_iter)?;
+        let filled_offer_account = next_account_info(account_info_iter)?;
+        let instructions_sysvar = next_account_info(account_info_iter)?;
+        let token_program = next_account_info(account_info_iter)?;
+        let pda_account = next_account_info(account_info_iter)?;
+        let system_program = next_account_info(account_info_iter)?;
+
+        offer.check_signature(instructions_sysvar, program_id)?;
+        if offer.offered_amount == 0 || offer.expected_amount == 0 {
+            return Err(EscrowError::ZeroAmount.into());
+        }
+        if offer.is_expired(Clock::get()?.unix_timestamp) {
+            return Err(EscrowError::EscrowExpired.into());
+        }
+        if *offered_token_account.key != offer.offered_token_account_pubkey {
+            return Err(EscrowError::TempAccountMismatch.into());
+        }
+        if *initializer_token_to_receive_account.key != offer.initializer_token_to_receive_account_pubkey {
+            return Err(EscrowError::ReceiveAccountMismatch.into());
+        }
+
+        let nonce = offer.nonce.to_le_bytes();
+        let (filled_offer_pda, filled_offer_bump_seed) =
+            Pubkey::find_program_address(&[b"filled", offer.initializer_pubkey.as_ref(), &nonce], program_id);
+        if filled_offer_pda != *filled_offer_account.key {
+            return Err(EscrowError::InvalidFilledOfferAccount.into());
+        }
+        if !filled_offer_account.data_is_empty() {
+            return Err(EscrowError::OfferAlreadyFilled.into());
+        }
+
+        Self::check_token_program(token_program)?;
+        let (pda, bump_seed) = Pubkey::find_program_address(&[b"escrow"], program_id);
+        if *pda_account.key != pda {
+            return Err(ProgramError::InvalidSeeds);
+        }
+
+        // the initializer signed for their own tokens only, an account someone else approved the PDA on isn't theirs to sell
+        let offered_token_account_info = utils::unpack_token_account(offered_token_account)?;
+        if offered_token_account_info.owner != offer.initializer_pubkey {
+            return Err(EscrowError::InitializerMismatch.into());
+        }
+        if offered_token_account_info.delegate != COption::Some(pda) || offered_token_account_info.delegated_amount < offer.offered_amount {
+            return Err(EscrowError::DelegationRevoked.into());
+        }
+
+        let create_filled_offer_ix = system_instruction::create_account(
+            taker.key,
+            filled_offer_account.key,
+            Rent::get()?.minimum_balance(FilledOffer::LEN),
+            FilledOffer::LEN as u64,
+            program_id,
+        );
+        msg!("Calling the system program to record the offer as filled...");
+        invoke_signed(
+            &create_filled_offer_ix,
+            &[
+                taker.clone(),
+                filled_offer_account.clone(),
+                system_program.clone(),
+            ],
+            &[&[b"filled", offer.initializer_pubkey.as_ref(), &nonce, &[filled_offer_bump_seed]]],
+        )?;
+        let filled_offer = FilledOffer {
+            is_initialized: true,
+            initializer_pubkey: offer.initializer_pubkey,
+            taker_pubkey: *taker.key,
+            nonce: offer.nonce,
+        };
+        FilledOffer::pack(filled_offer, &mut filled_offer_account.data.borrow_mut())?;
+
+        msg!("Calling the token program to transfer tokens to the offer's initializer...");
+        Self::transfer_tokens(
+            token_program,
+            taker_send_token_account,
+            initializer_token_to_receive_account,
+            taker,
+            None,
+            offer.expected_amount,
+            &[],
+        )?;
+
+        msg!("Calling the token program to transfer the offered tokens to the taker...");
+        Self::transfer_tokens(
+            token_program,
+            offered_token_account,
+            taker_receive_token_account,
+            pda_account,
+            None,
+            offer.offered_amount,
+            &[&[&b"escrow"[..], &[bump_seed]]],
+        )?;
+
+        SettlementEvent {
+            escrow_pubkey: *filled_offer_account.key,
+            initializer_pubkey: offer.initializer_pubkey,
+            taker_pubkey: *taker.key,
+            offered_amount: offer.offered_amount,
+            paid_amount: offer.expected_amount,
+            paid_lamports: 0,
+            referrer_token_account: None,
+            referral_fee: 0,
+            protocol_fee: 0,
+            memo: Memo::default(),
+            offer_id: 0, // a signed offer never gets a number
+        }.emit();
+
+        Ok(())
+    }
+
+    fn process_recover_orphan(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let claimant = next_account_info(account_info_iter)?;
+
+        if !claimant.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let orphan_token_account = next_account_info(account_info_iter)?;
+        let token_program = next_account_info(account_info_iter)?;
+        Self::check_any_token_program(token_program)?;
+        let pda_account = next_account_info(account_info_iter)?;
+        let orphan_authority = next_account_info(account_info_iter)?;
+
+        let (pda, bump_seed) = Pubkey::find_program_address(&[b"escrow"], program_id);
+        if *pda_account.key != pda {
+            return Err(ProgramError::InvalidSeeds);
+        }
+
+        // the escrow instructions don't take in an account with a close authority now, and before they did nobody had a reason to make
+        // it the claimant's orphan authority, so an account closable by that can't be backing an escrow whichever escrows there are
+        let (orphan_authority_pda, orphan_bump_seed) = Pubkey::find_program_address(&[b"orphan", claimant.key.as_ref()], program_id);
+        let orphan = utils::unpack_token_account(orphan_token_account)?;
+        if orphan_token_account.owner != token_program.key
+            || orphan.owner != pda
+            || orphan.close_authority != COption::Some(orphan_authority_pda)
+            || *orphan_authority.key != orphan_authority_pda
+        {
+            return Err(EscrowError::InvalidOrphanClaim.into());
+        }
+
+        // nothing would ever sign for the orphan authority again, so take it off before the account goes back
+        let clear_close_authority_ix = utils::for_token_program(
+            spl_token::instruction::set_authority(
+                &spl_token::id(),
+                orphan_token_account.key,
+                None,
+                spl_token::instruction::AuthorityType::CloseAccount,
+                orphan_authority.key,
+                &[orphan_authority.key],
+            )?,
+            token_program.key,
+        );
+        msg!("Calling the token program to clear the orphan authority...");
+        invoke_signed(
+            &clear_close_authority_ix,
+            &[
+                orphan_token_account.clone(),
+                orphan_authority.clone(),
+                token_program.clone(),
+            ],
+            &[&[&b"orphan"[..], claimant.key.as_ref(), &[orphan_bump_seed]]],
+        )?;
+
+        Self::return_vault_ownership(token_program, orphan_token_account, claimant.key, pda_account, bump_seed)
+    }
+
+    fn process_match_offers(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let (accounts, stats_accounts) = Self::split_stats_accounts(accounts, 3, program_id);
+        let account_info_iter = &mut accounts.iter();
+        let matcher = next_account_info(account_info_iter)?;
+
+        if !matcher.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let first_escrow_account = next_account_info(account_info_iter)?;
+        let first_vault = next_account_info(account_info_iter)?;
+        let first_initializers_main_account = next_account_info(account_info_iter)?;
+        let first_receive_account = next_account_info(account_info_iter)?;
+        let second_escrow_account = next_account_info(account_info_iter)?;
+        let second_vault = next_account_info(account_info_iter)?;
+        let second_initializers_main_account = next_account_info(account_info_iter)?;
+        let second_receive_account = next_account_info(account_info_iter)?;
+
+        if first_escrow_account.key == second_escrow_account.key {
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        let first = Self::unpack_initialized_escrow(first_escrow_account, program_id)?;
+        let second = Self::unpack_initialized_escrow(second_escrow_account, program_id)?;
+        let first_vault_info = Self::check_matchable_offer(&first, first_vault, first_initializers_main_account, first_receive_account)?;
+        let second_vault_info = Self::check_matchable_offer(&second, second_vault, second_initializers_main_account, second_receive_account)?;
+
+        // each side has to sell what the other one wants
+        if first_vault_info.mint != utils::unpack_token_account(second_receive_account)?.mint
+            || second_vault_info.mint != utils::unpack_token_account(first_receive_account)?.mint
+        {
+            return Err(EscrowError::WrongMint.into());
+        }
+
+        // and give it at least what it asks for, what's left over is the spread
+        let first_spread = first_vault_info.amount.checked_sub(second.expected_amount).ok_or(EscrowError::OffersDontCross)?;
+        let second_spread = second_vault_info.amount.checked_sub(first.expected_amount).ok_or(EscrowError::OffersDontCross)?;
+
+        let token_program = next_account_info(account_info_iter)?;
+        Self::check_token_program(token_program)?;
+        let pda_account = next_account_info(account_info_iter)?;
+        let config_account = next_account_info(account_info_iter)?;
+        let config = Self::unpack_config(config_account, program_id)?;
+        let first_fee_account = next_account_info(account_info_iter)?;
+        let second_fee_account = next_account_info(account_info_iter)?;
+        for (fee_account, mint) in [(first_fee_account, first_vault_info.mint), (second_fee_account, second_vault_info.mint)] {
+            let fee_account_info = utils::unpack_token_account(fee_account)?;
+            if fee_account_info.owner != config.fee_recipient || fee_account_info.mint != mint {
+                return Err(EscrowError::FeeAccountMismatch.into());
+            }
+        }
+
+        let (_pda, bump_seed) = Pubkey::find_program_address(&[b"escrow"], program_id);
+        Self::transfer_from_vault(token_program, first_vault, second_receive_account, pda_account, bump_seed, second.expected_amount)?;
+        Self::transfer_from_vault(token_program, second_vault, first_receive_account, pda_account, bump_seed, first.expected_amount)?;
+        if first_spread > 0 {
+            msg!("Paying the spread to the fee recipient...");
+            Self::transfer_from_vault(token_program, first_vault, first_fee_account, pda_account, bump_seed, first_spread)?;
+        }
+        if second_spread > 0 {
+            msg!("Paying the spread to the fee recipient...");
+            Self::transfer_from_vault(token_program, second_vault, second_fee_account, pda_account, bump_seed, second_spread)?;
+        }
+        Self::close_vault(token_program, first_vault, first_initializers_main_account, pda_account, bump_seed)?;
+        Self::close_vault(token_program, second_vault, second_initializers_main_account, pda_account, bump_seed)?;
+
+        for (escrow_account, escrow_info, offered_amount, counterparty) in [
+            (first_escrow_account, &first, first_vault_info.amount, second.initializer_pubkey),
+            (second_escrow_account, &second, second_vault_info.amount, first.initializer_pubkey),
+        ] {
+            SettlementEvent {
+                escrow_pubkey: *escrow_account.key,
+                initializer_pubkey: escrow_info.initializer_pubkey,
+                taker_pubkey: counterparty,
+                offered_amount,
+                paid_amount: escrow_info.expected_amount,
+                paid_lamports: 0,
+                referrer_token_account: None,
+                referral_fee: 0,
+                protocol_fee: 0,
+                memo: escrow_info.memo,
+                offer_id: escrow_info.offer_id,
+            }.emit();
+        }
+
+        // the global stats go first, then each initializer's, and the global count drops once for each escrow
+        if let Some(stats_accounts) = stats_accounts {
+            for (escrow_info, initializers_stats) in [(&first, &stats_accounts[1]), (&second, &stats_accounts[2])] {
+                let stats_pair = [stats_accounts[0].clone(), initializers_stats.clone()];
+                Self::update_stats(&stats_pair, &escrow_info.initializer_pubkey, program_id, |stats| {
+                    stats.open_escrows = stats.open_escrows.saturating_sub(1);
+                })?;
+            }
+        }
+
+        msg!("Closing the escrow accounts...");
+        utils::close_account(first_escrow_account, first_initializers_main_account)?;
+        utils::close_account(second_escrow_account, second_initializers_main_account)?;
+
+        Ok(())
+    }
+
+    /// Checks one side of a MatchOffers is a plain offer that could be taken right now through these accounts, and returns its vault
+    fn check_matchable_offer(
+        escrow_info: &Escrow,
+        vault: &AccountInfo,
+        initializers_main_account: &AccountInfo,
+        initializer_token_to_receive_account: &AccountInfo,
+    ) -> Result<TokenAccount, ProgramError> {
+        escrow_info.status.transition(EscrowStatus::Completed)?;
+
+        if !escrow_info.is_plain_offer() {
+            return Err(EscrowError::WrongEscrowMode.into());
+        }
+
+        let clock = Clock::get()?;
+        if escrow_info.is_expired(clock.unix_timestamp) {
+            return Err(EscrowError::EscrowExpired.into());
+        }
+
+        if !escrow_info.is_active(clock.unix_timestamp) {
+            return Err(EscrowError::OfferNotActiveYet.into());
+        }
+
+        if escrow_info.has_live_commit(clock.slot) { // the offer is reserved for the taker who committed
+            return Err(EscrowError::OfferCommitted.into());
+        }
+
+        if escrow_info.temp_token_account_pubkey != *vault.key {
+            return Err(EscrowError::TempAccountMismatch.into());
+        }
+
+        if escrow_info.initializer_token_to_receive_account_pubkey != *initializer_token_to_receive_account.key {
+            return Err(EscrowError::ReceiveAccountMismatch.into());
+        }
+
+        if escrow_info.initializer_pubkey != *initializers_main_account.key {
+            return Err(EscrowError::InitializerMismatch.into());
+        }
+
+        utils::unpack_token_account(vault)
+    }
+
+    /// Splits the optional stats accounts off the end of an instruction's accounts. They're only there when the last `count`
+    /// accounts are all stats sized and owned by this program, which nothing else an instruction takes can be
+    fn split_stats_accounts<'a, 'b>(
+        accounts: &'a [AccountInfo<'b>],
+        count: usize,
+        program_id: &Pubkey,
+    ) -> (&'a [AccountInfo<'b>], Option<&'a [AccountInfo<'b>]>) {
+        match accounts.len().checked_sub(count) {
+            Some(split) if accounts[split..].iter().all(|account| account.owner == program_id && account.data_len() == Stats::LEN) => {
+                (&accounts[..split], Some(&accounts[split..]))
+            },
+            _ => (accounts, None),
+        }
+    }
+
+    /// Splits the offer counter off the end of InitEscrow's accounts, where it's passed when the offer should be numbered
+    fn split_offer_counter<'a, 'b>(
+        accounts: &'a [AccountInfo<'b>],
+        program_id: &Pubkey,
+    ) -> (&'a [AccountInfo<'b>], Option<&'a AccountInfo<'b>>) {
+        match accounts.split_last() {
+            Some((last, rest)) if last.owner == program_id && last.data_len() == OfferCounter::LEN => (rest, Some(last)),
+            _ => (accounts, None),
+        }
+    }
+
+    /// Takes the next offer number from the counter, after checking it really is the counter PDA
+    fn take_offer_id(offer_counter_account: &AccountInfo, program_id: &Pubkey) -> Result<u64, ProgramError> {
+        utils::check_writable(offer_counter_account)?;
+        let mut offer_counter = OfferCounter::unpack(&offer_counter_account.data.borrow())?;
+        let counter_pda = Pubkey::create_program_address(&[b"offer_counter", &[offer_counter.bump_seed]], program_id)
+            .map_err(|_| EscrowError::InvalidOfferCounter)?;
+        if counter_pda != *offer_counter_account.key {
+            return Err(EscrowError::InvalidOfferCounter.into());
+        }
+
+        let offer_id = offer_counter.next_offer_id()?;
+        OfferCounter::pack(offer_counter, &mut offer_counter_account.data.borrow_mut())?;
+        Ok(offer_id)
+    }
+
+    /// Applies `update` to the global stats and then the initializer's, after checking they really are those two PDAs
+    fn update_stats<F: Fn(&mut Stats)>(
+        stats_accounts: &[AccountInfo],
+        initializer: &Pubkey,
+        program_id: &Pubkey,
+        update: F,
+    ) -> ProgramResult {
+        for (stats_account, owner_pubkey) in stats_accounts.iter().zip([Pubkey::default(), *initializer].iter()) {
+            utils::check_writable(stats_account)?;
+            let mut stats = Stats::unpack(&stats_account.data.borrow())?;
+
+            // the bump is stored so this is a single hash rather than a search
+            let bump = [stats.bump_seed];
+            let seeds: &[&[u8]] = if *owner_pubkey == Pubkey::default() {
+                &[b"stats", &bump]
+            } else {
+                &[b"stats", owner_pubkey.as_ref(), &bump]
+            };
+            let stats_pda = Pubkey::create_program_address(seeds, program_id)
+                .map_err(|_| EscrowError::InvalidStatsAccount)?;
+            if stats_pda != *stats_account.key || stats.owner_pubkey != *owner_pubkey {
+                return Err(EscrowError::InvalidStatsAccount.into());
+            }
+
+            update(&mut stats);
+            Stats::pack(stats, &mut stats_account.data.borrow_mut())?;
+        }
+
+        Ok(())
+    }
+
+    fn process_init_escrow_from_account(
+        accounts: &[AccountInfo],
+        amount: u64,
+        offered_amount: u64,
+        expires_at: i64,
+        crank_bounty: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        if amount == 0 || offered_amount == 0 {
+            return Err(EscrowError::ZeroAmount.into());
+        }
+
+        let account_info_iter = &mut accounts.iter();
+        let initializer = next_account_info(account_info_iter)?;
+        if !initializer.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let offered_token_account = next_account_info(account_info_iter)?;
+        let token_to_receive_account = next_account_info(account_info_iter)?;
+        let escrow_account = next_account_info(account_info_iter)?;
+        let vault = next_account_info(account_info_iter)?;
+        let offered_mint = next_account_info(account_info_iter)?;
+        let rent_sysvar = next_account_info(account_info_iter)?;
+        let system_program = next_account_info(account_info_iter)?;
+        let token_program = next_account_info(account_info_iter)?;
+        Self::check_token_program(token_program)?; // the vault is sized for an account of the original token program, with no extensions
+
+        let (vault_pda, vault_bump_seed) = Pubkey::find_program_address(&[b"vault", escrow_account.key.as_ref()], program_id);
+        if vault_pda != *vault.key {
+            return Err(EscrowError::InvalidVaultAccount.into());
+        }
+
+        let create_vault_ix = system_instruction::create_account(
+            initializer.key,
+            vault.key,
+            Rent::get()?.minimum_balance(TokenAccount::LEN),
+            TokenAccount::LEN as u64,
+            token_program.key,
+        );
+        msg!("Calling the system program to create the vault...");
+        invoke_signed(
+            &create_vault_ix,
+            &[
+                initializer.clone(),
+                vault.clone(),
+                system_program.clone(),
+            ],
+            &[&[b"vault", escrow_account.key.as_ref(), &[vault_bump_seed]]],
+        )?;
+
+        // the initializer owns the vault to begin with, process_init_escrow hands it to the PDA like any temp token account
+        let init_vault_ix = spl_token::instruction::initialize_account2(token_program.key, vault.key, offered_mint.key, initializer.key)?;
+        invoke(
+            &init_vault_ix,
+            &[
+                vault.clone(),
+                offered_mint.clone(),
+                rent_sysvar.clone(),
+                token_program.clone(),
+            ],
+        )?;
+
+        msg!("Calling the token program to move the offered tokens into the vault...");
+        Self::transfer_tokens(token_program, offered_token_account, vault, initializer, None, offered_amount, &[])?;
+
+        // from here on it's a plain escrow with the vault as its temp token account, followed by the config PDA and any stats accounts
+        let mut init_accounts = vec![
+            initializer.clone(),
+            vault.clone(),
+            token_to_receive_account.clone(),
+            escrow_account.clone(),
+            token_program.clone(),
+        ];
+        init_accounts.extend(account_info_iter.cloned());
+        Self::process_init_escrow(&init_accounts, amount, expires_at, crank_bounty, 0, None, Memo::default(), &[], false, None, 0, false, None, 0, program_id)
+    }
+
+    fn process_init_bid(
+        accounts: &[AccountInfo],
+        expires_at: i64,
+        crank_bounty: u64,
+        collection: Pubkey,
+        enforce_royalties: bool,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let bidder = next_account_info(account_info_iter)?;
+        if !bidder.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let temp_token_account = next_account_info(account_info_iter)?;
+        utils::check_not_confidential(&[temp_token_account])?;
+        let payment_tokens = utils::unpack_token_account(temp_token_account)?;
+        if payment_tokens.amount == 0 {
+            return Err(EscrowError::EmptyOfferAccount.into());
+        }
+        if payment_tokens.close_authority.is_some() {
+            return Err(EscrowError::CloseAuthoritySet.into());
+        }
+
+        let escrow_account = next_account_info(account_info_iter)?;
+        if !Rent::get()?.is_exempt(escrow_account.lamports(), escrow_account.data_len()) {
+            return Err(EscrowError::NotRentExempt.into());
+        }
+        if crank_bounty > escrow_account.lamports() {
+            return Err(EscrowError::AmountOverflow.into());
+        }
+
+        Self::check_escrow_account(escrow_account, program_id)?;
+        let mut escrow_info = Escrow::unpack_unchecked(&escrow_account.data.borrow())?;
+        if escrow_info.is_initialized() {
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+
+        escrow_info.is_initialized = true;
+        escrow_info.initializer_pubkey = *bidder.key;
+        escrow_info.temp_token_account_pubkey = *temp_token_account.key;
+        escrow_info.expected_amount = 1; // one NFT, whichever of the collection it turns out to be
+        escrow_info.expires_at = expires_at;
+        escrow_info.crank_bounty = crank_bounty;
+        escrow_info.status = EscrowStatus::Open;
+        escrow_info.created_at_slot = Clock::get()?.slot;
+        escrow_info.kind = EscrowKind::CollectionBid;
+        escrow_info.collection_pubkey = collection;
+        escrow_info.enforce_royalties = enforce_royalties;
+
+        let (pda, bump_seed) = Pubkey::find_program_address(&[b"escrow"], program_id);
+        escrow_info.bump_seed = bump_seed;
+
+        Escrow::pack(escrow_info, &mut escrow_account.data.borrow_mut())?;
+
+        let token_program = next_account_info(account_info_iter)?;
+        Self::check_token_program(token_program)?;
+        let owner_change_ix = spl_token::instruction::set_authority(
+            token_program.key,
+            temp_token_account.key,
+            Some(&pda),
+            spl_token::instruction::AuthorityType::AccountOwner,
+            bidder.key,
+            &[bidder.key],
+        )?;
+
+        msg!("Calling the token program to transfer token account ownership...");
+        invoke(
+            &owner_change_ix,
+            &[
+                temp_token_account.clone(),
+                bidder.clone(),
+                token_program.clone(),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    fn process_accept_bid(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let seller = next_account_info(account_info_iter)?;
+        if !seller.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let seller_nft_account = next_account_info(account_info_iter)?;
+        let seller_payment_account = next_account_info(account_info_iter)?;
+        let bidder_nft_account = next_account_info(account_info_iter)?;
+        let escrow_account = next_account_info(account_info_iter)?;
+        let pdas_temp_token_account = next_account_info(account_info_iter)?;
+        let bidders_main_account = next_account_info(account_info_iter)?;
+        let nft_mint = next_account_info(account_info_iter)?;
+        let metadata_account = next_account_info(account_info_iter)?;
+        let token_program = next_account_info(account_info_iter)?;
+        let pda_account = next_account_info(account_info_iter)?;
+
+        let escrow_info = Self::unpack_initialized_escrow(escrow_account, program_id)?;
+        escrow_info.status.transition(EscrowStatus::Completed)?;
+        if escrow_info.kind != EscrowKind::CollectionBid {
+            return Err(EscrowError::WrongEscrowMode.into());
+        }
+        if escrow_info.is_expired(Clock::get()?.unix_timestamp) {
+            return Err(EscrowError::EscrowExpired.into());
+        }
+        if escrow_info.temp_token_account_pubkey != *pdas_temp_token_account.key {
+            return Err(EscrowError::TempAccountMismatch.into());
+        }
+        if escrow_info.initializer_pubkey != *bidders_main_account.key {
+            return Err(EscrowError::InitializerMismatch.into());
+        }
+        Self::check_token_program(token_program)?;
+
+        // a single token of a mint without decimals, and the collection's authority has to have vouched for it
+        let mint = utils::unpack_mint(nft_mint)?;
+        if *nft_mint.owner != spl_token::id() || mint.decimals != 0 || mint.supply != 1 {
+            return Err(EscrowError::WrongMint.into());
+        }
+        let metadata = Metadata::load(metadata_account, nft_mint.key)?;
+        if !metadata.is_verified_member_of(&escrow_info.collection_pubkey) {
+            return Err(EscrowError::NotInCollection.into());
+        }
+
+        let bidder_nft_account_info = utils::unpack_token_account(bidder_nft_account)?;
+        if bidder_nft_account_info.owner != escrow_info.initializer_pubkey || bidder_nft_account_info.mint != *nft_mint.key {
+            return Err(EscrowError::ReceiveAccountMismatch.into());
+        }
+
+        let vault = utils::unpack_token_account(pdas_temp_token_account)?;
+        let payment = vault.amount;
+        let royalties = if escrow_info.enforce_royalties {
+            let creator_accounts = next_account_infos(account_info_iter, metadata.creators.len())?;
+            Self::royalty_payments(&metadata, creator_accounts, payment, &vault.mint)?
+        } else {
+            Vec::new()
+        };
+        let seller_payment = Self::seller_share(payment, 0, &royalties)?;
+
+        msg!("Calling the token program to transfer the NFT to the bidder...");
+        Self::transfer_tokens(token_program, seller_nft_account, bidder_nft_account, seller, None, 1, &[])?;
+
+        for (creator_account, royalty) in royalties {
+            msg!("Calling the token program to pay a creator's royalty...");
+            Self::transfer_tokens(
+                token_program,
+                pdas_temp_token_account,
+                creator_account,
+                pda_account,
+                None,
+                royalty,
+                &[&[&b"escrow"[..], &[escrow_info.bump_seed]]],
+            )?;
+        }
+
+        let amounts = Amounts {
+            offered_amount: seller_payment,
+            expected_amount: 1,
+            fully_filled: true,
+        };
+        let vault_accounts = VaultAccounts {
+            token_program,
+            vault: pdas_temp_token_account,
+            taker_receive_account: seller_payment_account,
+            initializers_main_account: bidders_main_account,
+            pda_account,
+            token_2022_transfer: None,
+        };
+        strategy::release_from_vault(&escrow_info, &amounts, &vault_accounts)?;
+        strategy::close_vault(&escrow_info, &vault_accounts)?;
+
+        SettlementEvent {
+            escrow_pubkey: *escrow_account.key,
+            initializer_pubkey: escrow_info.initializer_pubkey,
+            taker_pubkey: *seller.key,
+            offered_amount: payment,
+            paid_amount: 1,
+            paid_lamports: 0,
+            referrer_token_account: None,
+            referral_fee: 0,
+            protocol_fee: 0,
+            memo: escrow_info.memo,
+            offer_id: escrow_info.offer_id,
+        }.emit();
+
+        msg!("Closing the escrow account...");
+        utils::close_account(escrow_account, bidders_main_account)
+    }
+
+    fn process_deposit_taker_side(
+        accounts: &[AccountInfo],
+        amount_expected_by_taker: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let taker = next_account_info(account_info_iter)?;
+
+        if !taker.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let taker_temp_token_account = next_account_info(account_info_iter)?;
+        let taker_receive_token_account = next_account_info(account_info_iter)?;
+        let pdas_temp_token_account = next_account_info(account_info_iter)?;
+        let escrow_account = next_account_info(account_info_iter)?;
+
+        let mut escrow_info = Self::unpack_initialized_escrow(escrow_account, program_id)?;
+
+        // only a plain fixed price escrow has both sides sitting in whole token accounts the PDA can swap, and Settle pays nothing but
+        // the two sides, so royalties, a protocol fee or a cap per taker rule an escrow out as well
+        if escrow_info.kind != EscrowKind::FixedPrice
+            || escrow_info.has_accepted_mints()
+            || escrow_info.is_reusable
+            || escrow_info.expected_lamports != 0
+            || escrow_info.enforce_royalties
+            || escrow_info.charges_protocol_fee
+            || escrow_info.max_per_taker != 0
+        {
+            return Err(EscrowError::WrongEscrowMode.into());
+        }
+
+        if escrow_info.status != EscrowStatus::Open {
+            return Err(EscrowError::InvalidEscrowStatus.into());
+        }
+
+        if escrow_info.has_taker_deposit() {
+            return Err(EscrowError::TakerSideDeposited.into());
+        }
+
+        if escrow_info.temp_token_account_pubkey != *pdas_temp_token_account.key {
+            return Err(EscrowError::TempAccountMismatch.into());
+        }
+
+        let clock = Clock::get()?;
+        if escrow_info.has_live_commit(clock.slot) { // the offer is reserved for the taker who committed
+            return Err(EscrowError::OfferCommitted.into());
+        }
+
+        if escrow_info.is_expired(clock.unix_timestamp) {
+            return Err(EscrowError::EscrowExpired.into());
+        }
+
+        if !escrow_info.is_active(clock.unix_timestamp) {
+            return Err(EscrowError::OfferNotActiveYet.into());
+        }
+
+        let pdas_temp_token_account_info = TokenAccount::unpack(&pdas_temp_token_account.data.borrow())?;
+        if amount_expected_by_taker != pdas_temp_token_account_info.amount {
+            return Err(EscrowError::ExpectedAmountMismatch.into());
+        }
+
+        // the taker's vault has to hold the whole payment up front so Settle can't come up short
+        let taker_temp_token_account_info = TokenAccount::unpack(&taker_temp_token_account.data.borrow())?;
+        if taker_temp_token_account_info.close_authority.is_some() {
+            return Err(EscrowError::CloseAuthoritySet.into());
+        }
+        if taker_temp_token_account_info.amount != escrow_info.expected_amount {
+            return Err(EscrowError::ExpectedAmountMismatch.into());
+        }
+
+        let token_program = next_account_info(account_info_iter)?;
+        Self::check_token_program(token_program)?;
+        let initializer_token_to_receive_account = next_account_info(account_info_iter)?;
+
+        if escrow_info.initializer_token_to_receive_account_pubkey != *initializer_token_to_receive_account.key {
+            return Err(EscrowError::ReceiveAccountMismatch.into());
+        }
+
+        // Settle swaps the two vaults into the receive accounts as they are, so each has to be in the token the other side wants
+        let initializer_token_to_receive_account_info = TokenAccount::unpack(&initializer_token_to_receive_account.data.borrow())?;
+        if taker_temp_token_account_info.mint != initializer_token_to_receive_account_info.mint {
+            return Err(EscrowError::WrongMint.into());
+        }
+        if TokenAccount::unpack(&taker_receive_token_account.data.borrow())?.mint != pdas_temp_token_account_info.mint {
+            return Err(EscrowError::WrongMint.into());
+        }
+
+        escrow_info.taker_pubkey = *taker.key;
+        escrow_info.taker_vault_pubkey = *taker_temp_token_account.key;
+        escrow_info.taker_receive_account_pubkey = *taker_receive_token_account.key;
+        Escrow::pack(escrow_info, &mut escrow_account.data.borrow_mut())?;
+
+        let (pda, _bump_seed) = Pubkey::find_program_address(&[b"escrow"], program_id);
+        let owner_change_ix = spl_token::instruction::set_authority(
+            token_program.key,
+            taker_temp_token_account.key,
+            Some(&pda),
+            spl_token::instruction::AuthorityType::AccountOwner,
+            taker.key,
+            &[&taker.key],
+        )?;
+        msg!("Calling the token program to transfer the taker's token account ownership...");
+        invoke(
+            &owner_change_ix,
+            &[
+                taker_temp_token_account.clone(),
+                taker.clone(),
+                token_program.clone(),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    fn process_settle(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let (accounts, stats_accounts) = Self::split_stats_accounts(accounts, 2, program_id);
+        let account_info_iter = &mut accounts.iter();
+        let settler = next_account_info(account_info_iter)?;
+
+        if !settler.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let escrow_account = next_account_info(account_info_iter)?;
+        let initializer_vault = next_account_info(account_info_iter)?;
+        let taker_vault = next_account_info(account_info_iter)?;
+        let initializer_token_to_receive_account = next_account_info(account_info_iter)?;
+        let taker_token_to_receive_account = next_account_info(account_info_iter)?;
+        let initializers_main_account = next_account_info(account_info_iter)?;
+        let takers_main_account = next_account_info(account_info_iter)?;
+
+        let escrow_info = Self::unpack_initialized_escrow(escrow_account, program_id)?;
+        escrow_info.status.transition(EscrowStatus::Completed)?;
+
+        if !escrow_info.has_taker_deposit() {
+            return Err(EscrowError::TakerSideNotDeposited.into());
+        }
+
+        if escrow_info.temp_token_account_pubkey != *initializer_vault.key
+            || escrow_info.taker_vault_pubkey != *taker_vault.key
+        {
+            return Err(EscrowError::TempAccountMismatch.into());
+        }
+
+        if escrow_info.initializer_token_to_receive_account_pubkey != *initializer_token_to_receive_account.key
+            || escrow_info.taker_receive_account_pubkey != *taker_token_to_receive_account.key
+        {
+            return Err(EscrowError::ReceiveAccountMismatch.into());
+        }
+
+        if escrow_info.initializer_pubkey != *initializers_main_account.key {
+            return Err(EscrowError::InitializerMismatch.into());
+        }
+
+        if escrow_info.taker_pubkey != *takers_main_account.key {
+            return Err(EscrowError::TakerMismatch.into());
+        }
+
+        let clock = Clock::get()?;
+        if escrow_info.has_live_commit(clock.slot) {
+            return Err(EscrowError::OfferCommitted.into());
+        }
+
+        if escrow_info.is_expired(clock.unix_timestamp) { // both sides have to withdraw once the offer has run out
+            return Err(EscrowError::EscrowExpired.into());
+        }
+
+        let token_program = next_account_info(account_info_iter)?;
+        Self::check_token_program(token_program)?;
+        let pda_account = next_account_info(account_info_iter)?;
+
+        let (_pda, bump_seed) = Pubkey::find_program_address(&[b"escrow"], program_id);
+        let offered_amount = TokenAccount::unpack(&initializer_vault.data.borrow())?.amount;
+        let paid_amount = TokenAccount::unpack(&taker_vault.data.borrow())?.amount;
+
+        Self::transfer_from_vault(token_program, initializer_vault, taker_token_to_receive_account, pda_account, bump_seed, offered_amount)?;
+        Self::transfer_from_vault(token_program, taker_vault, initializer_token_to_receive_account, pda_account, bump_seed, paid_amount)?;
+        Self::close_vault(token_program, initializer_vault, initializers_main_account, pda_account, bump_seed)?;
+        Self::close_vault(token_program, taker_vault, takers_main_account, pda_account, bump_seed)?;
+
+        SettlementEvent {
+            escrow_pubkey: *escrow_account.key,
+            initializer_pubkey: escrow_info.initializer_pubkey,
+            taker_pubkey: escrow_info.taker_pubkey,
+            offered_amount,
+            paid_amount,
+            paid_lamports: 0, // deposited escrows reject a SOL price
+            referrer_token_account: None,
+            referral_fee: 0,
+            protocol_fee: 0,
+            memo: escrow_info.memo,
+            offer_id: escrow_info.offer_id,
+        }.emit();
+
+        if let Some(stats_accounts) = stats_accounts {
+            Self::update_stats(stats_accounts, &escrow_info.initializer_pubkey, program_id, |stats| {
+                stats.open_escrows = stats.open_escrows.saturating_sub(1);
+            })?;
+        }
+
+        msg!("Closing the escrow account...");
+        utils::close_account(escrow_account, initializers_main_account)?;
+
+        Ok(())
+    }
+
+    fn process_withdraw(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let (accounts, stats_accounts) = Self::split_stats_accounts(accounts, 2, program_id);
+        let account_info_iter = &mut accounts.iter();
+        let withdrawer = next_account_info(account_info_iter)?;
+
+        if !withdrawer.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let escrow_account = next_account_info(account_info_iter)?;
+        let withdrawers_vault = next_account_info(account_info_iter)?;
+
+        let mut escrow_info = Self::unpack_initialized_escrow(escrow_account, program_id)?;
+
+        if !escrow_info.has_taker_deposit() { // without a taker deposit this is just a Cancel
+            return Err(EscrowError::TakerSideNotDeposited.into());
+        }
+
+        let token_program = next_account_info(account_info_iter)?;
+        Self::check_token_program(token_program)?;
+        let pda_account = next_account_info(account_info_iter)?;
+        let (_pda, bump_seed) = Pubkey::find_program_address(&[b"escrow"], program_id);
+
+        if *withdrawer.key == escrow_info.taker_pubkey {
+            if escrow_info.taker_vault_pubkey != *withdrawers_vault.key {
+                return Err(EscrowError::TempAccountMismatch.into());
+            }
+
+            // the offer goes back to waiting for a taker
+            Self::return_vault_ownership(token_program, withdrawers_vault, withdrawer.key, pda_account, bump_seed)?;
+            escrow_info.taker_pubkey = Pubkey::default();
+            escrow_info.taker_vault_pubkey = Pubkey::default();
+            escrow_info.taker_receive_account_pubkey = Pubkey::default();
+            Escrow::pack(escrow_info, &mut escrow_account.data.borrow_mut())?;
+        } else if *withdrawer.key == escrow_info.initializer_pubkey {
+            escrow_info.status.transition(EscrowStatus::Cancelled)?;
+
+            if escrow_info.temp_token_account_pubkey != *withdrawers_vault.key {
+                return Err(EscrowError::TempAccountMismatch.into());
+            }
+
+            let taker_vault = next_account_info(account_info_iter)?;
+            if escrow_info.taker_vault_pubkey != *taker_vault.key {
+                return Err(EscrowError::TempAccountMismatch.into());
+            }
+            let penalty_account = if escrow_info.cancel_penalty_bps != 0 { Some(next_account_info(account_info_iter)?) } else { None };
+            let deposit_recipient = if escrow_info.creation_deposit != 0 { Some(next_account_info(account_info_iter)?) } else { None };
+
+            // the initializer backing out cancels the whole escrow, with the same penalty and deposit terms as a Cancel, and the
+            // taker gets their vault back as well
+            if let Some(penalty_account) = penalty_account {
+                Self::take_cancel_penalty(&escrow_info, withdrawers_vault, penalty_account, token_program, pda_account, None)?;
+            }
+            Self::return_vault_ownership(token_program, withdrawers_vault, withdrawer.key, pda_account, bump_seed)?;
+            Self::return_vault_ownership(token_program, taker_vault, &escrow_info.taker_pubkey, pda_account, bump_seed)?;
+
+            if let Some(deposit_recipient) = deposit_recipient {
+                Self::settle_creation_deposit(&escrow_info, escrow_account, deposit_recipient, withdrawer, false, Clock::get()?.slot)?;
+            }
+
+            if let Some(stats_accounts) = stats_accounts {
+                Self::update_stats(stats_accounts, &escrow_info.initializer_pubkey, program_id, |stats| {
+                    stats.open_escrows = stats.open_escrows.saturating_sub(1);
+                })?;
+            }
+
+            msg!("Closing the escrow account...");
+            utils::close_account(escrow_account, withdrawer)?;
+        } else {
+            return Err(EscrowError::ExpectedAccountMismatch.into());
+        }
+
+        Ok(())
+    }
+
+    /// Gives a PDA held temp token account, tokens and all, back to the wallet that deposited it
+    fn return_vault_ownership<'a>(
+        token_program: &AccountInfo<'a>,
+        vault: &AccountInfo<'a>,
+        new_owner: &Pubkey,
+        pda_account: &AccountInfo<'a>,
+        bump_seed: u8,
+    ) -> ProgramResult {
+        let owner_change_ix = spl_token::instruction::set_authority(
+            token_program.key,
+            vault.key,
+            Some(new_owner),
+            spl_token::instruction::AuthorityType::AccountOwner,
+            pda_account.key,
+            &[pda_account.key],
+        )?;
+        msg!("Calling the token program to hand the temp account back...");
+        invoke_signed(
+            &owner_change_ix,
+            &[
+                vault.clone(),
+                pda_account.clone(),
+                token_program.clone(),
+            ],
+            &[&[&b"escrow"[..], &[bump_seed]]],
+        )
+    }
+
+    /// Closes an emptied PDA held temp token account, sending its rent to `destination`
+    fn close_vault<'a>(
+        token_program: &AccountInfo<'a>,
+        vault: &AccountInfo<'a>,
+        destination: &AccountInfo<'a>,
+        pda_account: &AccountInfo<'a>,
+        bump_seed: u8,
+    ) -> ProgramResult {
+        let close_vault_ix = spl_token::instruction::close_account(
+            token_program.key,
+            vault.key,
+            destination.key,
+            pda_account.key,
+            &[pda_account.key],
+        )?;
+        msg!("Calling the token program to close pda's temp account...");
+        invoke_signed(
+            &close_vault_ix,
+            &[
+                vault.clone(),
+                destination.clone(),
+                pda_account.clone(),
+                token_program.clone(),
+            ],
+            &[&[&b"escrow"[..], &[bump_seed]]],
+        )
+    }
+
+    /// Checks the escrow is an active subscription between these parties
+    fn check_subscription_accounts(
+        escrow_info: &Escrow,
+        initializer: &Pubkey,
+        pdas_temp_token_account: &Pubkey,
+        initializer_token_to_receive_account: &Pubkey,
+        taker: &Pubkey,
+    ) -> ProgramResult {
+        if !escrow_info.is_subscription {
+            return Err(EscrowError::WrongEscrowMode.into());
+        }
+
+        if escrow_info.status != EscrowStatus::PartiallyFilled { // nobody has subscribed yet
+            return Err(EscrowError::InvalidEscrowStatus.into());
+        }
+
+        if escrow_info.initializer_pubkey != *initializer {
+            return Err(EscrowError::InitializerMismatch.into());
+        }
+
+        if escrow_info.temp_token_account_pubkey != *pdas_temp_token_account {
+            return Err(EscrowError::TempAccountMismatch.into());
+        }
+
+        if escrow_info.initializer_token_to_receive_account_pubkey != *initializer_token_to_receive_account {
+            return Err(EscrowError::ReceiveAccountMismatch.into());
+        }
+
+        if escrow_info.taker_pubkey != *taker {
+            return Err(EscrowError::TakerMismatch.into());
+        }
+
+        Ok(())
+    }
+
+    /// Transfers `amount` out of a PDA owned token account
+    fn transfer_from_vault<'a>(
+        token_program: &AccountInfo<'a>,
+        pdas_temp_token_account: &AccountInfo<'a>,
+        destination: &AccountInfo<'a>,
+        pda_account: &AccountInfo<'a>,
+        bump_seed: u8,
+        amount: u64,
+    ) -> ProgramResult {
+        let transfer_ix = spl_token::instruction::transfer(
+            token_program.key,
+            pdas_temp_token_account.key,
+            destination.key,
+            pda_account.key,
+            &[pda_account.key],
+            amount,
+        )?;
+        msg!("Calling the token program to transfer tokens out of the pda's temp account...");
+        invoke_signed(
+            &transfer_ix,
+            &[
+                pdas_temp_token_account.clone(),
+                destination.clone(),
+                pda_account.clone(),
+                token_program.clone(),
+            ],
+            &[&[&b"escrow"[..], &[bump_seed]]],
+        )
+    }
+
+    /// Unpacks the escrow state, failing with a clear error rather than a generic one when the account was never initialized
+    fn unpack_initialized_escrow(escrow_account: &AccountInfo, program_id: &Pubkey) -> Result<Escrow, ProgramError> {
+        Self::check_escrow_account(escrow_account, program_id)?;
+        let data = escrow_account.data.borrow();
+        if Escrow::unpack_any_version(&data).map_or(false, |escrow_info| escrow_info.version != Escrow::VERSION) {
+            return Err(EscrowError::EscrowNeedsMigration.into());
+        }
+        let escrow_info = Escrow::unpack_unchecked(&data)?;
+        if !escrow_info.is_initialized() {
+            return Err(EscrowError::EscrowNotInitialized.into());
+        }
+        Ok(escrow_info)
+    }
+
+    /// What the taker has bought of a capped escrow so far, nothing when this is their first fill and the claim doesn't exist yet
+    fn unpack_claimed_amount(claim_account: &AccountInfo, program_id: &Pubkey) -> Result<u64, ProgramError> {
+        if claim_account.data_is_empty() {
+            return Ok(0);
+        }
+        Self::check_program_account(claim_account, program_id)?;
+        Ok(TakerClaim::unpack(&claim_account.data.borrow())?.claimed_amount)
+    }
+
+    /// Writes the taker's claim, creating the account at the taker's expense on their first fill
+    fn write_claim<'a>(
+        claim: TakerClaim,
+        claim_account: &AccountInfo<'a>,
+        taker: &AccountInfo<'a>,
+        system_program: &AccountInfo<'a>,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        if claim_account.data_is_empty() {
+            let create_claim_ix = system_instruction::create_account(
+                taker.key,
+                claim_account.key,
+                Rent::get()?.minimum_balance(TakerClaim::LEN),
+                TakerClaim::LEN as u64,
+                program_id,
+            );
+            msg!("Calling the system program to create the taker's claim account...");
+            invoke_signed(
+                &create_claim_ix,
+                &[
+                    taker.clone(),
+                    claim_account.clone(),
+                    system_program.clone(),
+                ],
+                &[&[b"claim", claim.escrow_pubkey.as_ref(), claim.taker_pubkey.as_ref(), &[claim.bump_seed]]],
+            )?;
+        }
+        TakerClaim::pack(claim, &mut claim_account.data.borrow_mut())
+    }
+
+    /// Every handler writes to or closes the escrow account, so it has to be ours, writable and plain data.
+    /// Without the owner check a lookalike account owned by another program with the right bytes would pass for an escrow
+    fn check_escrow_account(escrow_account: &AccountInfo, program_id: &Pubkey) -> ProgramResult {
+        Self::check_program_account(escrow_account, program_id)?;
+        utils::check_writable(escrow_account)
+    }
+
+    /// Checks an account holding our state really is owned by this program and isn't a program itself
+    fn check_program_account(account: &AccountInfo, program_id: &Pubkey) -> ProgramResult {
+        if account.executable {
+            return Err(EscrowError::ExecutableAccount.into());
+        }
+        if account.owner != program_id {
+            return Err(EscrowError::InvalidAccountOwner.into());
+        }
+        Ok(())
+    }
+
+    /// Pairs each of the NFT's creators with the royalty they're owed on a sale for `price`, checking the token account passed for them
+    /// is theirs and in the payment mint
+    fn royalty_payments<'b, 'a>(
+        metadata: &Metadata,
+        creator_accounts: &'b [AccountInfo<'a>],
+        price: u64,
+        payment_mint: &Pubkey,
+    ) -> Result<Vec<(&'b AccountInfo<'a>, u64)>, ProgramError> {
+        metadata
+            .creators
+            .iter()
+            .zip(creator_accounts)
+            .zip(metadata.royalties(price))
+            .map(|((creator, creator_account), royalty)| {
+                let creator_account_info = utils::unpack_token_account(creator_account)?;
+                if creator_account_info.owner != creator.address || creator_account_info.mint != *payment_mint {
+                    return Err(EscrowError::RoyaltyAccountMismatch.into());
+                }
+                Ok((creator_account, royalty))
+            })
+            .collect()
+    }
+
+    /// What the seller is left with out of `price` once the referral and protocol fees and the royalties are paid
+    fn seller_share(price: u64, fees: u64, royalties: &[(&AccountInfo, u64)]) -> Result<u64, ProgramError> {
+        royalties
+            .iter()
+            .try_fold(0u64, |total, (_, royalty)| total.checked_add(*royalty))
+            .and_then(|royalties| price.checked_sub(fees)?.checked_sub(royalties))
+            .ok_or_else(|| EscrowError::AmountOverflow.into())
+    }
+
+    /// The mint a take is paid in, the one it names or else the initializer's receiving account's
+    fn paid_in_mint(payment_mint: Option<Pubkey>, initializer_token_to_receive_account: &AccountInfo) -> Result<Pubkey, ProgramError> {
+        match payment_mint {
+            Some(payment_mint) => Ok(payment_mint),
+            None => Ok(utils::unpack_token_account(initializer_token_to_receive_account)?.mint),
+        }
+    }
+
+    /// The token program account is supplied by the client, so it has to be checked before we hand it any accounts (or the PDA's signature) in a CPI
+    fn check_token_program(token_program: &AccountInfo) -> ProgramResult {
+        if *token_program.key != spl_token::id() {
+            return Err(EscrowError::InvalidTokenProgram.into());
+        }
+        Ok(())
+    }
+
+    /// Like `check_token_program` but Token-2022 is fine too, for the plain escrow paths that know how to drive it
+    fn check_any_token_program(token_program: &AccountInfo) -> ProgramResult {
+        if !utils::is_token_program(token_program.key) {
+            return Err(EscrowError::InvalidTokenProgram.into());
+        }
+        Ok(())
+    }
+
+    /// Moves tokens with a plain Transfer through the original token program, or with a TransferChecked through Token-2022 when
+    /// the mint and its transfer hook accounts are given
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn transfer_tokens<'a>(
+        token_program: &AccountInfo<'a>,
+        source: &AccountInfo<'a>,
+        destination: &AccountInfo<'a>,
+        authority: &AccountInfo<'a>,
+        token_2022_transfer: Option<(&AccountInfo<'a>, &[AccountInfo<'a>])>,
+        amount: u64,
+        signer_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        if let Some((mint, hook_accounts)) = token_2022_transfer {
+            return utils::invoke_transfer_checked(token_program, source, mint, destination, authority, hook_accounts, amount, signer_seeds);
+        }
+
+        let transfer_ix = spl_token::instruction::transfer(
+            token_program.key,
+            source.key,
+            destination.key,
+            authority.key,
+            &[authority.key],
+            amount,
+        )?;
+        invoke_signed(
+            &transfer_ix,
+            &[
+                source.clone(),
+                destination.clone(),
+                authority.clone(),
+                token_program.clone(),
+            ],
+            signer_seeds,
+        )
+    }
+
+    /// Checks the initializer has authorised the instruction, either by signing itself or, when it's an SPL Token multisig, through the signer accounts passed after the fixed accounts
+    fn check_initializer_signature(initializer: &AccountInfo, multisig_signers: &[AccountInfo]) -> ProgramResult {
+        if multisig_signers.is_empty() {
+            if !initializer.is_signer {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            return Ok(());
+        }
+
+        if *initializer.owner != spl_token::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let multisig = Multisig::unpack(&initializer.data.borrow())?;
+
+        // Cancel hands the vault back on the PDA's authority, so the token program never gets to check these signers against the m of n
+        // threshold and it has to be met here. Like the token program, each of the multisig's signers counts once however often it's passed
+        let mut matched = [false; spl_token::instruction::MAX_SIGNERS];
+        for signer in multisig_signers {
+            if !signer.is_signer {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            match multisig.signers[..multisig.n as usize].iter().position(|key| key == signer.key) {
+                Some(position) => matched[position] = true,
+                None => return Err(EscrowError::InvalidMultisigSigner.into()),
+            }
+        }
+        if matched.iter().filter(|&&matched| matched).count() < multisig.m as usize {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        Ok(())
+    }
+
+    /// The signer keys to pass to a token instruction the initializer authorises, just the initializer itself unless it's a multisig
+    fn signer_pubkeys<'a>(initializer: &'a AccountInfo, multisig_signers: &'a [AccountInfo]) -> Vec<&'a Pubkey> {
+        if multisig_signers.is_empty() {
+            vec![initializer.key]
+        } else {
+            multisig_signers.iter().map(|signer| signer.key).collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spl_token::state::AccountState;
+    use crate::{
+        instruction,
+        test_utils::{self, TestAccount, SLOT, UNIX_TIMESTAMP},
+    };
+
+    fn process(program_id: &Pubkey, accounts: &mut [TestAccount], instruction_data: &[u8]) -> ProgramResult {
+        test_utils::install_syscall_stubs();
+        Processor::process(program_id, &test_utils::infos(accounts), instruction_data)
+    }
+
+    fn fails_with(error: EscrowError) -> ProgramResult {
+        Err(error.into())
+    }
+
+    /// The accounts of a plain InitEscrow, in instruction order: initializer, temp account, receive account, escrow, token program, config
+    fn init_escrow_accounts(program_id: &Pubkey) -> Vec<TestAccount> {
+        let initializer = test_utils::wallet();
+        let temp_token_account = test_utils::token_account(&initializer.key, &Pubkey::new_unique(), 100);
+        let token_to_receive_account = test_utils::token_account(&initializer.key, &Pubkey::new_unique(), 0);
+        let escrow_account = test_utils::escrow_account(Escrow::default(), program_id);
+        let (config_pda, _bump_seed) = Pubkey::find_program_address(&[b"config"], program_id);
+        vec![
+            initializer,
+            temp_token_account,
+            token_to_receive_account,
+            escrow_account,
+            test_utils::program(&spl_token::id()),
+            TestAccount::new(&solana_program::system_program::id(), Vec::new()).with_key(config_pda),
+        ]
+    }
+
+    fn init_escrow_data(program_id: &Pubkey, amount: u64, crank_bounty: u64) -> Vec<u8> {
+        let [initializer, temp, receive, escrow] = [Pubkey::default(); 4];
+        instruction::init_escrow(program_id, &initializer, &temp, &receive, &escrow, amount, 0, crank_bounty, None, None).data
+    }
+
+    fn init_escrow_with(change: impl FnOnce(&mut [TestAccount])) -> ProgramResult {
+        let program_id = Pubkey::new_unique();
+        let mut accounts = init_escrow_accounts(&program_id);
+        change(&mut accounts);
+        process(&program_id, &mut accounts, &init_escrow_data(&program_id, 50, 0))
+    }
+
+    fn set_token_account(account: &mut TestAccount, change: impl FnOnce(&mut TokenAccount)) {
+        let mut token_account = TokenAccount::unpack(&account.data).unwrap();
+        change(&mut token_account);
+        TokenAccount::pack(token_account, &mut account.data).unwrap();
+    }
+
+    #[test]
+    fn init_escrow_writes_the_offer() {
+        let program_id = Pubkey::new_unique();
+        let mut accounts = init_escrow_accounts(&program_id);
+        process(&program_id, &mut accounts, &init_escrow_data(&program_id, 50, 0)).unwrap();
+
+        let escrow = Escrow::unpack(&accounts[3].data).unwrap();
+        assert_eq!(escrow.initializer_pubkey, accounts[0].key);
+        assert_eq!(escrow.temp_token_account_pubkey, accounts[1].key);
+        assert_eq!(escrow.initializer_token_to_receive_account_pubkey, accounts[2].key);
+        assert_eq!(escrow.expected_amount, 50);
+        assert_eq!(escrow.status, EscrowStatus::Open);
+    }
+
+    #[test]
+    fn init_escrow_rejects_a_zero_price() {
+        let program_id = Pubkey::new_unique();
+        let mut accounts = init_escrow_accounts(&program_id);
+        assert_eq!(process(&program_id, &mut accounts, &init_escrow_data(&program_id, 0, 0)), fails_with(EscrowError::ZeroAmount));
+    }
+
+    #[test]
+    fn init_escrow_needs_the_initializer_to_sign() {
+        assert_eq!(
+            init_escrow_with(|accounts| accounts[0].is_signer = false),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+    }
+
+    #[test]
+    fn init_escrow_needs_a_token_account_to_be_paid_into() {
+        assert_eq!(
+            init_escrow_with(|accounts| accounts[2].owner = Pubkey::new_unique()),
+            Err(ProgramError::IncorrectProgramId)
+        );
+    }
+
+    #[test]
+    fn init_escrow_rejects_an_empty_offer() {
+        assert_eq!(
+            init_escrow_with(|accounts| set_token_account(&mut accounts[1], |temp| temp.amount = 0)),
+            fails_with(EscrowError::EmptyOfferAccount)
+        );
+    }
+
+    #[test]
+    fn init_escrow_rejects_a_temp_account_with_a_close_authority() {
+        assert_eq!(
+            init_escrow_with(|accounts| set_token_account(&mut accounts[1], |temp| temp.close_authority = COption::Some(Pubkey::new_unique()))),
+            fails_with(EscrowError::CloseAuthoritySet)
+        );
+    }
+
+    #[test]
+    fn init_escrow_rejects_swapping_a_mint_for_itself() {
+        assert_eq!(
+            init_escrow_with(|accounts| {
+                let offered_mint = TokenAccount::unpack(&accounts[1].data).unwrap().mint;
+                set_token_account(&mut accounts[2], |receive| receive.mint = offered_mint);
+            }),
+            fails_with(EscrowError::SameMint)
+        );
+    }
+
+    #[test]
+    fn init_escrow_needs_a_rent_exempt_escrow_account() {
+        assert_eq!(init_escrow_with(|accounts| accounts[3].lamports = 1), fails_with(EscrowError::NotRentExempt));
+    }
+
+    #[test]
+    fn init_escrow_cant_promise_a_crank_bounty_above_the_escrow_rent() {
+        let program_id = Pubkey::new_unique();
+        let mut accounts = init_escrow_accounts(&program_id);
+        let crank_bounty = accounts[3].lamports + 1;
+        assert_eq!(
+            process(&program_id, &mut accounts, &init_escrow_data(&program_id, 50, crank_bounty)),
+            fails_with(EscrowError::AmountOverflow)
+        );
+    }
+
+    #[test]
+    fn init_escrow_only_writes_to_a_writable_escrow_account_of_the_program() {
+        assert_eq!(
+            init_escrow_with(|accounts| accounts[3].owner = Pubkey::new_unique()),
+            fails_with(EscrowError::InvalidAccountOwner)
+        );
+        assert_eq!(init_escrow_with(|accounts| accounts[3].executable = true), fails_with(EscrowError::ExecutableAccount));
+        assert_eq!(init_escrow_with(|accounts| accounts[3].is_writable = false), fails_with(EscrowError::AccountNotWritable));
+    }
+
+    #[test]
+    fn init_escrow_cant_overwrite_an_open_escrow() {
+        assert_eq!(
+            init_escrow_with(|accounts| {
+                Escrow::pack(Escrow { is_initialized: true, ..Escrow::default() }, &mut accounts[3].data).unwrap();
+            }),
+            Err(ProgramError::AccountAlreadyInitialized)
+        );
+    }
+
+    #[test]
+    fn init_escrow_only_hands_the_temp_account_to_a_token_program() {
+        assert_eq!(
+            init_escrow_with(|accounts| accounts[4] = test_utils::program(&Pubkey::new_unique())),
+            fails_with(EscrowError::InvalidTokenProgram)
+        );
+    }
+
+    #[test]
+    fn init_escrow_with_a_nonce_has_to_be_at_the_offer_address() {
+        let program_id = Pubkey::new_unique();
+        let mut accounts = init_escrow_accounts(&program_id);
+        let config = accounts.pop().unwrap();
+        accounts.push(test_utils::program(&solana_program::system_program::id()));
+        accounts.push(config);
+        let data = instruction::init_escrow_with_nonce(&program_id, &accounts[0].key, &accounts[1].key, &accounts[2].key, 50, 0, 0, 7).data;
+        assert_eq!(process(&program_id, &mut accounts, &data), fails_with(EscrowError::InvalidEscrowAddress));
+    }
+
+    fn offer_counter(program_id: &Pubkey, last_offer_id: u64) -> TestAccount {
+        let (counter_pda, bump_seed) = Pubkey::find_program_address(&[b"offer_counter"], program_id);
+        let mut counter = TestAccount::new(program_id, vec![0; OfferCounter::LEN]).with_key(counter_pda);
+        OfferCounter::pack(OfferCounter { is_initialized: true, bump_seed, last_offer_id }, &mut counter.data).unwrap();
+        counter
+    }
+
+    #[test]
+    fn init_escrow_numbers_the_offer_when_passed_the_offer_counter() {
+        let program_id = Pubkey::new_unique();
+        let mut accounts = init_escrow_accounts(&program_id);
+        accounts.push(offer_counter(&program_id, 4811));
+        process(&program_id, &mut accounts, &init_escrow_data(&program_id, 50, 0)).unwrap();
+        assert_eq!(Escrow::unpack(&accounts[3].data).unwrap().offer_id, 4812);
+        assert_eq!(OfferCounter::unpack(&accounts[6].data).unwrap().last_offer_id, 4812);
+
+        let mut unnumbered = init_escrow_accounts(&program_id);
+        process(&program_id, &mut unnumbered, &init_escrow_data(&program_id, 50, 0)).unwrap();
+        assert_eq!(Escrow::unpack(&unnumbered[3].data).unwrap().offer_id, 0);
+    }
+
+    #[test]
+    fn init_escrow_only_takes_a_number_from_the_offer_counter_pda() {
+        let program_id = Pubkey::new_unique();
+        let mut accounts = init_escrow_accounts(&program_id);
+        accounts.push(offer_counter(&program_id, 0).with_key(Pubkey::new_unique()));
+        assert_eq!(process(&program_id, &mut accounts, &init_escrow_data(&program_id, 50, 0)), fails_with(EscrowError::InvalidOfferCounter));
+
+        let mut accounts = init_escrow_accounts(&program_id);
+        accounts.push(offer_counter(&program_id, 0).read_only());
+        assert_eq!(process(&program_id, &mut accounts, &init_escrow_data(&program_id, 50, 0)), fails_with(EscrowError::AccountNotWritable));
+    }
+
+    /// The accounts of a plain Exchange taking 100 offered tokens for 50, in instruction order: taker, send account, receive account,
+    /// vault, initializer, initializer's receive account, escrow, token program, PDA
+    fn exchange_accounts(program_id: &Pubkey) -> Vec<TestAccount> {
+        let (pda, bump_seed) = Pubkey::find_program_address(&[b"escrow"], program_id);
+        let (offered_mint, expected_mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let taker = test_utils::wallet();
+        let send_token_account = test_utils::token_account(&taker.key, &expected_mint, 50);
+        let receive_token_account = test_utils::token_account(&taker.key, &offered_mint, 0);
+        let vault = test_utils::token_account(&pda, &offered_mint, 100);
+        let initializer = test_utils::wallet();
+        let initializer_token_to_receive_account = test_utils::token_account(&initializer.key, &expected_mint, 0);
+        let escrow_account = test_utils::escrow_account(
+            Escrow {
+                is_initialized: true,
+                initializer_pubkey: initializer.key,
+                temp_token_account_pubkey: vault.key,
+                initializer_token_to_receive_account_pubkey: initializer_token_to_receive_account.key,
+                expected_amount: 50,
+                bump_seed,
+                ..Escrow::default()
+            },
+            program_id,
+        );
+        vec![
+            taker,
+            send_token_account,
+            receive_token_account,
+            vault,
+            initializer,
+            initializer_token_to_receive_account,
+            escrow_account,
+            test_utils::program(&spl_token::id()),
+            TestAccount::new(&solana_program::system_program::id(), Vec::new()).with_key(pda).read_only(),
+        ]
+    }
+
+    // ValidateExchange goes through every check an Exchange makes and stops short of the transfers, which the stubs can't carry out
+    fn validate_exchange_data(amount: u64) -> Vec<u8> {
+        let mut data = vec![21];
+        data.extend_from_slice(&amount.to_le_bytes());
+        data
+    }
+
+    fn validate_exchange_with(change: impl FnOnce(&mut [TestAccount])) -> ProgramResult {
+        let program_id = Pubkey::new_unique();
+        let mut accounts = exchange_accounts(&program_id);
+        change(&mut accounts);
+        process(&program_id, &mut accounts, &validate_exchange_data(100))
+    }
+
+    fn set_escrow(account: &mut TestAccount, change: impl FnOnce(&mut Escrow)) {
+        let mut escrow = Escrow::unpack_unchecked(&account.data).unwrap();
+        change(&mut escrow);
+        Escrow::pack(escrow, &mut account.data).unwrap();
+    }
+
+    #[test]
+    fn validate_exchange_passes_a_take_that_would_go_through() {
+        assert_eq!(validate_exchange_with(|_accounts| ()), Ok(()));
+    }
+
+    #[test]
+    fn exchange_needs_the_taker_to_sign() {
+        assert_eq!(
+            validate_exchange_with(|accounts| accounts[0].is_signer = false),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+    }
+
+    #[test]
+    fn exchange_rejects_a_zero_amount() {
+        let program_id = Pubkey::new_unique();
+        let mut accounts = exchange_accounts(&program_id);
+        assert_eq!(process(&program_id, &mut accounts, &validate_exchange_data(0)), fails_with(EscrowError::ZeroAmount));
+    }
+
+    #[test]
+    fn exchange_rejects_a_take_past_the_takers_own_deadline() {
+        let program_id = Pubkey::new_unique();
+        let mut accounts = exchange_accounts(&program_id);
+        let mut data = validate_exchange_data(100);
+        data.extend_from_slice(&(SLOT - 1).to_le_bytes());
+        assert_eq!(process(&program_id, &mut accounts, &data), fails_with(EscrowError::FillWindowExpired));
+    }
+
+    #[test]
+    fn exchange_needs_an_initialized_escrow_in_the_current_layout() {
+        assert_eq!(
+            validate_exchange_with(|accounts| set_escrow(&mut accounts[6], |escrow| escrow.is_initialized = false)),
+            fails_with(EscrowError::EscrowNotInitialized)
+        );
+        assert_eq!(
+            validate_exchange_with(|accounts| {
+                let (version, len) = Escrow::PAST_LENS[Escrow::PAST_LENS.len() - 1];
+                accounts[6].data.truncate(len);
+                accounts[6].data[0] = version;
+            }),
+            fails_with(EscrowError::EscrowNeedsMigration)
+        );
+    }
+
+    #[test]
+    fn exchange_cant_take_a_finished_escrow() {
+        assert_eq!(
+            validate_exchange_with(|accounts| set_escrow(&mut accounts[6], |escrow| escrow.status = EscrowStatus::Completed)),
+            fails_with(EscrowError::InvalidEscrowStatus)
+        );
+    }
+
+    #[test]
+    fn exchange_cant_take_a_subscription() {
+        assert_eq!(
+            validate_exchange_with(|accounts| set_escrow(&mut accounts[6], |escrow| escrow.kind = EscrowKind::Subscription)),
+            fails_with(EscrowError::WrongEscrowMode)
+        );
+    }
+
+    #[test]
+    fn exchange_cant_take_an_escrow_the_taker_side_was_deposited_for() {
+        assert_eq!(
+            validate_exchange_with(|accounts| set_escrow(&mut accounts[6], |escrow| escrow.taker_vault_pubkey = Pubkey::new_unique())),
+            fails_with(EscrowError::TakerSideDeposited)
+        );
+    }
+
+    #[test]
+    fn exchange_cant_take_an_offer_someone_else_committed_to() {
+        assert_eq!(
+            validate_exchange_with(|accounts| set_escrow(&mut accounts[6], |escrow| {
+                escrow.commit_taker = Pubkey::new_unique();
+                escrow.commit_slot = SLOT;
+            })),
+            fails_with(EscrowError::OfferCommitted)
+        );
+    }
+
+    /// The accounts of a DepositTakerSide into the escrow of `exchange_accounts`: taker, send account, receive account, vault, escrow,
+    /// token program, initializer's receive account
+    fn deposit_taker_side_accounts(program_id: &Pubkey) -> Vec<TestAccount> {
+        let mut accounts = exchange_accounts(program_id);
+        accounts.truncate(8);
+        let initializer_token_to_receive_account = accounts.remove(5);
+        accounts.remove(4);
+        accounts.push(initializer_token_to_receive_account);
+        accounts
+    }
+
+    fn deposit_taker_side_with(change: impl FnOnce(&mut [TestAccount])) -> ProgramResult {
+        let program_id = Pubkey::new_unique();
+        let mut accounts = deposit_taker_side_accounts(&program_id);
+        change(&mut accounts);
+        process(&program_id, &mut accounts, &deposit_taker_side_data(100))
+    }
+
+    fn deposit_taker_side_data(amount: u64) -> Vec<u8> {
+        let mut data = vec![13];
+        data.extend_from_slice(&amount.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn deposit_taker_side_locks_in_a_plain_offer() {
+        assert_eq!(deposit_taker_side_with(|_accounts| ()), Ok(()));
+    }
+
+    #[test]
+    fn deposit_taker_side_needs_both_sides_in_the_mints_settle_swaps_them_into() {
+        assert_eq!(
+            deposit_taker_side_with(|accounts| set_token_account(&mut accounts[1], |account| account.mint = Pubkey::new_unique())),
+            fails_with(EscrowError::WrongMint)
+        );
+        assert_eq!(
+            deposit_taker_side_with(|accounts| set_token_account(&mut accounts[2], |account| account.mint = Pubkey::new_unique())),
+            fails_with(EscrowError::WrongMint)
+        );
+        assert_eq!(
+            deposit_taker_side_with(|accounts| accounts[6].key = Pubkey::new_unique()),
+            fails_with(EscrowError::ReceiveAccountMismatch)
+        );
+    }
+
+    #[test]
+    fn deposit_taker_side_only_takes_a_plain_fixed_price_offer() {
+        assert_eq!(
+            deposit_taker_side_with(|accounts| set_escrow(&mut accounts[4], |escrow| escrow.kind = EscrowKind::CollectionBid)),
+            fails_with(EscrowError::WrongEscrowMode)
+        );
+        assert_eq!(
+            deposit_taker_side_with(|accounts| set_escrow(&mut accounts[4], |escrow| escrow.charges_protocol_fee = true)),
+            fails_with(EscrowError::WrongEscrowMode)
+        );
+    }
+
+    #[test]
+    fn deposit_taker_side_cant_take_an_offer_someone_else_committed_to() {
+        let program_id = Pubkey::new_unique();
+        let mut accounts = deposit_taker_side_accounts(&program_id);
+
+        let mut commit_accounts = vec![
+            test_utils::wallet(),
+            accounts[4].clone(),
+            test_utils::wallet(),
+            test_utils::program(&solana_program::system_program::id()),
+        ];
+        let mut commit_data = vec![18];
+        commit_data.extend_from_slice(&[7; 32]);
+        process(&program_id, &mut commit_accounts, &commit_data).unwrap();
+        accounts[4] = commit_accounts.swap_remove(1);
+
+        assert_eq!(
+            process(&program_id, &mut accounts, &deposit_taker_side_data(100)),
+            fails_with(EscrowError::OfferCommitted)
+        );
+    }
+
+    #[test]
+    fn exchange_needs_the_accounts_the_escrow_names() {
+        assert_eq!(
+            validate_exchange_with(|accounts| accounts[3].key = Pubkey::new_unique()),
+            fails_with(EscrowError::TempAccountMismatch)
+        );
+        assert_eq!(
+            validate_exchange_with(|accounts| accounts[4].key = Pubkey::new_unique()),
+            fails_with(EscrowError::InitializerMismatch)
+        );
+        assert_eq!(
+            validate_exchange_with(|accounts| accounts[5].key = Pubkey::new_unique()),
+            fails_with(EscrowError::ReceiveAccountMismatch)
+        );
+    }
+
+    #[test]
+    fn exchange_only_takes_an_offer_between_its_start_and_expiry() {
+        assert_eq!(
+            validate_exchange_with(|accounts| set_escrow(&mut accounts[6], |escrow| escrow.expires_at = UNIX_TIMESTAMP)),
+            fails_with(EscrowError::EscrowExpired)
+        );
+        assert_eq!(
+            validate_exchange_with(|accounts| set_escrow(&mut accounts[6], |escrow| escrow.start_time = UNIX_TIMESTAMP + 1)),
+            fails_with(EscrowError::OfferNotActiveYet)
+        );
+    }
+
+    #[test]
+    fn exchange_needs_the_transfer_authority_it_names_to_sign() {
+        let program_id = Pubkey::new_unique();
+        let mut accounts = exchange_accounts(&program_id);
+        accounts.push(test_utils::wallet());
+        accounts[9].is_signer = false;
+        let mut data = validate_exchange_data(100);
+        data.extend_from_slice(&0u64.to_le_bytes()); // no deadline
+        data.extend_from_slice(&0u16.to_le_bytes()); // no referral
+        data.extend_from_slice(&[0, 0, 1]); // no hook accounts, a transfer authority
+        assert_eq!(process(&program_id, &mut accounts, &data), Err(ProgramError::MissingRequiredSignature));
+    }
+
+    #[test]
+    fn exchange_only_goes_through_a_token_program() {
+        assert_eq!(
+            validate_exchange_with(|accounts| accounts[7] = test_utils::program(&Pubkey::new_unique())),
+            fails_with(EscrowError::InvalidTokenProgram)
+        );
+    }
+
+    #[test]
+    fn exchange_has_to_take_the_whole_offer() {
+        let program_id = Pubkey::new_unique();
+        let mut accounts = exchange_accounts(&program_id);
+        assert_eq!(
+            process(&program_id, &mut accounts, &validate_exchange_data(99)),
+            fails_with(EscrowError::ExpectedAmountMismatch)
+        );
+    }
+
+    #[test]
+    fn validate_exchange_fails_a_taker_who_cant_pay() {
+        assert_eq!(
+            validate_exchange_with(|accounts| set_token_account(&mut accounts[1], |send| send.amount = 49)),
+            Err(ProgramError::InsufficientFunds)
+        );
+        assert_eq!(
+            validate_exchange_with(|accounts| set_token_account(&mut accounts[1], |send| send.owner = Pubkey::new_unique())),
+            Err(ProgramError::InsufficientFunds)
+        );
+    }
+
+    #[test]
+    fn exchange_fails_up_front_when_a_token_account_is_frozen() {
+        for frozen in [1, 2, 3, 5] {
+            assert_eq!(
+                validate_exchange_with(|accounts| set_token_account(&mut accounts[frozen], |account| account.state = AccountState::Frozen)),
+                fails_with(EscrowError::AccountFrozen)
+            );
+        }
+    }
+
+    /// Caps the escrow `exchange_accounts` set up at `max_per_taker` and passes the taker's claim, having bought `claimed_amount` already
+    fn capped_exchange_accounts(program_id: &Pubkey, max_per_taker: u64, claimed_amount: Option<u64>) -> Vec<TestAccount> {
+        let mut accounts = exchange_accounts(program_id);
+        set_escrow(&mut accounts[6], |escrow| escrow.max_per_taker = max_per_taker);
+        let claim_pda = crate::client::find_claim_address(&accounts[6].key, &accounts[0].key, program_id);
+        let claim_account = match claimed_amount {
+            Some(claimed_amount) => {
+                let mut claim_account = TestAccount::new(program_id, vec![0; TakerClaim::LEN]).with_key(claim_pda);
+                TakerClaim::pack(TakerClaim { is_initialized: true, claimed_amount, ..TakerClaim::default() }, &mut claim_account.data).unwrap();
+                claim_account
+            },
+            None => TestAccount::new(&solana_program::system_program::id(), Vec::new()).with_key(claim_pda).with_lamports(0),
+        };
+        accounts.push(claim_account);
+        accounts.push(test_utils::program(&solana_program::system_program::id()));
+        accounts
+    }
+
+    #[test]
+    fn exchange_of_a_capped_escrow_needs_the_takers_claim() {
+        let program_id = Pubkey::new_unique();
+        let mut accounts = capped_exchange_accounts(&program_id, 100, None);
+        accounts[9].key = Pubkey::new_unique();
+        assert_eq!(process(&program_id, &mut accounts, &validate_exchange_data(100)), fails_with(EscrowError::InvalidClaimAccount));
+    }
+
+    #[test]
+    fn exchange_only_takes_a_taker_up_to_the_cap() {
+        let program_id = Pubkey::new_unique();
+        let mut first_fill = capped_exchange_accounts(&program_id, 100, None);
+        assert_eq!(process(&program_id, &mut first_fill, &validate_exchange_data(100)), Ok(()));
+
+        let mut within_cap = capped_exchange_accounts(&program_id, 150, Some(50));
+        assert_eq!(process(&program_id, &mut within_cap, &validate_exchange_data(100)), Ok(()));
+
+        let mut past_cap = capped_exchange_accounts(&program_id, 150, Some(51));
+        assert_eq!(process(&program_id, &mut past_cap, &validate_exchange_data(100)), fails_with(EscrowError::PurchaseLimitExceeded));
+
+        let mut first_fill_past_cap = capped_exchange_accounts(&program_id, 99, None);
+        assert_eq!(
+            process(&program_id, &mut first_fill_past_cap, &validate_exchange_data(100)),
+            fails_with(EscrowError::PurchaseLimitExceeded)
+        );
+    }
+
+    const FEE_TIERS: [FeeTier; 3] = [
+        FeeTier { min_age_slots: 0, fee_bps: 100 },
+        FeeTier { min_age_slots: 500, fee_bps: 50 },
+        FeeTier { min_age_slots: 5_000, fee_bps: 10 },
+    ];
+
+    /// The accounts of an Exchange of an escrow opened under `FEE_TIERS`, with the config and the fee recipient's token account after them
+    fn fee_exchange_accounts(program_id: &Pubkey) -> Vec<TestAccount> {
+        let mut accounts = exchange_accounts(program_id);
+        set_escrow(&mut accounts[6], |escrow| {
+            escrow.created_at_slot = SLOT - 600;
+            escrow.charges_protocol_fee = true;
+        });
+        let fee_recipient = Pubkey::new_unique();
+        let mut config = Config {
+            is_initialized: true,
+            fee_recipient,
+            ..Config::default()
+        };
+        Processor::set_fee_tiers(&mut config, &FEE_TIERS).unwrap();
+        let (config_pda, _bump_seed) = Pubkey::find_program_address(&[b"config"], program_id);
+        let mut config_account = TestAccount::new(program_id, vec![0; Config::LEN]).with_key(config_pda);
+        config.pack_into_slice(&mut config_account.data);
+        let payment_mint = TokenAccount::unpack(&accounts[5].data).unwrap().mint;
+        accounts.push(config_account);
+        accounts.push(test_utils::token_account(&fee_recipient, &payment_mint, 0));
+        accounts
+    }
+
+    #[test]
+    fn protocol_fee_falls_as_the_offer_ages() {
+        let mut config = Config::default();
+        assert_eq!(config.protocol_fee_bps(10_000), 0);
+        Processor::set_fee_tiers(&mut config, &FEE_TIERS).unwrap();
+        assert!(config.has_protocol_fee());
+        assert_eq!(config.protocol_fee_bps(0), 100);
+        assert_eq!(config.protocol_fee_bps(499), 100);
+        assert_eq!(config.protocol_fee_bps(500), 50);
+        assert_eq!(config.protocol_fee_bps(u64::MAX), 10);
+
+        assert_eq!(Config::check_fee_tiers(&FEE_TIERS), Ok(()));
+        let rising = [FEE_TIERS[1], FEE_TIERS[0]];
+        assert_eq!(Config::check_fee_tiers(&rising), fails_with(EscrowError::InvalidFeeSchedule));
+        let dearer_with_age = [FEE_TIERS[0], FeeTier { min_age_slots: 500, fee_bps: 200 }];
+        assert_eq!(Config::check_fee_tiers(&dearer_with_age), fails_with(EscrowError::InvalidFeeSchedule));
+        let above_the_price = [FeeTier { min_age_slots: 0, fee_bps: Config::MAX_BPS + 1 }];
+        assert_eq!(Config::check_fee_tiers(&above_the_price), fails_with(EscrowError::InvalidFeeSchedule));
+    }
+
+    #[test]
+    fn exchange_pays_the_protocol_fee_to_the_fee_recipient_in_the_payment_token() {
+        let program_id = Pubkey::new_unique();
+        let mut accounts = fee_exchange_accounts(&program_id);
+        assert_eq!(process(&program_id, &mut accounts, &validate_exchange_data(100)), Ok(()));
+
+        let mut missing_fee_account = fee_exchange_accounts(&program_id);
+        missing_fee_account.pop();
+        assert_eq!(
+            process(&program_id, &mut missing_fee_account, &validate_exchange_data(100)),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+
+        let mut someone_elses = fee_exchange_accounts(&program_id);
+        set_token_account(&mut someone_elses[10], |fee_account| fee_account.owner = Pubkey::new_unique());
+        assert_eq!(process(&program_id, &mut someone_elses, &validate_exchange_data(100)), fails_with(EscrowError::FeeAccountMismatch));
+
+        let mut wrong_mint = fee_exchange_accounts(&program_id);
+        set_token_account(&mut wrong_mint[10], |fee_account| fee_account.mint = Pubkey::new_unique());
+        assert_eq!(process(&program_id, &mut wrong_mint, &validate_exchange_data(100)), fails_with(EscrowError::FeeAccountMismatch));
+    }
+
+    #[test]
+    fn crank_of_a_delegated_escrow_needs_the_initializer_to_revoke_the_approval() {
+        let program_id = Pubkey::new_unique();
+        let mut exchange_accounts = exchange_accounts(&program_id);
+        let mut accounts = vec![
+            test_utils::wallet(),
+            exchange_accounts[3].clone(),
+            exchange_accounts[4].clone(),
+            test_utils::token_account(&exchange_accounts[4].key, &Pubkey::new_unique(), 0),
+            exchange_accounts[6].clone(),
+            exchange_accounts.remove(7),
+            exchange_accounts.remove(7),
+        ];
+        set_escrow(&mut accounts[4], |escrow| {
+            escrow.is_delegated = true;
+            escrow.kind = EscrowKind::Delegated;
+            escrow.expires_at = UNIX_TIMESTAMP;
+        });
+        accounts[2].is_signer = false;
+        assert_eq!(process(&program_id, &mut accounts, &[2]), Err(ProgramError::MissingRequiredSignature));
+    }
+
+    /// The accounts of a Cancel of the escrow `exchange_accounts` sets up: initializer, vault, refund account, escrow, token program, PDA
+    fn cancel_accounts(program_id: &Pubkey) -> Vec<TestAccount> {
+        let mut exchange_accounts = exchange_accounts(program_id);
+        vec![
+            exchange_accounts[4].clone(),
+            exchange_accounts[3].clone(),
+            test_utils::token_account(&exchange_accounts[4].key, &Pubkey::new_unique(), 0),
+            exchange_accounts[6].clone(),
+            exchange_accounts.remove(7),
+            exchange_accounts.remove(7),
+        ]
+    }
+
+    fn cancel_with(change: impl FnOnce(&mut [TestAccount])) -> ProgramResult {
+        let program_id = Pubkey::new_unique();
+        let mut accounts = cancel_accounts(&program_id);
+        change(&mut accounts);
+        process(&program_id, &mut accounts, &[5])
+    }
+
+    #[test]
+    fn cancel_needs_the_initializer_to_sign() {
+        assert_eq!(cancel_with(|accounts| accounts[0].is_signer = false), Err(ProgramError::MissingRequiredSignature));
+    }
+
+    #[test]
+    fn cancel_is_only_for_the_initializer_of_an_unfinished_escrow() {
+        assert_eq!(cancel_with(|accounts| accounts[0].key = Pubkey::new_unique()), fails_with(EscrowError::InitializerMismatch));
+        assert_eq!(cancel_with(|accounts| accounts[1].key = Pubkey::new_unique()), fails_with(EscrowError::TempAccountMismatch));
+        assert_eq!(
+            cancel_with(|accounts| set_escrow(&mut accounts[3], |escrow| escrow.status = EscrowStatus::Cancelled)),
+            fails_with(EscrowError::InvalidEscrowStatus)
+        );
+        assert_eq!(
+            cancel_with(|accounts| set_escrow(&mut accounts[3], |escrow| escrow.taker_vault_pubkey = Pubkey::new_unique())),
+            fails_with(EscrowError::TakerSideDeposited)
+        );
+    }
+
+    #[test]
+    fn cancel_by_a_multisig_needs_its_threshold_of_signers() {
+        let program_id = Pubkey::new_unique();
+        let mut accounts = cancel_accounts(&program_id);
+        let (first_signer, second_signer) = (test_utils::wallet(), test_utils::wallet());
+        let mut signers = [Pubkey::default(); spl_token::instruction::MAX_SIGNERS];
+        signers[..2].copy_from_slice(&[first_signer.key, second_signer.key]);
+        let mut data = vec![0; Multisig::LEN];
+        Multisig::pack(Multisig { m: 2, n: 2, is_initialized: true, signers }, &mut data).unwrap();
+        accounts[0] = TestAccount::new(&spl_token::id(), data).with_key(accounts[0].key);
+
+        // one of the two signing, even when passed twice, isn't the multisig authorising the Cancel
+        accounts.push(first_signer.clone());
+        assert_eq!(process(&program_id, &mut accounts, &[5]), Err(ProgramError::MissingRequiredSignature));
+        accounts.push(first_signer);
+        assert_eq!(process(&program_id, &mut accounts, &[5]), Err(ProgramError::MissingRequiredSignature));
+
+        accounts.push(test_utils::wallet());
+        assert_eq!(process(&program_id, &mut accounts, &[5]), fails_with(EscrowError::InvalidMultisigSigner));
+    }
+
+    #[test]
+    fn cancel_of_a_frozen_vault_parks_the_escrow_until_its_thawed() {
+        let program_id = Pubkey::new_unique();
+        let mut accounts = cancel_accounts(&program_id);
+        set_token_account(&mut accounts[1], |vault| vault.state = AccountState::Frozen);
+        process(&program_id, &mut accounts, &[5]).unwrap();
+
+        // parking makes no CPI and leaves the escrow open for the Cancel after the thaw, which the stubs can't follow through
+        let parked = Escrow::unpack(&accounts[3].data).unwrap();
+        assert_eq!(parked.status, EscrowStatus::CancelPending);
+        assert_eq!(process(&program_id, &mut accounts, &[5]), fails_with(EscrowError::AccountFrozen));
+        assert_eq!(
+            validate_exchange_with(|accounts| set_escrow(&mut accounts[6], |escrow| escrow.status = EscrowStatus::CancelPending)),
+            fails_with(EscrowError::InvalidEscrowStatus)
+        );
+    }
+
+    #[test]
+    fn cancel_cant_park_a_frozen_vault_to_dodge_its_penalty() {
+        let program_id = Pubkey::new_unique();
+        let mut accounts = cancel_accounts(&program_id);
+        set_token_account(&mut accounts[1], |vault| vault.state = AccountState::Frozen);
+        set_escrow(&mut accounts[3], |escrow| escrow.cancel_penalty_bps = 500);
+        let penalty_account = test_utils::token_account(&Pubkey::new_unique(), &Pubkey::new_unique(), 0);
+        accounts.push(penalty_account);
+        assert_eq!(process(&program_id, &mut accounts, &[5]), fails_with(EscrowError::AccountFrozen));
+    }
+
+    /// The accounts of a RecoverOrphan of a token account the PDA holds with the claimant's orphan authority as its close authority:
+    /// claimant, token account, token program, PDA, orphan authority
+    fn recover_orphan_accounts(program_id: &Pubkey) -> Vec<TestAccount> {
+        let (pda, _bump_seed) = Pubkey::find_program_address(&[b"escrow"], program_id);
+        let claimant = test_utils::wallet();
+        let (orphan_authority, _bump_seed) = Pubkey::find_program_address(&[b"orphan", claimant.key.as_ref()], program_id);
+        let mut orphan = test_utils::token_account(&pda, &Pubkey::new_unique(), 100);
+        set_token_account(&mut orphan, |orphan| orphan.close_authority = COption::Some(orphan_authority));
+        vec![
+            claimant,
+            orphan,
+            test_utils::program(&spl_token::id()),
+            TestAccount::new(&solana_program::system_program::id(), Vec::new()).with_key(pda).read_only(),
+            TestAccount::new(&solana_program::system_program::id(), Vec::new()).with_key(orphan_authority).read_only(),
+        ]
+    }
+
+    #[test]
+    fn recover_orphan_refuses_a_vault_the_claimant_could_close_whatever_escrow_is_passed() {
+        let program_id = Pubkey::new_unique();
+        let mut accounts = recover_orphan_accounts(&program_id);
+        process(&program_id, &mut accounts, &[22]).unwrap();
+
+        // the vault of an escrow opened before InitEscrow refused close authorities, with the initializer as its close authority, and
+        // some unrelated escrow's key where the orphan authority goes
+        let claimant = accounts[0].key;
+        set_token_account(&mut accounts[1], |vault| vault.close_authority = COption::Some(claimant));
+        accounts[4] = test_utils::escrow_account(Escrow::default(), &program_id);
+        assert_eq!(process(&program_id, &mut accounts, &[22]), fails_with(EscrowError::InvalidOrphanClaim));
+
+        // nor does the orphan authority of someone other than the signer do
+        let mut accounts = recover_orphan_accounts(&program_id);
+        accounts[0] = test_utils::wallet();
+        assert_eq!(process(&program_id, &mut accounts, &[22]), fails_with(EscrowError::InvalidOrphanClaim));
+    }
+
+    /// The accounts of a MatchOffers of two plain offers that cross exactly: matcher, then escrow, vault, initializer and receive account
+    /// of each side. The token program and the rest aren't passed, so a match that gets past both sides' checks runs out of accounts
+    fn match_offers_accounts(program_id: &Pubkey) -> Vec<TestAccount> {
+        let (pda, bump_seed) = Pubkey::find_program_address(&[b"escrow"], program_id);
+        let mints = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let mut accounts = vec![test_utils::wallet()];
+        for (offered_mint, expected_mint) in [(mints[0], mints[1]), (mints[1], mints[0])] {
+            let vault = test_utils::token_account(&pda, &offered_mint, 100);
+            let initializer = test_utils::wallet();
+            let receive_account = test_utils::token_account(&initializer.key, &expected_mint, 0);
+            let escrow_account = test_utils::escrow_account(
+                Escrow {
+                    is_initialized: true,
+                    initializer_pubkey: initializer.key,
+                    temp_token_account_pubkey: vault.key,
+                    initializer_token_to_receive_account_pubkey: receive_account.key,
+                    expected_amount: 100,
+                    bump_seed,
+                    ..Escrow::default()
+                },
+                program_id,
+            );
+            accounts.extend([escrow_account, vault, initializer, receive_account]);
+        }
+        accounts
+    }
+
+    #[test]
+    fn match_offers_cant_skip_the_royalties_of_an_escrow() {
+        let program_id = Pubkey::new_unique();
+        for escrow in [1, 5] {
+            let mut accounts = match_offers_accounts(&program_id);
+            assert_eq!(process(&program_id, &mut accounts, &[23]), Err(ProgramError::NotEnoughAccountKeys));
+            set_escrow(&mut accounts[escrow], |escrow| escrow.enforce_royalties = true);
+            assert_eq!(process(&program_id, &mut accounts, &[23]), fails_with(EscrowError::WrongEscrowMode));
+        }
+    }
+
+    #[test]
+    fn match_offers_cant_skip_the_protocol_fee_of_an_escrow() {
+        let program_id = Pubkey::new_unique();
+        for escrow in [1, 5] {
+            let mut accounts = match_offers_accounts(&program_id);
+            set_escrow(&mut accounts[escrow], |escrow| escrow.charges_protocol_fee = true);
+            assert_eq!(process(&program_id, &mut accounts, &[23]), fails_with(EscrowError::WrongEscrowMode));
+        }
+    }
+
+    #[test]
+    fn init_subscription_rejects_installments_of_nothing_or_with_no_interval() {
+        let program_id = Pubkey::new_unique();
+        let initializer = test_utils::wallet();
+        let token_to_receive_account = test_utils::token_account(&initializer.key, &Pubkey::new_unique(), 0);
+        let escrow_account = test_utils::escrow_account(Escrow::default(), &program_id);
+        let mut accounts = vec![initializer, token_to_receive_account, escrow_account, test_utils::rent_sysvar()];
+        for (installment_amount, interval_slots) in [(0u64, 100u64), (10, 0)] {
+            let mut data = vec![6];
+            data.extend_from_slice(&installment_amount.to_le_bytes());
+            data.extend_from_slice(&interval_slots.to_le_bytes());
+            assert_eq!(process(&program_id, &mut accounts, &data), fails_with(EscrowError::InvalidSubscriptionTerms));
+        }
+    }
+
+    /// A TopUp of the escrow `exchange_accounts` sets up: initializer, source account, vault, escrow, token program, PDA
+    fn top_up_with(change: impl FnOnce(&mut [TestAccount])) -> ProgramResult {
+        let program_id = Pubkey::new_unique();
+        let mut exchange_accounts = exchange_accounts(&program_id);
+        let offered_mint = TokenAccount::unpack(&exchange_accounts[3].data).unwrap().mint;
+        let mut accounts = vec![
+            exchange_accounts[4].clone(),
+            test_utils::token_account(&exchange_accounts[4].key, &offered_mint, 10),
+            exchange_accounts[3].clone(),
+            exchange_accounts[6].clone(),
+            exchange_accounts.remove(7),
+            exchange_accounts.remove(7),
+        ];
+        change(&mut accounts);
+        let mut data = vec![10];
+        data.extend_from_slice(&10u64.to_le_bytes());
+        process(&program_id, &mut accounts, &data)
+    }
+
+    #[test]
+    fn top_up_adds_to_an_open_escrow_of_the_initializer() {
+        assert_eq!(top_up_with(|_accounts| ()), Ok(()));
+        assert_eq!(top_up_with(|accounts| accounts[0].is_signer = false), Err(ProgramError::MissingRequiredSignature));
+        assert_eq!(top_up_with(|accounts| accounts[0].key = Pubkey::new_unique()), fails_with(EscrowError::InitializerMismatch));
+        assert_eq!(top_up_with(|accounts| accounts[2].key = Pubkey::new_unique()), fails_with(EscrowError::TempAccountMismatch));
+        assert_eq!(
+            top_up_with(|accounts| set_escrow(&mut accounts[3], |escrow| escrow.status = EscrowStatus::Disputed)),
+            fails_with(EscrowError::InvalidEscrowStatus)
+        );
+        assert_eq!(
+            top_up_with(|accounts| set_escrow(&mut accounts[3], |escrow| escrow.is_subscription = true)),
+            fails_with(EscrowError::WrongEscrowMode)
+        );
+    }
+
+    #[test]
+    fn top_up_only_goes_through_the_original_token_program() {
+        assert_eq!(
+            top_up_with(|accounts| accounts[4] = test_utils::program(&utils::spl_token_2022::id())),
+            fails_with(EscrowError::InvalidTokenProgram)
+        );
+    }
+
+    #[test]
+    fn an_extended_escrow_is_read_from_the_start_of_its_account() {
+        assert_eq!(validate_exchange_with(|accounts| accounts[6].data.resize(Escrow::LEN + 100, 0)), Ok(()));
+        assert_eq!(
+            validate_exchange_with(|accounts| {
+                let (version, len) = Escrow::PAST_LENS[Escrow::PAST_LENS.len() - 1];
+                accounts[6].data.truncate(len);
+                accounts[6].data.resize(Escrow::LEN + 100, 0);
+                accounts[6].data[0] = version;
+            }),
+            fails_with(EscrowError::EscrowNeedsMigration)
+        );
+    }
+
+    /// An ExtendEscrow of the escrow `exchange_accounts` sets up to `new_len`: initializer, escrow, system program
+    fn extend_escrow_with(new_len: u64, change: impl FnOnce(&mut [TestAccount])) -> ProgramResult {
+        let program_id = Pubkey::new_unique();
+        let mut exchange_accounts = exchange_accounts(&program_id);
+        let mut accounts = vec![
+            exchange_accounts.remove(4),
+            exchange_accounts.remove(5),
+            test_utils::program(&solana_program::system_program::id()),
+        ];
+        change(&mut accounts);
+        let mut data = vec![27];
+        data.extend_from_slice(&new_len.to_le_bytes());
+        process(&program_id, &mut accounts, &data)
+    }
+
+    #[test]
+    fn extend_escrow_only_grows_an_escrow_for_its_initializer() {
+        let new_len = Escrow::LEN as u64 + 100;
+        assert_eq!(extend_escrow_with(new_len, |accounts| accounts[0].is_signer = false), Err(ProgramError::MissingRequiredSignature));
+        assert_eq!(
+            extend_escrow_with(new_len, |accounts| accounts[0].key = Pubkey::new_unique()),
+            fails_with(EscrowError::InitializerMismatch)
+        );
+        assert_eq!(extend_escrow_with(new_len, |accounts| accounts[1].is_writable = false), fails_with(EscrowError::AccountNotWritable));
+        assert_eq!(extend_escrow_with(Escrow::LEN as u64, |_accounts| ()), fails_with(EscrowError::InvalidEscrowLength));
+        assert_eq!(
+            extend_escrow_with(new_len, |accounts| accounts[1].data.resize(new_len as usize, 0)),
+            fails_with(EscrowError::InvalidEscrowLength)
+        );
+        assert_eq!(
+            extend_escrow_with(new_len, |accounts| {
+                let (version, len) = Escrow::PAST_LENS[Escrow::PAST_LENS.len() - 1];
+                accounts[1].data.truncate(len);
+                accounts[1].data[0] = version;
+            }),
+            fails_with(EscrowError::EscrowNeedsMigration)
+        );
+    }
+}
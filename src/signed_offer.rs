@@ -0,0 +1,222 @@
+use std::convert::TryInto;
+
+use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
+use solana_program::{
+    account_info::AccountInfo,
+    instruction::Instruction,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    sysvar::instructions::{load_current_index_checked, load_instruction_at_checked},
+};
+
+use crate::error::EscrowError;
+
+/// The native program that verifies ed25519 signatures, whose instruction has to come right before a FillSignedOffer
+pub mod ed25519_program {
+    solana_program::declare_id!("Ed25519SigVerify111111111111111111111111111");
+}
+
+// the ed25519 program's data is a signature count and a padding byte, then per signature the offsets of the signature, the public key
+// and the message, each paired with the index of the instruction holding it, u16::MAX meaning the ed25519 instruction itself
+const SIGNATURE_OFFSETS_START: usize = 2;
+const SIGNATURE_OFFSETS_LEN: usize = 14;
+const DATA_START: usize = SIGNATURE_OFFSETS_START + SIGNATURE_OFFSETS_LEN;
+const THIS_INSTRUCTION: u16 = u16::MAX;
+const PUBKEY_LEN: usize = 32;
+const SIGNATURE_LEN: usize = 64;
+
+/// An offer the initializer signs off-chain instead of opening an escrow for it. Nothing is locked up: the offered tokens stay in the
+/// initializer's account, which approves the PDA as its delegate, and FillSignedOffer moves them straight to the taker
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SignedOffer {
+    pub initializer_pubkey: Pubkey,
+    pub offered_token_account_pubkey: Pubkey, // the initializer's account the offered tokens come out of, the PDA has to be its delegate
+    pub initializer_token_to_receive_account_pubkey: Pubkey,
+    pub offered_amount: u64,
+    pub expected_amount: u64,
+    pub expires_at: i64, // 0 means it never expires
+    pub nonce: u64, // one fill per initializer and nonce, see FilledOffer
+}
+
+impl SignedOffer {
+    /// 3 pubkeys, 4 u64 = 3 * 32 + 4 * 8 = 128
+    pub const LEN: usize = 128;
+
+    /// The message is the program id, so a signature for one deployment can't be replayed against another, then the packed offer
+    pub const MESSAGE_LEN: usize = 32 + SignedOffer::LEN;
+
+    pub fn unpack(src: &[u8]) -> Result<SignedOffer, ProgramError> {
+        let src: &[u8; SignedOffer::LEN] = src.try_into().map_err(|_| EscrowError::InvalidInstructionData)?;
+        let (
+            initializer_pubkey,
+            offered_token_account_pubkey,
+            initializer_token_to_receive_account_pubkey,
+            offered_amount,
+            expected_amount,
+            expires_at,
+            nonce,
+        ) = array_refs![src, 32, 32, 32, 8, 8, 8, 8];
+
+        Ok(SignedOffer {
+            initializer_pubkey: Pubkey::new_from_array(*initializer_pubkey),
+            offered_token_account_pubkey: Pubkey::new_from_array(*offered_token_account_pubkey),
+            initializer_token_to_receive_account_pubkey: Pubkey::new_from_array(*initializer_token_to_receive_account_pubkey),
+            offered_amount: u64::from_le_bytes(*offered_amount),
+            expected_amount: u64::from_le_bytes(*expected_amount),
+            expires_at: i64::from_le_bytes(*expires_at),
+            nonce: u64::from_le_bytes(*nonce),
+        })
+    }
+
+    pub fn pack_into_slice(&self, dst: &mut [u8]) {
+        let dst = array_mut_ref![dst, 0, SignedOffer::LEN];
+        let (
+            initializer_pubkey_dst,
+            offered_token_account_pubkey_dst,
+            initializer_token_to_receive_account_pubkey_dst,
+            offered_amount_dst,
+            expected_amount_dst,
+            expires_at_dst,
+            nonce_dst,
+        ) = mut_array_refs![dst, 32, 32, 32, 8, 8, 8, 8];
+
+        initializer_pubkey_dst.copy_from_slice(self.initializer_pubkey.as_ref());
+        offered_token_account_pubkey_dst.copy_from_slice(self.offered_token_account_pubkey.as_ref());
+        initializer_token_to_receive_account_pubkey_dst.copy_from_slice(self.initializer_token_to_receive_account_pubkey.as_ref());
+        *offered_amount_dst = self.offered_amount.to_le_bytes();
+        *expected_amount_dst = self.expected_amount.to_le_bytes();
+        *expires_at_dst = self.expires_at.to_le_bytes();
+        *nonce_dst = self.nonce.to_le_bytes();
+    }
+
+    /// The bytes the initializer signs
+    pub fn message(&self, program_id: &Pubkey) -> [u8; SignedOffer::MESSAGE_LEN] {
+        let mut message = [0; SignedOffer::MESSAGE_LEN];
+        message[..32].copy_from_slice(program_id.as_ref());
+        self.pack_into_slice(&mut message[32..]);
+        message
+    }
+
+    pub fn is_expired(&self, unix_timestamp: i64) -> bool {
+        self.expires_at != 0 && unix_timestamp >= self.expires_at
+    }
+
+    /// Checks the instruction right before the current one is the ed25519 program verifying the initializer's signature of exactly
+    /// this offer. The runtime fails the whole transaction if that signature doesn't verify, so all that's left to check here is that it
+    /// was over our message and by our initializer, all of it read from the ed25519 instruction's own data
+    pub fn check_signature(&self, instructions_sysvar: &AccountInfo, program_id: &Pubkey) -> Result<(), ProgramError> {
+        let current_index = load_current_index_checked(instructions_sysvar)?;
+        let verify_index = current_index.checked_sub(1).ok_or(EscrowError::InvalidOfferSignature)?;
+        let verify_ix = load_instruction_at_checked(verify_index as usize, instructions_sysvar)?;
+        if verify_ix.program_id != ed25519_program::id() {
+            return Err(EscrowError::InvalidOfferSignature.into());
+        }
+
+        let data = &verify_ix.data;
+        if data.first() != Some(&1) || data.len() < DATA_START {
+            return Err(EscrowError::InvalidOfferSignature.into());
+        }
+        let offsets = array_ref![data, SIGNATURE_OFFSETS_START, SIGNATURE_OFFSETS_LEN];
+        let (_signature_offset, signature_ix, pubkey_offset, pubkey_ix, message_offset, message_len, message_ix) =
+            array_refs![offsets, 2, 2, 2, 2, 2, 2, 2];
+        if [signature_ix, pubkey_ix, message_ix].iter().any(|index| u16::from_le_bytes(**index) != THIS_INSTRUCTION) {
+            return Err(EscrowError::InvalidOfferSignature.into());
+        }
+
+        let read = |offset: &[u8; 2], len: usize| {
+            let offset = u16::from_le_bytes(*offset) as usize;
+            data.get(offset..offset.checked_add(len)?)
+        };
+        let message_len = u16::from_le_bytes(*message_len) as usize;
+        if read(pubkey_offset, PUBKEY_LEN) != Some(self.initializer_pubkey.as_ref())
+            || message_len != SignedOffer::MESSAGE_LEN
+            || read(message_offset, message_len) != Some(&self.message(program_id)[..])
+        {
+            return Err(EscrowError::InvalidOfferSignature.into());
+        }
+        Ok(())
+    }
+}
+
+/// The ed25519 program instruction verifying `signature` of `message` by `signer`, to go right before the FillSignedOffer. Built from
+/// a signature the initializer already made, e.g. with a wallet's message signing, so no keypair is needed here
+pub fn ed25519_instruction(signer: &Pubkey, signature: &[u8; SIGNATURE_LEN], message: &[u8]) -> Instruction {
+    let pubkey_offset = DATA_START;
+    let signature_offset = pubkey_offset + PUBKEY_LEN;
+    let message_offset = signature_offset + SIGNATURE_LEN;
+
+    let mut data = vec![1, 0];
+    for value in [
+        signature_offset as u16,
+        THIS_INSTRUCTION,
+        pubkey_offset as u16,
+        THIS_INSTRUCTION,
+        message_offset as u16,
+        message.len() as u16,
+        THIS_INSTRUCTION,
+    ] {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+    data.extend_from_slice(signer.as_ref());
+    data.extend_from_slice(signature);
+    data.extend_from_slice(message);
+
+    Instruction {
+        program_id: ed25519_program::id(),
+        accounts: Vec::new(),
+        data,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offer() -> SignedOffer {
+        SignedOffer {
+            initializer_pubkey: Pubkey::new_unique(),
+            offered_token_account_pubkey: Pubkey::new_unique(),
+            initializer_token_to_receive_account_pubkey: Pubkey::new_unique(),
+            offered_amount: 100,
+            expected_amount: 50,
+            expires_at: 1_600_000_000,
+            nonce: 7,
+        }
+    }
+
+    #[test]
+    fn offer_reads_back_as_it_was_packed() {
+        let offer = offer();
+        let mut data = [0; SignedOffer::LEN];
+        offer.pack_into_slice(&mut data);
+        assert_eq!(SignedOffer::unpack(&data), Ok(offer));
+        assert_eq!(SignedOffer::unpack(&data[1..]), Err(EscrowError::InvalidInstructionData.into()));
+
+        let program_id = Pubkey::new_unique();
+        let message = offer.message(&program_id);
+        assert_eq!(&message[..32], program_id.as_ref());
+        assert_eq!(&message[32..], &data[..]);
+    }
+
+    #[test]
+    fn ed25519_instruction_points_the_verify_at_its_own_data() {
+        let offer = offer();
+        let message = offer.message(&Pubkey::new_unique());
+        let signature = [9; SIGNATURE_LEN];
+        let verify_ix = ed25519_instruction(&offer.initializer_pubkey, &signature, &message);
+        assert_eq!(verify_ix.program_id, ed25519_program::id());
+
+        let data = &verify_ix.data;
+        let offsets: Vec<u16> = data[SIGNATURE_OFFSETS_START..DATA_START]
+            .chunks_exact(2)
+            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+            .collect();
+        let (signature_offset, pubkey_offset, message_offset) = (offsets[0] as usize, offsets[2] as usize, offsets[4] as usize);
+        assert_eq!(data[0], 1);
+        assert_eq!([offsets[1], offsets[3], offsets[6]], [THIS_INSTRUCTION; 3]);
+        assert_eq!(&data[pubkey_offset..pubkey_offset + PUBKEY_LEN], offer.initializer_pubkey.as_ref());
+        assert_eq!(&data[signature_offset..signature_offset + SIGNATURE_LEN], &signature[..]);
+        assert_eq!(offsets[5] as usize, SignedOffer::MESSAGE_LEN);
+        assert_eq!(&data[message_offset..], &message[..]);
+    }
+}
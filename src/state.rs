@@ -1,75 +1,1121 @@
-use solana_program::{
-    program_pack::{IsInitialized, Pack, Sealed},
-    program_error::ProgramError,
-    pubkey::Pubkey,
-};
-
-use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
-
-pub struct Escrow {
-    pub is_initialized: bool,
-    pub initializer_pubkey: Pubkey,
-    pub temp_token_account_pubkey: Pubkey,
-    pub initializer_token_to_receive_account_pubkey: Pubkey,
-    pub expected_amount: u64,
-}
-
-impl Sealed for Escrow {} //  Sealed is Solana's version of Rust's Sized trait
-
-impl IsInitialized for Escrow {
-    fn is_initialized(&self) -> bool {
-        self.is_initialized
-    }
-}
-
-impl Pack for Escrow {
-    const LEN: usize = 105; // this is the size of our type - which we get by summing the sizes of the variables in Escrow (1 bool, 3 pubkeys, 1 u64) = 1 + 3 * 32 + 1 * 8 = 105
-    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> { // unpack_from_slice turns an array of u8 into an instance of Escrow struct
-        let src = array_ref![src, 0, Escrow::LEN]; // arrayref is a library for getting references to sections of a slice
-        let (
-            is_initialized,
-            initializer_pubkey,
-            temp_token_account_pubkey,
-            initializer_token_to_receive_account_pubkey,
-            expected_amount,
-        ) = array_refs![src, 1, 32, 32, 32, 8];
-        let is_initialized = match is_initialized {
-            [0] => false,
-            [1] => true,
-            _ => return Err(ProgramError::InvalidAccountData),
-        };
-
-        Ok(Escrow {
-            is_initialized,
-            initializer_pubkey: Pubkey::new_from_array(*initializer_pubkey),
-            temp_token_account_pubkey: Pubkey::new_from_array(*temp_token_account_pubkey),
-            initializer_token_to_receive_account_pubkey: Pubkey::new_from_array(*initializer_token_to_receive_account_pubkey),
-            expected_amount: u64::from_le_bytes(*expected_amount), // what does it mean when there is a * before a variable
-        })
-    }
-
-    fn pack_into_slice(&self, dst: &mut [u8]) {
-        let dst = array_mut_ref![dst, 0, Escrow::LEN];
-        let (
-            is_initialized_dst,
-            initializer_pubkey_dst,
-            temp_token_account_pubkey_dst,
-            initializer_token_to_receive_account_pubkey_dst,
-            expected_amount_dst,
-        ) = mut_array_refs![dst, 1, 32, 32, 32, 8];
-
-        let Escrow {
-            is_initialized,
-            initializer_pubkey,
-            temp_token_account_pubkey,
-            initializer_token_to_receive_account_pubkey,
-            expected_amount,
-        } = self;
-
-        is_initialized_dst[0] = *is_initialized as u8;
-        initializer_pubkey_dst.copy_from_slice(initializer_pubkey.as_ref());
-        temp_token_account_pubkey_dst.copy_from_slice(temp_token_account_pubkey.as_ref());
-        initializer_token_to_receive_account_pubkey_dst.copy_from_slice(initializer_token_to_receive_account_pubkey.as_ref());
-        *expected_amount_dst = expected_amount.to_le_bytes();
-    }
-} 
\ No newline at end of file
+use solana_program::{
+    keccak,
+    msg,
+    program_pack::{IsInitialized, Pack, Sealed},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
+
+use crate::error::EscrowError;
+
+/// A free text label the initializer can attach to an offer, e.g. a deal reference. UTF-8 padded with zero bytes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Memo(pub [u8; Memo::LEN]);
+
+impl Memo {
+    pub const LEN: usize = 64;
+
+    /// Builds a memo from a string of at most `Memo::LEN` bytes
+    pub fn new(text: &str) -> Result<Self, ProgramError> {
+        if text.len() > Memo::LEN {
+            return Err(EscrowError::InvalidMemo.into());
+        }
+        let mut memo = [0; Memo::LEN];
+        memo[..text.len()].copy_from_slice(text.as_bytes());
+        Ok(Memo(memo))
+    }
+
+    /// The memo text without its padding, `None` when no memo was set or the bytes aren't valid UTF-8
+    pub fn as_str(&self) -> Option<&str> {
+        let len = self.0.iter().rposition(|byte| *byte != 0).map_or(0, |last| last + 1);
+        match std::str::from_utf8(&self.0[..len]) {
+            Ok(text) if !text.is_empty() => Some(text),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Memo {
+    fn default() -> Self {
+        Memo([0; Memo::LEN])
+    }
+}
+
+/// Where an escrow is in its lifecycle. Completed, Cancelled and Expired are terminal, nothing can happen to the escrow after them
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EscrowStatus {
+    Open,
+    PartiallyFilled,
+    Disputed,
+    Completed,
+    Cancelled,
+    Expired,
+    CancelPending, // cancelled while the vault was frozen, so the tokens couldn't go back yet. A Cancel once it's thawed finishes it
+}
+
+impl Default for EscrowStatus {
+    fn default() -> Self {
+        EscrowStatus::Open
+    }
+}
+
+impl EscrowStatus {
+    fn unpack(status: u8) -> Result<Self, ProgramError> {
+        Ok(match status {
+            0 => EscrowStatus::Open,
+            1 => EscrowStatus::PartiallyFilled,
+            2 => EscrowStatus::Disputed,
+            3 => EscrowStatus::Completed,
+            4 => EscrowStatus::Cancelled,
+            5 => EscrowStatus::Expired,
+            6 => EscrowStatus::CancelPending,
+            _ => return Err(ProgramError::InvalidAccountData),
+        })
+    }
+
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, EscrowStatus::Completed | EscrowStatus::Cancelled | EscrowStatus::Expired)
+    }
+
+    /// Moves to `next` if the lifecycle allows it, otherwise fails with `InvalidEscrowStatus`
+    pub fn transition(self, next: EscrowStatus) -> Result<EscrowStatus, ProgramError> {
+        let allowed = match self {
+            EscrowStatus::Open | EscrowStatus::PartiallyFilled => matches!(
+                next,
+                EscrowStatus::PartiallyFilled
+                    | EscrowStatus::Disputed
+                    | EscrowStatus::Completed
+                    | EscrowStatus::Cancelled
+                    | EscrowStatus::Expired
+                    | EscrowStatus::CancelPending
+            ),
+            EscrowStatus::Disputed => matches!(
+                next,
+                EscrowStatus::Open | EscrowStatus::Completed | EscrowStatus::Cancelled | EscrowStatus::CancelPending
+            ),
+            EscrowStatus::CancelPending => next == EscrowStatus::Cancelled,
+            EscrowStatus::Completed | EscrowStatus::Cancelled | EscrowStatus::Expired => false,
+        };
+        if !allowed {
+            return Err(EscrowError::InvalidEscrowStatus.into());
+        }
+        Ok(next)
+    }
+}
+
+/// How an escrow is priced and settled, each kind has its own `SettlementStrategy`. Reusable lots, an allowlist of payment mints
+/// and a SOL part of the price are options on top of a kind rather than kinds of their own
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EscrowKind {
+    /// A fixed price for the whole vault, or for each lot of a reusable escrow
+    FixedPrice,
+    /// Priced from a Pyth account at exchange time, never below the fixed price
+    OraclePriced,
+    /// A rate per offered token, takers pick their own size
+    RatePriced,
+    /// The offered tokens stay in the initializer's account with the PDA approved as a delegate
+    Delegated,
+    /// Paid out in installments through ClaimInstallment, never exchanged in one go
+    Subscription,
+    /// A bid of the vault's tokens for any one NFT of a verified collection, taken with AcceptBid rather than Exchange
+    CollectionBid,
+}
+
+impl Default for EscrowKind {
+    fn default() -> Self {
+        EscrowKind::FixedPrice
+    }
+}
+
+impl EscrowKind {
+    fn unpack(kind: u8) -> Result<Self, ProgramError> {
+        Ok(match kind {
+            0 => EscrowKind::FixedPrice,
+            1 => EscrowKind::OraclePriced,
+            2 => EscrowKind::RatePriced,
+            3 => EscrowKind::Delegated,
+            4 => EscrowKind::Subscription,
+            5 => EscrowKind::CollectionBid,
+            _ => return Err(ProgramError::InvalidAccountData),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "idl", derive(shank::ShankAccount))]
+pub struct Escrow {
+    pub version: u8, // the layout the account was read from, always written back as Escrow::VERSION
+    pub is_initialized: bool,
+    pub initializer_pubkey: Pubkey,
+    pub temp_token_account_pubkey: Pubkey,
+    pub initializer_token_to_receive_account_pubkey: Pubkey,
+    pub expected_amount: u64,
+    pub expires_at: i64,
+    pub crank_bounty: u64,
+    pub is_delegated: bool,
+    pub offered_amount: u64,
+    #[cfg_attr(feature = "idl", idl_type("u8"))] // 0 open, 1 partially filled, 2 disputed, 3 completed, 4 cancelled, 5 expired
+    pub status: EscrowStatus,
+    pub is_subscription: bool,
+    pub taker_pubkey: Pubkey,
+    pub installment_amount: u64,
+    pub interval_slots: u64,
+    pub last_claim_slot: u64,
+    pub start_time: i64,
+    pub oracle_pubkey: Pubkey,
+    pub oracle_tolerance_bps: u16,
+    pub offered_decimals: u8,
+    pub expected_decimals: u8,
+    pub taker_vault_pubkey: Pubkey,
+    pub taker_receive_account_pubkey: Pubkey,
+    pub bump_seed: u8,
+    #[cfg_attr(feature = "idl", idl_type("[u8; 64]"))]
+    pub memo: Memo,
+    pub rate_numerator: u64,
+    pub rate_denominator: u64,
+    pub accepted_mints: [AcceptedMint; Escrow::MAX_ACCEPTED_MINTS], // empty slots have the default mint
+    pub is_reusable: bool, // a standing offer, each Exchange takes one lot of offered_amount and the escrow stays open until the vault is empty
+    pub commit_taker: Pubkey, // the taker holding a CommitExchange on the escrow, the default pubkey when there's none
+    pub commit_hash: [u8; 32],
+    pub commit_slot: u64,
+    pub cancel_penalty_bps: u16, // taken from the vault when the initializer cancels before the offer expires
+    pub penalty_recipient: Pubkey, // the token account the penalty goes to, the default pubkey burns it
+    pub expected_lamports: u64, // a SOL part of the price, paid to the initializer's main account alongside the token payment
+    pub creation_deposit: u64, // lamports on top of rent, forfeited by cancelling too soon and otherwise returned with the rent
+    pub deposit_locked_until_slot: u64, // cancelling before this slot forfeits the creation deposit
+    pub deposit_recipient: Pubkey, // the config's fee recipient when the escrow was made
+    #[cfg_attr(feature = "idl", idl_type("u8"))] // 0 fixed price, 1 oracle priced, 2 rate priced, 3 delegated, 4 subscription, 5 collection bid
+    pub kind: EscrowKind, // picks the SettlementStrategy an Exchange goes through
+    pub collection_pubkey: Pubkey, // for a collection bid, the verified collection the NFT has to belong to
+    pub enforce_royalties: bool, // the NFT's creators are paid their Metaplex royalties out of the payment
+    pub max_per_taker: u64, // the most of the offered token one taker can buy across all their fills, tracked in their TakerClaim, 0 for no cap
+    pub created_at_slot: u64, // the slot the escrow was opened in, 0 for one opened before it was recorded
+    pub charges_protocol_fee: bool, // the config had a fee schedule when the escrow was opened, so every take pays the protocol fee
+    pub offer_id: u64, // the number InitEscrow gave the offer out of the OfferCounter, 0 for an unnumbered one
+}
+
+/// One of the payments an escrow with an allowlist accepts, `amount` of `mint` for the whole offer
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "idl", derive(shank::ShankType))]
+pub struct AcceptedMint {
+    pub mint: Pubkey,
+    pub amount: u64,
+}
+
+impl AcceptedMint {
+    pub const LEN: usize = 40;
+}
+
+impl Escrow {
+    /// The layout this program reads and writes, the first byte of every escrow account it has written
+    pub const VERSION: u8 = 11;
+
+    /// Escrows from before the version byte, which start with `is_initialized`, i.e. a 1
+    const UNVERSIONED: u8 = 1;
+
+    /// The first deployed layout, 1 bool, 3 pubkeys, 1 u64. Unversioned escrows are anything from this long up to their entry in PAST_LENS
+    const DEPLOYED_LEN: usize = 105;
+
+    /// How long each earlier layout was. Fields only ever get added at the end, so an older escrow is the start of the current
+    /// layout (after the version byte, for an unversioned one) and the fields it doesn't have read as zeros
+    pub(crate) const PAST_LENS: [(u8, usize); 10] = [
+        (Escrow::UNVERSIONED, 610), (2, 611), (3, 645), (4, 653), (5, 701), (6, 702), (7, 734), (8, 735), (9, 743), (10, 752),
+    ];
+
+    /// The first layout with the kind byte, older escrows have their kind worked out from their other fields
+    const KIND_VERSION: u8 = 6;
+
+    /// What the initializer forfeits for cancelling before the offer expires, in bps of what's left in the vault
+    pub fn cancel_penalty(&self, vault_amount: u64, unix_timestamp: i64) -> u64 {
+        if self.cancel_penalty_bps == 0 || self.is_expired(unix_timestamp) {
+            return 0;
+        }
+        (vault_amount as u128 * self.cancel_penalty_bps as u128 / 10_000) as u64
+    }
+
+    /// How many payment mints an escrow can accept
+    pub const MAX_ACCEPTED_MINTS: usize = 4;
+
+    /// Lamports a taker puts up with CommitExchange, handed back on reveal and paid to the initializer if the commit goes stale
+    pub const COMMIT_BOND: u64 = 10_000_000;
+
+    /// How many slots after its commit a taker has to reveal, about a minute
+    pub const REVEAL_WINDOW_SLOTS: u64 = 150;
+
+    /// Whether the offer has passed its expiry, an `expires_at` of 0 means it never expires
+    pub fn is_expired(&self, unix_timestamp: i64) -> bool {
+        self.expires_at != 0 && unix_timestamp >= self.expires_at
+    }
+
+    /// Whether the offer can be taken yet, a `start_time` of 0 means it was takeable straight away
+    pub fn is_active(&self, unix_timestamp: i64) -> bool {
+        unix_timestamp >= self.start_time
+    }
+
+    /// Whether the payment is worked out from a price oracle at exchange time rather than fixed at init
+    pub fn is_oracle_priced(&self) -> bool {
+        self.oracle_pubkey != Pubkey::default()
+    }
+
+    /// Whether the offer is a rate the taker pays per offered token rather than a fixed price for the whole vault
+    pub fn is_rate_priced(&self) -> bool {
+        self.rate_denominator != 0
+    }
+
+    /// What taking `take_amount` of the offered token costs at the escrow's rate. Rounded up, so the rounding can only
+    /// ever cost the taker less than one base unit and never short changes the initializer
+    pub fn rate_payment(&self, take_amount: u64) -> Result<u64, ProgramError> {
+        let numerator = (take_amount as u128)
+            .checked_mul(self.rate_numerator as u128)
+            .ok_or(EscrowError::AmountOverflow)?;
+        let denominator = self.rate_denominator as u128;
+        let payment = numerator / denominator + (numerator % denominator != 0) as u128;
+        if payment > u64::MAX as u128 {
+            return Err(EscrowError::AmountOverflow.into());
+        }
+        Ok(payment as u64)
+    }
+
+    /// Whether the escrow is a fixed price for its whole vault, paid in a single token with nothing else attached, the only kind MatchOffers pairs up.
+    /// MatchOffers pays nothing but the two initializers and the spread, so royalties or a protocol fee rule an escrow out as well
+    pub fn is_plain_offer(&self) -> bool {
+        self.kind == EscrowKind::FixedPrice
+            && !(self.has_accepted_mints() || self.is_reusable || self.has_taker_deposit() || self.expected_lamports != 0)
+            && !(self.enforce_royalties || self.charges_protocol_fee)
+    }
+
+    /// Whether the taker can pay in any of several mints, each at its own amount, rather than only into the initializer's receive account
+    pub fn has_accepted_mints(&self) -> bool {
+        self.accepted_mints[0].mint != Pubkey::default()
+    }
+
+    /// The amount the escrow accepts in `mint`, `None` if the mint isn't on its allowlist
+    pub fn accepted_amount(&self, mint: &Pubkey) -> Option<u64> {
+        self.accepted_mints
+            .iter()
+            .find(|accepted| accepted.mint == *mint && *mint != Pubkey::default())
+            .map(|accepted| accepted.amount)
+    }
+
+    /// Whether a taker's commit still reserves the escrow for them at `slot`. After `REVEAL_WINDOW_SLOTS` it's stale and anyone can take the offer
+    pub fn has_live_commit(&self, slot: u64) -> bool {
+        self.commit_taker != Pubkey::default() && slot <= self.commit_slot.saturating_add(Escrow::REVEAL_WINDOW_SLOTS)
+    }
+
+    /// The hash a taker commits to with CommitExchange: keccak(taker, amount, salt). Binding the taker in means nobody else can reveal it
+    pub fn commit_hash_of(taker: &Pubkey, amount: u64, salt: &[u8; 32]) -> [u8; 32] {
+        keccak::hashv(&[taker.as_ref(), &amount.to_le_bytes(), salt]).to_bytes()
+    }
+
+    /// Reads an initialized escrow in any layout this program has used, going by its first byte and length. Only the current
+    /// layout can be written back in place, an older one has to be brought up to date with Migrate first. An account ExtendEscrow
+    /// grew is read from its start, the bytes past its layout are zeros
+    pub fn unpack_any_version(data: &[u8]) -> Result<Escrow, ProgramError> {
+        let version = *data.first().ok_or(EscrowError::UnknownEscrowVersion)?;
+        if version == Escrow::VERSION && data.len() >= Escrow::LEN {
+            return Escrow::unpack(data);
+        }
+        let past_len = match Escrow::PAST_LENS.iter().find(|(past_version, _)| *past_version == version) {
+            Some((Escrow::UNVERSIONED, past_len)) if data.len() >= Escrow::DEPLOYED_LEN => data.len().min(*past_len),
+            Some((_, past_len)) if data.len() >= *past_len => *past_len,
+            _ => return Err(EscrowError::UnknownEscrowVersion.into()),
+        };
+        let data = &data[..past_len];
+
+        let mut current = [0; Escrow::LEN];
+        if version == Escrow::UNVERSIONED {
+            current[1..=data.len()].copy_from_slice(data);
+        } else {
+            current[..data.len()].copy_from_slice(data);
+        }
+        current[0] = Escrow::VERSION;
+        let mut escrow = Escrow::unpack_from_slice(&current)?;
+        if !escrow.is_initialized {
+            return Err(EscrowError::EscrowNotInitialized.into());
+        }
+        escrow.version = version;
+        if version < Escrow::KIND_VERSION { // layouts before it have no kind byte
+            escrow.kind = escrow.inferred_kind();
+        }
+        Ok(escrow)
+    }
+
+    /// The kind an escrow from before the kind byte was, going by the fields each kind sets at init
+    fn inferred_kind(&self) -> EscrowKind {
+        if self.is_subscription {
+            EscrowKind::Subscription
+        } else if self.is_delegated {
+            EscrowKind::Delegated
+        } else if self.is_rate_priced() {
+            EscrowKind::RatePriced
+        } else if self.is_oracle_priced() {
+            EscrowKind::OraclePriced
+        } else {
+            EscrowKind::FixedPrice
+        }
+    }
+
+    /// Whether a taker has locked their payment in with DepositTakerSide, after which the escrow can only be settled or withdrawn from
+    pub fn has_taker_deposit(&self) -> bool {
+        self.taker_vault_pubkey != Pubkey::default()
+    }
+
+    /// Whether the escrow is one of the stats' `open_escrows`, which only InitEscrow and the instructions built on it add to. Delegated
+    /// escrows, subscriptions and bids are never counted, so closing one leaves the count alone
+    pub fn counts_as_open(&self) -> bool {
+        matches!(self.kind, EscrowKind::FixedPrice | EscrowKind::OraclePriced | EscrowKind::RatePriced)
+    }
+}
+
+impl Sealed for Escrow {} //  Sealed is Solana's version of Rust's Sized trait
+
+impl IsInitialized for Escrow {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for Escrow {
+    const LEN: usize = 760; // this is the size of our type - which we get by summing the sizes of the variables in Escrow (6 bool, 11 pubkeys, 15 u64, 2 i64, 2 u16, 4 u8, 1 status byte, 1 kind byte, 1 memo, 4 accepted mints, 1 commit hash) = 6 + 11 * 32 + 17 * 8 + 2 * 2 + 4 + 1 + 1 + 64 + 4 * 40 + 32 = 760
+
+    // an escrow account can be longer than the layout after ExtendEscrow, the escrow is its first LEN bytes and the zeroed rest is
+    // room for fields later layouts add
+    fn unpack_unchecked(input: &[u8]) -> Result<Self, ProgramError> {
+        if input.len() < Escrow::LEN {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Escrow::unpack_from_slice(input)
+    }
+
+    fn pack(src: Self, dst: &mut [u8]) -> Result<(), ProgramError> {
+        if dst.len() < Escrow::LEN {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        src.pack_into_slice(dst);
+        Ok(())
+    }
+
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> { // unpack_from_slice turns an array of u8 into an instance of Escrow struct
+        let src = array_ref![src, 0, Escrow::LEN]; // arrayref is a library for getting references to sections of a slice
+        let (
+            version,
+            is_initialized,
+            initializer_pubkey,
+            temp_token_account_pubkey,
+            initializer_token_to_receive_account_pubkey,
+            expected_amount,
+            expires_at,
+            crank_bounty,
+            is_delegated,
+            offered_amount,
+            status,
+            is_subscription,
+            taker_pubkey,
+            installment_amount,
+            interval_slots,
+            last_claim_slot,
+            start_time,
+            oracle_pubkey,
+            oracle_tolerance_bps,
+            offered_decimals,
+            expected_decimals,
+            taker_vault_pubkey,
+            taker_receive_account_pubkey,
+            bump_seed,
+            memo,
+            rate_numerator,
+            rate_denominator,
+            accepted_mints_src,
+            is_reusable,
+            commit_taker,
+            commit_hash,
+            commit_slot,
+            cancel_penalty_bps,
+            penalty_recipient,
+            expected_lamports,
+            creation_deposit,
+            deposit_locked_until_slot,
+            deposit_recipient,
+            kind,
+            collection_pubkey,
+            enforce_royalties,
+            max_per_taker,
+            created_at_slot,
+            charges_protocol_fee,
+            offer_id,
+        ) = array_refs![src, 1, 1, 32, 32, 32, 8, 8, 8, 1, 8, 1, 1, 32, 8, 8, 8, 8, 32, 2, 1, 1, 32, 32, 1, 64, 8, 8, AcceptedMint::LEN * Escrow::MAX_ACCEPTED_MINTS, 1, 32, 32, 8, 2, 32, 8, 8, 8, 32, 1, 32, 1, 8, 8, 1, 8];
+
+        let mut accepted_mints = [AcceptedMint::default(); Escrow::MAX_ACCEPTED_MINTS];
+        for (accepted, accepted_src) in accepted_mints.iter_mut().zip(accepted_mints_src.chunks_exact(AcceptedMint::LEN)) {
+            let accepted_src = array_ref![accepted_src, 0, AcceptedMint::LEN];
+            let (mint, amount) = array_refs![accepted_src, 32, 8];
+            accepted.mint = Pubkey::new_from_array(*mint);
+            accepted.amount = u64::from_le_bytes(*amount);
+        }
+
+        // 0 is an account that has never been written to, what InitEscrow and friends unpack before filling it in
+        if version[0] != 0 && version[0] != Escrow::VERSION {
+            return Err(EscrowError::UnknownEscrowVersion.into());
+        }
+
+        Ok(Escrow {
+            version: version[0],
+            is_initialized: unpack_bool(is_initialized)?,
+            initializer_pubkey: Pubkey::new_from_array(*initializer_pubkey),
+            temp_token_account_pubkey: Pubkey::new_from_array(*temp_token_account_pubkey),
+            initializer_token_to_receive_account_pubkey: Pubkey::new_from_array(*initializer_token_to_receive_account_pubkey),
+            expected_amount: u64::from_le_bytes(*expected_amount), // what does it mean when there is a * before a variable
+            expires_at: i64::from_le_bytes(*expires_at),
+            crank_bounty: u64::from_le_bytes(*crank_bounty),
+            is_delegated: unpack_bool(is_delegated)?,
+            offered_amount: u64::from_le_bytes(*offered_amount),
+            status: EscrowStatus::unpack(status[0])?,
+            is_subscription: unpack_bool(is_subscription)?,
+            taker_pubkey: Pubkey::new_from_array(*taker_pubkey),
+            installment_amount: u64::from_le_bytes(*installment_amount),
+            interval_slots: u64::from_le_bytes(*interval_slots),
+            last_claim_slot: u64::from_le_bytes(*last_claim_slot),
+            start_time: i64::from_le_bytes(*start_time),
+            oracle_pubkey: Pubkey::new_from_array(*oracle_pubkey),
+            oracle_tolerance_bps: u16::from_le_bytes(*oracle_tolerance_bps),
+            offered_decimals: offered_decimals[0],
+            expected_decimals: expected_decimals[0],
+            taker_vault_pubkey: Pubkey::new_from_array(*taker_vault_pubkey),
+            taker_receive_account_pubkey: Pubkey::new_from_array(*taker_receive_account_pubkey),
+            bump_seed: bump_seed[0],
+            memo: Memo(*memo),
+            rate_numerator: u64::from_le_bytes(*rate_numerator),
+            rate_denominator: u64::from_le_bytes(*rate_denominator),
+            accepted_mints,
+            is_reusable: unpack_bool(is_reusable)?,
+            commit_taker: Pubkey::new_from_array(*commit_taker),
+            commit_hash: *commit_hash,
+            commit_slot: u64::from_le_bytes(*commit_slot),
+            cancel_penalty_bps: u16::from_le_bytes(*cancel_penalty_bps),
+            penalty_recipient: Pubkey::new_from_array(*penalty_recipient),
+            expected_lamports: u64::from_le_bytes(*expected_lamports),
+            creation_deposit: u64::from_le_bytes(*creation_deposit),
+            deposit_locked_until_slot: u64::from_le_bytes(*deposit_locked_until_slot),
+            deposit_recipient: Pubkey::new_from_array(*deposit_recipient),
+            kind: EscrowKind::unpack(kind[0])?,
+            collection_pubkey: Pubkey::new_from_array(*collection_pubkey),
+            enforce_royalties: unpack_bool(enforce_royalties)?,
+            max_per_taker: u64::from_le_bytes(*max_per_taker),
+            created_at_slot: u64::from_le_bytes(*created_at_slot),
+            charges_protocol_fee: unpack_bool(charges_protocol_fee)?,
+            offer_id: u64::from_le_bytes(*offer_id),
+        })
+    }
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        let dst = array_mut_ref![dst, 0, Escrow::LEN];
+        let (
+            version_dst,
+            is_initialized_dst,
+            initializer_pubkey_dst,
+            temp_token_account_pubkey_dst,
+            initializer_token_to_receive_account_pubkey_dst,
+            expected_amount_dst,
+            expires_at_dst,
+            crank_bounty_dst,
+            is_delegated_dst,
+            offered_amount_dst,
+            status_dst,
+            is_subscription_dst,
+            taker_pubkey_dst,
+            installment_amount_dst,
+            interval_slots_dst,
+            last_claim_slot_dst,
+            start_time_dst,
+            oracle_pubkey_dst,
+            oracle_tolerance_bps_dst,
+            offered_decimals_dst,
+            expected_decimals_dst,
+            taker_vault_pubkey_dst,
+            taker_receive_account_pubkey_dst,
+            bump_seed_dst,
+            memo_dst,
+            rate_numerator_dst,
+            rate_denominator_dst,
+            accepted_mints_dst,
+            is_reusable_dst,
+            commit_taker_dst,
+            commit_hash_dst,
+            commit_slot_dst,
+            cancel_penalty_bps_dst,
+            penalty_recipient_dst,
+            expected_lamports_dst,
+            creation_deposit_dst,
+            deposit_locked_until_slot_dst,
+            deposit_recipient_dst,
+            kind_dst,
+            collection_pubkey_dst,
+            enforce_royalties_dst,
+            max_per_taker_dst,
+            created_at_slot_dst,
+            charges_protocol_fee_dst,
+            offer_id_dst,
+        ) = mut_array_refs![dst, 1, 1, 32, 32, 32, 8, 8, 8, 1, 8, 1, 1, 32, 8, 8, 8, 8, 32, 2, 1, 1, 32, 32, 1, 64, 8, 8, AcceptedMint::LEN * Escrow::MAX_ACCEPTED_MINTS, 1, 32, 32, 8, 2, 32, 8, 8, 8, 32, 1, 32, 1, 8, 8, 1, 8];
+
+        let Escrow {
+            version: _,
+            is_initialized,
+            initializer_pubkey,
+            temp_token_account_pubkey,
+            initializer_token_to_receive_account_pubkey,
+            expected_amount,
+            expires_at,
+            crank_bounty,
+            is_delegated,
+            offered_amount,
+            status,
+            is_subscription,
+            taker_pubkey,
+            installment_amount,
+            interval_slots,
+            last_claim_slot,
+            start_time,
+            oracle_pubkey,
+            oracle_tolerance_bps,
+            offered_decimals,
+            expected_decimals,
+            taker_vault_pubkey,
+            taker_receive_account_pubkey,
+            bump_seed,
+            memo,
+            rate_numerator,
+            rate_denominator,
+            accepted_mints,
+            is_reusable,
+            commit_taker,
+            commit_hash,
+            commit_slot,
+            cancel_penalty_bps,
+            penalty_recipient,
+            expected_lamports,
+            creation_deposit,
+            deposit_locked_until_slot,
+            deposit_recipient,
+            kind,
+            collection_pubkey,
+            enforce_royalties,
+            max_per_taker,
+            created_at_slot,
+            charges_protocol_fee,
+            offer_id,
+        } = self;
+
+        version_dst[0] = Escrow::VERSION;
+        is_initialized_dst[0] = *is_initialized as u8;
+        initializer_pubkey_dst.copy_from_slice(initializer_pubkey.as_ref());
+        temp_token_account_pubkey_dst.copy_from_slice(temp_token_account_pubkey.as_ref());
+        initializer_token_to_receive_account_pubkey_dst.copy_from_slice(initializer_token_to_receive_account_pubkey.as_ref());
+        *expected_amount_dst = expected_amount.to_le_bytes();
+        *expires_at_dst = expires_at.to_le_bytes();
+        *crank_bounty_dst = crank_bounty.to_le_bytes();
+        is_delegated_dst[0] = *is_delegated as u8;
+        *offered_amount_dst = offered_amount.to_le_bytes();
+        status_dst[0] = *status as u8;
+        is_subscription_dst[0] = *is_subscription as u8;
+        taker_pubkey_dst.copy_from_slice(taker_pubkey.as_ref());
+        *installment_amount_dst = installment_amount.to_le_bytes();
+        *interval_slots_dst = interval_slots.to_le_bytes();
+        *last_claim_slot_dst = last_claim_slot.to_le_bytes();
+        *start_time_dst = start_time.to_le_bytes();
+        oracle_pubkey_dst.copy_from_slice(oracle_pubkey.as_ref());
+        *oracle_tolerance_bps_dst = oracle_tolerance_bps.to_le_bytes();
+        offered_decimals_dst[0] = *offered_decimals;
+        expected_decimals_dst[0] = *expected_decimals;
+        taker_vault_pubkey_dst.copy_from_slice(taker_vault_pubkey.as_ref());
+        taker_receive_account_pubkey_dst.copy_from_slice(taker_receive_account_pubkey.as_ref());
+        bump_seed_dst[0] = *bump_seed;
+        *memo_dst = memo.0;
+        *rate_numerator_dst = rate_numerator.to_le_bytes();
+        *rate_denominator_dst = rate_denominator.to_le_bytes();
+        for (accepted, accepted_dst) in accepted_mints.iter().zip(accepted_mints_dst.chunks_exact_mut(AcceptedMint::LEN)) {
+            accepted_dst[..32].copy_from_slice(accepted.mint.as_ref());
+            accepted_dst[32..].copy_from_slice(&accepted.amount.to_le_bytes());
+        }
+        is_reusable_dst[0] = *is_reusable as u8;
+        commit_taker_dst.copy_from_slice(commit_taker.as_ref());
+        *commit_hash_dst = *commit_hash;
+        *commit_slot_dst = commit_slot.to_le_bytes();
+        *cancel_penalty_bps_dst = cancel_penalty_bps.to_le_bytes();
+        penalty_recipient_dst.copy_from_slice(penalty_recipient.as_ref());
+        *expected_lamports_dst = expected_lamports.to_le_bytes();
+        *creation_deposit_dst = creation_deposit.to_le_bytes();
+        *deposit_locked_until_slot_dst = deposit_locked_until_slot.to_le_bytes();
+        deposit_recipient_dst.copy_from_slice(deposit_recipient.as_ref());
+        kind_dst[0] = *kind as u8;
+        collection_pubkey_dst.copy_from_slice(collection_pubkey.as_ref());
+        enforce_royalties_dst[0] = *enforce_royalties as u8;
+        *max_per_taker_dst = max_per_taker.to_le_bytes();
+        *created_at_slot_dst = created_at_slot.to_le_bytes();
+        charges_protocol_fee_dst[0] = *charges_protocol_fee as u8;
+        *offer_id_dst = offer_id.to_le_bytes();
+    }
+}
+
+/// Program wide settings, stored in the PDA at `[b"config"]` and changed only by its admin
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Config {
+    pub is_initialized: bool,
+    pub admin_pubkey: Pubkey,
+    pub max_referral_bps: u16, // the largest share of a payment an Exchange may route to a referrer
+    pub creation_deposit: u64, // lamports every new escrow has to hold on top of rent, 0 for none
+    pub deposit_lock_slots: u64, // how many slots after creation cancelling forfeits the deposit
+    pub fee_recipient: Pubkey, // the system account forfeited deposits go to, and the owner of the token accounts fees are paid into
+    pub fee_tier_count: u8,
+    pub fee_tiers: [FeeTier; Config::MAX_FEE_TIERS], // the protocol fee schedule, only the first `fee_tier_count` are used
+}
+
+/// One step of the protocol fee schedule: an offer that has been open at least `min_age_slots` when it's taken pays `fee_bps` of the
+/// payment, until it's old enough for the next tier
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FeeTier {
+    pub min_age_slots: u64,
+    pub fee_bps: u16,
+}
+
+impl FeeTier {
+    pub const LEN: usize = 10;
+}
+
+impl Config {
+    pub const MAX_BPS: u16 = 10_000;
+    pub const MAX_FEE_TIERS: usize = 4;
+
+    /// How long each earlier layout was: before creation deposits it ended after `max_referral_bps`, and before the fee schedule after
+    /// `fee_recipient`
+    const PAST_LENS: [usize; 2] = [35, 83];
+
+    /// Reads the config in any layout, an older one simply has no creation deposit or fee schedule. UpdateConfig grows it to the current one
+    pub fn unpack_any_len(data: &[u8]) -> Result<Config, ProgramError> {
+        if !Config::PAST_LENS.contains(&data.len()) {
+            return Config::unpack(data);
+        }
+        let mut current = [0; Config::LEN];
+        current[..data.len()].copy_from_slice(data);
+        Config::unpack(&current)
+    }
+
+    /// Checks a fee schedule can be stored: at most `MAX_FEE_TIERS` tiers, for ever older offers and never charging more for an older one
+    pub fn check_fee_tiers(fee_tiers: &[FeeTier]) -> Result<(), ProgramError> {
+        let in_order = fee_tiers
+            .windows(2)
+            .all(|pair| pair[0].min_age_slots < pair[1].min_age_slots && pair[0].fee_bps >= pair[1].fee_bps);
+        if fee_tiers.len() > Config::MAX_FEE_TIERS || !in_order || fee_tiers.iter().any(|tier| tier.fee_bps > Config::MAX_BPS) {
+            return Err(EscrowError::InvalidFeeSchedule.into());
+        }
+        Ok(())
+    }
+
+    pub fn has_protocol_fee(&self) -> bool {
+        self.fee_tier_count != 0
+    }
+
+    /// The protocol fee, in bps of the payment, for taking an offer that has been open `age_slots`. An offer younger than the first
+    /// tier pays nothing
+    pub fn protocol_fee_bps(&self, age_slots: u64) -> u16 {
+        self.fee_tiers[..(self.fee_tier_count as usize).min(Config::MAX_FEE_TIERS)]
+            .iter()
+            .rev()
+            .find(|tier| tier.min_age_slots <= age_slots)
+            .map_or(0, |tier| tier.fee_bps)
+    }
+}
+
+impl Sealed for Config {}
+
+impl IsInitialized for Config {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for Config {
+    const LEN: usize = 124; // 1 bool, 2 pubkeys, 1 u16, 2 u64, 1 u8, 4 fee tiers of 1 u64 and 1 u16 = 1 + 2 * 32 + 2 + 2 * 8 + 1 + 4 * 10 = 124
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        let src = array_ref![src, 0, Config::LEN];
+        let (
+            is_initialized,
+            admin_pubkey,
+            max_referral_bps,
+            creation_deposit,
+            deposit_lock_slots,
+            fee_recipient,
+            fee_tier_count,
+            fee_tiers_src,
+        ) = array_refs![src, 1, 32, 2, 8, 8, 32, 1, FeeTier::LEN * Config::MAX_FEE_TIERS];
+
+        let mut fee_tiers = [FeeTier::default(); Config::MAX_FEE_TIERS];
+        for (tier, tier_src) in fee_tiers.iter_mut().zip(fee_tiers_src.chunks_exact(FeeTier::LEN)) {
+            let tier_src = array_ref![tier_src, 0, FeeTier::LEN];
+            let (min_age_slots, fee_bps) = array_refs![tier_src, 8, 2];
+            tier.min_age_slots = u64::from_le_bytes(*min_age_slots);
+            tier.fee_bps = u16::from_le_bytes(*fee_bps);
+        }
+
+        Ok(Config {
+            is_initialized: unpack_bool(is_initialized)?,
+            admin_pubkey: Pubkey::new_from_array(*admin_pubkey),
+            max_referral_bps: u16::from_le_bytes(*max_referral_bps),
+            creation_deposit: u64::from_le_bytes(*creation_deposit),
+            deposit_lock_slots: u64::from_le_bytes(*deposit_lock_slots),
+            fee_recipient: Pubkey::new_from_array(*fee_recipient),
+            fee_tier_count: fee_tier_count[0],
+            fee_tiers,
+        })
+    }
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        let dst = array_mut_ref![dst, 0, Config::LEN];
+        let (
+            is_initialized_dst,
+            admin_pubkey_dst,
+            max_referral_bps_dst,
+            creation_deposit_dst,
+            deposit_lock_slots_dst,
+            fee_recipient_dst,
+            fee_tier_count_dst,
+            fee_tiers_dst,
+        ) = mut_array_refs![dst, 1, 32, 2, 8, 8, 32, 1, FeeTier::LEN * Config::MAX_FEE_TIERS];
+
+        let Config {
+            is_initialized,
+            admin_pubkey,
+            max_referral_bps,
+            creation_deposit,
+            deposit_lock_slots,
+            fee_recipient,
+            fee_tier_count,
+            fee_tiers,
+        } = self;
+
+        is_initialized_dst[0] = *is_initialized as u8;
+        admin_pubkey_dst.copy_from_slice(admin_pubkey.as_ref());
+        *max_referral_bps_dst = max_referral_bps.to_le_bytes();
+        *creation_deposit_dst = creation_deposit.to_le_bytes();
+        *deposit_lock_slots_dst = deposit_lock_slots.to_le_bytes();
+        fee_recipient_dst.copy_from_slice(fee_recipient.as_ref());
+        fee_tier_count_dst[0] = *fee_tier_count;
+        for (tier, tier_dst) in fee_tiers.iter().zip(fee_tiers_dst.chunks_exact_mut(FeeTier::LEN)) {
+            tier_dst[..8].copy_from_slice(&tier.min_age_slots.to_le_bytes());
+            tier_dst[8..].copy_from_slice(&tier.fee_bps.to_le_bytes());
+        }
+    }
+}
+
+/// Running totals for analytics, stored in the PDA at `[b"stats"]` for the whole program and at `[b"stats", initializer]` for each initializer
+/// that wants their own. InitEscrow, and every instruction that can close an escrow it opened, update whichever of the two are passed
+/// to them
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    pub is_initialized: bool,
+    pub owner_pubkey: Pubkey, // the initializer these stats are for, the default pubkey for the global stats
+    pub bump_seed: u8,
+    pub escrows_created: u64,
+    pub open_escrows: u64, // escrows created less those closed since, however they were closed
+    pub mint_volumes: [MintVolume; Stats::MAX_MINTS],
+}
+
+/// The total amount of one mint that has changed hands through exchanges
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MintVolume {
+    pub mint: Pubkey,
+    pub volume: u64,
+}
+
+impl MintVolume {
+    const LEN: usize = 40;
+}
+
+impl Stats {
+    /// How many mints the volume table tracks, volume in any mint beyond the first `MAX_MINTS` seen isn't recorded
+    pub const MAX_MINTS: usize = 8;
+
+    /// Adds `amount` to the volume of `mint`, taking a free slot in the table the first time the mint is seen
+    pub fn record_volume(&mut self, mint: &Pubkey, amount: u64) {
+        let slot = self
+            .mint_volumes
+            .iter()
+            .position(|entry| entry.mint == *mint || entry.mint == Pubkey::default());
+        match slot {
+            Some(index) => {
+                let entry = &mut self.mint_volumes[index];
+                entry.mint = *mint;
+                entry.volume = entry.volume.saturating_add(amount);
+            }
+            None => msg!("The stats volume table is full, not recording volume for {}", mint),
+        }
+    }
+
+    /// The volume recorded for `mint`, 0 if it has never been exchanged or didn't fit in the table
+    pub fn volume_of(&self, mint: &Pubkey) -> u64 {
+        self.mint_volumes
+            .iter()
+            .find(|entry| entry.mint == *mint && *mint != Pubkey::default())
+            .map_or(0, |entry| entry.volume)
+    }
+}
+
+impl Sealed for Stats {}
+
+impl IsInitialized for Stats {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for Stats {
+    const LEN: usize = 370; // 1 bool, 1 pubkey, 1 u8, 2 u64, 8 mint volumes of 1 pubkey and 1 u64 = 1 + 32 + 1 + 2 * 8 + 8 * 40 = 370
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        let src = array_ref![src, 0, Stats::LEN];
+        let (
+            is_initialized,
+            owner_pubkey,
+            bump_seed,
+            escrows_created,
+            open_escrows,
+            mint_volumes_src,
+        ) = array_refs![src, 1, 32, 1, 8, 8, MintVolume::LEN * Stats::MAX_MINTS];
+
+        let mut mint_volumes = [MintVolume::default(); Stats::MAX_MINTS];
+        for (entry, entry_src) in mint_volumes.iter_mut().zip(mint_volumes_src.chunks_exact(MintVolume::LEN)) {
+            let entry_src = array_ref![entry_src, 0, MintVolume::LEN];
+            let (mint, volume) = array_refs![entry_src, 32, 8];
+            entry.mint = Pubkey::new_from_array(*mint);
+            entry.volume = u64::from_le_bytes(*volume);
+        }
+
+        Ok(Stats {
+            is_initialized: unpack_bool(is_initialized)?,
+            owner_pubkey: Pubkey::new_from_array(*owner_pubkey),
+            bump_seed: bump_seed[0],
+            escrows_created: u64::from_le_bytes(*escrows_created),
+            open_escrows: u64::from_le_bytes(*open_escrows),
+            mint_volumes,
+        })
+    }
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        let dst = array_mut_ref![dst, 0, Stats::LEN];
+        let (
+            is_initialized_dst,
+            owner_pubkey_dst,
+            bump_seed_dst,
+            escrows_created_dst,
+            open_escrows_dst,
+            mint_volumes_dst,
+        ) = mut_array_refs![dst, 1, 32, 1, 8, 8, MintVolume::LEN * Stats::MAX_MINTS];
+
+        let Stats {
+            is_initialized,
+            owner_pubkey,
+            bump_seed,
+            escrows_created,
+            open_escrows,
+            mint_volumes,
+        } = self;
+
+        is_initialized_dst[0] = *is_initialized as u8;
+        owner_pubkey_dst.copy_from_slice(owner_pubkey.as_ref());
+        bump_seed_dst[0] = *bump_seed;
+        *escrows_created_dst = escrows_created.to_le_bytes();
+        *open_escrows_dst = open_escrows.to_le_bytes();
+        for (entry, entry_dst) in mint_volumes.iter().zip(mint_volumes_dst.chunks_exact_mut(MintVolume::LEN)) {
+            entry_dst[..32].copy_from_slice(entry.mint.as_ref());
+            entry_dst[32..].copy_from_slice(&entry.volume.to_le_bytes());
+        }
+    }
+}
+
+/// How much of one capped escrow's offer a taker has bought so far, stored in the PDA at `[b"claim", escrow, taker]`. Exchange creates it
+/// on the taker's first fill and turns down any fill that would take them past the escrow's `max_per_taker`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TakerClaim {
+    pub is_initialized: bool,
+    pub escrow_pubkey: Pubkey,
+    pub taker_pubkey: Pubkey,
+    pub bump_seed: u8,
+    pub claimed_amount: u64, // in the offered token
+}
+
+impl Sealed for TakerClaim {}
+
+impl IsInitialized for TakerClaim {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for TakerClaim {
+    const LEN: usize = 74; // 1 bool, 2 pubkeys, 1 u8, 1 u64 = 1 + 2 * 32 + 1 + 8 = 74
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        let src = array_ref![src, 0, TakerClaim::LEN];
+        let (
+            is_initialized,
+            escrow_pubkey,
+            taker_pubkey,
+            bump_seed,
+            claimed_amount,
+        ) = array_refs![src, 1, 32, 32, 1, 8];
+
+        Ok(TakerClaim {
+            is_initialized: unpack_bool(is_initialized)?,
+            escrow_pubkey: Pubkey::new_from_array(*escrow_pubkey),
+            taker_pubkey: Pubkey::new_from_array(*taker_pubkey),
+            bump_seed: bump_seed[0],
+            claimed_amount: u64::from_le_bytes(*claimed_amount),
+        })
+    }
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        let dst = array_mut_ref![dst, 0, TakerClaim::LEN];
+        let (
+            is_initialized_dst,
+            escrow_pubkey_dst,
+            taker_pubkey_dst,
+            bump_seed_dst,
+            claimed_amount_dst,
+        ) = mut_array_refs![dst, 1, 32, 32, 1, 8];
+
+        let TakerClaim {
+            is_initialized,
+            escrow_pubkey,
+            taker_pubkey,
+            bump_seed,
+            claimed_amount,
+        } = self;
+
+        is_initialized_dst[0] = *is_initialized as u8;
+        escrow_pubkey_dst.copy_from_slice(escrow_pubkey.as_ref());
+        taker_pubkey_dst.copy_from_slice(taker_pubkey.as_ref());
+        bump_seed_dst[0] = *bump_seed;
+        *claimed_amount_dst = claimed_amount.to_le_bytes();
+    }
+}
+
+/// The record that a signed offer was filled, stored in the PDA at `[b"filled", initializer, nonce]`. FillSignedOffer creates it, so the
+/// same signature can't be filled twice
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FilledOffer {
+    pub is_initialized: bool,
+    pub initializer_pubkey: Pubkey,
+    pub taker_pubkey: Pubkey,
+    pub nonce: u64,
+}
+
+impl Sealed for FilledOffer {}
+
+impl IsInitialized for FilledOffer {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for FilledOffer {
+    const LEN: usize = 73; // 1 bool, 2 pubkeys, 1 u64 = 1 + 2 * 32 + 8 = 73
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        let src = array_ref![src, 0, FilledOffer::LEN];
+        let (
+            is_initialized,
+            initializer_pubkey,
+            taker_pubkey,
+            nonce,
+        ) = array_refs![src, 1, 32, 32, 8];
+
+        Ok(FilledOffer {
+            is_initialized: unpack_bool(is_initialized)?,
+            initializer_pubkey: Pubkey::new_from_array(*initializer_pubkey),
+            taker_pubkey: Pubkey::new_from_array(*taker_pubkey),
+            nonce: u64::from_le_bytes(*nonce),
+        })
+    }
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        let dst = array_mut_ref![dst, 0, FilledOffer::LEN];
+        let (
+            is_initialized_dst,
+            initializer_pubkey_dst,
+            taker_pubkey_dst,
+            nonce_dst,
+        ) = mut_array_refs![dst, 1, 32, 32, 8];
+
+        let FilledOffer {
+            is_initialized,
+            initializer_pubkey,
+            taker_pubkey,
+            nonce,
+        } = self;
+
+        is_initialized_dst[0] = *is_initialized as u8;
+        initializer_pubkey_dst.copy_from_slice(initializer_pubkey.as_ref());
+        taker_pubkey_dst.copy_from_slice(taker_pubkey.as_ref());
+        *nonce_dst = nonce.to_le_bytes();
+    }
+}
+
+/// The program wide count of numbered offers, stored in the PDA at `[b"offer_counter"]`. InitEscrow takes the next number from it, so
+/// an offer can be referred to as "offer #4812" rather than by its escrow's address
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OfferCounter {
+    pub is_initialized: bool,
+    pub bump_seed: u8,
+    pub last_offer_id: u64, // the number the latest offer got, offers are numbered from 1
+}
+
+impl OfferCounter {
+    /// Takes the next offer number
+    pub fn next_offer_id(&mut self) -> Result<u64, ProgramError> {
+        self.last_offer_id = self.last_offer_id.checked_add(1).ok_or(EscrowError::AmountOverflow)?;
+        Ok(self.last_offer_id)
+    }
+}
+
+impl Sealed for OfferCounter {}
+
+impl IsInitialized for OfferCounter {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for OfferCounter {
+    const LEN: usize = 10; // 1 bool, 1 u8, 1 u64 = 1 + 1 + 8 = 10
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        let src = array_ref![src, 0, OfferCounter::LEN];
+        let (is_initialized, bump_seed, last_offer_id) = array_refs![src, 1, 1, 8];
+
+        Ok(OfferCounter {
+            is_initialized: unpack_bool(is_initialized)?,
+            bump_seed: bump_seed[0],
+            last_offer_id: u64::from_le_bytes(*last_offer_id),
+        })
+    }
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        let dst = array_mut_ref![dst, 0, OfferCounter::LEN];
+        let (is_initialized_dst, bump_seed_dst, last_offer_id_dst) = mut_array_refs![dst, 1, 1, 8];
+
+        let OfferCounter {
+            is_initialized,
+            bump_seed,
+            last_offer_id,
+        } = self;
+
+        is_initialized_dst[0] = *is_initialized as u8;
+        bump_seed_dst[0] = *bump_seed;
+        *last_offer_id_dst = last_offer_id.to_le_bytes();
+    }
+}
+
+fn unpack_bool(src: &[u8; 1]) -> Result<bool, ProgramError> {
+    match src {
+        [0] => Ok(false),
+        [1] => Ok(true),
+        _ => Err(ProgramError::InvalidAccountData),
+    }
+}
@@ -0,0 +1,435 @@
+//! How each kind of escrow is taken. process_exchange makes the checks every kind shares and moves the payment, and hands the
+//! parts that differ between kinds, what a take is worth and how the offered tokens leave, to the escrow's `SettlementStrategy`
+
+use std::slice::Iter;
+
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    msg,
+    program::invoke_signed,
+    program_error::ProgramError,
+    program_option::COption,
+    pubkey::Pubkey,
+};
+
+use spl_token::state::Account as TokenAccount;
+
+use crate::{
+    error::EscrowError,
+    oracle::OraclePrice,
+    processor::Processor,
+    state::{Escrow, EscrowKind},
+    utils,
+};
+
+/// The taker's side of an Exchange, as far as pricing it goes
+pub struct Take {
+    /// The amount of the offered token the taker expects, see `EscrowInstruction::Exchange`
+    pub amount: u64,
+    /// The mint the taker pays in, only read for an escrow with an allowlist
+    pub payment_mint: Option<Pubkey>,
+    pub unix_timestamp: i64,
+}
+
+impl Take {
+    /// Fails unless the taker expects exactly `offered_amount`, so a take can't land against an offer that changed under it
+    fn check_offered(&self, offered_amount: u64) -> ProgramResult {
+        if self.amount != offered_amount {
+            return Err(EscrowError::ExpectedAmountMismatch.into());
+        }
+        Ok(())
+    }
+}
+
+/// What a take moves
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Amounts {
+    /// Leaves the vault for the taker
+    pub offered_amount: u64,
+    /// What the taker pays, any referral fee included
+    pub expected_amount: u64,
+    /// Nothing is left to sell after the take, so the escrow closes
+    pub fully_filled: bool,
+}
+
+/// The accounts the offered tokens leave through
+pub struct VaultAccounts<'b, 'a> {
+    pub token_program: &'b AccountInfo<'a>,
+    pub vault: &'b AccountInfo<'a>,
+    pub taker_receive_account: &'b AccountInfo<'a>,
+    pub initializers_main_account: &'b AccountInfo<'a>,
+    pub pda_account: &'b AccountInfo<'a>,
+    /// The offered mint and its transfer hook accounts, with Token-2022
+    pub token_2022_transfer: Option<(&'b AccountInfo<'a>, &'b [AccountInfo<'a>])>,
+}
+
+pub trait SettlementStrategy {
+    /// Checks on the vault particular to the kind, on top of the ones process_exchange makes for every kind
+    fn validate(&self, _escrow: &Escrow, _vault: &TokenAccount, _pda: &Pubkey) -> ProgramResult {
+        Ok(())
+    }
+
+    /// What `take` moves. `price_accounts` are the optional trailing accounts, for a kind that reads its price from one
+    fn compute_amounts(
+        &self,
+        escrow: &Escrow,
+        vault: &TokenAccount,
+        take: &Take,
+        price_accounts: &mut Iter<AccountInfo>,
+    ) -> Result<Amounts, ProgramError>;
+
+    /// Sends the offered tokens to the taker, closing the vault once the take has emptied it
+    fn settle(&self, escrow: &Escrow, amounts: &Amounts, accounts: &VaultAccounts) -> ProgramResult {
+        release_from_vault(escrow, amounts, accounts)?;
+        if amounts.fully_filled {
+            close_vault(escrow, accounts)?;
+        }
+        Ok(())
+    }
+}
+
+/// The strategy an Exchange of `kind` goes through, `None` for a kind that can't be exchanged
+pub fn for_kind(kind: EscrowKind) -> Option<&'static dyn SettlementStrategy> {
+    match kind {
+        EscrowKind::FixedPrice => Some(&FixedPrice),
+        EscrowKind::OraclePriced => Some(&OraclePriced),
+        EscrowKind::RatePriced => Some(&RatePriced),
+        EscrowKind::Delegated => Some(&Delegated),
+        EscrowKind::Subscription => None, // settled through ClaimInstallment, not in one go
+        EscrowKind::CollectionBid => None, // the NFT is only known once someone accepts, through AcceptBid
+    }
+}
+
+pub struct FixedPrice;
+
+impl SettlementStrategy for FixedPrice {
+    fn compute_amounts(&self, escrow: &Escrow, vault: &TokenAccount, take: &Take, _price_accounts: &mut Iter<AccountInfo>) -> Result<Amounts, ProgramError> {
+        let (offered_amount, fully_filled) = lot_or_whole_vault(escrow, vault)?;
+        take.check_offered(offered_amount)?;
+
+        // with an allowlist the taker's payment mint picks the price
+        let expected_amount = if escrow.has_accepted_mints() {
+            let payment_mint = take.payment_mint.ok_or(EscrowError::MintNotAccepted)?;
+            escrow.accepted_amount(&payment_mint).ok_or(EscrowError::MintNotAccepted)?
+        } else {
+            escrow.expected_amount
+        };
+
+        Ok(Amounts { offered_amount, expected_amount, fully_filled })
+    }
+}
+
+pub struct OraclePriced;
+
+impl SettlementStrategy for OraclePriced {
+    fn compute_amounts(&self, escrow: &Escrow, vault: &TokenAccount, take: &Take, price_accounts: &mut Iter<AccountInfo>) -> Result<Amounts, ProgramError> {
+        let (offered_amount, fully_filled) = lot_or_whole_vault(escrow, vault)?;
+        take.check_offered(offered_amount)?;
+
+        // the payment is worked out now, never going below the amount the initializer asked for at init
+        let oracle_account = next_account_info(price_accounts)?;
+        if *oracle_account.key != escrow.oracle_pubkey {
+            return Err(EscrowError::InvalidOracleAccount.into());
+        }
+        let oracle_price = OraclePrice::load(oracle_account)?;
+        oracle_price.check_usable(take.unix_timestamp, escrow.oracle_tolerance_bps)?;
+        let expected_amount = oracle_price
+            .value_of(offered_amount, escrow.offered_decimals, escrow.expected_decimals)?
+            .max(escrow.expected_amount);
+
+        Ok(Amounts { offered_amount, expected_amount, fully_filled })
+    }
+}
+
+pub struct RatePriced;
+
+impl SettlementStrategy for RatePriced {
+    fn compute_amounts(&self, escrow: &Escrow, vault: &TokenAccount, take: &Take, _price_accounts: &mut Iter<AccountInfo>) -> Result<Amounts, ProgramError> {
+        // the taker picks their own size, anything up to what's left in the vault, and the escrow closes once that's nothing
+        if take.amount == 0 || take.amount > vault.amount {
+            return Err(EscrowError::InvalidTakeAmount.into());
+        }
+
+        Ok(Amounts {
+            offered_amount: take.amount,
+            expected_amount: escrow.rate_payment(take.amount)?,
+            fully_filled: take.amount == vault.amount,
+        })
+    }
+}
+
+pub struct Delegated;
+
+impl SettlementStrategy for Delegated {
+    fn validate(&self, escrow: &Escrow, vault: &TokenAccount, pda: &Pubkey) -> ProgramResult {
+        // the tokens are still in the initializer's account, which may have revoked or cut the approval since init
+        if vault.delegate != COption::Some(*pda) || vault.delegated_amount < escrow.offered_amount {
+            return Err(EscrowError::DelegationRevoked.into());
+        }
+        Ok(())
+    }
+
+    fn compute_amounts(&self, escrow: &Escrow, _vault: &TokenAccount, take: &Take, _price_accounts: &mut Iter<AccountInfo>) -> Result<Amounts, ProgramError> {
+        // the offer is whatever was approved at init rather than the account's whole balance
+        take.check_offered(escrow.offered_amount)?;
+        Ok(Amounts {
+            offered_amount: escrow.offered_amount,
+            expected_amount: escrow.expected_amount,
+            fully_filled: true,
+        })
+    }
+
+    fn settle(&self, escrow: &Escrow, amounts: &Amounts, accounts: &VaultAccounts) -> ProgramResult {
+        release_from_vault(escrow, amounts, accounts) // the initializer's own account stays open
+    }
+}
+
+/// What a take of a fixed size offer sells: one lot of a reusable escrow, which closes once its last lot goes, or else the whole vault
+pub fn lot_or_whole_vault(escrow: &Escrow, vault: &TokenAccount) -> Result<(u64, bool), ProgramError> {
+    if !escrow.is_reusable {
+        return Ok((vault.amount, true));
+    }
+
+    // the vault may hold several lots or, before a TopUp, less than one
+    if vault.amount < escrow.offered_amount {
+        return Err(EscrowError::InvalidTakeAmount.into());
+    }
+    Ok((escrow.offered_amount, escrow.offered_amount == vault.amount))
+}
+
+/// Transfers the offered tokens out of the vault to the taker, with the PDA signing
+pub fn release_from_vault(escrow: &Escrow, amounts: &Amounts, accounts: &VaultAccounts) -> ProgramResult {
+    msg!("Calling the token program to transfer tokens to the taker..");
+    Processor::transfer_tokens(
+        accounts.token_program,
+        accounts.vault,
+        accounts.taker_receive_account,
+        accounts.pda_account,
+        accounts.token_2022_transfer,
+        amounts.offered_amount,
+        &[&[&b"escrow"[..], &[escrow.bump_seed]]],
+    )
+}
+
+/// Closes the emptied vault, its rent going to the initializer
+pub fn close_vault(escrow: &Escrow, accounts: &VaultAccounts) -> ProgramResult {
+    let close_vault_ix = utils::for_token_program(
+        spl_token::instruction::close_account(
+            &spl_token::id(),
+            accounts.vault.key,
+            accounts.initializers_main_account.key,
+            accounts.pda_account.key,
+            &[accounts.pda_account.key],
+        )?,
+        accounts.token_program.key,
+    );
+    msg!("Calling the token program to close pda's temp account...");
+    invoke_signed(
+        &close_vault_ix,
+        &[
+            accounts.vault.clone(),
+            accounts.initializers_main_account.clone(),
+            accounts.pda_account.clone(),
+            accounts.token_program.clone(),
+        ],
+        &[&[&b"escrow"[..], &[escrow.bump_seed]]],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::AcceptedMint;
+
+    fn vault(amount: u64) -> TokenAccount {
+        TokenAccount {
+            amount,
+            ..TokenAccount::default()
+        }
+    }
+
+    fn take(amount: u64) -> Take {
+        Take {
+            amount,
+            payment_mint: None,
+            unix_timestamp: 1_000,
+        }
+    }
+
+    fn amounts(
+        strategy: &dyn SettlementStrategy,
+        escrow: &Escrow,
+        vault: &TokenAccount,
+        take: &Take,
+    ) -> Result<Amounts, ProgramError> {
+        strategy.compute_amounts(escrow, vault, take, &mut [].iter())
+    }
+
+    fn fixed_price_escrow() -> Escrow {
+        Escrow {
+            expected_amount: 50,
+            ..Escrow::default()
+        }
+    }
+
+    #[test]
+    fn every_kind_but_subscriptions_and_bids_can_be_exchanged() {
+        assert!(for_kind(EscrowKind::FixedPrice).is_some());
+        assert!(for_kind(EscrowKind::OraclePriced).is_some());
+        assert!(for_kind(EscrowKind::RatePriced).is_some());
+        assert!(for_kind(EscrowKind::Delegated).is_some());
+        assert!(for_kind(EscrowKind::Subscription).is_none());
+        assert!(for_kind(EscrowKind::CollectionBid).is_none());
+    }
+
+    #[test]
+    fn fixed_price_sells_the_whole_vault() {
+        assert_eq!(
+            amounts(&FixedPrice, &fixed_price_escrow(), &vault(100), &take(100)),
+            Ok(Amounts { offered_amount: 100, expected_amount: 50, fully_filled: true })
+        );
+        assert_eq!(
+            amounts(&FixedPrice, &fixed_price_escrow(), &vault(100), &take(99)),
+            Err(EscrowError::ExpectedAmountMismatch.into())
+        );
+    }
+
+    #[test]
+    fn fixed_price_sells_one_lot_of_a_reusable_escrow() {
+        let escrow = Escrow {
+            is_reusable: true,
+            offered_amount: 40,
+            ..fixed_price_escrow()
+        };
+        assert_eq!(
+            amounts(&FixedPrice, &escrow, &vault(100), &take(40)),
+            Ok(Amounts { offered_amount: 40, expected_amount: 50, fully_filled: false })
+        );
+        assert_eq!(
+            amounts(&FixedPrice, &escrow, &vault(40), &take(40)),
+            Ok(Amounts { offered_amount: 40, expected_amount: 50, fully_filled: true })
+        );
+        assert_eq!(
+            amounts(&FixedPrice, &escrow, &vault(39), &take(40)),
+            Err(EscrowError::InvalidTakeAmount.into())
+        );
+    }
+
+    #[test]
+    fn fixed_price_prices_an_allowlisted_mint_at_its_own_amount() {
+        let mint = Pubkey::new_unique();
+        let mut escrow = fixed_price_escrow();
+        escrow.accepted_mints[0] = AcceptedMint { mint, amount: 70 };
+
+        let mut allowlisted = take(100);
+        allowlisted.payment_mint = Some(mint);
+        assert_eq!(amounts(&FixedPrice, &escrow, &vault(100), &allowlisted).unwrap().expected_amount, 70);
+
+        allowlisted.payment_mint = Some(Pubkey::new_unique());
+        assert_eq!(
+            amounts(&FixedPrice, &escrow, &vault(100), &allowlisted),
+            Err(EscrowError::MintNotAccepted.into())
+        );
+    }
+
+    #[test]
+    fn rate_priced_lets_the_taker_pick_their_size() {
+        let escrow = Escrow {
+            kind: EscrowKind::RatePriced,
+            rate_numerator: 3,
+            rate_denominator: 2,
+            ..Escrow::default()
+        };
+        assert_eq!(
+            amounts(&RatePriced, &escrow, &vault(100), &take(25)),
+            Ok(Amounts { offered_amount: 25, expected_amount: 38, fully_filled: false })
+        );
+        assert_eq!(
+            amounts(&RatePriced, &escrow, &vault(100), &take(100)),
+            Ok(Amounts { offered_amount: 100, expected_amount: 150, fully_filled: true })
+        );
+        assert_eq!(amounts(&RatePriced, &escrow, &vault(100), &take(101)), Err(EscrowError::InvalidTakeAmount.into()));
+        assert_eq!(amounts(&RatePriced, &escrow, &vault(100), &take(0)), Err(EscrowError::InvalidTakeAmount.into()));
+    }
+
+    #[test]
+    fn delegated_sells_the_approved_amount_while_the_approval_stands() {
+        let pda = Pubkey::new_unique();
+        let escrow = Escrow {
+            kind: EscrowKind::Delegated,
+            offered_amount: 30,
+            ..fixed_price_escrow()
+        };
+        let mut account = vault(100);
+        account.delegate = COption::Some(pda);
+        account.delegated_amount = 30;
+
+        assert_eq!(Delegated.validate(&escrow, &account, &pda), Ok(()));
+        assert_eq!(
+            amounts(&Delegated, &escrow, &account, &take(30)),
+            Ok(Amounts { offered_amount: 30, expected_amount: 50, fully_filled: true })
+        );
+
+        account.delegated_amount = 29;
+        assert_eq!(Delegated.validate(&escrow, &account, &pda), Err(EscrowError::DelegationRevoked.into()));
+        account.delegate = COption::None;
+        assert_eq!(Delegated.validate(&escrow, &account, &pda), Err(EscrowError::DelegationRevoked.into()));
+    }
+
+    /// A Pyth v2 price account quoting `price` with the given confidence, published at `timestamp`
+    fn pyth_price_data(price: i64, conf: u64, expo: i32, timestamp: i64) -> Vec<u8> {
+        let mut data = vec![0; 240];
+        data[0..4].copy_from_slice(&0xa1b2_c3d4u32.to_le_bytes());
+        data[4..8].copy_from_slice(&2u32.to_le_bytes());
+        data[8..12].copy_from_slice(&3u32.to_le_bytes());
+        data[20..24].copy_from_slice(&expo.to_le_bytes());
+        data[96..104].copy_from_slice(&timestamp.to_le_bytes());
+        data[208..216].copy_from_slice(&price.to_le_bytes());
+        data[216..224].copy_from_slice(&conf.to_le_bytes());
+        data[224..228].copy_from_slice(&1u32.to_le_bytes()); // trading
+        data
+    }
+
+    #[test]
+    fn oracle_priced_reads_the_price_account_and_keeps_to_the_floor() {
+        let oracle_key = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let escrow = Escrow {
+            kind: EscrowKind::OraclePriced,
+            oracle_pubkey: oracle_key,
+            oracle_tolerance_bps: 100,
+            ..fixed_price_escrow()
+        };
+
+        // 2 whole expected tokens per offered token, both mints without decimals
+        let mut lamports = 0;
+        let mut data = pyth_price_data(200, 1, -2, 1_000);
+        let oracle = AccountInfo::new(&oracle_key, false, false, &mut lamports, &mut data, &owner, false, 0);
+        let price_accounts = [oracle];
+        assert_eq!(
+            OraclePriced.compute_amounts(&escrow, &vault(100), &take(100), &mut price_accounts.iter()),
+            Ok(Amounts { offered_amount: 100, expected_amount: 200, fully_filled: true })
+        );
+
+        // a price below the initializer's floor is lifted to it
+        assert_eq!(
+            OraclePriced.compute_amounts(&escrow, &vault(10), &take(10), &mut price_accounts.iter()).unwrap().expected_amount,
+            50
+        );
+
+        // any other account in the oracle's place is refused
+        assert_eq!(
+            OraclePriced.compute_amounts(&escrow, &vault(100), &take(100), &mut [].iter()),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+        let escrow = Escrow {
+            oracle_pubkey: Pubkey::new_unique(),
+            ..escrow
+        };
+        assert_eq!(
+            OraclePriced.compute_amounts(&escrow, &vault(100), &take(100), &mut price_accounts.iter()),
+            Err(EscrowError::InvalidOracleAccount.into())
+        );
+    }
+}
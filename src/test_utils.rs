@@ -0,0 +1,183 @@
+//! Builders for the accounts a unit test hands straight to `Processor::process`, without a validator or program-test bank behind it.
+//! Build the accounts, then borrow them all as `AccountInfo`s with `infos`. Call `install_syscall_stubs` first, it gives `Clock::get`
+//! and `Rent::get` the values below and turns every CPI into a no-op, so a test can only check what the program does before and
+//! around its CPIs, not what the token program would have done with them
+
+use std::sync::Once;
+
+use solana_program::{
+    account_info::AccountInfo,
+    clock::Clock,
+    entrypoint::SUCCESS,
+    program_pack::Pack,
+    program_stubs::{self, SyscallStubs},
+    pubkey::Pubkey,
+    rent::Rent,
+    system_program,
+    sysvar,
+};
+use spl_token::state::{Account as TokenAccount, AccountState, Mint};
+
+use crate::state::Escrow;
+
+/// The slot `Clock::get` returns once the stubs are installed
+pub const SLOT: u64 = 1_000;
+/// The unix timestamp `Clock::get` returns once the stubs are installed
+pub const UNIX_TIMESTAMP: i64 = 1_600_000_000;
+
+/// An account a test owns, to be borrowed as an `AccountInfo`. It starts out writable and not a signer
+#[derive(Clone, Debug)]
+pub struct TestAccount {
+    pub key: Pubkey,
+    pub owner: Pubkey,
+    pub lamports: u64,
+    pub data: Vec<u8>,
+    pub is_signer: bool,
+    pub is_writable: bool,
+    pub executable: bool,
+}
+
+impl TestAccount {
+    /// An account at a fresh address holding `data`, with just enough lamports to be rent exempt
+    pub fn new(owner: &Pubkey, data: Vec<u8>) -> Self {
+        TestAccount {
+            key: Pubkey::new_unique(),
+            owner: *owner,
+            lamports: Rent::default().minimum_balance(data.len()),
+            data,
+            is_signer: false,
+            is_writable: true,
+            executable: false,
+        }
+    }
+
+    pub fn signer(mut self) -> Self {
+        self.is_signer = true;
+        self
+    }
+
+    pub fn read_only(mut self) -> Self {
+        self.is_writable = false;
+        self
+    }
+
+    pub fn with_key(mut self, key: Pubkey) -> Self {
+        self.key = key;
+        self
+    }
+
+    pub fn with_owner(mut self, owner: Pubkey) -> Self {
+        self.owner = owner;
+        self
+    }
+
+    pub fn with_lamports(mut self, lamports: u64) -> Self {
+        self.lamports = lamports;
+        self
+    }
+
+    pub fn info(&mut self) -> AccountInfo<'_> {
+        AccountInfo::new(
+            &self.key,
+            self.is_signer,
+            self.is_writable,
+            &mut self.lamports,
+            &mut self.data,
+            &self.owner,
+            self.executable,
+            0,
+        )
+    }
+}
+
+/// Borrows every account as an `AccountInfo`, in order, ready to pass as an instruction's accounts
+pub fn infos(accounts: &mut [TestAccount]) -> Vec<AccountInfo<'_>> {
+    accounts.iter_mut().map(TestAccount::info).collect()
+}
+
+/// A system account with a balance, signing
+pub fn wallet() -> TestAccount {
+    TestAccount::new(&system_program::id(), Vec::new()).with_lamports(1_000_000_000).signer()
+}
+
+/// An initialized SPL Token account of `mint` holding `amount`
+pub fn token_account(owner: &Pubkey, mint: &Pubkey, amount: u64) -> TestAccount {
+    TestAccount::new(
+        &spl_token::id(),
+        packed(TokenAccount {
+            mint: *mint,
+            owner: *owner,
+            amount,
+            state: AccountState::Initialized,
+            ..TokenAccount::default()
+        }),
+    )
+}
+
+/// An initialized SPL Token mint
+pub fn mint_account(decimals: u8, supply: u64) -> TestAccount {
+    TestAccount::new(
+        &spl_token::id(),
+        packed(Mint {
+            decimals,
+            supply,
+            is_initialized: true,
+            ..Mint::default()
+        }),
+    )
+}
+
+/// An escrow account of `program_id` holding `state` in the current layout
+pub fn escrow_account(state: Escrow, program_id: &Pubkey) -> TestAccount {
+    TestAccount::new(program_id, packed(state))
+}
+
+/// The rent sysvar, with the default rent
+pub fn rent_sysvar() -> TestAccount {
+    let rent = Rent::default();
+    // bincode's layout of Rent, its fields one after the other
+    let mut data = rent.lamports_per_byte_year.to_le_bytes().to_vec();
+    data.extend_from_slice(&rent.exemption_threshold.to_le_bytes());
+    data.push(rent.burn_percent);
+    TestAccount::new(&sysvar::id(), data).with_key(sysvar::rent::id()).read_only()
+}
+
+/// A program account, e.g. the token program an instruction CPIs into
+pub fn program(program_id: &Pubkey) -> TestAccount {
+    let mut program = TestAccount::new(&solana_program::bpf_loader::id(), Vec::new()).with_key(*program_id).read_only();
+    program.executable = true;
+    program
+}
+
+/// Installs the syscall stubs for the whole test binary, once
+pub fn install_syscall_stubs() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        program_stubs::set_syscall_stubs(Box::new(TestSyscallStubs));
+    });
+}
+
+struct TestSyscallStubs;
+
+impl SyscallStubs for TestSyscallStubs {
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+        let clock = Clock {
+            slot: SLOT,
+            unix_timestamp: UNIX_TIMESTAMP,
+            ..Clock::default()
+        };
+        unsafe { *(var_addr as *mut Clock) = clock };
+        SUCCESS
+    }
+
+    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+        unsafe { *(var_addr as *mut Rent) = Rent::default() };
+        SUCCESS
+    }
+}
+
+fn packed<T: Pack>(value: T) -> Vec<u8> {
+    let mut data = vec![0; T::LEN];
+    value.pack_into_slice(&mut data);
+    data
+}
@@ -0,0 +1,221 @@
+use solana_program::{
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
+    msg,
+    program::{invoke, invoke_signed},
+    program_error::ProgramError,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    system_program,
+};
+
+use spl_token::state::{Account as TokenAccount, AccountState, Mint};
+
+use crate::error::EscrowError;
+
+/// The SPL Memo program, used to attach an escrow's memo to the transaction that settles it
+pub mod spl_memo {
+    solana_program::declare_id!("MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr");
+}
+
+/// The Token-2022 program, whose accounts and mints can carry extensions the original token program knows nothing about
+pub mod spl_token_2022 {
+    solana_program::declare_id!("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb");
+}
+
+// Token-2022 keeps its extensions in a type-length-value list after the 165 byte base account and a one byte account type
+const TOKEN_2022_EXTENSIONS_OFFSET: usize = 166;
+const EXTENSION_UNINITIALIZED: u16 = 0;
+const EXTENSION_CONFIDENTIAL_TRANSFER_MINT: u16 = 4;
+const EXTENSION_CONFIDENTIAL_TRANSFER_ACCOUNT: u16 = 5;
+
+// the state byte of a token account, after its mint, owner, amount and optional delegate, in both token programs
+const TOKEN_ACCOUNT_STATE_OFFSET: usize = 108;
+
+/// Closes a program owned account: every lamport goes to `destination`, the data is zeroed, shrunk to nothing and the
+/// account is handed back to the system program. Anyone funding it again later in the same transaction gets an empty
+/// system account, not an escrow the program would still read
+pub fn close_account(target: &AccountInfo, destination: &AccountInfo) -> ProgramResult {
+    check_writable(target)?;
+    check_writable(destination)?;
+
+    let mut target_lamports = target.lamports.borrow_mut();
+    let mut destination_lamports = destination.lamports.borrow_mut();
+    **destination_lamports = destination_lamports
+        .checked_add(**target_lamports)
+        .ok_or(EscrowError::AmountOverflow)?;
+    **target_lamports = 0;
+
+    target.data.borrow_mut().fill(0);
+    target.realloc(0, false)?; // the bytes are already zero, the runtime only has to drop them
+    target.assign(&system_program::id());
+
+    Ok(())
+}
+
+/// Moves `amount` lamports out of a program owned account, e.g. a bond held in the escrow account
+pub fn transfer_lamports(source: &AccountInfo, destination: &AccountInfo, amount: u64) -> ProgramResult {
+    check_writable(source)?;
+    check_writable(destination)?;
+
+    let mut source_lamports = source.lamports.borrow_mut();
+    let mut destination_lamports = destination.lamports.borrow_mut();
+    **source_lamports = source_lamports
+        .checked_sub(amount)
+        .ok_or(EscrowError::AmountOverflow)?;
+    **destination_lamports = destination_lamports
+        .checked_add(amount)
+        .ok_or(EscrowError::AmountOverflow)?;
+
+    Ok(())
+}
+
+/// Fails with a clear error rather than a runtime one when an account we're about to change wasn't passed as writable
+pub fn check_writable(account: &AccountInfo) -> ProgramResult {
+    if !account.is_writable {
+        return Err(EscrowError::AccountNotWritable.into());
+    }
+    Ok(())
+}
+
+/// Writes `memo` into the transaction through the SPL Memo program
+pub fn log_memo(memo_program: &AccountInfo, memo: &str) -> ProgramResult {
+    if *memo_program.key != spl_memo::id() {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    msg!("Calling the memo program to record the escrow's memo...");
+    invoke(
+        &Instruction {
+            program_id: spl_memo::id(),
+            accounts: vec![],
+            data: memo.as_bytes().to_vec(),
+        },
+        &[memo_program.clone()],
+    )
+}
+
+/// Whether `account` is a Token-2022 account or mint with the confidential transfer extension
+pub fn is_confidential(account: &AccountInfo) -> bool {
+    if *account.owner != spl_token_2022::id() {
+        return false;
+    }
+
+    let data = account.data.borrow();
+    let mut offset = TOKEN_2022_EXTENSIONS_OFFSET;
+    while let Some(header) = data.get(offset..offset + 4) {
+        let extension_type = u16::from_le_bytes([header[0], header[1]]);
+        let length = u16::from_le_bytes([header[2], header[3]]) as usize;
+        match extension_type {
+            EXTENSION_CONFIDENTIAL_TRANSFER_MINT | EXTENSION_CONFIDENTIAL_TRANSFER_ACCOUNT => return true,
+            EXTENSION_UNINITIALIZED => break, // the rest of the account is unused space
+            _ => offset += 4 + length,
+        }
+    }
+    false
+}
+
+/// Fails with `ConfidentialAccountNotSupported` if any of `accounts` is a confidential Token-2022 account or mint.
+/// Moving a confidential balance needs zero knowledge proofs only the holder can make, which the PDA can't do for the vault,
+/// so these are turned away up front rather than left to fail somewhere inside the token program
+pub fn check_not_confidential(accounts: &[&AccountInfo]) -> ProgramResult {
+    if let Some(account) = accounts.iter().find(|account| is_confidential(account)) {
+        msg!("{} has the Token-2022 confidential transfer extension, move the tokens to a plain token account first", account.key);
+        return Err(EscrowError::ConfidentialAccountNotSupported.into());
+    }
+    Ok(())
+}
+
+/// Fails with `AccountFrozen` if any of `accounts` is a token account its mint's freeze authority has frozen. The token program
+/// won't move tokens in or out of one, and the error it gives from inside the CPI doesn't say which account it was
+pub fn check_not_frozen(accounts: &[&AccountInfo]) -> ProgramResult {
+    if let Some(account) = accounts.iter().find(|account| is_frozen(account)) {
+        msg!("{} is frozen by its mint's freeze authority", account.key);
+        return Err(EscrowError::AccountFrozen.into());
+    }
+    Ok(())
+}
+
+/// Whether `account` is a frozen token account of either token program, read straight from its state byte so any other account is
+/// just not frozen
+pub fn is_frozen(account: &AccountInfo) -> bool {
+    is_token_program(account.owner) && account.data.borrow().get(TOKEN_ACCOUNT_STATE_OFFSET) == Some(&(AccountState::Frozen as u8))
+}
+
+/// Whether `key` is the original token program or Token-2022
+pub fn is_token_program(key: &Pubkey) -> bool {
+    *key == spl_token::id() || *key == spl_token_2022::id()
+}
+
+/// Points a token instruction built by `spl_token` at `token_program`. Token-2022 takes the original program's instructions
+/// unchanged, but the `spl_token` builders refuse any other program id, so they're built for `spl_token` and retargeted here
+pub fn for_token_program(mut instruction: Instruction, token_program: &Pubkey) -> Instruction {
+    instruction.program_id = *token_program;
+    instruction
+}
+
+/// Unpacks a token account of either token program, a Token-2022 account's extensions come after the usual layout and are ignored
+pub fn unpack_token_account(account: &AccountInfo) -> Result<TokenAccount, ProgramError> {
+    let data = account.data.borrow();
+    if *account.owner == spl_token_2022::id() && data.len() > TokenAccount::LEN {
+        return TokenAccount::unpack(&data[..TokenAccount::LEN]);
+    }
+    TokenAccount::unpack(&data)
+}
+
+/// Unpacks a mint of either token program, a Token-2022 mint's extensions come after the usual layout and are ignored
+pub fn unpack_mint(account: &AccountInfo) -> Result<Mint, ProgramError> {
+    let data = account.data.borrow();
+    if *account.owner == spl_token_2022::id() && data.len() > Mint::LEN {
+        return Mint::unpack(&data[..Mint::LEN]);
+    }
+    Mint::unpack(&data)
+}
+
+/// Makes a TransferChecked through `token_program`, forwarding `hook_accounts` so a Token-2022 mint with a transfer hook can
+/// run it. These are the hook program, its extra account metas account and whatever that lists, in the order the client
+/// resolved them. Token-2022 checks them against the extra account metas account itself, so they're passed on as is.
+/// This does what `spl_token_2022::onchain::invoke_transfer_checked` does, minus resolving the accounts on chain, which the
+/// solana-program version this program is pinned to can't build
+#[allow(clippy::too_many_arguments)]
+pub fn invoke_transfer_checked<'a>(
+    token_program: &AccountInfo<'a>,
+    source: &AccountInfo<'a>,
+    mint: &AccountInfo<'a>,
+    destination: &AccountInfo<'a>,
+    authority: &AccountInfo<'a>,
+    hook_accounts: &[AccountInfo<'a>],
+    amount: u64,
+    signer_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    let decimals = unpack_mint(mint)?.decimals;
+    let mut transfer_ix = for_token_program(
+        spl_token::instruction::transfer_checked(
+            &spl_token::id(),
+            source.key,
+            mint.key,
+            destination.key,
+            authority.key,
+            &[],
+            amount,
+            decimals,
+        )?,
+        token_program.key,
+    );
+    transfer_ix.accounts.extend(hook_accounts.iter().map(|account| AccountMeta {
+        pubkey: *account.key,
+        is_signer: false,
+        is_writable: account.is_writable,
+    }));
+
+    let mut transfer_accounts = vec![
+        source.clone(),
+        mint.clone(),
+        destination.clone(),
+        authority.clone(),
+        token_program.clone(),
+    ];
+    transfer_accounts.extend(hook_accounts.iter().cloned());
+
+    invoke_signed(&transfer_ix, &transfer_accounts, signer_seeds)
+}